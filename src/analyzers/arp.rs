@@ -0,0 +1,403 @@
+use super::{
+    Event, EventKind, ProtocolAnalyzer, TIME_FMT,
+    report::{self, DailyReport},
+};
+use crate::{
+    ArgsCommand,
+    formatters::{EventFormatter, create_formatter},
+};
+use ahash::{HashMap, HashSet};
+use chrono::{DateTime, Local, Utc};
+use std::{fmt::Write as _, net::IpAddr};
+
+/// A previous MAC seen within this window means two hosts claim the IP
+const CONFLICT_SECONDS: i64 = 60;
+/// Gratuitous announcements from one MAC above this count per window are a storm
+const STORM_COUNT: u32 = 20;
+const STORM_SECONDS: i64 = 10;
+
+struct Binding {
+    mac: String,
+    first_seen: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+    packets: u32,
+}
+
+#[derive(Default)]
+struct IpHistory {
+    bindings: Vec<Binding>,
+    changes: u32,
+    flip_flops: u32,
+    last_alert: Option<DateTime<Utc>>,
+}
+
+#[derive(Default)]
+struct GratuitousWindow {
+    start: DateTime<Utc>,
+    count: u32,
+    alerted: bool,
+}
+
+pub struct Analyzer {
+    ips: HashMap<String, IpHistory>,
+    gateways: HashSet<String>,
+    gratuitous: HashMap<String, GratuitousWindow>,
+    gratuitous_senders: HashMap<String, u32>,
+    conflicts: u32,
+    flip_flops: u32,
+    gateway_changes: u32,
+    storms: u32,
+    packets: u32,
+    top: usize,
+    formatter: Box<dyn EventFormatter>,
+    daily_report: DailyReport,
+}
+
+const PROTOCOL: &str = "arp";
+
+fn is_unspecified(ip: &str) -> bool {
+    ip.is_empty() || ip.parse::<IpAddr>().is_ok_and(|ip| ip.is_unspecified())
+}
+
+impl Analyzer {
+    fn alert(&mut self, ts: DateTime<Utc>, method: &str, mac: &str, ip: &str, detail: String) {
+        self.formatter.event(&Event {
+            ts,
+            protocol: PROTOCOL,
+            kind: EventKind::Alert,
+            user: mac.to_owned(),
+            method: method.to_owned(),
+            addr: Some(ip.to_owned()),
+            detail: Some(detail),
+            ..Default::default()
+        });
+    }
+
+    fn on_binding(&mut self, ts: DateTime<Utc>, method: &str, ip: &str, mac: &str) {
+        if is_unspecified(ip) || mac.is_empty() {
+            return;
+        }
+        let history = self.ips.entry(ip.to_owned()).or_default();
+        if let Some(current) = history.bindings.last_mut()
+            && current.mac == mac
+        {
+            current.last_seen = ts;
+            current.packets += 1;
+            return;
+        }
+        let previous = history
+            .bindings
+            .last()
+            .map(|b| (b.mac.clone(), (ts - b.last_seen).num_seconds()));
+        // one entry per MAC keeps flip-flopping IPs bounded, current binding is last
+        let returning = history.bindings.iter().position(|b| b.mac == mac);
+        let mut binding = returning
+            .map(|pos| history.bindings.remove(pos))
+            .unwrap_or_else(|| Binding {
+                mac: mac.to_owned(),
+                first_seen: ts,
+                last_seen: ts,
+                packets: 0,
+            });
+        binding.last_seen = ts;
+        binding.packets += 1;
+        history.bindings.push(binding);
+        let returning = returning.is_some();
+        let Some((previous_mac, idle_s)) = previous else {
+            return;
+        };
+        history.changes += 1;
+        let conflict = idle_s < CONFLICT_SECONDS;
+        if conflict && returning {
+            history.flip_flops += 1;
+            self.flip_flops += 1;
+        } else if conflict {
+            self.conflicts += 1;
+        }
+        // flip-flops repeat every few seconds, one alert per window is enough
+        let quiet = history
+            .last_alert
+            .is_some_and(|last| (ts - last).num_seconds() < CONFLICT_SECONDS);
+        let gateway = self.gateways.contains(ip);
+        if gateway {
+            self.gateway_changes += 1;
+        }
+        let detail = match (gateway, conflict, returning) {
+            (true, _, _) => format!("GATEWAY MAC changed from {previous_mac}"),
+            (false, true, true) => format!(
+                "MAC flip-flop with {previous_mac} ({} flips)",
+                history.flip_flops
+            ),
+            (false, true, false) => format!("IP conflict, also claimed by {previous_mac}"),
+            (false, false, _) => {
+                self.formatter.event(&Event {
+                    ts,
+                    protocol: PROTOCOL,
+                    kind: EventKind::Warning,
+                    user: mac.to_owned(),
+                    method: method.to_owned(),
+                    addr: Some(ip.to_owned()),
+                    detail: Some(format!(
+                        "binding changed from {previous_mac} idle {idle_s} s"
+                    )),
+                    ..Default::default()
+                });
+                return;
+            }
+        };
+        if gateway || !quiet {
+            history.last_alert = Some(ts);
+            self.alert(ts, method, mac, ip, detail);
+        }
+    }
+
+    fn on_gratuitous(&mut self, ts: DateTime<Utc>, method: &str, ip: &str, mac: &str) {
+        *self.gratuitous_senders.entry(mac.to_owned()).or_default() += 1;
+        let window = self.gratuitous.entry(mac.to_owned()).or_default();
+        if (ts - window.start).num_seconds() >= STORM_SECONDS {
+            *window = GratuitousWindow {
+                start: ts,
+                ..Default::default()
+            };
+        }
+        window.count += 1;
+        if window.count > STORM_COUNT && !window.alerted {
+            window.alerted = true;
+            self.storms += 1;
+            self.alert(
+                ts,
+                method,
+                mac,
+                ip,
+                format!("gratuitous storm, more than {STORM_COUNT} in {STORM_SECONDS} s"),
+            );
+        }
+    }
+
+    fn print_stats(&mut self, opt_ts: Option<DateTime<Utc>>) {
+        let mut output = String::with_capacity(200);
+        report::print_header(self.formatter.as_mut(), opt_ts);
+        report::print_section(self.formatter.as_mut(), "Bindings");
+        let mut ips = Vec::from_iter(self.ips.keys());
+        ips.sort_by_key(|ip| (ip.parse::<IpAddr>().ok(), *ip));
+        for ip in ips {
+            let history = &self.ips[ip];
+            let Some(current) = history.bindings.last() else {
+                continue;
+            };
+            write!(
+                output,
+                "{ip:<26} {} last seen {}",
+                current.mac,
+                current.last_seen.with_timezone(&Local).format(TIME_FMT)
+            )
+            .unwrap();
+            if self.gateways.contains(ip) {
+                write!(output, " GATEWAY").unwrap();
+            }
+            if history.changes > 0 {
+                write!(
+                    output,
+                    " {} changes {} flip-flops",
+                    history.changes, history.flip_flops
+                )
+                .unwrap();
+                for binding in &history.bindings {
+                    write!(
+                        output,
+                        "\n\t{} {} - {} {:6} packets",
+                        binding.mac,
+                        binding.first_seen.with_timezone(&Local).format(TIME_FMT),
+                        binding.last_seen.with_timezone(&Local).format(TIME_FMT),
+                        binding.packets
+                    )
+                    .unwrap();
+                }
+            }
+            self.formatter.report(&output, 1);
+            output.clear();
+        }
+
+        report::print_section(self.formatter.as_mut(), "IPs Claimed By Several MACs");
+        let mut macs_per_ip = HashMap::default();
+        for (ip, history) in &self.ips {
+            let macs = HashSet::from_iter(history.bindings.iter().map(|b| &b.mac));
+            if macs.len() > 1 {
+                macs_per_ip.insert(ip, macs.len());
+            }
+        }
+        for (ip, count) in report::top_n(&macs_per_ip, self.top) {
+            self.formatter.report(&format!("{count:8} {ip}"), 1);
+        }
+        report::print_section(self.formatter.as_mut(), "Top Gratuitous Senders");
+        for (mac, count) in report::top_n(&self.gratuitous_senders, self.top) {
+            self.formatter.report(&format!("{count:8} {mac}"), 1);
+        }
+        write!(
+            output,
+            r#"
+ ------------ STATS ------------
+
+- total packets: {}
+- known IPs: {}
+- binding changes: {}
+- IP conflicts: {}
+- MAC flip-flops: {}
+- gateway MAC changes: {}
+- gratuitous storms: {}
+"#,
+            self.packets,
+            self.ips.len(),
+            self.ips.values().map(|h| h.changes).sum::<u32>(),
+            self.conflicts,
+            self.flip_flops,
+            self.gateway_changes,
+            self.storms,
+        )
+        .unwrap();
+        self.formatter.report(&output, 0);
+        report::print_footer(self.formatter.as_mut());
+    }
+}
+
+impl ProtocolAnalyzer for Analyzer {
+    fn new(cmd_args: &ArgsCommand, verbosity: u8) -> Self {
+        let (top, gateways) = match cmd_args {
+            ArgsCommand::Analyzer { top, gateway, .. } => {
+                (*top, HashSet::from_iter(gateway.iter().cloned()))
+            }
+            _ => (10, HashSet::default()),
+        };
+        Self {
+            ips: HashMap::default(),
+            gateways,
+            gratuitous: HashMap::default(),
+            gratuitous_senders: HashMap::default(),
+            conflicts: 0,
+            flip_flops: 0,
+            gateway_changes: 0,
+            storms: 0,
+            packets: 0,
+            top,
+            formatter: create_formatter(cmd_args, verbosity),
+            daily_report: DailyReport::default(),
+        }
+    }
+
+    fn analyze(&mut self, ts: DateTime<Utc>, cols: Vec<&str>) {
+        let eth_src = cols[12];
+        if self.daily_report.is_due(ts) {
+            self.print_stats(Some(ts));
+        }
+        self.packets += 1;
+
+        if !cols[2].is_empty() {
+            let mac = cols[3];
+            let sender_ip = cols[4];
+            let gratuitous = matches!(cols[6], "1" | "True" | "true") || sender_ip == cols[5];
+            self.on_binding(ts, "ARP", sender_ip, mac);
+            if gratuitous && !is_unspecified(sender_ip) {
+                self.on_gratuitous(ts, "ARP", sender_ip, mac);
+            }
+            return;
+        }
+
+        let mac = if cols[11].is_empty() {
+            eth_src
+        } else {
+            cols[11].split(',').next().unwrap_or_default()
+        };
+        let src_ip = cols[8];
+        match cols[7] {
+            // router advertisements mark IPv6 gateways
+            "134" => {
+                self.gateways.insert(src_ip.to_owned());
+                self.on_binding(ts, "NDP", src_ip, mac);
+            }
+            "135" => self.on_binding(ts, "NDP", src_ip, mac),
+            "136" => {
+                let target = cols[9];
+                self.on_binding(ts, "NDP", target, mac);
+                if !matches!(cols[10], "1" | "True" | "true") {
+                    self.on_gratuitous(ts, "NDP", target, mac);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn add_protocol_fields(&self, tshark_args: &mut Vec<&str>) {
+        tshark_args.push("-e");
+        tshark_args.push("arp.opcode");
+        tshark_args.push("-e");
+        tshark_args.push("arp.src.hw_mac");
+        tshark_args.push("-e");
+        tshark_args.push("arp.src.proto_ipv4");
+        tshark_args.push("-e");
+        tshark_args.push("arp.dst.proto_ipv4");
+        tshark_args.push("-e");
+        tshark_args.push("arp.isgratuitous");
+        tshark_args.push("-e");
+        tshark_args.push("icmpv6.type");
+        tshark_args.push("-e");
+        tshark_args.push("ipv6.src");
+        tshark_args.push("-e");
+        tshark_args.push("icmpv6.nd.na.target_address");
+        tshark_args.push("-e");
+        tshark_args.push("icmpv6.nd.na.flag.s");
+        tshark_args.push("-e");
+        tshark_args.push("icmpv6.opt.linkaddr");
+        tshark_args.push("-e");
+        tshark_args.push("eth.src");
+        if !tshark_args.contains(&"-Y") {
+            tshark_args.push("-Y");
+            tshark_args.push("arp or (icmpv6.type >= 134 and icmpv6.type <= 136)");
+        }
+        if tshark_args.contains(&"-i") && !tshark_args.contains(&"-f") {
+            tshark_args.push("-f");
+            tshark_args.push("arp or icmp6");
+        }
+    }
+
+    fn end(&mut self) {
+        self.print_stats(None);
+    }
+}
+
+// MARK: TESTS
+#[cfg(test)]
+mod test {
+    use chrono::{DateTime, TimeDelta};
+
+    use super::{Analyzer, ProtocolAnalyzer as _};
+    use crate::analyzers::test_args;
+
+    #[test]
+    fn conflicts_flip_flops_and_gateway_change() {
+        let mut analyzer = Analyzer::new(&test_args(&["--gateway", "10.0.0.254"]), 0);
+        let start = DateTime::from_timestamp(1738062028, 0).unwrap();
+        // a second host claims .5 and the first one answers back, the gateway is replaced later
+        for (s, mac, ip) in [
+            (0, "00:00:00:00:00:0a", "10.0.0.5"),
+            (0, "00:00:00:00:00:fe", "10.0.0.254"),
+            (10, "00:00:00:00:00:0b", "10.0.0.5"),
+            (20, "00:00:00:00:00:0a", "10.0.0.5"),
+            (300, "00:00:00:00:00:ee", "10.0.0.254"),
+        ] {
+            let cols = vec![
+                "", "", "2", mac, ip, "10.0.0.1", "0", "", "", "", "", "", mac,
+            ];
+            analyzer.analyze(start + TimeDelta::seconds(s), cols);
+        }
+        analyzer.end();
+        assert_eq!(analyzer.conflicts, 1);
+        assert_eq!(analyzer.flip_flops, 1);
+        assert_eq!(analyzer.gateway_changes, 1);
+        let history = &analyzer.ips["10.0.0.5"];
+        assert_eq!(history.changes, 2);
+        assert_eq!(history.bindings.len(), 2);
+        assert_eq!(history.bindings[1].mac, "00:00:00:00:00:0a");
+        assert_eq!(history.bindings[1].packets, 2);
+        assert_eq!(analyzer.storms, 0);
+    }
+}
@@ -0,0 +1,633 @@
+use super::{
+    Event, EventKind, ProtocolAnalyzer, TIME_FMT,
+    report::{self, DailyReport},
+};
+use crate::{
+    ArgsCommand,
+    formatters::{EventFormatter, create_formatter},
+};
+use ahash::{HashMap, HashSet};
+use chrono::{DateTime, Local, Utc};
+use std::fmt::Write as _;
+
+const OPEN: &str = "1";
+const UPDATE: &str = "2";
+const NOTIFICATION: &str = "3";
+const KEEPALIVE: &str = "4";
+
+/// Re-announcements after a withdrawal before a prefix is reported as flapping
+const FLAP_WARN: u32 = 5;
+/// Prefixes beyond this are only counted, a full table is close to a million routes
+const MAX_PREFIXES: usize = 200_000;
+/// 4-octet AS numbers are carried in a capability, the OPEN field holds AS_TRANS
+const AS_TRANS: &str = "23456";
+
+fn notification_name(major: &str, minor: &str) -> String {
+    let major_name = match major {
+        "1" => "Message Header Error",
+        "2" => "OPEN Message Error",
+        "3" => "UPDATE Message Error",
+        "4" => "Hold Timer Expired",
+        "5" => "Finite State Machine Error",
+        "6" => "Cease",
+        "7" => "ROUTE-REFRESH Message Error",
+        _ => return format!("error {major}/{minor}"),
+    };
+    let minor_name = match (major, minor) {
+        (_, "" | "0") => return major_name.to_owned(),
+        ("2", "2") => "Bad Peer AS",
+        ("2", "3") => "Bad BGP Identifier",
+        ("2", "6") => "Unacceptable Hold Time",
+        ("2", "7") => "Unsupported Capability",
+        ("3", "1") => "Malformed Attribute List",
+        ("3", "3") => "Missing Well-known Attribute",
+        ("3", "11") => "Malformed AS_PATH",
+        ("6", "1") => "Maximum Number of Prefixes Reached",
+        ("6", "2") => "Administrative Shutdown",
+        ("6", "3") => "Peer De-configured",
+        ("6", "4") => "Administrative Reset",
+        ("6", "5") => "Connection Rejected",
+        ("6", "6") => "Other Configuration Change",
+        ("6", "7") => "Connection Collision Resolution",
+        ("6", "8") => "Out of Resources",
+        ("6", "9") => "Hard Reset",
+        ("6", "10") => "BFD Down",
+        _ => return format!("{major_name} subcode {minor}"),
+    };
+    format!("{major_name}/{minor_name}")
+}
+
+fn split(value: &str) -> Vec<&str> {
+    value.split(',').filter(|v| !v.is_empty()).collect()
+}
+
+/// Announced and withdrawn prefixes of the UPDATEs in a segment. Prefix lengths are one list for
+/// all prefix kinds, they are paired when the order is known and None otherwise, since the same
+/// route without its length would be tracked as a different prefix.
+fn update_prefixes(cols: &[&str], updates: usize) -> (Vec<Option<String>>, Vec<Option<String>>) {
+    // message order: withdrawn routes, MP_REACH_NLRI, MP_UNREACH_NLRI, NLRI
+    let lists = [
+        (split(cols[13]), false),
+        (split(cols[15]), true),
+        (split(cols[16]), true),
+        (split(cols[17]), false),
+        (split(cols[18]), false),
+        (split(cols[14]), true),
+    ];
+    let lengths = split(cols[19]);
+    let total = lists.iter().map(|(l, _)| l.len()).sum::<usize>();
+    let kinds = lists.iter().filter(|(l, _)| !l.is_empty()).count();
+    let mut lengths =
+        (total == lengths.len() && (updates == 1 || kinds == 1)).then_some(lengths.into_iter());
+    let mut announced = Vec::new();
+    let mut withdrawn = Vec::new();
+    for (prefixes, announce) in lists {
+        for prefix in prefixes {
+            let prefix = lengths
+                .as_mut()
+                .and_then(Iterator::next)
+                .map(|len| format!("{prefix}/{len}"));
+            if announce {
+                announced.push(prefix);
+            } else {
+                withdrawn.push(prefix);
+            }
+        }
+    }
+    (announced, withdrawn)
+}
+
+#[derive(Default)]
+struct Speaker {
+    asn: String,
+    /// OPEN sent in the current connection attempt
+    opened: bool,
+    hold_time: Option<u32>,
+    last_seen: Option<DateTime<Utc>>,
+    /// Hold timer already reported for the current silence
+    expired: bool,
+}
+
+#[derive(Default)]
+struct Peering {
+    speakers: HashMap<String, Speaker>,
+    up: bool,
+    up_since: Option<DateTime<Utc>>,
+    ups: u32,
+    downs: u32,
+    hold_violations: u32,
+    updates: u32,
+    announced: u64,
+    withdrawn: u64,
+    last_notification: Option<String>,
+}
+
+impl Peering {
+    /// Smaller of the two OPEN hold times, 0 disables keepalives
+    fn hold_time(&self) -> Option<u32> {
+        self.speakers.values().filter_map(|s| s.hold_time).min()
+    }
+
+    fn label(&self, addr: &str) -> String {
+        match self.speakers.get(addr).map(|s| s.asn.as_str()) {
+            Some(asn) if !asn.is_empty() => format!("{addr} AS{asn}"),
+            _ => addr.to_owned(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct PrefixStats {
+    announced: u32,
+    withdrawn: u32,
+    flaps: u32,
+    is_withdrawn: bool,
+    last_change: Option<DateTime<Utc>>,
+}
+
+pub struct Analyzer {
+    /// Keyed by the two speaker addresses in order
+    peerings: HashMap<(String, String), Peering>,
+    /// (peering speakers, prefix), a route moving between upstreams is not a flap
+    prefixes: HashMap<(String, String, String), PrefixStats>,
+    untracked_prefixes: u64,
+    /// Prefixes of segments whose lengths could not be paired
+    unpaired_prefixes: u64,
+    flap_reported: HashSet<(String, String, String)>,
+    notifications: HashMap<String, u32>,
+    last_timeout_check: Option<DateTime<Utc>>,
+    top: usize,
+    formatter: Box<dyn EventFormatter>,
+    daily_report: DailyReport,
+}
+
+const PROTOCOL: &str = "bgp";
+
+fn peering_key(src: &str, dst: &str) -> (String, String) {
+    if src <= dst {
+        (src.to_owned(), dst.to_owned())
+    } else {
+        (dst.to_owned(), src.to_owned())
+    }
+}
+
+impl Analyzer {
+    fn event(
+        &mut self,
+        ts: DateTime<Utc>,
+        kind: EventKind,
+        method: &str,
+        user: String,
+        addr: String,
+        detail: String,
+    ) {
+        self.formatter.event(&Event {
+            ts,
+            protocol: PROTOCOL,
+            kind,
+            user,
+            method: method.to_owned(),
+            addr: Some(addr),
+            detail: Some(detail),
+            ..Default::default()
+        });
+    }
+
+    /// Speakers silent for longer than the hold time, the peer should have dropped the session
+    fn check_hold_timers(&mut self, ts: DateTime<Utc>) {
+        let mut expired = Vec::new();
+        for ((a, b), peering) in &mut self.peerings {
+            let Some(hold) = peering.hold_time().filter(|h| *h > 0 && peering.up) else {
+                continue;
+            };
+            for (addr, other) in [(a, b), (b, a)] {
+                let Some(speaker) = peering.speakers.get_mut(addr) else {
+                    continue;
+                };
+                let Some(silent) = speaker.last_seen.map(|last| (ts - last).num_seconds()) else {
+                    continue;
+                };
+                if silent > hold as i64 && !speaker.expired {
+                    speaker.expired = true;
+                    expired.push((addr.clone(), other.clone(), silent, hold));
+                }
+            }
+            peering.hold_violations += expired
+                .iter()
+                .filter(|(addr, other, _, _)| peering_key(addr, other) == (a.clone(), b.clone()))
+                .count() as u32;
+        }
+        for (addr, other, silent, hold) in expired {
+            let peering = &self.peerings[&peering_key(&addr, &other)];
+            let (user, peer) = (peering.label(&addr), peering.label(&other));
+            self.event(
+                ts,
+                EventKind::Alert,
+                "HOLD",
+                user,
+                peer,
+                format!("hold timer expired, no message for {silent} s (hold time {hold} s)"),
+            );
+        }
+    }
+
+    fn on_update(
+        &mut self,
+        ts: DateTime<Utc>,
+        key: &(String, String),
+        announced: Vec<Option<String>>,
+        withdrawn: Vec<Option<String>>,
+        src: &str,
+    ) {
+        let peering = self.peerings.get_mut(key).unwrap();
+        peering.announced += announced.len() as u64;
+        peering.withdrawn += withdrawn.len() as u64;
+        let mut flapping = Vec::new();
+        for (prefix, announce) in announced
+            .into_iter()
+            .map(|p| (p, true))
+            .chain(withdrawn.into_iter().map(|p| (p, false)))
+        {
+            let Some(prefix) = prefix else {
+                self.unpaired_prefixes += 1;
+                continue;
+            };
+            let route = (key.0.clone(), key.1.clone(), prefix.clone());
+            if self.prefixes.len() >= MAX_PREFIXES && !self.prefixes.contains_key(&route) {
+                self.untracked_prefixes += 1;
+                continue;
+            }
+            let stats = self.prefixes.entry(route.clone()).or_default();
+            if announce {
+                stats.announced += 1;
+                if stats.is_withdrawn {
+                    stats.flaps += 1;
+                    if stats.flaps >= FLAP_WARN && self.flap_reported.insert(route) {
+                        flapping.push((prefix, stats.flaps, stats.withdrawn));
+                    }
+                }
+            } else {
+                stats.withdrawn += 1;
+            }
+            stats.is_withdrawn = !announce;
+            stats.last_change = Some(ts);
+        }
+        let (user, peer) = {
+            let peering = &self.peerings[key];
+            let dst = if key.0 == src { &key.1 } else { &key.0 };
+            (peering.label(src), peering.label(dst))
+        };
+        for (prefix, flaps, withdrawals) in flapping {
+            self.event(
+                ts,
+                EventKind::Warning,
+                "UPDATE",
+                user.clone(),
+                peer.clone(),
+                format!(
+                    "{prefix} flapping, {flaps} re-announcements after {withdrawals} withdrawals"
+                ),
+            );
+        }
+    }
+
+    fn print_stats(&mut self, opt_ts: Option<DateTime<Utc>>) {
+        let mut output = String::with_capacity(200);
+        report::print_header(self.formatter.as_mut(), opt_ts);
+        report::print_section(self.formatter.as_mut(), "Peerings");
+        let mut peerings = Vec::from_iter(&self.peerings);
+        peerings.sort_by(|a, b| b.1.downs.cmp(&a.1.downs).then(a.0.cmp(b.0)));
+        for ((a, b), peering) in peerings {
+            write!(
+                output,
+                "{:<28} <-> {:<28} {:<4} ups {:3} downs {:3} hold violations {:3} updates {:7} announced {:8} withdrawn {:8}",
+                peering.label(a),
+                peering.label(b),
+                if peering.up { "UP" } else { "DOWN" },
+                peering.ups,
+                peering.downs,
+                peering.hold_violations,
+                peering.updates,
+                peering.announced,
+                peering.withdrawn,
+            )
+            .unwrap();
+            if let Some(hold) = peering.hold_time() {
+                write!(output, " hold {hold} s").unwrap();
+            }
+            if let Some(since) = peering.up_since.filter(|_| peering.up) {
+                write!(
+                    output,
+                    " up since {}",
+                    since.with_timezone(&Local).format(TIME_FMT)
+                )
+                .unwrap();
+            }
+            if let Some(notification) = &peering.last_notification {
+                write!(output, " last NOTIFICATION {notification}").unwrap();
+            }
+            self.formatter.report(&output, 1);
+            output.clear();
+        }
+        report::print_section(self.formatter.as_mut(), "Notifications");
+        for (name, count) in report::top_n(&self.notifications, self.top) {
+            self.formatter.report(&format!("{count:8} {name}"), 1);
+        }
+        report::print_section(self.formatter.as_mut(), "Flapping Prefixes");
+        let mut prefixes = self
+            .prefixes
+            .iter()
+            .filter(|(_, s)| s.flaps > 0)
+            .collect::<Vec<_>>();
+        prefixes.sort_by(|a, b| b.1.flaps.cmp(&a.1.flaps).then(a.0.cmp(b.0)));
+        for ((a, b, prefix), stats) in prefixes.into_iter().take(self.top) {
+            let peering = &self.peerings[&(a.clone(), b.clone())];
+            write!(
+                output,
+                "{prefix:<24} {:<28} <-> {:<28} flaps {:5} announced {:6} withdrawn {:6}",
+                peering.label(a),
+                peering.label(b),
+                stats.flaps,
+                stats.announced,
+                stats.withdrawn
+            )
+            .unwrap();
+            if let Some(last) = stats.last_change {
+                write!(
+                    output,
+                    " last change {}",
+                    last.with_timezone(&Local).format(TIME_FMT)
+                )
+                .unwrap();
+            }
+            self.formatter.report(&output, 1);
+            output.clear();
+        }
+        write!(
+            output,
+            r#"
+ ------------ STATS ------------
+
+- peerings: {}
+- established: {}
+- prefixes seen per peering: {}
+- untracked prefix updates: {}
+- prefix updates without length: {}
+"#,
+            self.peerings.len(),
+            self.peerings.values().filter(|p| p.up).count(),
+            self.prefixes.len(),
+            self.untracked_prefixes,
+            self.unpaired_prefixes,
+        )
+        .unwrap();
+        self.formatter.report(&output, 0);
+        report::print_footer(self.formatter.as_mut());
+    }
+}
+
+impl ProtocolAnalyzer for Analyzer {
+    fn new(cmd_args: &ArgsCommand, verbosity: u8) -> Self {
+        let top = match cmd_args {
+            ArgsCommand::Analyzer { top, .. } => *top,
+            _ => 10,
+        };
+        Self {
+            peerings: HashMap::default(),
+            prefixes: HashMap::default(),
+            untracked_prefixes: 0,
+            unpaired_prefixes: 0,
+            flap_reported: HashSet::default(),
+            notifications: HashMap::default(),
+            last_timeout_check: None,
+            top,
+            formatter: create_formatter(cmd_args, verbosity),
+            daily_report: DailyReport::default(),
+        }
+    }
+
+    fn analyze(&mut self, ts: DateTime<Utc>, cols: Vec<&str>) {
+        if self
+            .last_timeout_check
+            .is_none_or(|last| (ts - last).num_seconds() >= 1)
+        {
+            self.check_hold_timers(ts);
+            self.last_timeout_check = Some(ts);
+        }
+        if self.daily_report.is_due(ts) {
+            self.print_stats(Some(ts));
+        }
+        let (src, dst) = (cols[0], cols[1]);
+        let key = peering_key(src, dst);
+        let types = split(cols[2]);
+        let updates = types.iter().filter(|t| **t == UPDATE).count();
+        let mut asns = cols[3].split(',');
+        let mut hold_times = cols[4].split(',');
+        let mut as4 = cols[6].split(',');
+        let minor = cols[8..13]
+            .iter()
+            .find(|m| !m.is_empty())
+            .map(|m| m.split(',').next().unwrap_or_default())
+            .unwrap_or_default();
+        let mut majors = cols[7].split(',');
+
+        let peering = self.peerings.entry(key.clone()).or_default();
+        let speaker = peering.speakers.entry(src.to_owned()).or_default();
+        speaker.last_seen = Some(ts);
+        speaker.expired = false;
+
+        for message_type in types {
+            let peering = self.peerings.get_mut(&key).unwrap();
+            match message_type {
+                OPEN => {
+                    let asn = asns.next().unwrap_or_default();
+                    let as4 = as4.next().unwrap_or_default();
+                    let speaker = peering.speakers.get_mut(src).unwrap();
+                    speaker.asn = if asn == AS_TRANS && !as4.is_empty() {
+                        as4
+                    } else {
+                        asn
+                    }
+                    .to_owned();
+                    speaker.opened = true;
+                    speaker.hold_time = hold_times.next().and_then(|h| h.parse().ok());
+                    if peering.up {
+                        // a new OPEN restarts the session
+                        peering.up = false;
+                        peering.downs += 1;
+                    }
+                }
+                KEEPALIVE | UPDATE => {
+                    if message_type == UPDATE {
+                        peering.updates += 1;
+                    }
+                    if !peering.up {
+                        let opened = peering.speakers.len() == 2
+                            && peering.speakers.values().all(|s| s.opened);
+                        let pre_existing = peering.ups == 0 && peering.downs == 0 && !opened;
+                        peering.up = true;
+                        peering.up_since = Some(ts);
+                        // sessions established before the capture started are not transitions
+                        if !pre_existing {
+                            peering.ups += 1;
+                            let (user, peer) = (peering.label(src), peering.label(dst));
+                            let hold = peering.hold_time().unwrap_or_default();
+                            self.event(
+                                ts,
+                                EventKind::Handshake,
+                                "OPEN",
+                                user,
+                                peer,
+                                format!("session established, hold time {hold} s"),
+                            );
+                        }
+                    }
+                }
+                NOTIFICATION => {
+                    let name = notification_name(majors.next().unwrap_or_default(), minor);
+                    *self.notifications.entry(name.clone()).or_default() += 1;
+                    peering.last_notification = Some(name.clone());
+                    let was_up = peering.up;
+                    if was_up {
+                        peering.up = false;
+                        peering.downs += 1;
+                    }
+                    // the next attempt starts with a new OPEN exchange
+                    for speaker in peering.speakers.values_mut() {
+                        speaker.opened = false;
+                    }
+                    let (user, peer) = (peering.label(src), peering.label(dst));
+                    self.event(
+                        ts,
+                        EventKind::Alert,
+                        "NOTIFY",
+                        user,
+                        peer,
+                        if was_up {
+                            format!("session down: {name}")
+                        } else {
+                            name
+                        },
+                    );
+                }
+                _ => {}
+            }
+        }
+        if updates > 0 {
+            let (announced, withdrawn) = update_prefixes(&cols, updates);
+            self.on_update(ts, &key, announced, withdrawn, src);
+        }
+    }
+
+    fn add_protocol_fields(&self, tshark_args: &mut Vec<&str>) {
+        tshark_args.push("-e");
+        tshark_args.push("bgp.type");
+        tshark_args.push("-e");
+        tshark_args.push("bgp.open.myas");
+        tshark_args.push("-e");
+        tshark_args.push("bgp.open.holdtime");
+        tshark_args.push("-e");
+        tshark_args.push("bgp.open.identifier");
+        tshark_args.push("-e");
+        tshark_args.push("bgp.cap.4as");
+        tshark_args.push("-e");
+        tshark_args.push("bgp.notify.major_error");
+        tshark_args.push("-e");
+        tshark_args.push("bgp.notify.minor_error");
+        tshark_args.push("-e");
+        tshark_args.push("bgp.notify.minor_error_open");
+        tshark_args.push("-e");
+        tshark_args.push("bgp.notify.minor_error_update");
+        tshark_args.push("-e");
+        tshark_args.push("bgp.notify.minor_error_state");
+        tshark_args.push("-e");
+        tshark_args.push("bgp.notify.minor_error_cease");
+        tshark_args.push("-e");
+        tshark_args.push("bgp.withdrawn_prefix");
+        tshark_args.push("-e");
+        tshark_args.push("bgp.nlri_prefix");
+        tshark_args.push("-e");
+        tshark_args.push("bgp.mp_reach_nlri_ipv4_prefix");
+        tshark_args.push("-e");
+        tshark_args.push("bgp.mp_reach_nlri_ipv6_prefix");
+        tshark_args.push("-e");
+        tshark_args.push("bgp.mp_unreach_nlri_ipv4_prefix");
+        tshark_args.push("-e");
+        tshark_args.push("bgp.mp_unreach_nlri_ipv6_prefix");
+        tshark_args.push("-e");
+        tshark_args.push("bgp.prefix_length");
+        if !tshark_args.contains(&"-Y") {
+            tshark_args.push("-Y");
+            tshark_args.push("bgp");
+        }
+        if tshark_args.contains(&"-i") && !tshark_args.contains(&"-f") {
+            tshark_args.push("-f");
+            tshark_args.push("tcp port 179");
+        }
+    }
+
+    fn end(&mut self) {
+        self.print_stats(None);
+    }
+}
+
+// MARK: TESTS
+#[cfg(test)]
+mod test {
+    use chrono::{DateTime, TimeDelta};
+
+    use super::{Analyzer, ProtocolAnalyzer as _, notification_name, update_prefixes};
+    use crate::analyzers::test_args;
+
+    #[test]
+    fn prefixes_and_notifications() {
+        let mut cols = vec![""; 20];
+        cols[13] = "10.1.0.0";
+        cols[14] = "10.2.0.0,10.3.3.0";
+        cols[19] = "16,16,24";
+        assert_eq!(
+            update_prefixes(&cols, 1),
+            (
+                vec![
+                    Some("10.2.0.0/16".to_owned()),
+                    Some("10.3.3.0/24".to_owned())
+                ],
+                vec![Some("10.1.0.0/16".to_owned())]
+            )
+        );
+        // several UPDATEs mixing kinds cannot be paired with their lengths
+        assert_eq!(update_prefixes(&cols, 2).1, vec![None]);
+        assert_eq!(notification_name("6", "2"), "Cease/Administrative Shutdown");
+        assert_eq!(notification_name("4", ""), "Hold Timer Expired");
+    }
+
+    #[test]
+    fn flaps_per_peering() {
+        let mut analyzer = Analyzer::new(&test_args(&[]), 0);
+        let start = DateTime::from_timestamp(1738062028, 0).unwrap();
+        // the route moves between two upstreams, then upstream A withdraws and re-announces it
+        for (s, upstream, withdraw) in [
+            (0, "10.0.0.1", true),
+            (1, "10.0.0.2", false),
+            (2, "10.0.0.2", true),
+            (3, "10.0.0.1", false),
+            (4, "10.0.0.1", true),
+            (5, "10.0.0.1", false),
+        ] {
+            let mut cols = vec![""; 20];
+            cols[..3].copy_from_slice(&[upstream, "10.0.0.9", "2"]);
+            cols[if withdraw { 13 } else { 14 }] = "10.1.0.0";
+            cols[19] = "16";
+            analyzer.analyze(start + TimeDelta::seconds(s), cols);
+        }
+        let flaps = |upstream: &str| {
+            let route = (
+                upstream.to_owned(),
+                "10.0.0.9".to_owned(),
+                "10.1.0.0/16".to_owned(),
+            );
+            analyzer.prefixes[&route].flaps
+        };
+        assert_eq!((flaps("10.0.0.1"), flaps("10.0.0.2")), (2, 0));
+    }
+}
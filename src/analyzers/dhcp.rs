@@ -0,0 +1,523 @@
+use super::{
+    Event, EventKind, ProtocolAnalyzer, TIME_FMT,
+    report::{self, DailyReport, LatencyStats},
+};
+use crate::{
+    ArgsCommand,
+    formatters::{EventFormatter, create_formatter},
+};
+use ahash::HashMap;
+use chrono::{DateTime, Local, Utc};
+use std::fmt::Write as _;
+
+const DISCOVER: &str = "1";
+const OFFER: &str = "2";
+const REQUEST: &str = "3";
+const DECLINE: &str = "4";
+const ACK: &str = "5";
+const NAK: &str = "6";
+const RELEASE: &str = "7";
+
+/// DORA or renewal in progress for one client MAC
+#[derive(Default)]
+struct Exchange {
+    start: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+    discovers: u32,
+    requests: u32,
+    offers: Vec<String>,
+    renewal: bool,
+    hostname: String,
+    vendor_class: String,
+}
+
+#[derive(Default)]
+struct Lease {
+    ip: String,
+    server: String,
+    lease_s: Option<u32>,
+    acquired: DateTime<Utc>,
+    renewals: u32,
+    naks: u32,
+    hostname: String,
+    vendor_class: String,
+    tftp_name: String,
+    tftp_addr: String,
+    released: bool,
+}
+
+#[derive(Default)]
+struct ServerStats {
+    offers: u32,
+    acks: u32,
+    naks: u32,
+    latency: LatencyStats,
+}
+
+pub struct Analyzer {
+    pending: HashMap<String, Exchange>,
+    leases: HashMap<String, Lease>,
+    servers: HashMap<String, ServerStats>,
+    provisioning: HashMap<String, u32>,
+    messages: HashMap<&'static str, u32>,
+    failures: HashMap<(String, &'static str), u32>,
+    multi_offer: u32,
+    declines: u32,
+    timeout: i64,
+    top: usize,
+    last_timeout_check: Option<DateTime<Utc>>,
+    formatter: Box<dyn EventFormatter>,
+    daily_report: DailyReport,
+}
+
+const PROTOCOL: &str = "dhcp";
+
+fn message_name(msg_type: &str) -> &'static str {
+    match msg_type {
+        DISCOVER => "DISCOVER",
+        OFFER => "OFFER",
+        REQUEST => "REQUEST",
+        DECLINE => "DECLINE",
+        ACK => "ACK",
+        NAK => "NAK",
+        RELEASE => "RELEASE",
+        "8" => "INFORM",
+        _ => "OTHER",
+    }
+}
+
+/// Option 66 name and option 150 addresses as a single label
+fn provisioning_label(tftp_name: &str, tftp_addr: &str) -> String {
+    match (tftp_name.is_empty(), tftp_addr.is_empty()) {
+        (true, true) => "-".to_owned(),
+        (false, true) => format!("66={tftp_name}"),
+        (true, false) => format!("150={tftp_addr}"),
+        (false, false) => format!("66={tftp_name} 150={tftp_addr}"),
+    }
+}
+
+impl Analyzer {
+    fn on_server_seen(&mut self, ts: DateTime<Utc>, server: &str, mac: &str, ip: &str) {
+        if self.servers.contains_key(server) {
+            return;
+        }
+        if !self.servers.is_empty() {
+            let mut known = Vec::from_iter(self.servers.keys().cloned());
+            known.sort();
+            self.formatter.event(&Event {
+                ts,
+                protocol: PROTOCOL,
+                kind: EventKind::Alert,
+                user: mac.to_owned(),
+                method: "OFFER".into(),
+                addr: Some(server.to_owned()),
+                detail: Some(format!(
+                    "new DHCP server offering {ip}, already seen: {}",
+                    known.join(", ")
+                )),
+                ..Default::default()
+            });
+        }
+        self.servers
+            .insert(server.to_owned(), ServerStats::default());
+    }
+
+    fn expire_pending(&mut self, opt_ts: Option<DateTime<Utc>>) {
+        let timeout = self.timeout;
+        let expired = self
+            .pending
+            .extract_if(|_, e| opt_ts.is_none_or(|ts| (ts - e.last_seen).num_seconds() >= timeout))
+            .collect::<Vec<_>>();
+        for (mac, exchange) in expired {
+            let (reason, detail) = if exchange.offers.is_empty() && !exchange.renewal {
+                (
+                    "no offer",
+                    format!("no OFFER after {} DISCOVER", exchange.discovers),
+                )
+            } else {
+                (
+                    "no ack",
+                    format!("no ACK after {} REQUEST", exchange.requests),
+                )
+            };
+            let server = exchange.offers.last().cloned().unwrap_or_default();
+            *self.failures.entry((mac.clone(), reason)).or_default() += 1;
+            self.formatter.event(&Event {
+                ts: opt_ts.unwrap_or(exchange.last_seen),
+                protocol: PROTOCOL,
+                kind: EventKind::NoResponse,
+                user: mac,
+                method: if exchange.requests > 0 {
+                    "REQUEST"
+                } else {
+                    "DISCOVER"
+                }
+                .into(),
+                addr: Some(server),
+                elapsed_s: opt_ts.map(|ts| (ts - exchange.start).num_seconds()),
+                detail: Some(detail),
+                ..Default::default()
+            });
+        }
+    }
+
+    fn print_stats(&mut self, opt_ts: Option<DateTime<Utc>>) {
+        let mut output = String::with_capacity(200);
+        report::print_header(self.formatter.as_mut(), opt_ts);
+        report::print_section(self.formatter.as_mut(), "Servers");
+        let mut servers = Vec::from_iter(self.servers.keys());
+        servers.sort();
+        for server in servers {
+            let stats = &self.servers[server];
+            write!(
+                output,
+                "{server:<16} {:6} offers {:6} acks {:4} naks\n{:<16} DISCOVER to ACK {}",
+                stats.offers,
+                stats.acks,
+                stats.naks,
+                "",
+                stats.latency.summary()
+            )
+            .unwrap();
+            self.formatter.report(&output, 1);
+            output.clear();
+        }
+
+        report::print_section(self.formatter.as_mut(), "Leases");
+        let mut leases = Vec::from_iter(&self.leases);
+        leases.sort_by(|a, b| a.1.ip.cmp(&b.1.ip).then(a.0.cmp(b.0)));
+        for (mac, lease) in leases {
+            write!(
+                output,
+                "{mac:<17} {:<15} from {:<15} lease {:>7} s  acquired {}  renewals {:3} naks {:2}  {}",
+                lease.ip,
+                lease.server,
+                lease.lease_s.map_or_else(|| "-".to_owned(), |s| s.to_string()),
+                lease.acquired.with_timezone(&Local).format(TIME_FMT),
+                lease.renewals,
+                lease.naks,
+                provisioning_label(&lease.tftp_name, &lease.tftp_addr),
+            )
+            .unwrap();
+            for value in [&lease.hostname, &lease.vendor_class] {
+                if !value.is_empty() {
+                    write!(output, "  {value}").unwrap();
+                }
+            }
+            if lease.released {
+                write!(output, "  RELEASED").unwrap();
+            }
+            self.formatter.report(&output, 1);
+            output.clear();
+        }
+
+        report::print_section(self.formatter.as_mut(), "Offered Provisioning Servers");
+        for (label, count) in report::top_n(&self.provisioning, usize::MAX) {
+            self.formatter.report(&format!("{count:8} {label}"), 1);
+        }
+        report::print_section(self.formatter.as_mut(), "Failed Clients");
+        for ((mac, reason), count) in report::top_n(&self.failures, self.top) {
+            self.formatter
+                .report(&format!("{count:8} {mac:<17} {reason}"), 1);
+        }
+        report::print_section(self.formatter.as_mut(), "Messages");
+        for (msg, count) in report::top_n(&self.messages, usize::MAX) {
+            self.formatter.report(&format!("{count:8} {msg}"), 1);
+        }
+        write!(
+            output,
+            r#"
+ ------------ STATS ------------
+
+- total leases: {}
+- offering servers: {}
+- exchanges with multiple offers: {}
+- failed exchanges: {}
+- pending exchanges: {}
+- declined addresses: {}
+"#,
+            self.leases.len(),
+            self.servers.len(),
+            self.multi_offer,
+            self.failures.values().sum::<u32>(),
+            self.pending.len(),
+            self.declines,
+        )
+        .unwrap();
+        self.formatter.report(&output, 0);
+        report::print_footer(self.formatter.as_mut());
+    }
+}
+
+impl ProtocolAnalyzer for Analyzer {
+    fn new(cmd_args: &ArgsCommand, verbosity: u8) -> Self {
+        let (timeout, top) = match cmd_args {
+            ArgsCommand::Analyzer { timeout, top, .. } => (*timeout as i64, *top),
+            _ => (5, 10),
+        };
+        Self {
+            pending: HashMap::default(),
+            leases: HashMap::default(),
+            servers: HashMap::default(),
+            provisioning: HashMap::default(),
+            messages: HashMap::default(),
+            failures: HashMap::default(),
+            multi_offer: 0,
+            declines: 0,
+            timeout,
+            top,
+            last_timeout_check: None,
+            formatter: create_formatter(cmd_args, verbosity),
+            daily_report: DailyReport::default(),
+        }
+    }
+
+    fn analyze(&mut self, ts: DateTime<Utc>, cols: Vec<&str>) {
+        let src_addr = cols[0];
+        let msg_type = cols[2];
+        let mac = cols[3].to_owned();
+        let client_ip = cols[4];
+        let your_ip = cols[5];
+        // relayed replies come from the relay, the server id option is authoritative
+        let server = if cols[6].is_empty() {
+            src_addr
+        } else {
+            cols[6]
+        }
+        .to_owned();
+        let lease_s = cols[7].parse::<u32>().ok();
+        let requested_ip = cols[8];
+        let tftp_name = cols[9];
+        let tftp_addr = cols[10];
+        let hostname = cols[11];
+        let vendor_class = cols[12];
+        if self
+            .last_timeout_check
+            .is_none_or(|last| (ts - last).num_seconds() >= 1)
+        {
+            self.expire_pending(Some(ts));
+            self.last_timeout_check = Some(ts);
+        }
+        if self.daily_report.is_due(ts) {
+            self.print_stats(Some(ts));
+        }
+        *self.messages.entry(message_name(msg_type)).or_default() += 1;
+
+        match msg_type {
+            DISCOVER | REQUEST => {
+                let exchange = self.pending.entry(mac).or_insert_with(|| Exchange {
+                    start: ts,
+                    // REQUEST without DISCOVER is a renewal, rebind or reboot
+                    renewal: msg_type == REQUEST,
+                    ..Default::default()
+                });
+                exchange.last_seen = ts;
+                if msg_type == DISCOVER {
+                    exchange.discovers += 1;
+                } else {
+                    exchange.requests += 1;
+                }
+                if !hostname.is_empty() {
+                    exchange.hostname = hostname.to_owned();
+                }
+                if !vendor_class.is_empty() {
+                    exchange.vendor_class = vendor_class.to_owned();
+                }
+            }
+            OFFER => {
+                self.on_server_seen(ts, &server, &mac, your_ip);
+                self.servers.get_mut(&server).unwrap().offers += 1;
+                *self
+                    .provisioning
+                    .entry(format!(
+                        "{server:<15} {}",
+                        provisioning_label(tftp_name, tftp_addr)
+                    ))
+                    .or_default() += 1;
+                if let Some(exchange) = self.pending.get_mut(&mac)
+                    && !exchange.offers.contains(&server)
+                {
+                    exchange.offers.push(server);
+                    if exchange.offers.len() == 2 {
+                        self.multi_offer += 1;
+                    }
+                }
+            }
+            ACK => {
+                self.on_server_seen(ts, &server, &mac, your_ip);
+                let exchange = self.pending.remove(&mac).unwrap_or_default();
+                let stats = self.servers.get_mut(&server).unwrap();
+                stats.acks += 1;
+                let latency_ms = (exchange.discovers > 0).then(|| {
+                    (ts - exchange.start).num_microseconds().unwrap_or_default() as f64 / 1000.0
+                });
+                if let Some(latency_ms) = latency_ms {
+                    stats.latency.add(latency_ms);
+                }
+                // INFORM answers carry no address
+                let ip = if your_ip.is_empty() || your_ip == "0.0.0.0" {
+                    client_ip
+                } else {
+                    your_ip
+                };
+                let lease = self.leases.entry(mac).or_default();
+                if exchange.renewal && lease.ip == ip {
+                    lease.renewals += 1;
+                } else {
+                    lease.acquired = ts;
+                }
+                lease.ip = ip.to_owned();
+                lease.server = server;
+                lease.lease_s = lease_s.or(lease.lease_s);
+                lease.released = false;
+                if !tftp_name.is_empty() || !tftp_addr.is_empty() {
+                    lease.tftp_name = tftp_name.to_owned();
+                    lease.tftp_addr = tftp_addr.to_owned();
+                }
+                if !exchange.hostname.is_empty() {
+                    lease.hostname = exchange.hostname;
+                }
+                if !exchange.vendor_class.is_empty() {
+                    lease.vendor_class = exchange.vendor_class;
+                }
+            }
+            NAK => {
+                self.on_server_seen(ts, &server, &mac, requested_ip);
+                self.servers.get_mut(&server).unwrap().naks += 1;
+                let exchange = self.pending.remove(&mac);
+                *self.failures.entry((mac.clone(), "nak")).or_default() += 1;
+                let lease = self.leases.get_mut(&mac);
+                let ip = lease.as_ref().map_or(requested_ip, |l| &l.ip).to_owned();
+                if let Some(lease) = lease {
+                    lease.naks += 1;
+                }
+                self.formatter.event(&Event {
+                    ts,
+                    protocol: PROTOCOL,
+                    kind: EventKind::ErrorResponse,
+                    user: mac,
+                    method: "REQUEST".into(),
+                    addr: Some(server),
+                    latency_ms: exchange.map(|e| {
+                        (ts - e.last_seen).num_microseconds().unwrap_or_default() as f64 / 1000.0
+                    }),
+                    detail: Some(format!("NAK {ip}")),
+                    ..Default::default()
+                });
+            }
+            DECLINE => {
+                self.declines += 1;
+                self.pending.remove(&mac);
+                *self.failures.entry((mac.clone(), "decline")).or_default() += 1;
+                self.formatter.event(&Event {
+                    ts,
+                    protocol: PROTOCOL,
+                    kind: EventKind::Warning,
+                    user: mac,
+                    method: "DECLINE".into(),
+                    addr: Some(server),
+                    detail: Some(format!("{requested_ip} declined, address in use")),
+                    ..Default::default()
+                });
+            }
+            RELEASE => {
+                if let Some(lease) = self.leases.get_mut(&mac) {
+                    lease.released = true;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn add_protocol_fields(&self, tshark_args: &mut Vec<&str>) {
+        tshark_args.push("-e");
+        tshark_args.push("dhcp.option.dhcp");
+        tshark_args.push("-e");
+        tshark_args.push("dhcp.hw.mac_addr");
+        tshark_args.push("-e");
+        tshark_args.push("dhcp.ip.client");
+        tshark_args.push("-e");
+        tshark_args.push("dhcp.ip.your");
+        tshark_args.push("-e");
+        tshark_args.push("dhcp.option.dhcp_server_id");
+        tshark_args.push("-e");
+        tshark_args.push("dhcp.option.ip_address_lease_time");
+        tshark_args.push("-e");
+        tshark_args.push("dhcp.option.requested_ip_address");
+        tshark_args.push("-e");
+        tshark_args.push("dhcp.option.tftp_server_name");
+        tshark_args.push("-e");
+        tshark_args.push("dhcp.option.tftp_server_address");
+        tshark_args.push("-e");
+        tshark_args.push("dhcp.option.hostname");
+        tshark_args.push("-e");
+        tshark_args.push("dhcp.option.vendor_class_id");
+        if !tshark_args.contains(&"-Y") {
+            tshark_args.push("-Y");
+            tshark_args.push("dhcp");
+        }
+        if tshark_args.contains(&"-i") && !tshark_args.contains(&"-f") {
+            tshark_args.push("-f");
+            tshark_args.push("port 67 or port 68");
+        }
+    }
+
+    fn end(&mut self) {
+        self.expire_pending(None);
+        self.print_stats(None);
+    }
+}
+
+// MARK: TESTS
+#[cfg(test)]
+mod test {
+    use chrono::{DateTime, TimeDelta};
+
+    use super::{ACK, Analyzer, DISCOVER, OFFER, ProtocolAnalyzer as _, REQUEST};
+    use crate::analyzers::test_args;
+
+    #[test]
+    fn dora_rogue_offer_and_renewal() {
+        let mut analyzer = Analyzer::new(&test_args(&[]), 0);
+        let start = DateTime::from_timestamp(1738062028, 0).unwrap();
+        let mac = "00:11:22:33:44:55";
+        // DORA answered by the real server, a second server offers too, renewal 30 min later
+        for (s, src, msg_type, your_ip, server) in [
+            (0, "0.0.0.0", DISCOVER, "", ""),
+            (0, "10.0.0.1", OFFER, "10.0.0.50", "10.0.0.1"),
+            (0, "10.0.0.66", OFFER, "192.168.1.10", "10.0.0.66"),
+            (1, "0.0.0.0", REQUEST, "", ""),
+            (1, "10.0.0.1", ACK, "10.0.0.50", "10.0.0.1"),
+            (1800, "10.0.0.50", REQUEST, "", ""),
+            (1800, "10.0.0.1", ACK, "10.0.0.50", "10.0.0.1"),
+        ] {
+            let cols = vec![
+                src,
+                "255.255.255.255",
+                msg_type,
+                mac,
+                "",
+                your_ip,
+                server,
+                "3600",
+                "",
+                "",
+                "",
+                "",
+                "",
+            ];
+            analyzer.analyze(start + TimeDelta::seconds(s), cols);
+        }
+        analyzer.end();
+        assert_eq!(analyzer.servers.len(), 2);
+        assert_eq!(analyzer.multi_offer, 1);
+        assert!(analyzer.pending.is_empty());
+        assert!(analyzer.failures.is_empty());
+        let lease = &analyzer.leases[mac];
+        assert_eq!(lease.ip, "10.0.0.50");
+        assert_eq!(lease.server, "10.0.0.1");
+        assert_eq!(lease.renewals, 1);
+        assert_eq!(lease.acquired, start + TimeDelta::seconds(1));
+        assert_eq!(analyzer.servers["10.0.0.1"].latency.max(), 1000.0);
+    }
+}
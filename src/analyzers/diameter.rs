@@ -0,0 +1,498 @@
+use super::{
+    Event, EventKind, ProtocolAnalyzer,
+    report::{self, DailyReport, LatencyStats},
+};
+use crate::{
+    ArgsCommand,
+    formatters::{EventFormatter, create_formatter},
+};
+use ahash::HashMap;
+use chrono::{DateTime, Utc};
+use std::{fmt::Write as _, str::Split};
+
+const DEVICE_WATCHDOG: &str = "280";
+const DISCONNECT_PEER: &str = "282";
+
+struct PendingRequest {
+    ts: DateTime<Utc>,
+    app: String,
+    cmd: String,
+    origin_host: String,
+}
+
+#[derive(Default)]
+struct CommandStats {
+    requests: u32,
+    answers: u32,
+    timeouts: u32,
+    result_codes: HashMap<String, u32>,
+    latency: LatencyStats,
+}
+
+#[derive(Default)]
+struct PeerStats {
+    origin_host: String,
+    watchdogs: u32,
+    watchdog_failures: u32,
+    consecutive_failures: u32,
+    disconnects: u32,
+}
+
+pub struct Analyzer {
+    /// (requester, responder, hop-by-hop id, end-to-end id)
+    pending: HashMap<(String, String, String, String), PendingRequest>,
+    commands: HashMap<(String, String), CommandStats>,
+    peers: HashMap<String, PeerStats>,
+    unmatched_answers: u32,
+    timeout: i64,
+    top: usize,
+    last_timeout_check: Option<DateTime<Utc>>,
+    formatter: Box<dyn EventFormatter>,
+    daily_report: DailyReport,
+}
+
+const PROTOCOL: &str = "diameter";
+
+fn command_name(code: &str) -> String {
+    match code {
+        "257" => "CER/CEA",
+        "258" => "RAR/RAA",
+        "265" => "AAR/AAA",
+        "271" => "ACR/ACA",
+        "272" => "CCR/CCA",
+        "274" => "ASR/ASA",
+        "275" => "STR/STA",
+        "280" => "DWR/DWA",
+        "282" => "DPR/DPA",
+        "300" => "UAR/UAA",
+        "301" => "SAR/SAA",
+        "302" => "LIR/LIA",
+        "303" => "MAR/MAA",
+        "304" => "RTR/RTA",
+        "305" => "PPR/PPA",
+        "306" => "UDR/UDA",
+        "307" => "PUR/PUA",
+        "308" => "SNR/SNA",
+        "309" => "PNR/PNA",
+        "316" => "ULR/ULA",
+        "317" => "CLR/CLA",
+        "318" => "AIR/AIA",
+        "321" => "PUR/PUA",
+        "323" => "NOR/NOA",
+        other => return format!("cmd {other}"),
+    }
+    .to_owned()
+}
+
+fn application_name(id: &str) -> String {
+    match id {
+        "0" => "Base",
+        "3" => "Base Accounting",
+        "4" => "Credit Control",
+        "16777216" => "Cx",
+        "16777217" => "Sh",
+        "16777236" => "Rx",
+        "16777238" => "Gx",
+        "16777251" => "S6a",
+        "16777252" => "S13",
+        "16777265" => "SWx",
+        "16777272" => "S6b",
+        "16777291" => "SLh",
+        other => return format!("app {other}"),
+    }
+    .to_owned()
+}
+
+fn result_code_name(code: &str) -> &'static str {
+    match code {
+        "2001" => "SUCCESS",
+        "2002" => "LIMITED_SUCCESS",
+        "3001" => "COMMAND_UNSUPPORTED",
+        "3002" => "UNABLE_TO_DELIVER",
+        "3003" => "REALM_NOT_SERVED",
+        "3004" => "TOO_BUSY",
+        "3005" => "LOOP_DETECTED",
+        "3007" => "APPLICATION_UNSUPPORTED",
+        "4001" => "AUTHENTICATION_REJECTED",
+        "4010" => "END_USER_SERVICE_DENIED",
+        "4012" => "CREDIT_LIMIT_REACHED",
+        "5001" => "AVP_UNSUPPORTED",
+        "5002" => "UNKNOWN_SESSION_ID",
+        "5003" => "AUTHORIZATION_REJECTED",
+        "5004" => "INVALID_AVP_VALUE",
+        "5005" => "MISSING_AVP",
+        "5006" => "RESOURCES_EXCEEDED",
+        "5010" => "NO_COMMON_APPLICATION",
+        "5012" => "UNABLE_TO_COMPLY",
+        "5030" => "USER_UNKNOWN",
+        _ => "",
+    }
+}
+
+/// Next value of a comma separated multi-message field
+fn next<'a>(values: &mut Split<'a, char>) -> &'a str {
+    values.next().unwrap_or_default()
+}
+
+/// Result of every answer in a segment, `true` for an Experimental-Result-Code. Both fields only
+/// list the answers carrying them and Result-Code may also be nested (Gy credit control), so
+/// codes are only paired when the assignment is unambiguous.
+fn answer_results<'a>(
+    answers: usize,
+    result_codes: &'a str,
+    experimental_codes: &'a str,
+) -> Vec<Option<(&'a str, bool)>> {
+    let results = result_codes
+        .split(',')
+        .filter(|c| !c.is_empty())
+        .collect::<Vec<_>>();
+    let experimental = experimental_codes
+        .split(',')
+        .filter(|c| !c.is_empty())
+        .collect::<Vec<_>>();
+    if answers == 1 {
+        // the message level Result-Code precedes nested ones
+        let result = results.first().map(|code| (*code, false));
+        return vec![result.or_else(|| experimental.first().map(|code| (*code, true)))];
+    }
+    if experimental.is_empty() && results.len() == answers {
+        results
+            .into_iter()
+            .map(|code| Some((code, false)))
+            .collect()
+    } else if results.is_empty() && experimental.len() == answers {
+        experimental
+            .into_iter()
+            .map(|code| Some((code, true)))
+            .collect()
+    } else {
+        vec![None; answers]
+    }
+}
+
+impl Analyzer {
+    /// `peer_host` is the Origin-Host of the answering peer, None when the DWR timed out
+    fn on_watchdog(
+        &mut self,
+        ts: DateTime<Utc>,
+        peer: &str,
+        requester: &str,
+        peer_host: Option<&str>,
+    ) {
+        let stats = self.peers.entry(peer.to_owned()).or_default();
+        if let Some(peer_host) = peer_host {
+            if !peer_host.is_empty() {
+                stats.origin_host = peer_host.to_owned();
+            }
+            stats.consecutive_failures = 0;
+            return;
+        }
+        stats.watchdog_failures += 1;
+        stats.consecutive_failures += 1;
+        let detail = format!(
+            "watchdog failure {} in a row for {}",
+            stats.consecutive_failures, stats.origin_host
+        );
+        self.formatter.event(&Event {
+            ts,
+            protocol: PROTOCOL,
+            kind: EventKind::Alert,
+            user: requester.to_owned(),
+            method: "DWR".into(),
+            addr: Some(peer.to_owned()),
+            detail: Some(detail),
+            ..Default::default()
+        });
+    }
+
+    fn expire_pending(&mut self, opt_ts: Option<DateTime<Utc>>) {
+        let timeout = self.timeout;
+        let expired = self
+            .pending
+            .extract_if(|_, r| opt_ts.is_none_or(|ts| (ts - r.ts).num_seconds() >= timeout))
+            .collect::<Vec<_>>();
+        for ((_, responder, hop_by_hop, _), request) in expired {
+            let ts = opt_ts.unwrap_or(request.ts);
+            if request.cmd == DEVICE_WATCHDOG {
+                self.on_watchdog(ts, &responder, &request.origin_host, None);
+                continue;
+            }
+            let app = application_name(&request.app);
+            let cmd = command_name(&request.cmd);
+            self.commands
+                .entry((app.clone(), cmd.clone()))
+                .or_default()
+                .timeouts += 1;
+            self.formatter.event(&Event {
+                ts,
+                protocol: PROTOCOL,
+                kind: EventKind::NoResponse,
+                user: request.origin_host,
+                method: cmd,
+                addr: Some(responder),
+                elapsed_s: opt_ts.map(|ts| (ts - request.ts).num_seconds()),
+                detail: Some(format!("{app} hop-by-hop {hop_by_hop}")),
+                ..Default::default()
+            });
+        }
+    }
+
+    fn print_stats(&mut self, opt_ts: Option<DateTime<Utc>>) {
+        let mut output = String::with_capacity(200);
+        report::print_header(self.formatter.as_mut(), opt_ts);
+        report::print_section(self.formatter.as_mut(), "Applications And Commands");
+        let mut commands = Vec::from_iter(self.commands.keys());
+        commands.sort();
+        for key in commands {
+            let stats = &self.commands[key];
+            write!(
+                output,
+                "{:<16} {:<10} {:6} requests {:6} answers {:4} timeouts\n{:<27} {}",
+                key.0,
+                key.1,
+                stats.requests,
+                stats.answers,
+                stats.timeouts,
+                "",
+                stats.latency.summary()
+            )
+            .unwrap();
+            for (code, count) in report::top_n(&stats.result_codes, self.top) {
+                write!(output, "\n\t{count:8} {code}").unwrap();
+            }
+            self.formatter.report(&output, 1);
+            output.clear();
+        }
+
+        report::print_section(self.formatter.as_mut(), "Peers");
+        let mut peers = Vec::from_iter(self.peers.keys());
+        peers.sort();
+        for peer in peers {
+            let stats = &self.peers[peer];
+            self.formatter.report(
+                &format!(
+                    "{peer:<22} {:<30} {:6} watchdogs {:4} failures {:4} disconnects",
+                    stats.origin_host, stats.watchdogs, stats.watchdog_failures, stats.disconnects
+                ),
+                1,
+            );
+        }
+        write!(
+            output,
+            r#"
+ ------------ STATS ------------
+
+- total requests: {}
+- total answers: {}
+- total timeouts: {}
+- pending requests: {}
+- unmatched answers: {}
+- watchdog failures: {}
+"#,
+            self.commands.values().map(|s| s.requests).sum::<u32>(),
+            self.commands.values().map(|s| s.answers).sum::<u32>(),
+            self.commands.values().map(|s| s.timeouts).sum::<u32>(),
+            self.pending.len(),
+            self.unmatched_answers,
+            self.peers
+                .values()
+                .map(|s| s.watchdog_failures)
+                .sum::<u32>(),
+        )
+        .unwrap();
+        self.formatter.report(&output, 0);
+        report::print_footer(self.formatter.as_mut());
+    }
+}
+
+impl ProtocolAnalyzer for Analyzer {
+    fn new(cmd_args: &ArgsCommand, verbosity: u8) -> Self {
+        let (timeout, top) = match cmd_args {
+            ArgsCommand::Analyzer { timeout, top, .. } => (*timeout as i64, *top),
+            _ => (5, 10),
+        };
+        Self {
+            pending: HashMap::default(),
+            commands: HashMap::default(),
+            peers: HashMap::default(),
+            unmatched_answers: 0,
+            timeout,
+            top,
+            last_timeout_check: None,
+            formatter: create_formatter(cmd_args, verbosity),
+            daily_report: DailyReport::default(),
+        }
+    }
+
+    fn analyze(&mut self, ts: DateTime<Utc>, cols: Vec<&str>) {
+        let src = cols[0];
+        let dst = cols[1];
+        if self
+            .last_timeout_check
+            .is_none_or(|last| (ts - last).num_seconds() >= 1)
+        {
+            self.expire_pending(Some(ts));
+            self.last_timeout_check = Some(ts);
+        }
+        if self.daily_report.is_due(ts) {
+            self.print_stats(Some(ts));
+        }
+
+        // one segment can carry several messages, header fields are present in every one
+        let mut is_requests = cols[2].split(',');
+        let mut apps = cols[4].split(',');
+        let mut hop_by_hops = cols[5].split(',');
+        let mut end_to_ends = cols[6].split(',');
+        let is_answer = |flag: &str| !matches!(flag, "1" | "True" | "true");
+        let answers = cols[2].split(',').filter(|flag| is_answer(flag)).count();
+        let mut results = answer_results(answers, cols[7], cols[8]).into_iter();
+        let mut origin_hosts = cols[9].split(',');
+        for cmd in cols[3].split(',') {
+            let is_request = matches!(next(&mut is_requests), "1" | "True" | "true");
+            let app = next(&mut apps);
+            let hop_by_hop = next(&mut hop_by_hops);
+            let end_to_end = next(&mut end_to_ends);
+            let origin_host = next(&mut origin_hosts);
+            if is_request {
+                let app_cmd = (application_name(app), command_name(cmd));
+                self.commands.entry(app_cmd).or_default().requests += 1;
+                if cmd == DEVICE_WATCHDOG {
+                    self.peers.entry(dst.to_owned()).or_default().watchdogs += 1;
+                }
+                if cmd == DISCONNECT_PEER {
+                    self.peers.entry(src.to_owned()).or_default().disconnects += 1;
+                    self.formatter.event(&Event {
+                        ts,
+                        protocol: PROTOCOL,
+                        kind: EventKind::Warning,
+                        user: origin_host.to_owned(),
+                        method: "DPR".into(),
+                        addr: Some(dst.to_owned()),
+                        detail: Some(format!("disconnect peer request from {src}")),
+                        ..Default::default()
+                    });
+                }
+                self.pending.insert(
+                    (
+                        src.to_owned(),
+                        dst.to_owned(),
+                        hop_by_hop.to_owned(),
+                        end_to_end.to_owned(),
+                    ),
+                    PendingRequest {
+                        ts,
+                        app: app.to_owned(),
+                        cmd: cmd.to_owned(),
+                        origin_host: origin_host.to_owned(),
+                    },
+                );
+                continue;
+            }
+
+            let result = results.next().flatten();
+            // Experimental-Result-Code replaces Result-Code in 3GPP answers
+            let code = match result {
+                Some((code, true)) => format!("exp {code}"),
+                Some((code, false)) => {
+                    let name = result_code_name(code);
+                    format!("{code} {name}").trim_end().to_owned()
+                }
+                None => "unknown".to_owned(),
+            };
+            let key = (
+                dst.to_owned(),
+                src.to_owned(),
+                hop_by_hop.to_owned(),
+                end_to_end.to_owned(),
+            );
+            let Some(request) = self.pending.remove(&key) else {
+                self.unmatched_answers += 1;
+                continue;
+            };
+            let latency_ms =
+                (ts - request.ts).num_microseconds().unwrap_or_default() as f64 / 1000.0;
+            if request.cmd == DEVICE_WATCHDOG {
+                self.on_watchdog(ts, src, &request.origin_host, Some(origin_host));
+            }
+            let app = application_name(&request.app);
+            let cmd_name = command_name(&request.cmd);
+            let stats = self
+                .commands
+                .entry((app.clone(), cmd_name.clone()))
+                .or_default();
+            stats.answers += 1;
+            stats.latency.add(latency_ms);
+            *stats.result_codes.entry(code.clone()).or_default() += 1;
+            // 2xxx is success for both kinds of result codes
+            if let Some((result_code, _)) = result
+                && !result_code.starts_with('2')
+            {
+                self.formatter.event(&Event {
+                    ts,
+                    protocol: PROTOCOL,
+                    kind: EventKind::ErrorResponse,
+                    user: request.origin_host,
+                    method: cmd_name,
+                    status: result_code.parse().ok(),
+                    addr: Some(src.to_owned()),
+                    latency_ms: Some(latency_ms),
+                    detail: Some(format!("{app} {code} from {origin_host}")),
+                    ..Default::default()
+                });
+            }
+        }
+    }
+
+    fn add_protocol_fields(&self, tshark_args: &mut Vec<&str>) {
+        tshark_args.push("-e");
+        tshark_args.push("diameter.flags.request");
+        tshark_args.push("-e");
+        tshark_args.push("diameter.cmd.code");
+        tshark_args.push("-e");
+        tshark_args.push("diameter.applicationId");
+        tshark_args.push("-e");
+        tshark_args.push("diameter.hopbyhopid");
+        tshark_args.push("-e");
+        tshark_args.push("diameter.endtoendid");
+        tshark_args.push("-e");
+        tshark_args.push("diameter.Result-Code");
+        tshark_args.push("-e");
+        tshark_args.push("diameter.Experimental-Result-Code");
+        tshark_args.push("-e");
+        tshark_args.push("diameter.Origin-Host");
+        if !tshark_args.contains(&"-Y") {
+            tshark_args.push("-Y");
+            tshark_args.push("diameter");
+        }
+        if tshark_args.contains(&"-i") && !tshark_args.contains(&"-f") {
+            tshark_args.push("-f");
+            tshark_args.push("port 3868");
+        }
+    }
+
+    fn end(&mut self) {
+        self.expire_pending(None);
+        self.print_stats(None);
+    }
+}
+
+// MARK: TESTS
+#[cfg(test)]
+mod test {
+    use super::answer_results;
+
+    #[test]
+    fn pairs_result_codes() {
+        assert_eq!(answer_results(1, "", "2001"), vec![Some(("2001", true))]);
+        assert_eq!(
+            answer_results(1, "2001,4012", ""),
+            vec![Some(("2001", false))]
+        );
+        assert_eq!(
+            answer_results(2, "", "2001,5420"),
+            vec![Some(("2001", true)), Some(("5420", true))]
+        );
+        // which answer carries which kind is unknown
+        assert_eq!(answer_results(2, "2001", "5420"), vec![None, None]);
+    }
+}
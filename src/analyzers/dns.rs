@@ -0,0 +1,381 @@
+use super::{
+    Event, EventKind, ProtocolAnalyzer,
+    report::{self, DailyReport, LatencyStats},
+};
+use crate::{
+    ArgsCommand,
+    formatters::{EventFormatter, create_formatter},
+};
+use ahash::HashMap;
+use chrono::{DateTime, Utc};
+use std::fmt::Write as _;
+
+struct PendingQuery {
+    ts: DateTime<Utc>,
+    name: String,
+    qtype: String,
+}
+
+#[derive(Default)]
+struct ServerStats {
+    queries: u32,
+    answered: u32,
+    timeouts: u32,
+    errors: HashMap<&'static str, u32>,
+    latency: LatencyStats,
+}
+
+pub struct Analyzer {
+    pending: HashMap<(String, String, String), PendingQuery>,
+    servers: HashMap<String, ServerStats>,
+    names: HashMap<String, u32>,
+    failed_names: HashMap<String, u32>,
+    clients: HashMap<String, u32>,
+    qtypes: HashMap<String, u32>,
+    rcodes: HashMap<&'static str, u32>,
+    queries: u32,
+    retransmissions: u32,
+    unmatched_responses: u32,
+    unanswered: u32,
+    timeout: i64,
+    top: usize,
+    last_timeout_check: Option<DateTime<Utc>>,
+    formatter: Box<dyn EventFormatter>,
+    daily_report: DailyReport,
+}
+
+const PROTOCOL: &str = "dns";
+
+fn qtype_name(qtype: &str) -> String {
+    match qtype {
+        "1" => "A",
+        "2" => "NS",
+        "5" => "CNAME",
+        "6" => "SOA",
+        "12" => "PTR",
+        "15" => "MX",
+        "16" => "TXT",
+        "28" => "AAAA",
+        "33" => "SRV",
+        "35" => "NAPTR",
+        "64" => "SVCB",
+        "65" => "HTTPS",
+        "255" => "ANY",
+        other => return format!("TYPE{other}"),
+    }
+    .to_owned()
+}
+
+fn rcode_name(rcode: &str) -> &'static str {
+    match rcode {
+        "0" | "" => "NOERROR",
+        "1" => "FORMERR",
+        "2" => "SERVFAIL",
+        "3" => "NXDOMAIN",
+        "4" => "NOTIMP",
+        "5" => "REFUSED",
+        _ => "OTHER",
+    }
+}
+
+fn endpoint(addr: &str, port: &str) -> String {
+    if port.is_empty() {
+        addr.to_owned()
+    } else {
+        format!("{addr}:{port}")
+    }
+}
+
+impl Analyzer {
+    fn expire_pending(&mut self, opt_ts: Option<DateTime<Utc>>) {
+        let timeout = self.timeout;
+        let expired = self
+            .pending
+            .extract_if(|_, q| opt_ts.is_none_or(|ts| (ts - q.ts).num_seconds() >= timeout))
+            .collect::<Vec<_>>();
+        for ((_, client, server), query) in expired {
+            self.unanswered += 1;
+            self.servers.entry(server.clone()).or_default().timeouts += 1;
+            *self.failed_names.entry(query.name.clone()).or_default() += 1;
+            self.formatter.event(&Event {
+                ts: opt_ts.unwrap_or(query.ts),
+                protocol: PROTOCOL,
+                kind: EventKind::NoResponse,
+                user: client,
+                method: query.qtype,
+                addr: Some(server),
+                elapsed_s: opt_ts.map(|ts| (ts - query.ts).num_seconds()),
+                detail: Some(query.name),
+                ..Default::default()
+            });
+        }
+    }
+
+    fn print_stats(&mut self, opt_ts: Option<DateTime<Utc>>) {
+        let mut output = String::with_capacity(200);
+        report::print_header(self.formatter.as_mut(), opt_ts);
+        report::print_section(self.formatter.as_mut(), "Servers");
+        let mut servers = Vec::from_iter(self.servers.keys());
+        servers.sort();
+        for server in servers {
+            let stats = &self.servers[server];
+            write!(
+                output,
+                "{server:<22} {:6} queries {:6} answered {:4} timeouts",
+                stats.queries, stats.answered, stats.timeouts
+            )
+            .unwrap();
+            for (rcode, count) in report::top_n(&stats.errors, usize::MAX) {
+                write!(output, " {rcode} {count}").unwrap();
+            }
+            write!(output, "\n{:<22} {}", "", stats.latency.summary()).unwrap();
+            self.formatter.report(&output, 1);
+            output.clear();
+        }
+        for (title, counts) in [
+            ("Top Names", &self.names),
+            ("Top Failing Names", &self.failed_names),
+            ("Top Clients", &self.clients),
+            ("Query Types", &self.qtypes),
+        ] {
+            report::print_section(self.formatter.as_mut(), title);
+            for (key, count) in report::top_n(counts, self.top) {
+                self.formatter.report(&format!("{count:8} {key}"), 1);
+            }
+        }
+        report::print_section(self.formatter.as_mut(), "Response Codes");
+        for (rcode, count) in report::top_n(&self.rcodes, usize::MAX) {
+            self.formatter.report(&format!("{count:8} {rcode}"), 1);
+        }
+        write!(
+            output,
+            r#"
+ ------------ STATS ------------
+
+- total queries: {}
+- total responses: {}
+- unanswered queries: {}
+- pending queries: {}
+- unmatched responses: {}
+- retransmissions: {}
+"#,
+            self.queries,
+            self.rcodes.values().sum::<u32>(),
+            self.unanswered,
+            self.pending.len(),
+            self.unmatched_responses,
+            self.retransmissions,
+        )
+        .unwrap();
+        self.formatter.report(&output, 0);
+        report::print_footer(self.formatter.as_mut());
+    }
+}
+
+impl ProtocolAnalyzer for Analyzer {
+    fn new(cmd_args: &ArgsCommand, verbosity: u8) -> Self {
+        let (timeout, top) = match cmd_args {
+            ArgsCommand::Analyzer { timeout, top, .. } => (*timeout as i64, *top),
+            _ => (5, 10),
+        };
+        Self {
+            pending: HashMap::default(),
+            servers: HashMap::default(),
+            names: HashMap::default(),
+            failed_names: HashMap::default(),
+            clients: HashMap::default(),
+            qtypes: HashMap::default(),
+            rcodes: HashMap::default(),
+            queries: 0,
+            retransmissions: 0,
+            unmatched_responses: 0,
+            unanswered: 0,
+            timeout,
+            top,
+            last_timeout_check: None,
+            formatter: create_formatter(cmd_args, verbosity),
+            daily_report: DailyReport::default(),
+        }
+    }
+
+    fn analyze(&mut self, ts: DateTime<Utc>, cols: Vec<&str>) {
+        let src_addr = cols[0];
+        let dst_addr = cols[1];
+        let id = cols[2];
+        let is_response = matches!(cols[3], "1" | "True" | "true");
+        let rcode = rcode_name(cols[4]);
+        let name = cols[5].split(',').next().unwrap_or_default();
+        let qtype = qtype_name(cols[6].split(',').next().unwrap_or_default());
+        let (src_port, dst_port) = if cols[7].is_empty() {
+            (cols[9], cols[10])
+        } else {
+            (cols[7], cols[8])
+        };
+        if self
+            .last_timeout_check
+            .is_none_or(|last| (ts - last).num_seconds() >= 1)
+        {
+            self.expire_pending(Some(ts));
+            self.last_timeout_check = Some(ts);
+        }
+        if self.daily_report.is_due(ts) {
+            self.print_stats(Some(ts));
+        }
+
+        if !is_response {
+            let client = endpoint(src_addr, src_port);
+            let server = endpoint(dst_addr, dst_port);
+            let key = (id.to_owned(), client, server);
+            if self.pending.contains_key(&key) {
+                self.retransmissions += 1;
+                return;
+            }
+            self.queries += 1;
+            self.servers.entry(key.2.clone()).or_default().queries += 1;
+            *self.names.entry(name.to_owned()).or_default() += 1;
+            *self.clients.entry(src_addr.to_owned()).or_default() += 1;
+            *self.qtypes.entry(qtype.clone()).or_default() += 1;
+            self.pending.insert(
+                key,
+                PendingQuery {
+                    ts,
+                    name: name.to_owned(),
+                    qtype,
+                },
+            );
+            return;
+        }
+
+        let client = endpoint(dst_addr, dst_port);
+        let server = endpoint(src_addr, src_port);
+        let key = (id.to_owned(), client, server);
+        let Some(query) = self.pending.remove(&key) else {
+            self.unmatched_responses += 1;
+            return;
+        };
+        let (_, client, server) = key;
+        let latency_ms = (ts - query.ts).num_microseconds().unwrap_or_default() as f64 / 1000.0;
+        *self.rcodes.entry(rcode).or_default() += 1;
+        let stats = self.servers.entry(server.clone()).or_default();
+        stats.answered += 1;
+        stats.latency.add(latency_ms);
+        if rcode != "NOERROR" {
+            *stats.errors.entry(rcode).or_default() += 1;
+            *self.failed_names.entry(query.name.clone()).or_default() += 1;
+            self.formatter.event(&Event {
+                ts,
+                protocol: PROTOCOL,
+                kind: EventKind::ErrorResponse,
+                user: client,
+                method: query.qtype,
+                status: cols[4].parse().ok(),
+                addr: Some(server),
+                latency_ms: Some(latency_ms),
+                detail: Some(format!("{rcode} {}", query.name)),
+                ..Default::default()
+            });
+        }
+    }
+
+    fn add_protocol_fields(&self, tshark_args: &mut Vec<&str>) {
+        tshark_args.push("-e");
+        tshark_args.push("dns.id");
+        tshark_args.push("-e");
+        tshark_args.push("dns.flags.response");
+        tshark_args.push("-e");
+        tshark_args.push("dns.flags.rcode");
+        tshark_args.push("-e");
+        tshark_args.push("dns.qry.name");
+        tshark_args.push("-e");
+        tshark_args.push("dns.qry.type");
+        tshark_args.push("-e");
+        tshark_args.push("udp.srcport");
+        tshark_args.push("-e");
+        tshark_args.push("udp.dstport");
+        tshark_args.push("-e");
+        tshark_args.push("tcp.srcport");
+        tshark_args.push("-e");
+        tshark_args.push("tcp.dstport");
+        if !tshark_args.contains(&"-Y") {
+            tshark_args.push("-Y");
+            tshark_args.push("dns");
+        }
+        if tshark_args.contains(&"-i") && !tshark_args.contains(&"-f") {
+            tshark_args.push("-f");
+            tshark_args.push("port 53");
+        }
+    }
+
+    fn end(&mut self) {
+        self.expire_pending(None);
+        self.print_stats(None);
+    }
+}
+
+// MARK: TESTS
+#[cfg(test)]
+mod test {
+    use chrono::{DateTime, TimeDelta};
+
+    use super::{Analyzer, ProtocolAnalyzer as _};
+    use crate::analyzers::test_args;
+
+    #[test]
+    fn pairs_queries_and_counts_rcodes() {
+        let mut analyzer = Analyzer::new(&test_args(&[]), 0);
+        let start = DateTime::from_timestamp(1738062028, 0).unwrap();
+        let query = |id, name| {
+            vec![
+                "10.0.0.1",
+                "10.0.0.53",
+                id,
+                "0",
+                "",
+                name,
+                "1",
+                "5000",
+                "53",
+            ]
+        };
+        let response = |id, rcode| {
+            vec![
+                "10.0.0.53",
+                "10.0.0.1",
+                id,
+                "1",
+                rcode,
+                "",
+                "",
+                "53",
+                "5000",
+            ]
+        };
+        for (ms, mut cols) in [
+            (0, query("1", "a.example")),
+            (20, response("1", "0")),
+            (100, query("2", "missing.example")),
+            // retransmission of the same query
+            (600, query("2", "missing.example")),
+            (640, response("2", "3")),
+            (700, query("3", "slow.example")),
+            (800, response("9", "0")),
+            (8000, query("4", "b.example")),
+        ] {
+            cols.extend(["", ""]);
+            analyzer.analyze(start + TimeDelta::milliseconds(ms), cols);
+        }
+        assert_eq!((analyzer.queries, analyzer.retransmissions), (4, 1));
+        assert_eq!((analyzer.unanswered, analyzer.unmatched_responses), (1, 1));
+        assert_eq!(
+            (analyzer.rcodes["NOERROR"], analyzer.rcodes["NXDOMAIN"]),
+            (1, 1)
+        );
+        let server = &analyzer.servers["10.0.0.53:53"];
+        assert_eq!((server.answered, server.timeouts), (2, 1));
+        assert_eq!(server.errors["NXDOMAIN"], 1);
+        assert_eq!(server.latency.max(), 540.0);
+        assert_eq!(analyzer.failed_names["missing.example"], 1);
+        assert_eq!(analyzer.failed_names["slow.example"], 1);
+        assert_eq!(analyzer.pending.len(), 1);
+    }
+}
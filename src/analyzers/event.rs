@@ -0,0 +1,165 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+#[derive(Serialize, Default, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    RegistrationOk,
+    Unregistered,
+    Expired,
+    Timeout,
+    Unauthorized,
+    Redirect,
+    RegistrationError,
+    CallRequest,
+    CallResponse,
+    Negotiated,
+    NegotiationFailed,
+    Request,
+    Response,
+    ErrorResponse,
+    NoResponse,
+    Handshake,
+    Alert,
+    Warning,
+    Log,
+    Audit,
+    #[default]
+    Unknown,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCategory {
+    Expired,
+    Timeout,
+    Unauthorized,
+    Redirect,
+    ClientError,
+    ServerError,
+    GlobalFailure,
+    MediaMismatch,
+}
+
+impl ErrorCategory {
+    pub fn from_status(status_code: u16) -> Option<Self> {
+        match status_code {
+            300..400 => Some(Self::Redirect),
+            401 | 407 => Some(Self::Unauthorized),
+            408 => Some(Self::Timeout),
+            400..500 => Some(Self::ClientError),
+            500..600 => Some(Self::ServerError),
+            600..700 => Some(Self::GlobalFailure),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Default, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    #[default]
+    SendRecv,
+    SendOnly,
+    RecvOnly,
+    Inactive,
+}
+
+impl Direction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Direction::SendRecv => "sendrecv",
+            Direction::SendOnly => "sendonly",
+            Direction::RecvOnly => "recvonly",
+            Direction::Inactive => "inactive",
+        }
+    }
+}
+
+#[derive(Serialize, Default, Clone, PartialEq, Debug)]
+pub struct Media {
+    pub addr: String,
+    pub port: String,
+    pub codecs: Vec<String>,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub formats: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ptime: Option<u16>,
+    pub direction: Direction,
+}
+
+#[derive(Serialize, Default, Clone, Debug)]
+pub struct Negotiation {
+    pub offered: Vec<String>,
+    pub answered: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub codec: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ptime: Option<u16>,
+    pub direction: Direction,
+    pub reinvite: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub change: Option<String>,
+}
+
+#[derive(Serialize, Default, Clone, Debug)]
+pub struct LogRecord {
+    pub facility: &'static str,
+    pub severity: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub app: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proc_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub msg_id: Option<String>,
+    pub message: String,
+}
+
+#[derive(Serialize, Default, Clone, Debug)]
+pub struct Event {
+    pub ts: DateTime<Utc>,
+    pub protocol: &'static str,
+    pub kind: EventKind,
+    pub user: String,
+    pub method: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub media: Option<Media>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub negotiation: Option<Negotiation>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log: Option<LogRecord>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorCategory>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub peer: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub addr: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub call_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auth_user: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repeat: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub elapsed_s: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_minutes: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_seen: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
@@ -0,0 +1,50 @@
+use std::vec::IntoIter;
+
+/// First of the comma joined values of a field
+pub fn first(value: &str) -> &str {
+    value.split(',').next().unwrap_or_default()
+}
+
+/// Occurrences of a field in a segment carrying `count` messages that use it. Values such as
+/// query text may contain commas, so they are only split when the split is unambiguous and are
+/// blank otherwise rather than paired with the wrong message
+pub fn occurrences(value: &str, count: usize) -> IntoIter<&str> {
+    if count <= 1 {
+        return vec![value].into_iter();
+    }
+    let parts = value.split(',').collect::<Vec<_>>();
+    if parts.len() == count {
+        parts.into_iter()
+    } else {
+        vec![""; count].into_iter()
+    }
+}
+
+/// Next value of a field, blank once the values run out
+pub fn next<'a>(values: &mut impl Iterator<Item = &'a str>) -> &'a str {
+    values.next().unwrap_or_default()
+}
+
+/// Decimal or 0x prefixed hex as printed by tshark
+pub fn parse_number(value: &str) -> Option<u32> {
+    match value.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+// MARK: TESTS
+#[cfg(test)]
+mod test {
+    use super::{occurrences, parse_number};
+
+    #[test]
+    fn field_values() {
+        let dn = "ou=people,dc=corp,dc=com";
+        assert_eq!(occurrences(dn, 1).collect::<Vec<_>>(), vec![dn]);
+        assert_eq!(occurrences("0,32", 2).collect::<Vec<_>>(), vec!["0", "32"]);
+        assert_eq!(occurrences("0", 2).collect::<Vec<_>>(), vec!["", ""]);
+        assert_eq!(parse_number("0x0012"), Some(18));
+        assert_eq!(parse_number("18"), Some(18));
+    }
+}
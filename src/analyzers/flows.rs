@@ -0,0 +1,460 @@
+use super::{
+    ProtocolAnalyzer, TIME_FMT,
+    report::{self, DailyReport},
+};
+use crate::{
+    ArgsCommand,
+    formatters::{EventFormatter, create_formatter},
+};
+use ahash::HashMap;
+use chrono::{DateTime, Local, Utc};
+use std::{collections::BTreeMap, fmt::Write as _, hash::Hash};
+
+/// Throughput bucket when no --interval is given
+const DEFAULT_INTERVAL_S: i64 = 60;
+/// Throughput buckets kept for the report
+const MAX_INTERVALS: usize = 1440;
+/// Above this many 5-tuples, idle ones are dropped (host pairs keep their totals)
+const MAX_FLOWS: usize = 100_000;
+const FLOW_IDLE_S: i64 = 300;
+/// Hosts shown in the traffic matrix
+const MATRIX_HOSTS: usize = 8;
+
+/// Traffic between two endpoints, index 0 is the direction of the first packet seen
+struct Conversation {
+    packets: [u64; 2],
+    bytes: [u64; 2],
+    first: DateTime<Utc>,
+    last: DateTime<Utc>,
+    app: String,
+}
+
+impl Conversation {
+    fn total_bytes(&self) -> u64 {
+        self.bytes[0] + self.bytes[1]
+    }
+
+    fn total_packets(&self) -> u64 {
+        self.packets[0] + self.packets[1]
+    }
+
+    fn summary(&self) -> String {
+        format!(
+            "{:>10} -> {:>10} <- {:8} pkts  {} - {}",
+            human_bytes(self.bytes[0]),
+            human_bytes(self.bytes[1]),
+            self.total_packets(),
+            self.first.with_timezone(&Local).format(TIME_FMT),
+            self.last.with_timezone(&Local).format(TIME_FMT),
+        )
+    }
+}
+
+#[derive(Default)]
+struct HostStats {
+    tx_bytes: u64,
+    rx_bytes: u64,
+    tx_packets: u64,
+    rx_packets: u64,
+}
+
+#[derive(Default, Clone, Copy)]
+struct Throughput {
+    packets: u64,
+    bytes: u64,
+}
+
+pub struct Analyzer {
+    /// (protocol, initiator, responder) endpoints
+    flows: HashMap<(&'static str, String, String), Conversation>,
+    host_pairs: HashMap<(String, String), Conversation>,
+    hosts: HashMap<String, HostStats>,
+    /// Interval start (unix seconds) to traffic
+    intervals: BTreeMap<i64, Throughput>,
+    /// Host pair bytes of the current interval, for the running top-N
+    running: HashMap<(String, String), u64>,
+    running_start: Option<i64>,
+    interval: i64,
+    live_top: bool,
+    pruned_flows: u64,
+    last_prune: Option<DateTime<Utc>>,
+    top: usize,
+    formatter: Box<dyn EventFormatter>,
+    daily_report: DailyReport,
+}
+
+fn protocol_name(proto: &str) -> &'static str {
+    match proto {
+        "1" => "ICMP",
+        "2" => "IGMP",
+        "6" => "TCP",
+        "17" => "UDP",
+        "47" => "GRE",
+        "50" => "ESP",
+        "58" => "ICMPv6",
+        "89" => "OSPF",
+        "132" => "SCTP",
+        "" => "-",
+        _ => "IP",
+    }
+}
+
+fn first(value: &str) -> &str {
+    value.split(',').next().unwrap_or_default()
+}
+
+fn human_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1000.0 && unit < UNITS.len() - 1 {
+        value /= 1000.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
+fn endpoint(addr: &str, port: &str) -> String {
+    if port.is_empty() {
+        addr.to_owned()
+    } else if addr.contains(':') {
+        format!("[{addr}]:{port}")
+    } else {
+        format!("{addr}:{port}")
+    }
+}
+
+/// Adds a packet to the conversation of either direction
+fn record<K: Hash + Eq>(
+    map: &mut HashMap<K, Conversation>,
+    key: K,
+    reverse: K,
+    ts: DateTime<Utc>,
+    len: u64,
+) -> &mut Conversation {
+    let (conversation, dir) = if map.contains_key(&reverse) {
+        (map.get_mut(&reverse).unwrap(), 1)
+    } else {
+        let conversation = map.entry(key).or_insert_with(|| Conversation {
+            packets: [0; 2],
+            bytes: [0; 2],
+            first: ts,
+            last: ts,
+            app: String::new(),
+        });
+        (conversation, 0)
+    };
+    conversation.packets[dir] += 1;
+    conversation.bytes[dir] += len;
+    conversation.last = ts;
+    conversation
+}
+
+impl Analyzer {
+    fn print_running(&mut self, start: i64) {
+        let Some(throughput) = self.intervals.get(&start).copied() else {
+            return;
+        };
+        let start_ts = DateTime::from_timestamp(start, 0).unwrap_or_default();
+        report::print_section(
+            self.formatter.as_mut(),
+            &format!(
+                "Top Talkers {} ({} s) {:8} pkts {:>10} {:8.3} Mbit/s",
+                start_ts.with_timezone(&Local).format(TIME_FMT),
+                self.interval,
+                throughput.packets,
+                human_bytes(throughput.bytes),
+                throughput.bytes as f64 * 8.0 / self.interval as f64 / 1_000_000.0,
+            ),
+        );
+        for ((a, b), bytes) in report::top_n(&self.running, self.top) {
+            self.formatter
+                .report(&format!("{:>10} {a} <-> {b}", human_bytes(bytes)), 1);
+        }
+        self.formatter.report("", 1);
+        self.running.clear();
+    }
+
+    fn print_matrix(&mut self) {
+        report::print_section(
+            self.formatter.as_mut(),
+            "Traffic Matrix (row sends to column)",
+        );
+        let totals = self
+            .hosts
+            .iter()
+            .map(|(host, s)| (host, s.tx_bytes + s.rx_bytes))
+            .collect::<HashMap<_, _>>();
+        let hosts = report::top_n(&totals, self.top.min(MATRIX_HOSTS))
+            .into_iter()
+            .map(|(host, _)| (*host).clone())
+            .collect::<Vec<_>>();
+        let mut output = format!("{:<4}", "");
+        for i in 1..=hosts.len() {
+            write!(output, " {:>10}", format!("[{i}]")).unwrap();
+        }
+        self.formatter.report(&output, 1);
+        for (i, src) in hosts.iter().enumerate() {
+            output = format!("{:<4}", format!("[{}]", i + 1));
+            for dst in &hosts {
+                let bytes = if let Some(c) = self.host_pairs.get(&(src.clone(), dst.clone())) {
+                    c.bytes[0]
+                } else if let Some(c) = self.host_pairs.get(&(dst.clone(), src.clone())) {
+                    c.bytes[1]
+                } else {
+                    0
+                };
+                let cell = if bytes == 0 {
+                    "-".to_owned()
+                } else {
+                    human_bytes(bytes)
+                };
+                write!(output, " {cell:>10}").unwrap();
+            }
+            write!(output, "  {src}").unwrap();
+            self.formatter.report(&output, 1);
+        }
+    }
+
+    fn print_stats(&mut self, opt_ts: Option<DateTime<Utc>>) {
+        report::print_header(self.formatter.as_mut(), opt_ts);
+        report::print_section(self.formatter.as_mut(), "Top Talkers");
+        let mut hosts = Vec::from_iter(&self.hosts);
+        hosts.sort_by(|a, b| {
+            (b.1.tx_bytes + b.1.rx_bytes)
+                .cmp(&(a.1.tx_bytes + a.1.rx_bytes))
+                .then(a.0.cmp(b.0))
+        });
+        for (host, stats) in hosts.into_iter().take(self.top) {
+            self.formatter.report(
+                &format!(
+                    "{host:<40} sent {:>10} {:8} pkts  received {:>10} {:8} pkts",
+                    human_bytes(stats.tx_bytes),
+                    stats.tx_packets,
+                    human_bytes(stats.rx_bytes),
+                    stats.rx_packets
+                ),
+                1,
+            );
+        }
+
+        report::print_section(self.formatter.as_mut(), "Top Host Pairs");
+        let mut pairs = Vec::from_iter(&self.host_pairs);
+        pairs.sort_by(|a, b| b.1.total_bytes().cmp(&a.1.total_bytes()).then(a.0.cmp(b.0)));
+        for ((a, b), conversation) in pairs.into_iter().take(self.top) {
+            self.formatter
+                .report(&format!("{a} <-> {b}\n    {}", conversation.summary()), 1);
+        }
+
+        report::print_section(self.formatter.as_mut(), "Top Flows");
+        let mut flows = Vec::from_iter(&self.flows);
+        flows.sort_by(|a, b| b.1.total_bytes().cmp(&a.1.total_bytes()).then(a.0.cmp(b.0)));
+        for ((proto, a, b), conversation) in flows.into_iter().take(self.top) {
+            self.formatter.report(
+                &format!(
+                    "{proto:<6} {a} -> {b} {}\n    {}",
+                    conversation.app,
+                    conversation.summary()
+                ),
+                1,
+            );
+        }
+
+        self.print_matrix();
+
+        report::print_section(
+            self.formatter.as_mut(),
+            &format!("Throughput ({} s)", self.interval),
+        );
+        for (start, throughput) in &self.intervals {
+            let start = DateTime::from_timestamp(*start, 0).unwrap_or_default();
+            self.formatter.report(
+                &format!(
+                    "{} {:8} pkts {:>10} {:8.3} Mbit/s",
+                    start.with_timezone(&Local).format(TIME_FMT),
+                    throughput.packets,
+                    human_bytes(throughput.bytes),
+                    throughput.bytes as f64 * 8.0 / self.interval as f64 / 1_000_000.0,
+                ),
+                1,
+            );
+        }
+        let peak = self
+            .intervals
+            .values()
+            .map(|t| t.bytes)
+            .max()
+            .unwrap_or_default();
+        let output = format!(
+            r#"
+ ------------ STATS ------------
+
+- total packets: {}
+- total bytes: {}
+- hosts: {}
+- host pairs: {}
+- flows: {}
+- idle flows pruned: {}
+- peak throughput: {:.3} Mbit/s
+"#,
+            self.hosts.values().map(|h| h.tx_packets).sum::<u64>(),
+            human_bytes(self.hosts.values().map(|h| h.tx_bytes).sum::<u64>()),
+            self.hosts.len(),
+            self.host_pairs.len(),
+            self.flows.len(),
+            self.pruned_flows,
+            peak as f64 * 8.0 / self.interval as f64 / 1_000_000.0,
+        );
+        self.formatter.report(&output, 0);
+        report::print_footer(self.formatter.as_mut());
+    }
+}
+
+impl ProtocolAnalyzer for Analyzer {
+    fn new(cmd_args: &ArgsCommand, verbosity: u8) -> Self {
+        let (interval, top) = match cmd_args {
+            ArgsCommand::Analyzer { interval, top, .. } => (*interval, *top),
+            _ => (None, 10),
+        };
+        Self {
+            flows: HashMap::default(),
+            host_pairs: HashMap::default(),
+            hosts: HashMap::default(),
+            intervals: BTreeMap::new(),
+            running: HashMap::default(),
+            running_start: None,
+            interval: interval.map_or(DEFAULT_INTERVAL_S, |i| i.max(1) as i64),
+            live_top: interval.is_some(),
+            pruned_flows: 0,
+            last_prune: None,
+            top,
+            formatter: create_formatter(cmd_args, verbosity),
+            daily_report: DailyReport::default(),
+        }
+    }
+
+    fn analyze(&mut self, ts: DateTime<Utc>, cols: Vec<&str>) {
+        if self.daily_report.is_due(ts) {
+            self.print_stats(Some(ts));
+        }
+        let start = ts.timestamp() - ts.timestamp().rem_euclid(self.interval);
+        if self.live_top
+            && let Some(running_start) = self.running_start
+            && running_start != start
+        {
+            self.print_running(running_start);
+        }
+        self.running_start = Some(start);
+        if self
+            .last_prune
+            .is_none_or(|last| (ts - last).num_seconds() >= 60)
+        {
+            self.last_prune = Some(ts);
+            if self.flows.len() > MAX_FLOWS {
+                let before = self.flows.len();
+                self.flows
+                    .retain(|_, c| (ts - c.last).num_seconds() < FLOW_IDLE_S);
+                self.pruned_flows += (before - self.flows.len()) as u64;
+            }
+        }
+
+        let src = cols[0];
+        let dst = cols[1];
+        let proto = protocol_name(first(if cols[2].is_empty() { cols[3] } else { cols[2] }));
+        let len = cols[4].parse::<u64>().unwrap_or_default();
+        let (sport, dport) = match proto {
+            "TCP" => (first(cols[5]), first(cols[6])),
+            "UDP" => (first(cols[7]), first(cols[8])),
+            _ => ("", ""),
+        };
+
+        let throughput = self.intervals.entry(start).or_default();
+        throughput.packets += 1;
+        throughput.bytes += len;
+        while self.intervals.len() > MAX_INTERVALS {
+            self.intervals.pop_first();
+        }
+        let tx = self.hosts.entry(src.to_owned()).or_default();
+        tx.tx_bytes += len;
+        tx.tx_packets += 1;
+        let rx = self.hosts.entry(dst.to_owned()).or_default();
+        rx.rx_bytes += len;
+        rx.rx_packets += 1;
+
+        let (a, b) = (endpoint(src, sport), endpoint(dst, dport));
+        let flow = record(
+            &mut self.flows,
+            (proto, a.clone(), b.clone()),
+            (proto, b, a),
+            ts,
+            len,
+        );
+        // keep the application protocol over bare transport names
+        let app = cols[9];
+        if flow.app.is_empty() || (!app.is_empty() && app != proto) {
+            flow.app = app.to_owned();
+        }
+        record(
+            &mut self.host_pairs,
+            (src.to_owned(), dst.to_owned()),
+            (dst.to_owned(), src.to_owned()),
+            ts,
+            len,
+        );
+        if self.live_top {
+            let key = if src <= dst {
+                (src.to_owned(), dst.to_owned())
+            } else {
+                (dst.to_owned(), src.to_owned())
+            };
+            *self.running.entry(key).or_default() += len;
+        }
+    }
+
+    fn add_protocol_fields(&self, tshark_args: &mut Vec<&str>) {
+        tshark_args.push("-e");
+        tshark_args.push("ip.proto");
+        tshark_args.push("-e");
+        tshark_args.push("ipv6.nxt");
+        tshark_args.push("-e");
+        tshark_args.push("frame.len");
+        tshark_args.push("-e");
+        tshark_args.push("tcp.srcport");
+        tshark_args.push("-e");
+        tshark_args.push("tcp.dstport");
+        tshark_args.push("-e");
+        tshark_args.push("udp.srcport");
+        tshark_args.push("-e");
+        tshark_args.push("udp.dstport");
+        tshark_args.push("-e");
+        tshark_args.push("_ws.col.Protocol");
+    }
+
+    fn end(&mut self) {
+        if self.live_top
+            && let Some(start) = self.running_start
+        {
+            self.print_running(start);
+        }
+        self.print_stats(None);
+    }
+}
+
+// MARK: TESTS
+#[cfg(test)]
+mod test {
+    use super::{endpoint, human_bytes};
+
+    #[test]
+    fn formatting() {
+        assert_eq!(human_bytes(999), "999 B");
+        assert_eq!(human_bytes(1_500_000), "1.5 MB");
+        assert_eq!(endpoint("10.0.0.1", "80"), "10.0.0.1:80");
+        assert_eq!(endpoint("2001:db8::1", "443"), "[2001:db8::1]:443");
+        assert_eq!(endpoint("10.0.0.1", ""), "10.0.0.1");
+    }
+}
@@ -0,0 +1,646 @@
+use super::{
+    Event, EventKind, ProtocolAnalyzer,
+    report::{self, DailyReport, LatencyStats},
+};
+use crate::{
+    ArgsCommand,
+    formatters::{EventFormatter, create_formatter},
+};
+use ahash::HashMap;
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use serde::Deserialize;
+use std::{collections::VecDeque, fmt::Write as _, path::Path};
+
+/// Unterminated frames larger than this are dropped
+const MAX_FRAME_LEN: usize = 64 * 1024;
+/// Distinct values kept per configured field
+const MAX_FIELD_VALUES: usize = 10_000;
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+enum Checksum {
+    /// XOR of all bytes (BCC)
+    Xor,
+    /// Sum of all bytes modulo 256
+    Sum,
+    /// Two's complement of the sum, as Modbus ASCII
+    Lrc,
+}
+
+/// `--config` JSON, bytes are decimal numbers, every key is optional:
+/// `{"start": 2, "end": 3, "escape": 16, "checksum": "xor", "ack": 6, "nak": 21,
+///   "message_type": "^(\\w+)", "fields": {"device": "ID=(\\d+)"}}`
+#[derive(Deserialize)]
+#[serde(default)]
+struct FrameConfig {
+    start: u8,
+    end: u8,
+    escape: Option<u8>,
+    checksum: Option<Checksum>,
+    /// Checksum sent as two ASCII hex digits instead of one byte
+    checksum_hex: bool,
+    checksum_includes_end: bool,
+    ack: Option<u8>,
+    nak: Option<u8>,
+    /// First capture group (or the whole match) names the message type
+    message_type: Option<String>,
+    ack_types: Vec<String>,
+    nak_types: Vec<String>,
+    /// Field name to regex, the first capture group is the value
+    fields: HashMap<String, String>,
+}
+
+impl Default for FrameConfig {
+    fn default() -> Self {
+        Self {
+            start: 0x02,
+            end: 0x03,
+            escape: None,
+            checksum: None,
+            checksum_hex: false,
+            checksum_includes_end: true,
+            ack: Some(0x06),
+            nak: Some(0x15),
+            message_type: None,
+            ack_types: Vec::new(),
+            nak_types: Vec::new(),
+            fields: HashMap::default(),
+        }
+    }
+}
+
+struct Framing {
+    config: FrameConfig,
+    message_type: Option<Regex>,
+    fields: Vec<(String, Regex)>,
+}
+
+impl Framing {
+    fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let config = serde_json::from_str(&text).map_err(|e| e.to_string())?;
+        Self::new(config)
+    }
+
+    fn new(config: FrameConfig) -> Result<Self, String> {
+        let message_type = config
+            .message_type
+            .as_deref()
+            .map(Regex::new)
+            .transpose()
+            .map_err(|e| e.to_string())?;
+        let mut fields = config
+            .fields
+            .iter()
+            .map(|(name, re)| Ok((name.clone(), Regex::new(re).map_err(|e| e.to_string())?)))
+            .collect::<Result<Vec<_>, String>>()?;
+        fields.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(Self {
+            config,
+            message_type,
+            fields,
+        })
+    }
+
+    fn checksum_len(&self) -> usize {
+        match (self.config.checksum, self.config.checksum_hex) {
+            (None, _) => 0,
+            (Some(_), false) => 1,
+            (Some(_), true) => 2,
+        }
+    }
+
+    fn message_type(&self, text: &str) -> String {
+        self.message_type
+            .as_ref()
+            .and_then(|re| re.captures(text))
+            .and_then(|c| c.get(1).or_else(|| c.get(0)))
+            .map_or_else(|| "-".to_owned(), |m| m.as_str().to_owned())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Frame { data: Vec<u8>, checksum_ok: bool },
+    Ack,
+    Nak,
+    Overflow,
+}
+
+fn checksum(kind: Checksum, data: &[u8]) -> u8 {
+    match kind {
+        Checksum::Xor => data.iter().fold(0, |acc, b| acc ^ b),
+        Checksum::Sum => data.iter().fold(0u8, |acc, b| acc.wrapping_add(*b)),
+        Checksum::Lrc => data
+            .iter()
+            .fold(0u8, |acc, b| acc.wrapping_add(*b))
+            .wrapping_neg(),
+    }
+}
+
+/// Complete frames and ACK/NAK bytes at the front of `buf`, a trailing partial frame stays in it
+fn split_frames(framing: &Framing, buf: &mut Vec<u8>) -> Vec<Token> {
+    let config = &framing.config;
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    'frames: while pos < buf.len() {
+        let byte = buf[pos];
+        if byte != config.start {
+            if Some(byte) == config.ack {
+                tokens.push(Token::Ack);
+            } else if Some(byte) == config.nak {
+                tokens.push(Token::Nak);
+            }
+            pos += 1;
+            continue;
+        }
+        let mut data = Vec::new();
+        let mut escaped = false;
+        for i in pos + 1..buf.len() {
+            let byte = buf[i];
+            if escaped {
+                escaped = false;
+                data.push(byte);
+            } else if Some(byte) == config.escape {
+                escaped = true;
+            } else if byte == config.end {
+                let checksum_end = i + 1 + framing.checksum_len();
+                if checksum_end > buf.len() {
+                    break;
+                }
+                let checksum_ok = config.checksum.is_none_or(|kind| {
+                    if config.checksum_includes_end {
+                        data.push(byte);
+                    }
+                    let expected = checksum(kind, &data);
+                    if config.checksum_includes_end {
+                        data.pop();
+                    }
+                    let received = &buf[i + 1..checksum_end];
+                    if config.checksum_hex {
+                        std::str::from_utf8(received)
+                            .ok()
+                            .and_then(|h| u8::from_str_radix(h, 16).ok())
+                            == Some(expected)
+                    } else {
+                        received[0] == expected
+                    }
+                });
+                tokens.push(Token::Frame { data, checksum_ok });
+                pos = checksum_end;
+                continue 'frames;
+            } else {
+                data.push(byte);
+            }
+        }
+        // frame continues in a later segment
+        if buf.len() - pos > MAX_FRAME_LEN {
+            tokens.push(Token::Overflow);
+            pos = buf.len();
+        }
+        break;
+    }
+    buf.drain(..pos.min(buf.len()));
+    tokens
+}
+
+struct PendingCommand {
+    ts: DateTime<Utc>,
+    message_type: String,
+}
+
+#[derive(Default)]
+struct DeviceStats {
+    commands: u32,
+    acks: u32,
+    naks: u32,
+    timeouts: u32,
+    frames_sent: u32,
+    checksum_errors: u32,
+    latency: LatencyStats,
+}
+
+impl DeviceStats {
+    fn nak_rate(&self) -> f64 {
+        self.naks as f64 * 100.0 / (self.acks + self.naks).max(1) as f64
+    }
+}
+
+#[derive(Default)]
+struct TypeStats {
+    frames: u32,
+    naks: u32,
+    latency: LatencyStats,
+}
+
+pub struct Analyzer {
+    framing: Framing,
+    /// Unparsed bytes per (sender, receiver) endpoint
+    buffers: HashMap<(String, String), Vec<u8>>,
+    /// Commands waiting for ACK/NAK per (sender, receiver) endpoint, oldest first
+    pending: HashMap<(String, String), VecDeque<PendingCommand>>,
+    devices: HashMap<String, DeviceStats>,
+    types: HashMap<String, TypeStats>,
+    field_values: HashMap<String, HashMap<String, u32>>,
+    unmatched_acks: u32,
+    overflows: u32,
+    timeout: i64,
+    top: usize,
+    last_timeout_check: Option<DateTime<Utc>>,
+    formatter: Box<dyn EventFormatter>,
+    daily_report: DailyReport,
+}
+
+const PROTOCOL: &str = "framed";
+
+fn host(endpoint: &str) -> &str {
+    endpoint.rsplit_once(':').map_or(endpoint, |(h, _)| h)
+}
+
+impl Analyzer {
+    fn acknowledged(&self) -> bool {
+        let config = &self.framing.config;
+        config.ack.is_some()
+            || config.nak.is_some()
+            || !config.ack_types.is_empty()
+            || !config.nak_types.is_empty()
+    }
+
+    fn expire_pending(&mut self, opt_ts: Option<DateTime<Utc>>) {
+        let timeout = self.timeout;
+        for ((src, dst), queue) in &mut self.pending {
+            while let Some(command) = queue.front() {
+                if opt_ts.is_some_and(|ts| (ts - command.ts).num_seconds() < timeout) {
+                    break;
+                }
+                let command = queue.pop_front().unwrap();
+                self.devices
+                    .entry(host(dst).to_owned())
+                    .or_default()
+                    .timeouts += 1;
+                self.formatter.event(&Event {
+                    ts: opt_ts.unwrap_or(command.ts),
+                    protocol: PROTOCOL,
+                    kind: EventKind::NoResponse,
+                    user: src.clone(),
+                    method: command.message_type,
+                    addr: Some(dst.clone()),
+                    elapsed_s: opt_ts.map(|ts| (ts - command.ts).num_seconds()),
+                    ..Default::default()
+                });
+            }
+        }
+        self.pending.retain(|_, queue| !queue.is_empty());
+    }
+
+    /// ACK or NAK sent by `src` for the oldest command `dst` sent to it
+    fn on_reply(&mut self, ts: DateTime<Utc>, src: &str, dst: &str, nak: bool) {
+        let Some(command) = self
+            .pending
+            .get_mut(&(dst.to_owned(), src.to_owned()))
+            .and_then(VecDeque::pop_front)
+        else {
+            self.unmatched_acks += 1;
+            return;
+        };
+        let latency = (ts - command.ts).num_microseconds().unwrap_or_default() as f64 / 1000.0;
+        let device = self.devices.entry(host(src).to_owned()).or_default();
+        device.latency.add(latency);
+        let stats = self.types.entry(command.message_type.clone()).or_default();
+        stats.latency.add(latency);
+        if !nak {
+            device.acks += 1;
+            return;
+        }
+        device.naks += 1;
+        stats.naks += 1;
+        self.formatter.event(&Event {
+            ts,
+            protocol: PROTOCOL,
+            kind: EventKind::ErrorResponse,
+            user: dst.to_owned(),
+            method: command.message_type,
+            addr: Some(src.to_owned()),
+            latency_ms: Some(latency),
+            detail: Some("NAK".into()),
+            ..Default::default()
+        });
+    }
+
+    fn on_frame(
+        &mut self,
+        ts: DateTime<Utc>,
+        src: &str,
+        dst: &str,
+        data: &[u8],
+        checksum_ok: bool,
+    ) {
+        let text = String::from_utf8_lossy(data);
+        let message_type = self.framing.message_type(&text);
+        self.devices
+            .entry(host(src).to_owned())
+            .or_default()
+            .frames_sent += 1;
+        if !checksum_ok {
+            self.devices
+                .entry(host(src).to_owned())
+                .or_default()
+                .checksum_errors += 1;
+            self.formatter.event(&Event {
+                ts,
+                protocol: PROTOCOL,
+                kind: EventKind::Warning,
+                user: src.to_owned(),
+                method: message_type,
+                addr: Some(dst.to_owned()),
+                detail: Some("checksum mismatch".into()),
+                ..Default::default()
+            });
+            return;
+        }
+        let config = &self.framing.config;
+        if config.ack_types.contains(&message_type) || config.nak_types.contains(&message_type) {
+            let nak = config.nak_types.contains(&message_type);
+            self.on_reply(ts, src, dst, nak);
+            return;
+        }
+        self.types.entry(message_type.clone()).or_default().frames += 1;
+        for (name, re) in &self.framing.fields {
+            let Some(value) = re.captures(&text).and_then(|c| c.get(1)) else {
+                continue;
+            };
+            let values = self.field_values.entry(name.clone()).or_default();
+            if values.len() < MAX_FIELD_VALUES || values.contains_key(value.as_str()) {
+                *values.entry(value.as_str().to_owned()).or_default() += 1;
+            }
+        }
+        if self.acknowledged() {
+            self.devices
+                .entry(host(dst).to_owned())
+                .or_default()
+                .commands += 1;
+            self.pending
+                .entry((src.to_owned(), dst.to_owned()))
+                .or_default()
+                .push_back(PendingCommand { ts, message_type });
+        }
+    }
+
+    fn print_stats(&mut self, opt_ts: Option<DateTime<Utc>>) {
+        let mut output = String::with_capacity(200);
+        report::print_header(self.formatter.as_mut(), opt_ts);
+        report::print_section(self.formatter.as_mut(), "Devices");
+        let mut devices = Vec::from_iter(&self.devices);
+        devices.sort_by(|a, b| b.1.nak_rate().total_cmp(&a.1.nak_rate()).then(a.0.cmp(b.0)));
+        for (device, stats) in devices {
+            write!(
+                output,
+                "{device:<16} {:7} commands {:6} ACK {:5} NAK ({:5.1} %) {:5} timeouts {:7} frames sent {:4} bad checksum",
+                stats.commands,
+                stats.acks,
+                stats.naks,
+                stats.nak_rate(),
+                stats.timeouts,
+                stats.frames_sent,
+                stats.checksum_errors,
+            )
+            .unwrap();
+            if stats.latency.max() > 0.0 {
+                write!(output, "\n{:<16} {}", "", stats.latency.summary()).unwrap();
+            }
+            self.formatter.report(&output, 1);
+            output.clear();
+        }
+
+        report::print_section(self.formatter.as_mut(), "Message Types");
+        let mut types = Vec::from_iter(&self.types);
+        types.sort_by(|a, b| b.1.frames.cmp(&a.1.frames).then(a.0.cmp(b.0)));
+        for (message_type, stats) in types.into_iter().take(self.top) {
+            self.formatter.report(
+                &format!(
+                    "{message_type:<16} {:7} frames {:5} NAK  {}",
+                    stats.frames,
+                    stats.naks,
+                    stats.latency.summary()
+                ),
+                1,
+            );
+        }
+
+        for (name, _) in &self.framing.fields {
+            let Some(values) = self.field_values.get(name) else {
+                continue;
+            };
+            report::print_section(self.formatter.as_mut(), &format!("Field {name}"));
+            for (value, count) in report::top_n(values, self.top) {
+                self.formatter.report(&format!("{count:8} {value}"), 1);
+            }
+        }
+        write!(
+            output,
+            r#"
+ ------------ STATS ------------
+
+- total frames: {}
+- checksum errors: {}
+- pending commands: {}
+- unmatched ACK/NAK: {}
+- oversized frames: {}
+"#,
+            self.devices.values().map(|d| d.frames_sent).sum::<u32>(),
+            self.devices
+                .values()
+                .map(|d| d.checksum_errors)
+                .sum::<u32>(),
+            self.pending.values().map(VecDeque::len).sum::<usize>(),
+            self.unmatched_acks,
+            self.overflows,
+        )
+        .unwrap();
+        self.formatter.report(&output, 0);
+        report::print_footer(self.formatter.as_mut());
+    }
+}
+
+impl ProtocolAnalyzer for Analyzer {
+    fn new(cmd_args: &ArgsCommand, verbosity: u8) -> Self {
+        let (timeout, top, config) = match cmd_args {
+            ArgsCommand::Analyzer {
+                timeout,
+                top,
+                config,
+                ..
+            } => (*timeout as i64, *top, config.as_ref()),
+            _ => (5, 10, None),
+        };
+        // analyzing with other framing than asked for gives a plausible but wrong report
+        let framing = match config {
+            Some(path) => Framing::load(path).unwrap_or_else(|err| {
+                eprintln!("Failed to load {}: {err}", path.display());
+                std::process::exit(1);
+            }),
+            None => Framing::new(FrameConfig::default()).expect("default framing"),
+        };
+        Self {
+            framing,
+            buffers: HashMap::default(),
+            pending: HashMap::default(),
+            devices: HashMap::default(),
+            types: HashMap::default(),
+            field_values: HashMap::default(),
+            unmatched_acks: 0,
+            overflows: 0,
+            timeout,
+            top,
+            last_timeout_check: None,
+            formatter: create_formatter(cmd_args, verbosity),
+            daily_report: DailyReport::default(),
+        }
+    }
+
+    fn analyze(&mut self, ts: DateTime<Utc>, cols: Vec<&str>) {
+        if self
+            .last_timeout_check
+            .is_none_or(|last| (ts - last).num_seconds() >= 1)
+        {
+            self.expire_pending(Some(ts));
+            self.last_timeout_check = Some(ts);
+        }
+        if self.daily_report.is_due(ts) {
+            self.print_stats(Some(ts));
+        }
+        let (sport, dport, payload) = if cols[4].is_empty() {
+            (cols[5], cols[6], cols[7])
+        } else {
+            (cols[2], cols[3], cols[4])
+        };
+        let Ok(payload) = hex::decode(payload.replace(':', "")) else {
+            return;
+        };
+        let src = format!("{}:{sport}", cols[0]);
+        let dst = format!("{}:{dport}", cols[1]);
+        let buf = self.buffers.entry((src.clone(), dst.clone())).or_default();
+        buf.extend_from_slice(&payload);
+        let tokens = split_frames(&self.framing, buf);
+        for token in tokens {
+            match token {
+                Token::Frame { data, checksum_ok } => {
+                    self.on_frame(ts, &src, &dst, &data, checksum_ok);
+                }
+                Token::Ack => self.on_reply(ts, &src, &dst, false),
+                Token::Nak => self.on_reply(ts, &src, &dst, true),
+                Token::Overflow => {
+                    self.overflows += 1;
+                    self.formatter.event(&Event {
+                        ts,
+                        protocol: PROTOCOL,
+                        kind: EventKind::Warning,
+                        user: src.clone(),
+                        method: "FRAME".into(),
+                        addr: Some(dst.clone()),
+                        detail: Some(format!("no end byte within {MAX_FRAME_LEN} bytes")),
+                        ..Default::default()
+                    });
+                }
+            }
+        }
+    }
+
+    fn add_protocol_fields(&self, tshark_args: &mut Vec<&str>) {
+        tshark_args.push("-e");
+        tshark_args.push("tcp.srcport");
+        tshark_args.push("-e");
+        tshark_args.push("tcp.dstport");
+        tshark_args.push("-e");
+        tshark_args.push("tcp.payload");
+        tshark_args.push("-e");
+        tshark_args.push("udp.srcport");
+        tshark_args.push("-e");
+        tshark_args.push("udp.dstport");
+        tshark_args.push("-e");
+        tshark_args.push("udp.payload");
+        if !tshark_args.contains(&"-Y") {
+            tshark_args.push("-Y");
+            tshark_args.push("tcp.payload || udp.payload");
+        }
+    }
+
+    fn end(&mut self) {
+        self.expire_pending(None);
+        self.print_stats(None);
+    }
+}
+
+// MARK: TESTS
+#[cfg(test)]
+mod test {
+    use chrono::{DateTime, TimeDelta};
+    use std::collections::VecDeque;
+
+    use super::{
+        Analyzer, Checksum, FrameConfig, Framing, ProtocolAnalyzer as _, Token, split_frames,
+    };
+    use crate::analyzers::test_args;
+
+    #[test]
+    fn frames_across_segments() {
+        let framing = Framing::new(FrameConfig {
+            escape: Some(0x10),
+            checksum: Some(Checksum::Xor),
+            ..Default::default()
+        })
+        .unwrap();
+        // BCC covers the data and ETX
+        let bcc = b'A' ^ 0x02 ^ b'B' ^ 0x03;
+        let mut buf = vec![0x06, 0x02, b'A', 0x10, 0x02];
+        assert_eq!(split_frames(&framing, &mut buf), vec![Token::Ack]);
+        assert_eq!(buf, vec![0x02, b'A', 0x10, 0x02]);
+        buf.extend_from_slice(&[b'B', 0x03]);
+        assert_eq!(split_frames(&framing, &mut buf), vec![]);
+        buf.extend_from_slice(&[bcc, 0x15, 0x02, b'C', 0x03, 0x00]);
+        assert_eq!(
+            split_frames(&framing, &mut buf),
+            vec![
+                Token::Frame {
+                    data: vec![b'A', 0x02, b'B'],
+                    checksum_ok: true
+                },
+                Token::Nak,
+                Token::Frame {
+                    data: vec![b'C'],
+                    checksum_ok: false
+                },
+            ]
+        );
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn ack_nak_pairing() {
+        let mut analyzer = Analyzer::new(&test_args(&[]), 0);
+        let start = DateTime::from_timestamp(1738062028, 0).unwrap();
+        let host = ["10.0.0.1", "10.0.0.2", "5000", "6000"];
+        let device = ["10.0.0.2", "10.0.0.1", "6000", "5000"];
+        // frame ACKed, frame NAKed, then an ACK nothing waits for
+        for (ms, [src, dst, sport, dport], payload) in [
+            (0, host, "024103"),
+            (20, device, "06"),
+            (100, host, "024203"),
+            (140, device, "15"),
+            (200, device, "06"),
+        ] {
+            let cols = vec![src, dst, sport, dport, payload, "", "", ""];
+            analyzer.analyze(start + TimeDelta::milliseconds(ms), cols);
+        }
+        let stats = &analyzer.devices["10.0.0.2"];
+        assert_eq!((stats.acks, stats.naks), (1, 1));
+        assert_eq!(stats.nak_rate(), 50.0);
+        assert_eq!(stats.latency.max(), 40.0);
+        assert_eq!(analyzer.unmatched_acks, 1);
+        assert_eq!(
+            analyzer.pending.values().map(VecDeque::len).sum::<usize>(),
+            0
+        );
+    }
+}
@@ -0,0 +1,538 @@
+use super::{
+    ErrorCategory, Event, EventKind, ProtocolAnalyzer, TIME_FMT,
+    fields::{next, occurrences},
+    report::{self, DailyReport, LatencyStats},
+};
+use crate::{
+    ArgsCommand,
+    formatters::{EventFormatter, create_formatter},
+};
+use ahash::HashMap;
+use chrono::{DateTime, Local, Utc};
+use std::{collections::VecDeque, fmt::Write as _};
+
+struct PendingRequest {
+    ts: DateTime<Utc>,
+    client: String,
+    method: String,
+    host: String,
+    uri: String,
+}
+
+#[derive(Default)]
+struct UriStats {
+    requests: u32,
+    errors: u32,
+    latency: LatencyStats,
+    /// Responses with a Content-Length header, chunked bodies have none
+    sized: u32,
+    bytes: u64,
+    max_bytes: u64,
+}
+
+struct SlowRequest {
+    ts: DateTime<Utc>,
+    latency_ms: f64,
+    method: String,
+    host: String,
+    uri: String,
+    status: u16,
+}
+
+pub struct Analyzer {
+    /// FIFO per (tcp.stream, http2 stream id), HTTP/1.1 pipelining answers in order
+    pending: HashMap<(u32, u32), VecDeque<PendingRequest>>,
+    uris: HashMap<(String, String), UriStats>,
+    statuses: HashMap<u16, u32>,
+    failing: HashMap<(String, String, u16), u32>,
+    slowest: Vec<SlowRequest>,
+    requests: u32,
+    unmatched_responses: u32,
+    unanswered: u32,
+    timeout: i64,
+    top: usize,
+    last_timeout_check: Option<DateTime<Utc>>,
+    formatter: Box<dyn EventFormatter>,
+    daily_report: DailyReport,
+}
+
+const PROTOCOL: &str = "http";
+
+/// Groups URIs by replacing ids (numbers, MACs, hex tokens) in path segments, query is dropped
+fn uri_pattern(uri: &str) -> String {
+    let path = uri.split(['?', '#']).next().unwrap_or_default();
+    let path = path
+        .find("://")
+        .and_then(|i| path[i + 3..].find('/').map(|j| &path[i + 3 + j..]))
+        .unwrap_or(path);
+    path.split('/')
+        .map(|segment| {
+            let (stem, ext) = match segment.rsplit_once('.') {
+                Some((stem, ext)) if !stem.is_empty() => (stem, Some(ext)),
+                _ => (segment, None),
+            };
+            let is_id = !stem.is_empty()
+                && (stem.chars().all(|c| c.is_ascii_digit())
+                    || (stem.len() >= 8
+                        && stem
+                            .chars()
+                            .all(|c| c.is_ascii_hexdigit() || c == '-' || c == ':')));
+            match (is_id, ext) {
+                (true, Some(ext)) => format!("{{id}}.{ext}"),
+                (true, None) => "{id}".to_owned(),
+                _ => segment.to_owned(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn split_values(col: &str) -> Vec<&str> {
+    if col.is_empty() {
+        Vec::new()
+    } else {
+        col.split(',').collect()
+    }
+}
+
+/// Stream ids of the HEADERS frames carrying `:method` and of those carrying `:status`, from the
+/// header count of every frame and the header names of all of them. Without counts for every
+/// frame, all HEADERS frames are assumed to carry pseudo headers.
+fn pseudo_header_streams(stream_ids: &[u32], counts: &str, names: &str) -> (Vec<u32>, Vec<u32>) {
+    let counts = split_values(counts)
+        .into_iter()
+        .map(|c| c.parse::<usize>().unwrap_or_default())
+        .collect::<Vec<_>>();
+    if counts.len() != stream_ids.len() {
+        return (stream_ids.to_vec(), stream_ids.to_vec());
+    }
+    let mut names = split_values(names).into_iter();
+    let mut requests = Vec::new();
+    let mut responses = Vec::new();
+    for (id, count) in stream_ids.iter().zip(counts) {
+        for name in names.by_ref().take(count) {
+            match name {
+                ":method" => requests.push(*id),
+                ":status" => responses.push(*id),
+                _ => {}
+            }
+        }
+    }
+    (requests, responses)
+}
+
+impl Analyzer {
+    fn expire_pending(&mut self, opt_ts: Option<DateTime<Utc>>) {
+        let timeout = self.timeout;
+        for queue in self.pending.values_mut() {
+            while let Some(req) = queue.front() {
+                if opt_ts.is_some_and(|ts| (ts - req.ts).num_seconds() < timeout) {
+                    break;
+                }
+                let req = queue.pop_front().unwrap();
+                self.unanswered += 1;
+                self.formatter.event(&Event {
+                    ts: opt_ts.unwrap_or(req.ts),
+                    protocol: PROTOCOL,
+                    kind: EventKind::NoResponse,
+                    user: req.client,
+                    method: req.method,
+                    addr: Some(req.host),
+                    elapsed_s: opt_ts.map(|ts| (ts - req.ts).num_seconds()),
+                    detail: Some(req.uri),
+                    ..Default::default()
+                });
+            }
+        }
+        self.pending.retain(|_, queue| !queue.is_empty());
+    }
+
+    fn on_request(&mut self, key: (u32, u32), req: PendingRequest) {
+        self.requests += 1;
+        self.pending.entry(key).or_default().push_back(req);
+    }
+
+    fn on_response(&mut self, ts: DateTime<Utc>, key: (u32, u32), status: u16, bytes: Option<u64>) {
+        // interim responses such as 100 Continue precede the final one, 101 ends the exchange
+        if (100..200).contains(&status) && status != 101 {
+            return;
+        }
+        let Some(req) = self.pending.get_mut(&key).and_then(VecDeque::pop_front) else {
+            self.unmatched_responses += 1;
+            return;
+        };
+        let latency_ms = (ts - req.ts).num_microseconds().unwrap_or_default() as f64 / 1000.0;
+        *self.statuses.entry(status).or_default() += 1;
+        let stats = self
+            .uris
+            .entry((req.host.clone(), uri_pattern(&req.uri)))
+            .or_default();
+        stats.requests += 1;
+        stats.latency.add(latency_ms);
+        if let Some(bytes) = bytes {
+            stats.sized += 1;
+            stats.bytes += bytes;
+            stats.max_bytes = stats.max_bytes.max(bytes);
+        }
+        if status >= 400 {
+            stats.errors += 1;
+            *self
+                .failing
+                .entry((req.host.clone(), req.uri.clone(), status))
+                .or_default() += 1;
+            self.formatter.event(&Event {
+                ts,
+                protocol: PROTOCOL,
+                kind: EventKind::ErrorResponse,
+                user: req.client.clone(),
+                method: req.method.clone(),
+                status: Some(status),
+                error: ErrorCategory::from_status(status),
+                addr: Some(req.host.clone()),
+                latency_ms: Some(latency_ms),
+                detail: Some(format!("{status} {}", req.uri)),
+                ..Default::default()
+            });
+        }
+        if self.slowest.len() < self.top
+            || self
+                .slowest
+                .last()
+                .is_some_and(|slow| slow.latency_ms < latency_ms)
+        {
+            self.slowest.push(SlowRequest {
+                ts: req.ts,
+                latency_ms,
+                method: req.method,
+                host: req.host,
+                uri: req.uri,
+                status,
+            });
+            self.slowest
+                .sort_by(|a, b| b.latency_ms.total_cmp(&a.latency_ms));
+            self.slowest.truncate(self.top);
+        }
+    }
+
+    fn print_stats(&mut self, opt_ts: Option<DateTime<Utc>>) {
+        let mut output = String::with_capacity(200);
+        report::print_header(self.formatter.as_mut(), opt_ts);
+        report::print_section(self.formatter.as_mut(), "URI Patterns");
+        let mut uris = Vec::from_iter(self.uris.iter());
+        uris.sort_by(|a, b| b.1.requests.cmp(&a.1.requests).then(a.0.cmp(b.0)));
+        for ((host, pattern), stats) in uris.into_iter().take(self.top) {
+            write!(
+                output,
+                "{host}{pattern}\n\t{:6} requests {:4} errors  {}  declared size avg {} max {} bytes",
+                stats.requests,
+                stats.errors,
+                stats.latency.summary(),
+                stats.bytes / u64::from(stats.sized.max(1)),
+                stats.max_bytes
+            )
+            .unwrap();
+            self.formatter.report(&output, 1);
+            output.clear();
+        }
+        report::print_section(self.formatter.as_mut(), "Status Codes");
+        for (status, count) in report::top_n(&self.statuses, usize::MAX) {
+            self.formatter.report(&format!("{count:8} {status:03}"), 1);
+        }
+        report::print_section(self.formatter.as_mut(), "Slowest Requests");
+        for slow in &self.slowest {
+            let line = format!(
+                "{} {:10.1} ms {:03} {:<7} {}{}",
+                slow.ts.with_timezone(&Local).format(TIME_FMT),
+                slow.latency_ms,
+                slow.status,
+                slow.method,
+                slow.host,
+                slow.uri
+            );
+            self.formatter.report(&line, 1);
+        }
+        report::print_section(self.formatter.as_mut(), "Failing Requests");
+        for ((host, uri, status), count) in report::top_n(&self.failing, self.top) {
+            self.formatter
+                .report(&format!("{count:8} {status:03} {host}{uri}"), 1);
+        }
+        write!(
+            output,
+            r#"
+ ------------ STATS ------------
+
+- total requests: {}
+- total responses: {}
+- error responses: {}
+- unanswered requests: {}
+- pending requests: {}
+- unmatched responses: {}
+"#,
+            self.requests,
+            self.statuses.values().sum::<u32>(),
+            self.statuses
+                .iter()
+                .filter(|(status, _)| **status >= 400)
+                .map(|(_, count)| count)
+                .sum::<u32>(),
+            self.unanswered,
+            self.pending.values().map(VecDeque::len).sum::<usize>(),
+            self.unmatched_responses,
+        )
+        .unwrap();
+        self.formatter.report(&output, 0);
+        report::print_footer(self.formatter.as_mut());
+    }
+}
+
+impl ProtocolAnalyzer for Analyzer {
+    fn new(cmd_args: &ArgsCommand, verbosity: u8) -> Self {
+        let (timeout, top) = match cmd_args {
+            ArgsCommand::Analyzer { timeout, top, .. } => (*timeout as i64, *top),
+            _ => (5, 10),
+        };
+        Self {
+            pending: HashMap::default(),
+            uris: HashMap::default(),
+            statuses: HashMap::default(),
+            failing: HashMap::default(),
+            slowest: Vec::new(),
+            requests: 0,
+            unmatched_responses: 0,
+            unanswered: 0,
+            timeout,
+            top,
+            last_timeout_check: None,
+            formatter: create_formatter(cmd_args, verbosity),
+            daily_report: DailyReport::default(),
+        }
+    }
+
+    fn analyze(&mut self, ts: DateTime<Utc>, cols: Vec<&str>) {
+        let src_addr = cols[0];
+        let tcp_stream = cols[2].parse::<u32>().unwrap_or_default();
+        if self
+            .last_timeout_check
+            .is_none_or(|last| (ts - last).num_seconds() >= 1)
+        {
+            self.expire_pending(Some(ts));
+            self.last_timeout_check = Some(ts);
+        }
+        if self.daily_report.is_due(ts) {
+            self.print_stats(Some(ts));
+        }
+
+        // HTTP/1.x, several pipelined messages may end in the same segment
+        let methods = split_values(cols[3]);
+        let mut uris = occurrences(cols[4], methods.len());
+        let mut hosts = occurrences(cols[5], methods.len());
+        for method in methods {
+            let host = Some(next(&mut hosts)).filter(|h| !h.is_empty());
+            self.on_request(
+                (tcp_stream, 0),
+                PendingRequest {
+                    ts,
+                    client: src_addr.to_owned(),
+                    method: method.to_owned(),
+                    host: host.unwrap_or(cols[1]).to_owned(),
+                    uri: next(&mut uris).to_owned(),
+                },
+            );
+        }
+        let statuses = split_values(cols[6]);
+        // Content-Length is missing on chunked responses, lengths only pair up when all have one
+        let lengths = Some(split_values(cols[7])).filter(|l| l.len() == statuses.len());
+        for (i, status) in statuses.iter().enumerate() {
+            let bytes = lengths.as_ref().and_then(|l| l[i].parse::<u64>().ok());
+            self.on_response(
+                ts,
+                (tcp_stream, 0),
+                status.parse().unwrap_or_default(),
+                bytes,
+            );
+        }
+
+        // HTTP/2, stream id and type are listed for every frame, pseudo headers only for the
+        // HEADERS frames carrying them, trailers have none
+        let headers_stream_ids = split_values(cols[8])
+            .into_iter()
+            .zip(split_values(cols[9]))
+            .filter(|(_, frame_type)| *frame_type == "1")
+            .map(|(id, _)| id.parse::<u32>().unwrap_or_default())
+            .collect::<Vec<_>>();
+        let (request_ids, response_ids) =
+            pseudo_header_streams(&headers_stream_ids, cols[14], cols[15]);
+        let h2_methods = split_values(cols[10]);
+        let mut h2_paths = occurrences(cols[11], h2_methods.len());
+        let mut h2_authorities = occurrences(cols[12], h2_methods.len());
+        let h2_statuses = split_values(cols[13]);
+        for (i, method) in h2_methods.into_iter().enumerate() {
+            let host = Some(next(&mut h2_authorities)).filter(|h| !h.is_empty());
+            self.on_request(
+                (tcp_stream, request_ids.get(i).copied().unwrap_or_default()),
+                PendingRequest {
+                    ts,
+                    client: src_addr.to_owned(),
+                    method: method.to_owned(),
+                    host: host.unwrap_or(cols[1]).to_owned(),
+                    uri: next(&mut h2_paths).to_owned(),
+                },
+            );
+        }
+        for (i, status) in h2_statuses.iter().enumerate() {
+            self.on_response(
+                ts,
+                (tcp_stream, response_ids.get(i).copied().unwrap_or_default()),
+                status.parse().unwrap_or_default(),
+                None,
+            );
+        }
+    }
+
+    fn add_protocol_fields(&self, tshark_args: &mut Vec<&str>) {
+        tshark_args.push("-e");
+        tshark_args.push("tcp.stream");
+        tshark_args.push("-e");
+        tshark_args.push("http.request.method");
+        tshark_args.push("-e");
+        tshark_args.push("http.request.uri");
+        tshark_args.push("-e");
+        tshark_args.push("http.host");
+        tshark_args.push("-e");
+        tshark_args.push("http.response.code");
+        tshark_args.push("-e");
+        tshark_args.push("http.content_length_header");
+        tshark_args.push("-e");
+        tshark_args.push("http2.streamid");
+        tshark_args.push("-e");
+        tshark_args.push("http2.type");
+        tshark_args.push("-e");
+        tshark_args.push("http2.headers.method");
+        tshark_args.push("-e");
+        tshark_args.push("http2.headers.path");
+        tshark_args.push("-e");
+        tshark_args.push("http2.headers.authority");
+        tshark_args.push("-e");
+        tshark_args.push("http2.headers.status");
+        tshark_args.push("-e");
+        tshark_args.push("http2.header.count");
+        tshark_args.push("-e");
+        tshark_args.push("http2.header.name");
+        if !tshark_args.contains(&"-Y") {
+            tshark_args.push("-Y");
+            tshark_args.push("http.request || http.response || http2.type == 1");
+        }
+        if tshark_args.contains(&"-i") && !tshark_args.contains(&"-f") {
+            tshark_args.push("-f");
+            tshark_args.push("tcp port 80");
+        }
+    }
+
+    fn end(&mut self) {
+        self.expire_pending(None);
+        self.print_stats(None);
+    }
+}
+
+// MARK: TESTS
+#[cfg(test)]
+mod test {
+    use chrono::{DateTime, TimeDelta};
+
+    use super::{Analyzer, ProtocolAnalyzer as _, uri_pattern};
+    use crate::analyzers::test_args;
+
+    #[test]
+    fn uri_patterns() {
+        assert_eq!(uri_pattern("/cfg/001122aabbcc.xml"), "/cfg/{id}.xml");
+        assert_eq!(
+            uri_pattern("/api/users/42/lines?x=1"),
+            "/api/users/{id}/lines"
+        );
+        assert_eq!(
+            uri_pattern("http://prov.local/y000000000028.cfg"),
+            "/y000000000028.cfg"
+        );
+        assert_eq!(
+            uri_pattern("/firmware/v1.2.3/phone.rom"),
+            "/firmware/v1.2.3/phone.rom"
+        );
+        assert_eq!(uri_pattern("/"), "/");
+    }
+
+    #[test]
+    fn grpc_streams_and_uri_commas() {
+        let mut analyzer = Analyzer::new(&test_args(&[]), 0);
+        let start = DateTime::from_timestamp(1738062028, 0).unwrap();
+        let request = vec![
+            "10.0.0.1",
+            "10.0.0.2",
+            "3",
+            "GET",
+            "/api/users?ids=1,2,3",
+            "prov.local",
+            "",
+            "",
+            "",
+            "",
+            "",
+            "",
+            "",
+            "",
+            "",
+            "",
+        ];
+        let response = vec![
+            "10.0.0.2", "10.0.0.1", "3", "", "", "", "200", "", "", "", "", "", "", "", "", "",
+        ];
+        // two calls on streams 1 and 3, the first answered with headers, data and trailers
+        let calls = vec![
+            "10.0.0.1",
+            "10.0.0.2",
+            "4",
+            "",
+            "",
+            "",
+            "",
+            "",
+            "1,3",
+            "1,1",
+            "POST,POST",
+            "/svc/A,/svc/B",
+            "grpc.local,grpc.local",
+            "",
+            "3,3",
+            ":method,:path,:authority,:method,:path,:authority",
+        ];
+        let answers = vec![
+            "10.0.0.2",
+            "10.0.0.1",
+            "4",
+            "",
+            "",
+            "",
+            "",
+            "",
+            "1,1,1,3",
+            "1,0,1,1",
+            "",
+            "",
+            "",
+            "200,200",
+            "2,1,2",
+            ":status,content-type,grpc-status,:status,content-type",
+        ];
+        for (ms, cols) in [(0, request), (10, response), (20, calls), (50, answers)] {
+            analyzer.analyze(start + TimeDelta::milliseconds(ms), cols);
+        }
+        analyzer.end();
+        assert_eq!(analyzer.unmatched_responses, 0);
+        assert_eq!(analyzer.unanswered, 0);
+        let users = &analyzer.uris[&("prov.local".to_owned(), "/api/users".to_owned())];
+        assert_eq!(users.requests, 1);
+        for path in ["/svc/A", "/svc/B"] {
+            let stats = &analyzer.uris[&("grpc.local".to_owned(), path.to_owned())];
+            assert_eq!(stats.latency.max(), 30.0);
+        }
+    }
+}
@@ -0,0 +1,426 @@
+use super::{
+    Event, EventKind, ProtocolAnalyzer, TIME_FMT,
+    report::{self, DailyReport, LatencyStats},
+};
+use crate::{
+    ArgsCommand,
+    formatters::{EventFormatter, create_formatter},
+};
+use ahash::HashMap;
+use chrono::{DateTime, Local, Utc};
+use std::{collections::BTreeMap, fmt::Write as _};
+
+/// Loss and RTT are also accounted per window of this many seconds
+const WINDOW_SECONDS: i64 = 60;
+
+#[derive(Default)]
+struct Window {
+    sent: u32,
+    received: u32,
+    rtt_sum_ms: f64,
+}
+
+#[derive(Default)]
+struct TargetStats {
+    sent: u32,
+    received: u32,
+    rtt: LatencyStats,
+    windows: BTreeMap<i64, Window>,
+    lost_streak: u32,
+}
+
+pub struct Analyzer {
+    /// (requester, target, id, seq) to request timestamp
+    pending: HashMap<(String, String, String, String), DateTime<Utc>>,
+    targets: HashMap<String, TargetStats>,
+    errors: HashMap<(String, String, String), u32>,
+    unmatched_replies: u32,
+    timeout: i64,
+    top: usize,
+    last_timeout_check: Option<DateTime<Utc>>,
+    formatter: Box<dyn EventFormatter>,
+    daily_report: DailyReport,
+}
+
+const PROTOCOL: &str = "icmp";
+
+/// Values of the packet inside an ICMP error, tshark lists outer first
+fn inner(value: &str) -> &str {
+    value.split(',').nth(1).unwrap_or_default()
+}
+
+fn first(value: &str) -> &str {
+    value.split(',').next().unwrap_or_default()
+}
+
+fn proto_name(proto: &str) -> &str {
+    match proto {
+        "1" => "ICMP",
+        "6" => "TCP",
+        "17" => "UDP",
+        "58" => "ICMPv6",
+        "132" => "SCTP",
+        other => other,
+    }
+}
+
+fn endpoint(addr: &str, port: &str) -> String {
+    match (port.is_empty(), addr.contains(':')) {
+        (true, _) => addr.to_owned(),
+        (false, true) => format!("[{addr}]:{port}"),
+        (false, false) => format!("{addr}:{port}"),
+    }
+}
+
+/// Error label for (ICMPv6, type, code), None for messages that are not errors
+fn error_name(v6: bool, icmp_type: &str, code: &str) -> Option<String> {
+    let name = match (v6, icmp_type, code) {
+        (false, "3", "0") | (true, "1", "0") => "net unreachable",
+        (false, "3", "1") | (true, "1", "3") => "host unreachable",
+        (false, "3", "2") => "protocol unreachable",
+        (false, "3", "3") | (true, "1", "4") => "port unreachable",
+        (false, "3", "4") | (true, "2", _) => "fragmentation needed",
+        (false, "3", "9" | "10" | "13") | (true, "1", "1") => "administratively prohibited",
+        (false, "11", "0") | (true, "3", "0") => "TTL exceeded",
+        (false, "11", "1") | (true, "3", "1") => "reassembly time exceeded",
+        (false, "3", code) | (true, "1", code) => return Some(format!("unreachable code {code}")),
+        _ => return None,
+    };
+    Some(name.to_owned())
+}
+
+impl Analyzer {
+    fn on_echo_result(
+        &mut self,
+        ts: DateTime<Utc>,
+        requester: String,
+        target: String,
+        request_ts: DateTime<Utc>,
+        rtt_ms: Option<f64>,
+    ) {
+        let stats = self.targets.entry(target.clone()).or_default();
+        let window = stats
+            .windows
+            .entry(request_ts.timestamp() / WINDOW_SECONDS * WINDOW_SECONDS)
+            .or_default();
+        stats.sent += 1;
+        window.sent += 1;
+        let Some(rtt_ms) = rtt_ms else {
+            stats.lost_streak += 1;
+            // only the start of an outage is an event, recovery closes it
+            if stats.lost_streak == 1 {
+                self.formatter.event(&Event {
+                    ts,
+                    protocol: PROTOCOL,
+                    kind: EventKind::NoResponse,
+                    user: requester,
+                    method: "ECHO".into(),
+                    addr: Some(target),
+                    elapsed_s: Some((ts - request_ts).num_seconds()),
+                    detail: Some("echo reply lost".into()),
+                    ..Default::default()
+                });
+            }
+            return;
+        };
+        stats.received += 1;
+        stats.rtt.add(rtt_ms);
+        window.received += 1;
+        window.rtt_sum_ms += rtt_ms;
+        if stats.lost_streak > 0 {
+            self.formatter.event(&Event {
+                ts,
+                protocol: PROTOCOL,
+                kind: EventKind::Warning,
+                user: requester,
+                method: "ECHO".into(),
+                addr: Some(target),
+                latency_ms: Some(rtt_ms),
+                detail: Some(format!("reachable again after {} lost", stats.lost_streak)),
+                ..Default::default()
+            });
+            stats.lost_streak = 0;
+        }
+    }
+
+    fn on_error(&mut self, ts: DateTime<Utc>, reporter: &str, label: String, cols: &[&str]) {
+        let (src, dst, proto) = if cols[10].is_empty() {
+            (inner(cols[13]), inner(cols[14]), inner(cols[15]))
+        } else {
+            (inner(cols[10]), inner(cols[11]), inner(cols[12]))
+        };
+        let (sport, dport) = if cols[16].is_empty() {
+            (cols[18], cols[19])
+        } else {
+            (cols[16], cols[17])
+        };
+        let flow = format!(
+            "{} {} -> {}",
+            proto_name(proto),
+            endpoint(src, sport),
+            endpoint(dst, dport)
+        );
+        let count = self
+            .errors
+            .entry((label.clone(), reporter.to_owned(), flow.clone()))
+            .or_default();
+        *count += 1;
+        // repeated errors for the same flow are only counted
+        if *count == 1 {
+            self.formatter.event(&Event {
+                ts,
+                protocol: PROTOCOL,
+                kind: EventKind::ErrorResponse,
+                user: src.to_owned(),
+                method: proto_name(proto).to_owned(),
+                addr: Some(reporter.to_owned()),
+                detail: Some(format!("{label} {flow}")),
+                ..Default::default()
+            });
+        }
+    }
+
+    fn expire_pending(&mut self, opt_ts: Option<DateTime<Utc>>) {
+        let timeout = self.timeout;
+        let mut expired = self
+            .pending
+            .extract_if(|_, req_ts| opt_ts.is_none_or(|ts| (ts - *req_ts).num_seconds() >= timeout))
+            .collect::<Vec<_>>();
+        expired.sort_by_key(|(_, req_ts)| *req_ts);
+        for ((requester, target, _, _), req_ts) in expired {
+            self.on_echo_result(opt_ts.unwrap_or(req_ts), requester, target, req_ts, None);
+        }
+    }
+
+    fn print_stats(&mut self, opt_ts: Option<DateTime<Utc>>) {
+        let mut output = String::with_capacity(200);
+        report::print_header(self.formatter.as_mut(), opt_ts);
+        report::print_section(self.formatter.as_mut(), "Targets");
+        let mut targets = Vec::from_iter(self.targets.keys());
+        targets.sort();
+        for target in targets {
+            let stats = &self.targets[target];
+            write!(
+                output,
+                "{target:<26} {:6} sent {:6} received {:6.2} % loss\n{:<26} {}",
+                stats.sent,
+                stats.received,
+                (stats.sent - stats.received) as f64 * 100.0 / stats.sent.max(1) as f64,
+                "",
+                stats.rtt.summary()
+            )
+            .unwrap();
+            for (start, window) in stats.windows.iter().filter(|(_, w)| w.received < w.sent) {
+                write!(
+                    output,
+                    "\n\t{} {:4} sent {:4} lost {:6.2} % avg rtt {:8.1} ms",
+                    DateTime::from_timestamp(*start, 0)
+                        .unwrap_or_default()
+                        .with_timezone(&Local)
+                        .format(TIME_FMT),
+                    window.sent,
+                    window.sent - window.received,
+                    (window.sent - window.received) as f64 * 100.0 / window.sent as f64,
+                    window.rtt_sum_ms / window.received.max(1) as f64,
+                )
+                .unwrap();
+            }
+            self.formatter.report(&output, 1);
+            output.clear();
+        }
+
+        report::print_section(self.formatter.as_mut(), "Unreachable And TTL Exceeded");
+        for ((label, reporter, flow), count) in report::top_n(&self.errors, self.top) {
+            self.formatter.report(
+                &format!("{count:8} {label:<28} from {reporter:<26} {flow}"),
+                1,
+            );
+        }
+        write!(
+            output,
+            r#"
+ ------------ STATS ------------
+
+- total echo requests: {}
+- total echo replies: {}
+- pending echo requests: {}
+- unmatched echo replies: {}
+- total error messages: {}
+"#,
+            self.targets.values().map(|s| s.sent).sum::<u32>(),
+            self.targets.values().map(|s| s.received).sum::<u32>(),
+            self.pending.len(),
+            self.unmatched_replies,
+            self.errors.values().sum::<u32>(),
+        )
+        .unwrap();
+        self.formatter.report(&output, 0);
+        report::print_footer(self.formatter.as_mut());
+    }
+}
+
+impl ProtocolAnalyzer for Analyzer {
+    fn new(cmd_args: &ArgsCommand, verbosity: u8) -> Self {
+        let (timeout, top) = match cmd_args {
+            ArgsCommand::Analyzer { timeout, top, .. } => (*timeout as i64, *top),
+            _ => (5, 10),
+        };
+        Self {
+            pending: HashMap::default(),
+            targets: HashMap::default(),
+            errors: HashMap::default(),
+            unmatched_replies: 0,
+            timeout,
+            top,
+            last_timeout_check: None,
+            formatter: create_formatter(cmd_args, verbosity),
+            daily_report: DailyReport::default(),
+        }
+    }
+
+    fn analyze(&mut self, ts: DateTime<Utc>, cols: Vec<&str>) {
+        let src_addr = cols[0];
+        let dst_addr = cols[1];
+        let v6 = cols[2].is_empty();
+        let (icmp_type, code, id, seq) = if v6 {
+            (
+                first(cols[6]),
+                first(cols[7]),
+                first(cols[8]),
+                first(cols[9]),
+            )
+        } else {
+            (
+                first(cols[2]),
+                first(cols[3]),
+                first(cols[4]),
+                first(cols[5]),
+            )
+        };
+        if self
+            .last_timeout_check
+            .is_none_or(|last| (ts - last).num_seconds() >= 1)
+        {
+            self.expire_pending(Some(ts));
+            self.last_timeout_check = Some(ts);
+        }
+        if self.daily_report.is_due(ts) {
+            self.print_stats(Some(ts));
+        }
+
+        match (v6, icmp_type) {
+            (false, "8") | (true, "128") => {
+                self.pending
+                    .entry((
+                        src_addr.to_owned(),
+                        dst_addr.to_owned(),
+                        id.to_owned(),
+                        seq.to_owned(),
+                    ))
+                    .or_insert(ts);
+            }
+            (false, "0") | (true, "129") => {
+                let key = (
+                    dst_addr.to_owned(),
+                    src_addr.to_owned(),
+                    id.to_owned(),
+                    seq.to_owned(),
+                );
+                let Some(req_ts) = self.pending.remove(&key) else {
+                    self.unmatched_replies += 1;
+                    return;
+                };
+                let rtt_ms = (ts - req_ts).num_microseconds().unwrap_or_default() as f64 / 1000.0;
+                let (requester, target, _, _) = key;
+                self.on_echo_result(ts, requester, target, req_ts, Some(rtt_ms));
+            }
+            _ => {
+                if let Some(label) = error_name(v6, icmp_type, code) {
+                    self.on_error(ts, src_addr, label, &cols);
+                }
+            }
+        }
+    }
+
+    fn add_protocol_fields(&self, tshark_args: &mut Vec<&str>) {
+        tshark_args.push("-e");
+        tshark_args.push("icmp.type");
+        tshark_args.push("-e");
+        tshark_args.push("icmp.code");
+        tshark_args.push("-e");
+        tshark_args.push("icmp.ident");
+        tshark_args.push("-e");
+        tshark_args.push("icmp.seq");
+        tshark_args.push("-e");
+        tshark_args.push("icmpv6.type");
+        tshark_args.push("-e");
+        tshark_args.push("icmpv6.code");
+        tshark_args.push("-e");
+        tshark_args.push("icmpv6.echo.identifier");
+        tshark_args.push("-e");
+        tshark_args.push("icmpv6.echo.sequence_number");
+        tshark_args.push("-e");
+        tshark_args.push("ip.src");
+        tshark_args.push("-e");
+        tshark_args.push("ip.dst");
+        tshark_args.push("-e");
+        tshark_args.push("ip.proto");
+        tshark_args.push("-e");
+        tshark_args.push("ipv6.src");
+        tshark_args.push("-e");
+        tshark_args.push("ipv6.dst");
+        tshark_args.push("-e");
+        tshark_args.push("ipv6.nxt");
+        tshark_args.push("-e");
+        tshark_args.push("udp.srcport");
+        tshark_args.push("-e");
+        tshark_args.push("udp.dstport");
+        tshark_args.push("-e");
+        tshark_args.push("tcp.srcport");
+        tshark_args.push("-e");
+        tshark_args.push("tcp.dstport");
+        if !tshark_args.contains(&"-Y") {
+            tshark_args.push("-Y");
+            tshark_args
+                .push("icmp or (icmpv6.type <= 3 or icmpv6.type == 128 or icmpv6.type == 129)");
+        }
+        if tshark_args.contains(&"-i") && !tshark_args.contains(&"-f") {
+            tshark_args.push("-f");
+            tshark_args.push("icmp or icmp6");
+        }
+    }
+
+    fn end(&mut self) {
+        self.expire_pending(None);
+        self.print_stats(None);
+    }
+}
+
+// MARK: TESTS
+#[cfg(test)]
+mod test {
+    use super::error_name;
+
+    #[test]
+    fn error_names_v4_and_v6() {
+        assert_eq!(
+            error_name(false, "3", "3").as_deref(),
+            Some("port unreachable")
+        );
+        assert_eq!(
+            error_name(true, "1", "4").as_deref(),
+            Some("port unreachable")
+        );
+        assert_eq!(
+            error_name(false, "11", "0").as_deref(),
+            Some("TTL exceeded")
+        );
+        assert_eq!(error_name(true, "3", "0").as_deref(), Some("TTL exceeded"));
+        assert_eq!(
+            error_name(false, "3", "7").as_deref(),
+            Some("unreachable code 7")
+        );
+        assert_eq!(error_name(false, "8", "0"), None);
+        assert_eq!(error_name(true, "128", "0"), None);
+    }
+}
@@ -0,0 +1,648 @@
+use super::{
+    Event, EventKind, ProtocolAnalyzer,
+    report::{self, DailyReport, LatencyStats},
+};
+use crate::{
+    ArgsCommand,
+    formatters::{EventFormatter, create_formatter},
+};
+use ahash::{HashMap, HashSet};
+use chrono::{DateTime, Utc};
+use std::{fmt::Write as _, vec::IntoIter};
+
+/// Searches slower than this are reported as they complete
+const SLOW_SEARCH_MS: f64 = 1000.0;
+
+struct PendingRequest {
+    ts: DateTime<Utc>,
+    op: &'static str,
+    /// Bind DN or search base
+    dn: String,
+    filter: String,
+    fingerprint: String,
+    entries: u32,
+}
+
+#[derive(Default)]
+struct OpStats {
+    requests: u32,
+    successes: u32,
+    failures: u32,
+    timeouts: u32,
+    latency: LatencyStats,
+}
+
+#[derive(Default)]
+struct BindStats {
+    successes: u32,
+    failures: HashMap<&'static str, u32>,
+}
+
+impl BindStats {
+    fn failure_count(&self) -> u32 {
+        self.failures.values().sum()
+    }
+
+    fn summary(&self, key: &str) -> String {
+        let mut output = format!(
+            "{key:<50} {:6} ok {:6} failed",
+            self.successes,
+            self.failure_count()
+        );
+        for (name, count) in report::top_n(&self.failures, usize::MAX) {
+            write!(output, " {name} {count}").unwrap();
+        }
+        output
+    }
+}
+
+#[derive(Default)]
+struct FilterStats {
+    entries: u64,
+    latency: LatencyStats,
+}
+
+struct SlowSearch {
+    latency_ms: f64,
+    ts: DateTime<Utc>,
+    client: String,
+    base: String,
+    filter: String,
+    entries: u32,
+}
+
+pub struct Analyzer {
+    /// (client, server, message id)
+    pending: HashMap<(String, String, String), PendingRequest>,
+    ops: HashMap<&'static str, OpStats>,
+    binds_by_dn: HashMap<String, BindStats>,
+    binds_by_client: HashMap<String, BindStats>,
+    result_codes: HashMap<&'static str, u32>,
+    /// Search latency per filter with the assertion values masked
+    filters: HashMap<String, FilterStats>,
+    slowest: Vec<SlowSearch>,
+    /// (client, DN) already reported for a cleartext simple bind
+    cleartext_binds: HashSet<(String, String)>,
+    unmatched_responses: u32,
+    timeout: i64,
+    top: usize,
+    last_timeout_check: Option<DateTime<Utc>>,
+    formatter: Box<dyn EventFormatter>,
+    daily_report: DailyReport,
+}
+
+const PROTOCOL: &str = "ldap";
+
+/// Request name of a protocolOp choice, None for responses and fire-and-forget requests
+fn request_name(op: &str) -> Option<&'static str> {
+    match op {
+        "0" => Some("bind"),
+        "3" => Some("search"),
+        "6" => Some("modify"),
+        "8" => Some("add"),
+        "10" => Some("delete"),
+        "12" => Some("modDN"),
+        "14" => Some("compare"),
+        "23" => Some("extended"),
+        _ => None,
+    }
+}
+
+fn scope_name(scope: &str) -> &'static str {
+    match scope {
+        "0" => "base",
+        "1" => "one",
+        "2" => "sub",
+        _ => "-",
+    }
+}
+
+/// Responses carrying an LDAPResult
+fn is_result(op: &str) -> bool {
+    matches!(op, "1" | "5" | "7" | "9" | "11" | "13" | "15" | "24")
+}
+
+fn result_name(code: &str) -> &'static str {
+    match code {
+        "0" => "success",
+        "1" => "operationsError",
+        "2" => "protocolError",
+        "3" => "timeLimitExceeded",
+        "4" => "sizeLimitExceeded",
+        "5" => "compareFalse",
+        "6" => "compareTrue",
+        "7" => "authMethodNotSupported",
+        "8" => "strongerAuthRequired",
+        "10" => "referral",
+        "11" => "adminLimitExceeded",
+        "13" => "confidentialityRequired",
+        "14" => "saslBindInProgress",
+        "16" => "noSuchAttribute",
+        "17" => "undefinedAttributeType",
+        "19" => "constraintViolation",
+        "20" => "attributeOrValueExists",
+        "21" => "invalidAttributeSyntax",
+        "32" => "noSuchObject",
+        "34" => "invalidDNSyntax",
+        "48" => "inappropriateAuthentication",
+        "49" => "invalidCredentials",
+        "50" => "insufficientAccessRights",
+        "51" => "busy",
+        "52" => "unavailable",
+        "53" => "unwillingToPerform",
+        "64" => "namingViolation",
+        "65" => "objectClassViolation",
+        "68" => "entryAlreadyExists",
+        "80" => "other",
+        _ => "unknown",
+    }
+}
+
+/// Codes that complete an operation normally, size limits still return entries
+fn is_success(code: &str) -> bool {
+    matches!(code, "0" | "4" | "5" | "6" | "10" | "14")
+}
+
+/// Occurrences of a field, DNs and diagnostic messages contain commas so they are only split
+/// when the segment carries several messages using the field
+fn occurrences(value: &str, count: usize) -> IntoIter<&str> {
+    if count > 1 {
+        value.split(',').collect::<Vec<_>>().into_iter()
+    } else {
+        vec![value].into_iter()
+    }
+}
+
+fn next<'a>(values: &mut IntoIter<&'a str>) -> &'a str {
+    values.next().unwrap_or_default()
+}
+
+/// Filter terms of a search as (display, fingerprint), boolean operators are not exported by tshark.
+/// Assertion values such as DNs may contain commas, values are only shown when they pair up with
+/// the attributes
+fn filter_terms(cols: &[&str]) -> (String, String) {
+    let mut display = Vec::new();
+    let mut fingerprint = Vec::new();
+    let split = |value: &'_ str| {
+        value
+            .split(',')
+            .filter(|v| !v.is_empty())
+            .map(str::to_owned)
+            .collect::<Vec<_>>()
+    };
+    let (attrs, values) = (split(cols[10]), split(cols[11]));
+    if attrs.len() == values.len() {
+        for (attr, value) in attrs.into_iter().zip(values) {
+            display.push(format!("({attr}={value})"));
+            fingerprint.push(format!("({attr}=?)"));
+        }
+    } else {
+        for attr in attrs {
+            display.push(format!("({attr}=?)"));
+            fingerprint.push(format!("({attr}=?)"));
+        }
+    }
+    for attr in split(cols[13]) {
+        display.push(format!("({attr}=*?*)"));
+        fingerprint.push(format!("({attr}=*?*)"));
+    }
+    for attr in split(cols[12]) {
+        display.push(format!("({attr}=*)"));
+        fingerprint.push(format!("({attr}=*)"));
+    }
+    (display.concat(), fingerprint.concat())
+}
+
+fn latency_ms(ts: DateTime<Utc>, request: &PendingRequest) -> f64 {
+    (ts - request.ts).num_microseconds().unwrap_or_default() as f64 / 1000.0
+}
+
+impl Analyzer {
+    fn expire_pending(&mut self, opt_ts: Option<DateTime<Utc>>) {
+        let timeout = self.timeout;
+        let expired = self
+            .pending
+            .extract_if(|_, r| opt_ts.is_none_or(|ts| (ts - r.ts).num_seconds() >= timeout))
+            .collect::<Vec<_>>();
+        for ((client, server, _), request) in expired {
+            self.ops.entry(request.op).or_default().timeouts += 1;
+            self.formatter.event(&Event {
+                ts: opt_ts.unwrap_or(request.ts),
+                protocol: PROTOCOL,
+                kind: EventKind::NoResponse,
+                user: client,
+                method: request.op.into(),
+                addr: Some(server),
+                elapsed_s: opt_ts.map(|ts| (ts - request.ts).num_seconds()),
+                detail: Some(format!("{} {}", request.dn, request.filter)),
+                ..Default::default()
+            });
+        }
+    }
+
+    fn add_slow_search(&mut self, search: SlowSearch) {
+        let pos = self
+            .slowest
+            .partition_point(|s| s.latency_ms >= search.latency_ms);
+        if pos < self.top {
+            self.slowest.insert(pos, search);
+            self.slowest.truncate(self.top);
+        }
+    }
+
+    fn on_result(
+        &mut self,
+        ts: DateTime<Utc>,
+        client: &str,
+        server: &str,
+        request: PendingRequest,
+        code: &str,
+        message: &str,
+    ) {
+        let latency = latency_ms(ts, &request);
+        let success = is_success(code);
+        let name = result_name(code);
+        let stats = self.ops.entry(request.op).or_default();
+        stats.latency.add(latency);
+        if success {
+            stats.successes += 1;
+        } else {
+            stats.failures += 1;
+            *self.result_codes.entry(name).or_default() += 1;
+        }
+        let client_host = client.rsplit_once(':').map_or(client, |(h, _)| h);
+
+        // SASL exchanges answer saslBindInProgress until the last step
+        if request.op == "bind" && code != "14" {
+            for stats in [
+                self.binds_by_dn.entry(request.dn.clone()).or_default(),
+                self.binds_by_client
+                    .entry(client_host.to_owned())
+                    .or_default(),
+            ] {
+                if success {
+                    stats.successes += 1;
+                } else {
+                    *stats.failures.entry(name).or_default() += 1;
+                }
+            }
+        }
+        if request.op == "search" {
+            let stats = self.filters.entry(request.fingerprint.clone()).or_default();
+            stats.entries += request.entries as u64;
+            stats.latency.add(latency);
+            if latency > SLOW_SEARCH_MS {
+                self.formatter.event(&Event {
+                    ts,
+                    protocol: PROTOCOL,
+                    kind: EventKind::Warning,
+                    user: client.to_owned(),
+                    method: request.op.into(),
+                    addr: Some(server.to_owned()),
+                    latency_ms: Some(latency),
+                    detail: Some(format!(
+                        "slow search base \"{}\" {} returned {} entries",
+                        request.dn, request.filter, request.entries
+                    )),
+                    ..Default::default()
+                });
+            }
+            self.add_slow_search(SlowSearch {
+                latency_ms: latency,
+                ts,
+                client: client_host.to_owned(),
+                base: request.dn.clone(),
+                filter: request.filter.clone(),
+                entries: request.entries,
+            });
+        }
+        if success {
+            return;
+        }
+        let mut detail = format!("{name} \"{}\"", request.dn);
+        if !message.is_empty() {
+            write!(detail, " {message}").unwrap();
+        }
+        self.formatter.event(&Event {
+            ts,
+            protocol: PROTOCOL,
+            kind: EventKind::ErrorResponse,
+            user: client.to_owned(),
+            method: request.op.into(),
+            addr: Some(server.to_owned()),
+            status: code.parse().ok(),
+            latency_ms: Some(latency),
+            detail: Some(detail),
+            ..Default::default()
+        });
+    }
+
+    fn print_stats(&mut self, opt_ts: Option<DateTime<Utc>>) {
+        let mut output = String::with_capacity(200);
+        report::print_header(self.formatter.as_mut(), opt_ts);
+        report::print_section(self.formatter.as_mut(), "Operations");
+        let mut ops = Vec::from_iter(self.ops.keys().copied());
+        ops.sort();
+        for op in ops {
+            let stats = &self.ops[op];
+            self.formatter.report(
+                &format!(
+                    "{op:<10} {:7} requests {:7} ok {:5} failed {:5} timeouts  {}",
+                    stats.requests,
+                    stats.successes,
+                    stats.failures,
+                    stats.timeouts,
+                    stats.latency.summary()
+                ),
+                1,
+            );
+        }
+        report::print_section(self.formatter.as_mut(), "Result Codes");
+        for (name, count) in report::top_n(&self.result_codes, self.top) {
+            self.formatter.report(&format!("{count:8} {name}"), 1);
+        }
+
+        for (title, binds) in [
+            ("Bind Failures By DN", &self.binds_by_dn),
+            ("Bind Failures By Client", &self.binds_by_client),
+        ] {
+            report::print_section(self.formatter.as_mut(), title);
+            let mut failed = binds
+                .iter()
+                .filter(|(_, b)| b.failure_count() > 0)
+                .collect::<Vec<_>>();
+            failed.sort_by(|a, b| {
+                b.1.failure_count()
+                    .cmp(&a.1.failure_count())
+                    .then(a.0.cmp(b.0))
+            });
+            for (key, stats) in failed.into_iter().take(self.top) {
+                self.formatter.report(&stats.summary(key), 1);
+            }
+        }
+
+        report::print_section(self.formatter.as_mut(), "Slowest Filters");
+        let mut filters = Vec::from_iter(&self.filters);
+        filters.sort_by(|a, b| {
+            b.1.latency
+                .percentile(95.0)
+                .total_cmp(&a.1.latency.percentile(95.0))
+                .then(a.0.cmp(b.0))
+        });
+        for (filter, stats) in filters.into_iter().take(self.top) {
+            self.formatter.report(
+                &format!(
+                    "{filter}\n    {:7} entries  {}",
+                    stats.entries,
+                    stats.latency.summary()
+                ),
+                1,
+            );
+        }
+        report::print_section(self.formatter.as_mut(), "Slowest Searches");
+        for search in &self.slowest {
+            write!(
+                output,
+                "{:10.1} ms {} {:<16} base \"{}\" {} ({} entries)",
+                search.latency_ms,
+                search.ts.format("%Y-%m-%d %H:%M:%S"),
+                search.client,
+                search.base,
+                search.filter,
+                search.entries
+            )
+            .unwrap();
+            self.formatter.report(&output, 1);
+            output.clear();
+        }
+        write!(
+            output,
+            r#"
+ ------------ STATS ------------
+
+- total requests: {}
+- pending requests: {}
+- unmatched responses: {}
+- cleartext simple binds: {}
+"#,
+            self.ops.values().map(|o| o.requests).sum::<u32>(),
+            self.pending.len(),
+            self.unmatched_responses,
+            self.cleartext_binds.len(),
+        )
+        .unwrap();
+        self.formatter.report(&output, 0);
+        report::print_footer(self.formatter.as_mut());
+    }
+}
+
+impl ProtocolAnalyzer for Analyzer {
+    fn new(cmd_args: &ArgsCommand, verbosity: u8) -> Self {
+        let (timeout, top) = match cmd_args {
+            ArgsCommand::Analyzer { timeout, top, .. } => (*timeout as i64, *top),
+            _ => (5, 10),
+        };
+        Self {
+            pending: HashMap::default(),
+            ops: HashMap::default(),
+            binds_by_dn: HashMap::default(),
+            binds_by_client: HashMap::default(),
+            result_codes: HashMap::default(),
+            filters: HashMap::default(),
+            slowest: Vec::new(),
+            cleartext_binds: HashSet::default(),
+            unmatched_responses: 0,
+            timeout,
+            top,
+            last_timeout_check: None,
+            formatter: create_formatter(cmd_args, verbosity),
+            daily_report: DailyReport::default(),
+        }
+    }
+
+    fn analyze(&mut self, ts: DateTime<Utc>, cols: Vec<&str>) {
+        if self
+            .last_timeout_check
+            .is_none_or(|last| (ts - last).num_seconds() >= 1)
+        {
+            self.expire_pending(Some(ts));
+            self.last_timeout_check = Some(ts);
+        }
+        if self.daily_report.is_due(ts) {
+            self.print_stats(Some(ts));
+        }
+        let src = format!("{}:{}", cols[0], cols[15]);
+        let dst = format!("{}:{}", cols[1], cols[16]);
+        let encrypted = !cols[17].is_empty();
+        let count = |f: fn(&str) -> bool| cols[3].split(',').filter(|op| f(op)).count();
+        let binds = count(|op| op == "0");
+        let searches = count(|op| op == "3");
+        let results = count(is_result);
+
+        // one TCP segment may carry several LDAP messages
+        let mut result_codes = occurrences(cols[4], results);
+        let mut messages = occurrences(cols[14], results);
+        let mut bind_names = occurrences(cols[5], binds);
+        let mut authentications = occurrences(cols[6], binds);
+        let mut mechanisms = occurrences(cols[7], binds);
+        let mut bases = occurrences(cols[8], searches);
+        let mut scopes = occurrences(cols[9], searches);
+        for (id, op) in cols[2].split(',').zip(cols[3].split(',')) {
+            if let Some(name) = request_name(op) {
+                let mut request = PendingRequest {
+                    ts,
+                    op: name,
+                    dn: String::new(),
+                    filter: String::new(),
+                    fingerprint: String::new(),
+                    entries: 0,
+                };
+                match name {
+                    "bind" => {
+                        request.dn = next(&mut bind_names).to_owned();
+                        let authentication = next(&mut authentications);
+                        let mechanism = next(&mut mechanisms);
+                        if authentication != "0" {
+                            request.filter = format!("SASL {mechanism}");
+                        } else if !encrypted
+                            && !request.dn.is_empty()
+                            && self
+                                .cleartext_binds
+                                .insert((cols[0].to_owned(), request.dn.clone()))
+                        {
+                            self.formatter.event(&Event {
+                                ts,
+                                protocol: PROTOCOL,
+                                kind: EventKind::Alert,
+                                user: src.clone(),
+                                method: name.into(),
+                                addr: Some(dst.clone()),
+                                detail: Some(format!("unencrypted simple bind \"{}\"", request.dn)),
+                                ..Default::default()
+                            });
+                        }
+                    }
+                    "search" => {
+                        request.dn = next(&mut bases).to_owned();
+                        // filter fields cannot be split between several searches
+                        let (filter, fingerprint) = if searches == 1 {
+                            filter_terms(&cols)
+                        } else {
+                            Default::default()
+                        };
+                        let scope = scope_name(next(&mut scopes));
+                        request.filter = format!("{scope} {filter}");
+                        request.fingerprint = format!("{scope} {fingerprint}");
+                    }
+                    _ => {}
+                }
+                self.ops.entry(name).or_default().requests += 1;
+                self.pending
+                    .insert((src.clone(), dst.clone(), id.to_owned()), request);
+            } else if op == "4" {
+                if let Some(request) =
+                    self.pending
+                        .get_mut(&(dst.clone(), src.clone(), id.to_owned()))
+                {
+                    request.entries += 1;
+                }
+            } else if is_result(op) {
+                let code = next(&mut result_codes);
+                let message = next(&mut messages);
+                let Some(request) = self
+                    .pending
+                    .remove(&(dst.clone(), src.clone(), id.to_owned()))
+                else {
+                    self.unmatched_responses += 1;
+                    continue;
+                };
+                self.on_result(ts, &dst, &src, request, code, message);
+            }
+        }
+    }
+
+    fn add_protocol_fields(&self, tshark_args: &mut Vec<&str>) {
+        tshark_args.push("-e");
+        tshark_args.push("ldap.messageID");
+        tshark_args.push("-e");
+        tshark_args.push("ldap.protocolOp");
+        tshark_args.push("-e");
+        tshark_args.push("ldap.resultCode");
+        tshark_args.push("-e");
+        tshark_args.push("ldap.name");
+        tshark_args.push("-e");
+        tshark_args.push("ldap.authentication");
+        tshark_args.push("-e");
+        tshark_args.push("ldap.mechanism");
+        tshark_args.push("-e");
+        tshark_args.push("ldap.baseObject");
+        tshark_args.push("-e");
+        tshark_args.push("ldap.scope");
+        tshark_args.push("-e");
+        tshark_args.push("ldap.attributeDesc");
+        tshark_args.push("-e");
+        tshark_args.push("ldap.assertionValue");
+        tshark_args.push("-e");
+        tshark_args.push("ldap.present");
+        tshark_args.push("-e");
+        tshark_args.push("ldap.type");
+        tshark_args.push("-e");
+        tshark_args.push("ldap.errorMessage");
+        tshark_args.push("-e");
+        tshark_args.push("tcp.srcport");
+        tshark_args.push("-e");
+        tshark_args.push("tcp.dstport");
+        tshark_args.push("-e");
+        tshark_args.push("tls.record.content_type");
+        if !tshark_args.contains(&"-Y") {
+            tshark_args.push("-Y");
+            tshark_args.push("ldap");
+        }
+        if tshark_args.contains(&"-i") && !tshark_args.contains(&"-f") {
+            tshark_args.push("-f");
+            tshark_args.push("tcp port 389 or tcp port 3268");
+        }
+    }
+
+    fn end(&mut self) {
+        self.expire_pending(None);
+        self.print_stats(None);
+    }
+}
+
+// MARK: TESTS
+#[cfg(test)]
+mod test {
+    use super::{filter_terms, occurrences, scope_name};
+
+    #[test]
+    fn search_fields() {
+        let dn = "ou=people,dc=corp,dc=com";
+        assert_eq!(occurrences(dn, 1).collect::<Vec<_>>(), vec![dn]);
+        assert_eq!(occurrences("0,32", 2).collect::<Vec<_>>(), vec!["0", "32"]);
+
+        let mut cols = vec![""; 18];
+        cols[10] = "telephoneNumber,mail";
+        cols[11] = "1234,a@corp.com";
+        cols[12] = "objectClass";
+        cols[13] = "cn";
+        assert_eq!(
+            filter_terms(&cols),
+            (
+                "(telephoneNumber=1234)(mail=a@corp.com)(cn=*?*)(objectClass=*)".to_owned(),
+                "(telephoneNumber=?)(mail=?)(cn=*?*)(objectClass=*)".to_owned()
+            )
+        );
+        // a DN value splits into several, only the attribute is kept
+        cols[10] = "member";
+        cols[11] = "cn=admins,ou=groups,dc=corp,dc=com";
+        assert_eq!(
+            filter_terms(&cols),
+            (
+                "(member=?)(cn=*?*)(objectClass=*)".to_owned(),
+                "(member=?)(cn=*?*)(objectClass=*)".to_owned()
+            )
+        );
+        assert_eq!(scope_name("2"), "sub");
+    }
+}
@@ -2,14 +2,73 @@ use chrono::{DateTime, Utc};
 
 use crate::{Args, ArgsCommand};
 
+mod arp;
+mod bgp;
+mod dhcp;
+mod diameter;
+mod dns;
+mod event;
+mod fields;
+mod flows;
+mod framed;
+mod http;
+mod icmp;
+mod ldap;
+mod modbus;
+mod mqtt;
+mod mysql;
+mod ntp;
+mod pgsql;
+mod radius;
+mod redis;
+mod report;
+mod rtcp;
+mod sdp;
 mod sip;
+mod smb;
+mod snmp;
+mod sql;
+mod stun;
+mod syslog;
+mod tcp;
+mod tls;
 
-pub fn create_analyzer(args: &Args) -> Option<impl ProtocolAnalyzer + use<>> {
+pub use event::{Direction, ErrorCategory, Event, EventKind, LogRecord, Media, Negotiation};
+
+pub const TIME_FMT: &str = "%Y-%m-%d %H:%M:%S%.3f";
+
+pub fn create_analyzer(args: &Args) -> Option<Box<dyn ProtocolAnalyzer>> {
     match &args.cmd {
-        ArgsCommand::Analyzer => {
+        ArgsCommand::Analyzer { .. } => {
             if let Some(protocol) = args.protocol.as_deref() {
                 match protocol {
-                    "sip" => Some(sip::Analyzer::new(&args.cmd, args.verbosity)),
+                    "sip" => Some(Box::new(sip::Analyzer::new(&args.cmd, args.verbosity))),
+                    "dns" => Some(Box::new(dns::Analyzer::new(&args.cmd, args.verbosity))),
+                    "http" => Some(Box::new(http::Analyzer::new(&args.cmd, args.verbosity))),
+                    "tls" => Some(Box::new(tls::Analyzer::new(&args.cmd, args.verbosity))),
+                    "tcp" => Some(Box::new(tcp::Analyzer::new(&args.cmd, args.verbosity))),
+                    "dhcp" => Some(Box::new(dhcp::Analyzer::new(&args.cmd, args.verbosity))),
+                    "arp" => Some(Box::new(arp::Analyzer::new(&args.cmd, args.verbosity))),
+                    "icmp" => Some(Box::new(icmp::Analyzer::new(&args.cmd, args.verbosity))),
+                    "modbus" => Some(Box::new(modbus::Analyzer::new(&args.cmd, args.verbosity))),
+                    "mqtt" => Some(Box::new(mqtt::Analyzer::new(&args.cmd, args.verbosity))),
+                    "rtcp" => Some(Box::new(rtcp::Analyzer::new(&args.cmd, args.verbosity))),
+                    "radius" => Some(Box::new(radius::Analyzer::new(&args.cmd, args.verbosity))),
+                    "diameter" => {
+                        Some(Box::new(diameter::Analyzer::new(&args.cmd, args.verbosity)))
+                    }
+                    "ntp" => Some(Box::new(ntp::Analyzer::new(&args.cmd, args.verbosity))),
+                    "syslog" => Some(Box::new(syslog::Analyzer::new(&args.cmd, args.verbosity))),
+                    "stun" => Some(Box::new(stun::Analyzer::new(&args.cmd, args.verbosity))),
+                    "framed" => Some(Box::new(framed::Analyzer::new(&args.cmd, args.verbosity))),
+                    "flows" => Some(Box::new(flows::Analyzer::new(&args.cmd, args.verbosity))),
+                    "snmp" => Some(Box::new(snmp::Analyzer::new(&args.cmd, args.verbosity))),
+                    "ldap" => Some(Box::new(ldap::Analyzer::new(&args.cmd, args.verbosity))),
+                    "pgsql" => Some(Box::new(pgsql::Analyzer::new(&args.cmd, args.verbosity))),
+                    "mysql" => Some(Box::new(mysql::Analyzer::new(&args.cmd, args.verbosity))),
+                    "redis" => Some(Box::new(redis::Analyzer::new(&args.cmd, args.verbosity))),
+                    "smb" => Some(Box::new(smb::Analyzer::new(&args.cmd, args.verbosity))),
+                    "bgp" => Some(Box::new(bgp::Analyzer::new(&args.cmd, args.verbosity))),
                     _ => None,
                 }
             } else {
@@ -20,12 +79,19 @@ pub fn create_analyzer(args: &Args) -> Option<impl ProtocolAnalyzer + use<>> {
     }
 }
 
-pub trait ProtocolAnalyzer
-where
-    Self: Sized,
-{
+pub trait ProtocolAnalyzer {
     fn add_protocol_fields(&self, tshark_args: &mut Vec<&str>);
-    fn new(cmd_args: &ArgsCommand, verbosity: u8) -> Self;
+    fn new(cmd_args: &ArgsCommand, verbosity: u8) -> Self
+    where
+        Self: Sized;
     fn analyze(&mut self, ts: DateTime<Utc>, cols: Vec<&str>);
     fn end(&mut self);
 }
+
+/// Analyzer subcommand arguments as parsed from a command line
+#[cfg(test)]
+pub fn test_args(args: &[&str]) -> ArgsCommand {
+    use clap::Parser as _;
+    let cmd_line = ["tshark_wrapper", "analyzer"].iter().chain(args);
+    Args::parse_from(cmd_line).cmd
+}
@@ -0,0 +1,520 @@
+use super::{
+    Event, EventKind, ProtocolAnalyzer, TIME_FMT,
+    report::{self, DailyReport, LatencyStats},
+};
+use crate::{
+    ArgsCommand,
+    formatters::{EventFormatter, create_formatter},
+};
+use ahash::HashMap;
+use chrono::{DateTime, Local, Utc};
+use std::{
+    fmt::Write as _,
+    fs::File,
+    io::{BufWriter, Write as _},
+};
+
+const MODBUS_PORT: &str = "502";
+/// Polls are only judged once the interval has settled
+const MIN_POLL_SAMPLES: u32 = 5;
+/// Interval further than this fraction from the average is a deviation
+const POLL_DEVIATION: f64 = 0.5;
+
+struct PendingRequest {
+    ts: DateTime<Utc>,
+    func: u8,
+    reference: Option<u32>,
+}
+
+#[derive(Default)]
+struct SlaveStats {
+    requests: u32,
+    responses: u32,
+    timeouts: u32,
+    exceptions: HashMap<&'static str, u32>,
+    latency: LatencyStats,
+}
+
+#[derive(Default)]
+struct Poll {
+    last_ts: Option<DateTime<Utc>>,
+    samples: u32,
+    interval_sum_ms: f64,
+    min_ms: f64,
+    max_ms: f64,
+    deviations: u32,
+}
+
+struct Register {
+    first: u16,
+    last: u16,
+    min: u16,
+    max: u16,
+    changes: u32,
+    last_seen: DateTime<Utc>,
+}
+
+pub struct Analyzer {
+    pending: HashMap<(String, String, String, String), PendingRequest>,
+    slaves: HashMap<String, SlaveStats>,
+    polls: HashMap<(String, &'static str, Option<u32>), Poll>,
+    registers: HashMap<(String, u32), Register>,
+    functions: HashMap<&'static str, u32>,
+    csv: Option<BufWriter<File>>,
+    unmatched_responses: u32,
+    timeout: i64,
+    top: usize,
+    last_timeout_check: Option<DateTime<Utc>>,
+    formatter: Box<dyn EventFormatter>,
+    daily_report: DailyReport,
+}
+
+const PROTOCOL: &str = "modbus";
+
+fn function_name(func: u8) -> &'static str {
+    match func {
+        1 => "Read Coils",
+        2 => "Read Discrete Inputs",
+        3 => "Read Holding Registers",
+        4 => "Read Input Registers",
+        5 => "Write Single Coil",
+        6 => "Write Single Register",
+        15 => "Write Multiple Coils",
+        16 => "Write Multiple Registers",
+        22 => "Mask Write Register",
+        23 => "Read Write Registers",
+        43 => "Device Identification",
+        _ => "Other",
+    }
+}
+
+fn exception_name(code: &str) -> &'static str {
+    match code {
+        "1" => "Illegal Function",
+        "2" => "Illegal Data Address",
+        "3" => "Illegal Data Value",
+        "4" => "Slave Device Failure",
+        "5" => "Acknowledge",
+        "6" => "Slave Device Busy",
+        "8" => "Memory Parity Error",
+        "10" => "Gateway Path Unavailable",
+        "11" => "Gateway Target Failed",
+        _ => "Other Exception",
+    }
+}
+
+/// Decimal or 0x prefixed hex as printed by tshark
+fn parse_number(value: &str) -> Option<u32> {
+    match value.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+impl Analyzer {
+    fn on_poll(
+        &mut self,
+        ts: DateTime<Utc>,
+        client: &str,
+        slave: &str,
+        func: u8,
+        reference: Option<u32>,
+    ) {
+        let poll = self
+            .polls
+            .entry((slave.to_owned(), function_name(func), reference))
+            .or_default();
+        let Some(last_ts) = poll.last_ts.replace(ts) else {
+            return;
+        };
+        let interval_ms = (ts - last_ts).num_microseconds().unwrap_or_default() as f64 / 1000.0;
+        if poll.samples >= MIN_POLL_SAMPLES {
+            let avg_ms = poll.interval_sum_ms / poll.samples as f64;
+            if (interval_ms - avg_ms).abs() > avg_ms * POLL_DEVIATION {
+                poll.deviations += 1;
+                self.formatter.event(&Event {
+                    ts,
+                    protocol: PROTOCOL,
+                    kind: EventKind::Warning,
+                    user: client.to_owned(),
+                    method: func.to_string(),
+                    addr: Some(slave.to_owned()),
+                    detail: Some(format!(
+                        "{} {} polled after {interval_ms:.0} ms, average {avg_ms:.0} ms",
+                        function_name(func),
+                        reference.map_or_else(|| "-".to_owned(), |r| r.to_string()),
+                    )),
+                    ..Default::default()
+                });
+            }
+        }
+        if poll.samples == 0 || interval_ms < poll.min_ms {
+            poll.min_ms = interval_ms;
+        }
+        poll.max_ms = poll.max_ms.max(interval_ms);
+        poll.samples += 1;
+        poll.interval_sum_ms += interval_ms;
+    }
+
+    fn on_registers(&mut self, ts: DateTime<Utc>, slave: &str, reference: u32, values: &str) {
+        for (offset, value) in values.split(',').enumerate() {
+            let Some(value) = parse_number(value).and_then(|v| u16::try_from(v).ok()) else {
+                continue;
+            };
+            let register = reference + offset as u32;
+            if let Some(csv) = &mut self.csv
+                && let Err(err) = writeln!(csv, "{},{slave},{register},{value}", ts.to_rfc3339())
+            {
+                eprintln!("Failed to write CSV: {err}");
+                self.csv = None;
+            }
+            let track = self
+                .registers
+                .entry((slave.to_owned(), register))
+                .or_insert(Register {
+                    first: value,
+                    last: value,
+                    min: value,
+                    max: value,
+                    changes: 0,
+                    last_seen: ts,
+                });
+            if track.last != value {
+                track.changes += 1;
+            }
+            track.last = value;
+            track.min = track.min.min(value);
+            track.max = track.max.max(value);
+            track.last_seen = ts;
+        }
+    }
+
+    fn expire_pending(&mut self, opt_ts: Option<DateTime<Utc>>) {
+        let timeout = self.timeout;
+        let expired = self
+            .pending
+            .extract_if(|_, r| opt_ts.is_none_or(|ts| (ts - r.ts).num_seconds() >= timeout))
+            .collect::<Vec<_>>();
+        for ((client, server, trans_id, unit), request) in expired {
+            let slave = format!("{server}/{unit}");
+            self.slaves.entry(slave.clone()).or_default().timeouts += 1;
+            self.formatter.event(&Event {
+                ts: opt_ts.unwrap_or(request.ts),
+                protocol: PROTOCOL,
+                kind: EventKind::NoResponse,
+                user: client,
+                method: request.func.to_string(),
+                addr: Some(slave),
+                elapsed_s: opt_ts.map(|ts| (ts - request.ts).num_seconds()),
+                detail: Some(format!(
+                    "{} transaction {trans_id}",
+                    function_name(request.func)
+                )),
+                ..Default::default()
+            });
+        }
+    }
+
+    fn print_stats(&mut self, opt_ts: Option<DateTime<Utc>>) {
+        let mut output = String::with_capacity(200);
+        report::print_header(self.formatter.as_mut(), opt_ts);
+        report::print_section(self.formatter.as_mut(), "Slaves");
+        let mut slaves = Vec::from_iter(self.slaves.keys());
+        slaves.sort();
+        for slave in slaves {
+            let stats = &self.slaves[slave];
+            write!(
+                output,
+                "{slave:<26} {:6} requests {:6} responses {:4} timeouts",
+                stats.requests, stats.responses, stats.timeouts
+            )
+            .unwrap();
+            for (exception, count) in report::top_n(&stats.exceptions, usize::MAX) {
+                write!(output, " {exception} {count}").unwrap();
+            }
+            write!(output, "\n{:<26} {}", "", stats.latency.summary()).unwrap();
+            self.formatter.report(&output, 1);
+            output.clear();
+        }
+
+        report::print_section(self.formatter.as_mut(), "Polling");
+        let mut polls = Vec::from_iter(self.polls.iter().filter(|(_, p)| p.samples > 0));
+        polls.sort_by(|a, b| a.0.cmp(b.0));
+        for ((slave, func, reference), poll) in polls {
+            self.formatter.report(
+                &format!(
+                    "{slave:<26} {func:<24} {:>6} {:6} polls every {:8.0} ms  min {:8.0} ms  max {:8.0} ms  {:4} deviations",
+                    reference.map_or_else(|| "-".to_owned(), |r| r.to_string()),
+                    poll.samples + 1,
+                    poll.interval_sum_ms / poll.samples as f64,
+                    poll.min_ms,
+                    poll.max_ms,
+                    poll.deviations
+                ),
+                1,
+            );
+        }
+
+        report::print_section(self.formatter.as_mut(), "Most Changing Registers");
+        let changes = HashMap::from_iter(self.registers.iter().map(|(k, r)| (k, r.changes)));
+        for ((slave, register), _) in report::top_n(&changes, self.top) {
+            let track = &self.registers[&(slave.clone(), *register)];
+            self.formatter.report(
+                &format!(
+                    "{slave:<26} {register:6} {:6} changes  first {:5} last {:5} min {:5} max {:5}  at {}",
+                    track.changes,
+                    track.first,
+                    track.last,
+                    track.min,
+                    track.max,
+                    track.last_seen.with_timezone(&Local).format(TIME_FMT)
+                ),
+                1,
+            );
+        }
+        report::print_section(self.formatter.as_mut(), "Functions");
+        for (func, count) in report::top_n(&self.functions, usize::MAX) {
+            self.formatter.report(&format!("{count:8} {func}"), 1);
+        }
+        write!(
+            output,
+            r#"
+ ------------ STATS ------------
+
+- total requests: {}
+- total responses: {}
+- total exceptions: {}
+- total timeouts: {}
+- pending requests: {}
+- unmatched responses: {}
+- tracked registers: {}
+"#,
+            self.slaves.values().map(|s| s.requests).sum::<u32>(),
+            self.slaves.values().map(|s| s.responses).sum::<u32>(),
+            self.slaves
+                .values()
+                .flat_map(|s| s.exceptions.values())
+                .sum::<u32>(),
+            self.slaves.values().map(|s| s.timeouts).sum::<u32>(),
+            self.pending.len(),
+            self.unmatched_responses,
+            self.registers.len(),
+        )
+        .unwrap();
+        self.formatter.report(&output, 0);
+        report::print_footer(self.formatter.as_mut());
+    }
+}
+
+impl ProtocolAnalyzer for Analyzer {
+    fn new(cmd_args: &ArgsCommand, verbosity: u8) -> Self {
+        let (timeout, top, csv) = match cmd_args {
+            ArgsCommand::Analyzer {
+                timeout, top, csv, ..
+            } => (*timeout as i64, *top, csv.as_ref()),
+            _ => (5, 10, None),
+        };
+        let csv = csv.and_then(|path| match File::create(path) {
+            Ok(file) => {
+                let mut writer = BufWriter::new(file);
+                writeln!(writer, "timestamp,slave,register,value").ok()?;
+                Some(writer)
+            }
+            Err(err) => {
+                eprintln!("Failed to create {}: {err}", path.display());
+                None
+            }
+        });
+        Self {
+            pending: HashMap::default(),
+            slaves: HashMap::default(),
+            polls: HashMap::default(),
+            registers: HashMap::default(),
+            functions: HashMap::default(),
+            csv,
+            unmatched_responses: 0,
+            timeout,
+            top,
+            last_timeout_check: None,
+            formatter: create_formatter(cmd_args, verbosity),
+            daily_report: DailyReport::default(),
+        }
+    }
+
+    fn analyze(&mut self, ts: DateTime<Utc>, cols: Vec<&str>) {
+        let trans_id = cols[2];
+        let unit = cols[3];
+        let func = parse_number(cols[4]).unwrap_or_default() as u8 & 0x7f;
+        let exception = cols[5];
+        let reference = parse_number(cols[6]);
+        let values = cols[7];
+        let (src_port, dst_port) = (cols[8], cols[9]);
+        let src = format!("{}:{src_port}", cols[0]);
+        let dst = format!("{}:{dst_port}", cols[1]);
+        if self
+            .last_timeout_check
+            .is_none_or(|last| (ts - last).num_seconds() >= 1)
+        {
+            self.expire_pending(Some(ts));
+            self.last_timeout_check = Some(ts);
+        }
+        if self.daily_report.is_due(ts) {
+            self.print_stats(Some(ts));
+        }
+
+        // the slave listens on 502, gateways on other ports have the lower port
+        let is_request = if dst_port == MODBUS_PORT || src_port == MODBUS_PORT {
+            dst_port == MODBUS_PORT
+        } else {
+            dst_port.parse::<u16>().ok() < src_port.parse::<u16>().ok()
+        };
+        if is_request {
+            let slave = format!("{}/{unit}", cols[1]);
+            *self.functions.entry(function_name(func)).or_default() += 1;
+            self.slaves.entry(slave.clone()).or_default().requests += 1;
+            self.on_poll(ts, &src, &slave, func, reference);
+            self.pending.insert(
+                (
+                    src,
+                    cols[1].to_owned(),
+                    trans_id.to_owned(),
+                    unit.to_owned(),
+                ),
+                PendingRequest {
+                    ts,
+                    func,
+                    reference,
+                },
+            );
+            return;
+        }
+
+        let key = (
+            dst,
+            cols[0].to_owned(),
+            trans_id.to_owned(),
+            unit.to_owned(),
+        );
+        let Some(request) = self.pending.remove(&key) else {
+            self.unmatched_responses += 1;
+            return;
+        };
+        let (client, server, _, _) = key;
+        let slave = format!("{server}/{unit}");
+        let latency_ms = (ts - request.ts).num_microseconds().unwrap_or_default() as f64 / 1000.0;
+        let stats = self.slaves.entry(slave.clone()).or_default();
+        stats.responses += 1;
+        stats.latency.add(latency_ms);
+        if !exception.is_empty() {
+            let name = exception_name(exception);
+            *stats.exceptions.entry(name).or_default() += 1;
+            self.formatter.event(&Event {
+                ts,
+                protocol: PROTOCOL,
+                kind: EventKind::ErrorResponse,
+                user: client,
+                method: func.to_string(),
+                status: exception.parse().ok(),
+                addr: Some(slave),
+                latency_ms: Some(latency_ms),
+                detail: Some(format!("{} {name}", function_name(request.func))),
+                ..Default::default()
+            });
+            return;
+        }
+        if matches!(request.func, 3 | 4 | 23)
+            && let Some(reference) = request.reference
+            && !values.is_empty()
+        {
+            self.on_registers(ts, &slave, reference, values);
+        }
+    }
+
+    fn add_protocol_fields(&self, tshark_args: &mut Vec<&str>) {
+        tshark_args.push("-e");
+        tshark_args.push("mbtcp.trans_id");
+        tshark_args.push("-e");
+        tshark_args.push("mbtcp.unit_id");
+        tshark_args.push("-e");
+        tshark_args.push("modbus.func_code");
+        tshark_args.push("-e");
+        tshark_args.push("modbus.exception_code");
+        tshark_args.push("-e");
+        tshark_args.push("modbus.reference_num");
+        tshark_args.push("-e");
+        tshark_args.push("modbus.regval_uint16");
+        tshark_args.push("-e");
+        tshark_args.push("tcp.srcport");
+        tshark_args.push("-e");
+        tshark_args.push("tcp.dstport");
+        if !tshark_args.contains(&"-Y") {
+            tshark_args.push("-Y");
+            tshark_args.push("mbtcp");
+        }
+        if tshark_args.contains(&"-i") && !tshark_args.contains(&"-f") {
+            tshark_args.push("-f");
+            tshark_args.push("tcp port 502");
+        }
+    }
+
+    fn end(&mut self) {
+        self.expire_pending(None);
+        self.print_stats(None);
+        if let Some(csv) = &mut self.csv
+            && let Err(err) = csv.flush()
+        {
+            eprintln!("Failed to write CSV: {err}");
+        }
+    }
+}
+
+// MARK: TESTS
+#[cfg(test)]
+mod test {
+    use chrono::{DateTime, TimeDelta};
+
+    use super::{Analyzer, ProtocolAnalyzer as _};
+    use crate::analyzers::test_args;
+
+    #[test]
+    fn pairing_exceptions_and_poll_deviation() {
+        let mut analyzer = Analyzer::new(&test_args(&[]), 0);
+        let start = DateTime::from_timestamp(1738062028, 0).unwrap();
+        let values = ["100,200", "101,200", "102,200", "103,200", "104,200"];
+        // holding registers polled every second, the sixth poll fails, the late last one times out
+        for (i, s) in [0, 1, 2, 3, 4, 5, 8].into_iter().enumerate() {
+            let trans_id = i.to_string();
+            let ts = start + TimeDelta::seconds(s);
+            let request = vec![
+                "10.0.0.1", "10.0.0.2", &trans_id, "1", "3", "", "40", "", "50000", "502",
+            ];
+            analyzer.analyze(ts, request);
+            let (func, exception, values) = match values.get(i) {
+                Some(values) => ("3", "", *values),
+                None if s == 5 => ("131", "2", ""),
+                None => continue,
+            };
+            let response = vec![
+                "10.0.0.2", "10.0.0.1", &trans_id, "1", func, exception, "", values, "502", "50000",
+            ];
+            analyzer.analyze(ts + TimeDelta::milliseconds(20), response);
+        }
+        analyzer.end();
+        assert_eq!(analyzer.unmatched_responses, 0);
+        let slave = &analyzer.slaves["10.0.0.2/1"];
+        assert_eq!((slave.requests, slave.responses, slave.timeouts), (7, 6, 1));
+        assert_eq!(slave.exceptions["Illegal Data Address"], 1);
+        assert_eq!(slave.latency.max(), 20.0);
+        let poll = &analyzer.polls[&("10.0.0.2/1".to_owned(), "Read Holding Registers", Some(40))];
+        assert_eq!(poll.deviations, 1);
+        assert_eq!(poll.max_ms, 3000.0);
+        assert_eq!(
+            analyzer.registers[&("10.0.0.2/1".to_owned(), 40)].changes,
+            4
+        );
+        assert_eq!(
+            analyzer.registers[&("10.0.0.2/1".to_owned(), 41)].changes,
+            0
+        );
+    }
+}
@@ -0,0 +1,749 @@
+use super::{
+    Event, EventKind, ProtocolAnalyzer,
+    fields::next,
+    report::{self, DailyReport, LatencyStats},
+};
+use crate::{
+    ArgsCommand,
+    formatters::{EventFormatter, create_formatter},
+};
+use ahash::HashMap;
+use chrono::{DateTime, Utc};
+use std::fmt::Write as _;
+
+const CONNECT: &str = "1";
+const CONNACK: &str = "2";
+const PUBLISH: &str = "3";
+const PUBACK: &str = "4";
+const PUBREC: &str = "5";
+const PUBREL: &str = "6";
+const PUBCOMP: &str = "7";
+const SUBSCRIBE: &str = "8";
+const SUBACK: &str = "9";
+const UNSUBSCRIBE: &str = "10";
+const UNSUBACK: &str = "11";
+const DISCONNECT: &str = "14";
+const AUTH: &str = "15";
+
+const MQTT_PORTS: [&str; 2] = ["1883", "8883"];
+
+/// Brokers disconnect after one and a half keepalive periods of silence
+const KEEPALIVE_GRACE: f64 = 1.5;
+
+/// One TCP stream between a client and the broker
+#[derive(Default)]
+struct Session {
+    client_id: String,
+    client: String,
+    broker: String,
+    keepalive_s: i64,
+    /// Protocol level from CONNECT, 5 adds properties to most messages
+    version: u8,
+    connect_ts: Option<DateTime<Utc>>,
+    last_client_ts: DateTime<Utc>,
+    silent: bool,
+    disconnected: bool,
+}
+
+struct PendingPublish {
+    ts: DateTime<Utc>,
+    stream: String,
+    topic: String,
+    qos: u8,
+    publisher: String,
+    receiver: String,
+}
+
+#[derive(Default)]
+struct ClientStats {
+    connects: u32,
+    rejected: u32,
+    reconnects: u32,
+    keepalive_violations: u32,
+    published: u32,
+    unacknowledged: u32,
+    keepalive_s: i64,
+    connect_latency: LatencyStats,
+}
+
+#[derive(Default)]
+struct TopicStats {
+    /// Publishes from the publisher to the broker, deliveries to subscribers are not counted
+    messages: u32,
+    /// Publishes with a known payload, payloads of several publishes in a segment may not align
+    sized: u32,
+    bytes: u64,
+    max_size: u32,
+    first_seen: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+}
+
+pub struct Analyzer {
+    sessions: HashMap<String, Session>,
+    /// (stream, publisher, message id) for QoS 1 and 2 publishes
+    pending: HashMap<(String, String, String), PendingPublish>,
+    clients: HashMap<String, ClientStats>,
+    topics: HashMap<String, TopicStats>,
+    return_codes: HashMap<&'static str, u32>,
+    messages: u32,
+    timeout: i64,
+    top: usize,
+    last_timeout_check: Option<DateTime<Utc>>,
+    formatter: Box<dyn EventFormatter>,
+    daily_report: DailyReport,
+}
+
+const PROTOCOL: &str = "mqtt";
+
+fn return_code_name(code: &str) -> &'static str {
+    match code {
+        "0" => "Accepted",
+        "1" => "Unacceptable Protocol Version",
+        "2" => "Identifier Rejected",
+        "3" => "Server Unavailable",
+        "4" => "Bad Username Or Password",
+        "5" => "Not Authorized",
+        "128" => "Unspecified Error",
+        "133" => "Client Identifier Not Valid",
+        "134" => "Bad User Name Or Password",
+        "135" => "Not Authorized",
+        "136" => "Server Unavailable",
+        "137" => "Server Busy",
+        "138" => "Banned",
+        _ => "Other",
+    }
+}
+
+/// Number of property lengths an MQTT 5 message carries, tshark only decodes them after the CONNECT
+fn property_lengths(msg_type: &str, length: u32, will: bool) -> usize {
+    match msg_type {
+        CONNECT => 1 + usize::from(will),
+        PUBLISH | SUBSCRIBE | SUBACK | UNSUBSCRIBE | UNSUBACK => 1,
+        // optional once the reason code is present
+        CONNACK => usize::from(length > 2),
+        PUBACK | PUBREC | PUBREL | PUBCOMP => usize::from(length > 3),
+        DISCONNECT | AUTH => usize::from(length > 1),
+        _ => 0,
+    }
+}
+
+/// Size of the variable byte integer encoding a length
+fn varint_size(value: u32) -> u32 {
+    match value {
+        0..128 => 1,
+        128..16_384 => 2,
+        16_384..2_097_152 => 3,
+        _ => 4,
+    }
+}
+
+impl Analyzer {
+    fn client_id(&self, stream: &str) -> String {
+        self.sessions
+            .get(stream)
+            .map(|s| s.client_id.clone())
+            .unwrap_or_default()
+    }
+
+    fn on_connect(
+        &mut self,
+        ts: DateTime<Utc>,
+        stream: &str,
+        client: &str,
+        broker: &str,
+        client_id: &str,
+        keepalive_s: i64,
+    ) {
+        let stats = self.clients.entry(client_id.to_owned()).or_default();
+        stats.connects += 1;
+        stats.keepalive_s = keepalive_s;
+        // the same client id still connected on another stream never said goodbye
+        let previous = self
+            .sessions
+            .iter()
+            .find(|(s, session)| {
+                *s != stream && session.client_id == client_id && !session.disconnected
+            })
+            .map(|(s, session)| (s.clone(), session.client.clone()));
+        if let Some((old_stream, old_client)) = previous {
+            self.sessions.remove(&old_stream);
+            stats.reconnects += 1;
+            self.formatter.event(&Event {
+                ts,
+                protocol: PROTOCOL,
+                kind: EventKind::Warning,
+                user: client_id.to_owned(),
+                method: "CONNECT".into(),
+                addr: Some(broker.to_owned()),
+                detail: Some(format!(
+                    "reconnect from {client} without DISCONNECT on {old_client}"
+                )),
+                ..Default::default()
+            });
+        }
+        self.sessions.insert(
+            stream.to_owned(),
+            Session {
+                client_id: client_id.to_owned(),
+                client: client.to_owned(),
+                broker: broker.to_owned(),
+                keepalive_s,
+                connect_ts: Some(ts),
+                last_client_ts: ts,
+                ..Default::default()
+            },
+        );
+    }
+
+    fn on_connack(&mut self, ts: DateTime<Utc>, stream: &str, code: &str) {
+        let Some(session) = self.sessions.get_mut(stream) else {
+            return;
+        };
+        let Some(connect_ts) = session.connect_ts.take() else {
+            return;
+        };
+        let name = return_code_name(code);
+        *self.return_codes.entry(name).or_default() += 1;
+        let latency_ms = (ts - connect_ts).num_microseconds().unwrap_or_default() as f64 / 1000.0;
+        let stats = self.clients.entry(session.client_id.clone()).or_default();
+        stats.connect_latency.add(latency_ms);
+        if matches!(code, "0" | "") {
+            return;
+        }
+        stats.rejected += 1;
+        session.disconnected = true;
+        self.formatter.event(&Event {
+            ts,
+            protocol: PROTOCOL,
+            kind: EventKind::ErrorResponse,
+            user: session.client_id.clone(),
+            method: "CONNECT".into(),
+            status: code.parse().ok(),
+            addr: Some(session.broker.clone()),
+            latency_ms: Some(latency_ms),
+            detail: Some(format!("CONNACK {code} {name}")),
+            ..Default::default()
+        });
+    }
+
+    fn on_unacknowledged(&mut self, ts: DateTime<Utc>, msg_id: &str, publish: PendingPublish) {
+        let client_id = self.client_id(&publish.stream);
+        self.clients
+            .entry(client_id.clone())
+            .or_default()
+            .unacknowledged += 1;
+        self.formatter.event(&Event {
+            ts,
+            protocol: PROTOCOL,
+            kind: EventKind::NoResponse,
+            user: client_id,
+            method: format!("QoS{}", publish.qos),
+            addr: Some(publish.receiver),
+            elapsed_s: Some((ts - publish.ts).num_seconds()),
+            detail: Some(format!(
+                "{} id {msg_id} from {} unacknowledged",
+                publish.topic, publish.publisher
+            )),
+            ..Default::default()
+        });
+    }
+
+    fn expire_pending(&mut self, opt_ts: Option<DateTime<Utc>>) {
+        let timeout = self.timeout;
+        let expired = self
+            .pending
+            .extract_if(|_, p| opt_ts.is_none_or(|ts| (ts - p.ts).num_seconds() >= timeout))
+            .collect::<Vec<_>>();
+        for ((_, _, msg_id), publish) in expired {
+            self.on_unacknowledged(opt_ts.unwrap_or(publish.ts), &msg_id, publish);
+        }
+
+        let mut notices = Vec::new();
+        for session in self.sessions.values_mut() {
+            if let Some(connect_ts) = session.connect_ts
+                && opt_ts.is_none_or(|ts| (ts - connect_ts).num_seconds() >= timeout)
+            {
+                session.connect_ts = None;
+                notices.push((
+                    opt_ts.unwrap_or(connect_ts),
+                    EventKind::NoResponse,
+                    session.client_id.clone(),
+                    session.broker.clone(),
+                    "CONNECT without CONNACK".to_owned(),
+                ));
+                continue;
+            }
+            let Some(ts) = opt_ts else {
+                continue;
+            };
+            let idle_s = (ts - session.last_client_ts).num_seconds();
+            if session.keepalive_s > 0
+                && !session.silent
+                && !session.disconnected
+                && idle_s as f64 > session.keepalive_s as f64 * KEEPALIVE_GRACE
+            {
+                session.silent = true;
+                notices.push((
+                    ts,
+                    EventKind::Warning,
+                    session.client_id.clone(),
+                    session.broker.clone(),
+                    format!(
+                        "keepalive {} s exceeded, silent for {idle_s} s",
+                        session.keepalive_s
+                    ),
+                ));
+            }
+        }
+        for (ts, kind, client_id, broker, detail) in notices {
+            if kind == EventKind::Warning {
+                self.clients
+                    .entry(client_id.clone())
+                    .or_default()
+                    .keepalive_violations += 1;
+            }
+            self.formatter.event(&Event {
+                ts,
+                protocol: PROTOCOL,
+                kind,
+                user: client_id,
+                method: "CONNECT".into(),
+                addr: Some(broker),
+                detail: Some(detail),
+                ..Default::default()
+            });
+        }
+    }
+
+    fn print_stats(&mut self, opt_ts: Option<DateTime<Utc>>) {
+        let mut output = String::with_capacity(200);
+        report::print_header(self.formatter.as_mut(), opt_ts);
+        report::print_section(self.formatter.as_mut(), "Clients");
+        let mut clients = Vec::from_iter(self.clients.keys());
+        clients.sort();
+        for client_id in clients {
+            let stats = &self.clients[client_id];
+            write!(
+                output,
+                "{client_id:<30} {:4} connects {:4} rejected {:4} reconnects  keepalive {:4} s {:4} violations {:8} published {:4} unacked\n{:<30} CONNACK {}",
+                stats.connects,
+                stats.rejected,
+                stats.reconnects,
+                stats.keepalive_s,
+                stats.keepalive_violations,
+                stats.published,
+                stats.unacknowledged,
+                "",
+                stats.connect_latency.summary()
+            )
+            .unwrap();
+            self.formatter.report(&output, 1);
+            output.clear();
+        }
+
+        report::print_section(self.formatter.as_mut(), "Top Topics");
+        let counts = HashMap::from_iter(self.topics.iter().map(|(t, s)| (t, s.messages)));
+        for (topic, count) in report::top_n(&counts, self.top) {
+            let stats = &self.topics[*topic];
+            let span_min = (stats.last_seen - stats.first_seen).num_seconds().max(60) as f64 / 60.0;
+            self.formatter.report(
+                &format!(
+                    "{count:8} {topic:<40} {:8.1} msg/min  avg {:6} bytes  max {:6} bytes",
+                    count as f64 / span_min,
+                    stats.bytes / u64::from(stats.sized.max(1)),
+                    stats.max_size
+                ),
+                1,
+            );
+        }
+        report::print_section(self.formatter.as_mut(), "CONNACK Return Codes");
+        for (code, count) in report::top_n(&self.return_codes, usize::MAX) {
+            self.formatter.report(&format!("{count:8} {code}"), 1);
+        }
+        write!(
+            output,
+            r#"
+ ------------ STATS ------------
+
+- total messages: {}
+- total clients: {}
+- open sessions: {}
+- pending QoS messages: {}
+- unacknowledged messages: {}
+- keepalive violations: {}
+"#,
+            self.messages,
+            self.clients.len(),
+            self.sessions.values().filter(|s| !s.disconnected).count(),
+            self.pending.len(),
+            self.clients.values().map(|c| c.unacknowledged).sum::<u32>(),
+            self.clients
+                .values()
+                .map(|c| c.keepalive_violations)
+                .sum::<u32>(),
+        )
+        .unwrap();
+        self.formatter.report(&output, 0);
+        report::print_footer(self.formatter.as_mut());
+    }
+}
+
+impl ProtocolAnalyzer for Analyzer {
+    fn new(cmd_args: &ArgsCommand, verbosity: u8) -> Self {
+        let (timeout, top) = match cmd_args {
+            ArgsCommand::Analyzer { timeout, top, .. } => (*timeout as i64, *top),
+            _ => (5, 10),
+        };
+        Self {
+            sessions: HashMap::default(),
+            pending: HashMap::default(),
+            clients: HashMap::default(),
+            topics: HashMap::default(),
+            return_codes: HashMap::default(),
+            messages: 0,
+            timeout,
+            top,
+            last_timeout_check: None,
+            formatter: create_formatter(cmd_args, verbosity),
+            daily_report: DailyReport::default(),
+        }
+    }
+
+    fn analyze(&mut self, ts: DateTime<Utc>, cols: Vec<&str>) {
+        let stream = cols[2];
+        let src = format!("{}:{}", cols[0], cols[3]);
+        let dst = format!("{}:{}", cols[1], cols[4]);
+        if self
+            .last_timeout_check
+            .is_none_or(|last| (ts - last).num_seconds() >= 1)
+        {
+            self.expire_pending(Some(ts));
+            self.last_timeout_check = Some(ts);
+        }
+        if self.daily_report.is_due(ts) {
+            self.print_stats(Some(ts));
+        }
+
+        let from_client = match self.sessions.get_mut(stream) {
+            Some(session) if session.client == src => {
+                session.last_client_ts = ts;
+                session.silent = false;
+                true
+            }
+            Some(_) => false,
+            None => MQTT_PORTS.contains(&cols[4]),
+        };
+        // one segment can carry several messages, each field only lists the messages having it
+        let mut client_ids = cols[6].split(',');
+        let mut keepalives = cols[7].split(',');
+        let mut return_codes = cols[8].split(',');
+        let mut qos_values = cols[9].split(',');
+        let mut msg_ids = cols[10].split(',');
+        let mut topics = cols[11].split(',');
+        let mut lengths = cols[12].split(',');
+        let mut versions = cols[14].split(',');
+        let mut will_flags = cols[15].split(',');
+        let mut property_lens = cols[16].split(',');
+        // payloads are hex, an empty one may be missing so they only pair when the count matches
+        let publishes = cols[5].split(',').filter(|t| *t == PUBLISH).count();
+        let payloads = cols[13].split(',').collect::<Vec<_>>();
+        let mut payload_sizes = payloads
+            .iter()
+            .map(|p| (payloads.len() == publishes).then_some(p.len() as u32 / 2));
+        for msg_type in cols[5].split(',') {
+            self.messages += 1;
+            let length = next(&mut lengths).parse::<u32>().unwrap_or_default();
+            let will =
+                msg_type == CONNECT && matches!(next(&mut will_flags), "1" | "True" | "true");
+            let v5 = match msg_type {
+                CONNECT => versions.clone().next() == Some("5"),
+                _ => self.sessions.get(stream).is_some_and(|s| s.version >= 5),
+            };
+            let count = if v5 {
+                property_lengths(msg_type, length, will)
+            } else {
+                0
+            };
+            let properties = property_lens.by_ref().take(count).collect::<Vec<_>>();
+            let properties_len = properties.first().and_then(|len| len.parse::<u32>().ok());
+            match msg_type {
+                CONNECT => {
+                    let client_id = next(&mut client_ids);
+                    let keepalive_s = next(&mut keepalives).parse().unwrap_or_default();
+                    let version = next(&mut versions).parse().unwrap_or_default();
+                    self.on_connect(ts, stream, &src, &dst, client_id, keepalive_s);
+                    if let Some(session) = self.sessions.get_mut(stream) {
+                        session.version = version;
+                    }
+                }
+                CONNACK => self.on_connack(ts, stream, next(&mut return_codes)),
+                PUBLISH => {
+                    let qos = next(&mut qos_values).parse::<u8>().unwrap_or_default();
+                    let topic = next(&mut topics);
+                    let size = payload_sizes.next().flatten();
+                    if from_client {
+                        let stats =
+                            self.topics
+                                .entry(topic.to_owned())
+                                .or_insert_with(|| TopicStats {
+                                    first_seen: ts,
+                                    ..Default::default()
+                                });
+                        stats.messages += 1;
+                        if let Some(size) = size {
+                            stats.sized += 1;
+                            stats.bytes += u64::from(size);
+                            stats.max_size = stats.max_size.max(size);
+                        }
+                        stats.last_seen = ts;
+                    }
+                    if from_client && self.sessions.contains_key(stream) {
+                        let client_id = self.client_id(stream);
+                        self.clients.entry(client_id).or_default().published += 1;
+                    }
+                    if qos > 0 {
+                        let msg_id = next(&mut msg_ids);
+                        self.pending.insert(
+                            (stream.to_owned(), src.clone(), msg_id.to_owned()),
+                            PendingPublish {
+                                ts,
+                                stream: stream.to_owned(),
+                                topic: topic.to_owned(),
+                                qos,
+                                publisher: src.clone(),
+                                receiver: dst.clone(),
+                            },
+                        );
+                    }
+                }
+                // acknowledgements travel back to the publisher
+                PUBACK | PUBCOMP => {
+                    let msg_id = next(&mut msg_ids);
+                    self.pending
+                        .remove(&(stream.to_owned(), dst.clone(), msg_id.to_owned()));
+                }
+                PUBREC => {
+                    let msg_id = next(&mut msg_ids);
+                    if let Some(publish) =
+                        self.pending
+                            .get_mut(&(stream.to_owned(), dst.clone(), msg_id.to_owned()))
+                    {
+                        publish.ts = ts;
+                    }
+                }
+                PUBREL => {
+                    next(&mut msg_ids);
+                }
+                DISCONNECT => {
+                    if let Some(session) = self.sessions.get_mut(stream) {
+                        session.disconnected = true;
+                    }
+                }
+                // SUBSCRIBE and UNSUBSCRIBE list their topic filters in mqtt.topic too, the
+                // remaining length tells how many belong to this message
+                SUBSCRIBE | UNSUBSCRIBE => {
+                    next(&mut msg_ids);
+                    // length prefix, filter and for SUBSCRIBE the options byte
+                    let overhead = if msg_type == SUBSCRIBE { 3 } else { 2 };
+                    let properties = properties_len.map_or(0, |len| varint_size(len) + len);
+                    let mut remaining = length.saturating_sub(2 + properties);
+                    loop {
+                        remaining =
+                            remaining.saturating_sub(overhead + next(&mut topics).len() as u32);
+                        if topics
+                            .clone()
+                            .next()
+                            .is_none_or(|t| overhead + t.len() as u32 > remaining)
+                        {
+                            break;
+                        }
+                    }
+                }
+                // SUBACK and UNSUBACK carry message ids
+                SUBACK | UNSUBACK => {
+                    next(&mut msg_ids);
+                }
+                _ => (),
+            }
+        }
+    }
+
+    fn add_protocol_fields(&self, tshark_args: &mut Vec<&str>) {
+        tshark_args.push("-e");
+        tshark_args.push("tcp.stream");
+        tshark_args.push("-e");
+        tshark_args.push("tcp.srcport");
+        tshark_args.push("-e");
+        tshark_args.push("tcp.dstport");
+        tshark_args.push("-e");
+        tshark_args.push("mqtt.msgtype");
+        tshark_args.push("-e");
+        tshark_args.push("mqtt.clientid");
+        tshark_args.push("-e");
+        tshark_args.push("mqtt.kalive");
+        tshark_args.push("-e");
+        tshark_args.push("mqtt.conack.val");
+        tshark_args.push("-e");
+        tshark_args.push("mqtt.qos");
+        tshark_args.push("-e");
+        tshark_args.push("mqtt.msgid");
+        tshark_args.push("-e");
+        tshark_args.push("mqtt.topic");
+        tshark_args.push("-e");
+        tshark_args.push("mqtt.len");
+        tshark_args.push("-e");
+        tshark_args.push("mqtt.msg");
+        tshark_args.push("-e");
+        tshark_args.push("mqtt.ver");
+        tshark_args.push("-e");
+        tshark_args.push("mqtt.conflag.willflag");
+        tshark_args.push("-e");
+        tshark_args.push("mqtt.proplen");
+        if !tshark_args.contains(&"-Y") {
+            tshark_args.push("-Y");
+            tshark_args.push("mqtt");
+        }
+        if tshark_args.contains(&"-i") && !tshark_args.contains(&"-f") {
+            tshark_args.push("-f");
+            tshark_args.push("tcp port 1883");
+        }
+    }
+
+    fn end(&mut self) {
+        self.expire_pending(None);
+        self.print_stats(None);
+    }
+}
+
+// MARK: TESTS
+#[cfg(test)]
+mod test {
+    use chrono::{DateTime, TimeDelta};
+
+    use super::{Analyzer, ProtocolAnalyzer as _};
+    use crate::analyzers::test_args;
+
+    /// Address columns followed by type, client id, keepalive, return code, QoS, message id,
+    /// topic, length, payload, version, will flag and property length
+    fn message(addrs: [&'static str; 5], fields: [&'static str; 12]) -> Vec<&'static str> {
+        addrs.into_iter().chain(fields).collect()
+    }
+
+    #[test]
+    fn subscribe_publish_alignment_and_qos2() {
+        let mut analyzer = Analyzer::new(&test_args(&[]), 0);
+        let start = DateTime::from_timestamp(1738062028, 0).unwrap();
+        let client = ["10.0.0.1", "10.0.0.2", "1", "50000", "1883"];
+        let broker = ["10.0.0.2", "10.0.0.1", "1", "1883", "50000"];
+        // SUBSCRIBE with two filters and a QoS 2 PUBLISH in one segment, then the QoS 2
+        // handshake and a delivery back to the client
+        for (ms, cols) in [
+            (
+                0,
+                message(
+                    client,
+                    ["1", "sensor", "60", "", "", "", "", "", "", "4", "0", ""],
+                ),
+            ),
+            (
+                10,
+                message(broker, ["2", "", "", "0", "", "", "", "2", "", "", "", ""]),
+            ),
+            (
+                20,
+                message(
+                    client,
+                    [
+                        "8,3",
+                        "",
+                        "",
+                        "",
+                        "2",
+                        "3,7",
+                        "a/#,b,a/x",
+                        "12,12",
+                        "68656c6c6f",
+                        "",
+                        "",
+                        "",
+                    ],
+                ),
+            ),
+            (
+                30,
+                message(
+                    broker,
+                    ["9,5", "", "", "", "", "3,7", "", "3,2", "", "", "", ""],
+                ),
+            ),
+            (
+                40,
+                message(client, ["6", "", "", "", "", "7", "", "2", "", "", "", ""]),
+            ),
+            (
+                50,
+                message(broker, ["7", "", "", "", "", "7", "", "2", "", "", "", ""]),
+            ),
+            (
+                60,
+                message(
+                    broker,
+                    ["3", "", "", "", "0", "", "b", "6", "6869", "", "", ""],
+                ),
+            ),
+        ] {
+            analyzer.analyze(start + TimeDelta::milliseconds(ms), cols);
+        }
+        analyzer.end();
+        assert!(analyzer.pending.is_empty());
+        assert_eq!(analyzer.topics.len(), 1);
+        let topic = &analyzer.topics["a/x"];
+        assert_eq!((topic.messages, topic.bytes, topic.max_size), (1, 5, 5));
+        let stats = &analyzer.clients["sensor"];
+        assert_eq!((stats.published, stats.unacknowledged), (1, 0));
+    }
+
+    #[test]
+    fn v5_subscribe_properties() {
+        let mut analyzer = Analyzer::new(&test_args(&[]), 0);
+        let start = DateTime::from_timestamp(1738062028, 0).unwrap();
+        let client = ["10.0.0.3", "10.0.0.2", "2", "50001", "1883"];
+        let broker = ["10.0.0.2", "10.0.0.3", "2", "1883", "50001"];
+        // 20 bytes of SUBSCRIBE user properties must not swallow the topic of the PUBLISH
+        for (ms, cols) in [
+            (
+                0,
+                message(
+                    client,
+                    ["1", "meter", "30", "", "", "", "", "", "", "5", "0", "0"],
+                ),
+            ),
+            (
+                10,
+                message(broker, ["2", "", "", "0", "", "", "", "3", "", "", "", "0"]),
+            ),
+            (
+                20,
+                message(
+                    client,
+                    [
+                        "8,3", "", "", "", "1", "4,9", "c/d/e,f", "31,8", "6869", "", "", "20,0",
+                    ],
+                ),
+            ),
+            (
+                30,
+                message(
+                    broker,
+                    ["9,4", "", "", "", "", "4,9", "", "4,2", "", "", "", "0"],
+                ),
+            ),
+        ] {
+            analyzer.analyze(start + TimeDelta::milliseconds(ms), cols);
+        }
+        analyzer.end();
+        assert!(analyzer.pending.is_empty());
+        let topic = &analyzer.topics["f"];
+        assert_eq!((topic.messages, topic.bytes), (1, 2));
+    }
+}
@@ -0,0 +1,480 @@
+use super::{
+    Event, EventKind, ProtocolAnalyzer,
+    fields::{first, parse_number},
+    report::{self, DailyReport},
+    sql::{self, QueryProfile},
+};
+use crate::{
+    ArgsCommand,
+    formatters::{EventFormatter, create_formatter},
+};
+use ahash::HashMap;
+use chrono::{DateTime, Utc};
+use std::fmt::Write as _;
+
+const COM_QUIT: u32 = 1;
+const COM_QUERY: u32 = 3;
+const COM_STMT_PREPARE: u32 = 22;
+const COM_STMT_EXECUTE: u32 = 23;
+const COM_STMT_CLOSE: u32 = 25;
+
+const RESPONSE_OK: u32 = 0x00;
+const RESPONSE_EOF: u32 = 0xfe;
+const RESPONSE_ERR: u32 = 0xff;
+
+/// CLIENT_DEPRECATE_EOF in the extended (upper 16 bit) capability flags
+const EXTCAPS_DEPRECATE_EOF: u32 = 0x0100;
+
+#[derive(PartialEq)]
+enum Command {
+    Query,
+    Prepare,
+    Execute,
+}
+
+struct PendingCommand {
+    ts: DateTime<Utc>,
+    command: Command,
+    query: String,
+    /// Column count once a result set header was seen
+    num_fields: Option<u32>,
+    /// Server packets of the response
+    packets: u32,
+    eofs: u32,
+    last_response_ts: Option<DateTime<Utc>>,
+}
+
+impl PendingCommand {
+    /// Header, column definitions and EOF markers are not rows
+    fn rows(&self) -> u64 {
+        self.packets
+            .saturating_sub(1 + self.num_fields.unwrap_or_default() + self.eofs) as u64
+    }
+}
+
+#[derive(Default)]
+struct Stream {
+    client: String,
+    server: String,
+    /// Statement id to query text
+    statements: HashMap<String, String>,
+    /// Negotiated at login, result sets then end with a single OK instead of two EOF markers
+    deprecate_eof: bool,
+    /// Between the login request and the first command, server packets are authentication
+    authenticating: bool,
+    pending: Option<PendingCommand>,
+}
+
+pub struct Analyzer {
+    streams: HashMap<String, Stream>,
+    profile: QueryProfile,
+    timeouts: u32,
+    unmatched_responses: u32,
+    timeout: i64,
+    top: usize,
+    last_timeout_check: Option<DateTime<Utc>>,
+    formatter: Box<dyn EventFormatter>,
+    daily_report: DailyReport,
+}
+
+const PROTOCOL: &str = "mysql";
+
+impl Analyzer {
+    fn finish(
+        &mut self,
+        client: &str,
+        server: &str,
+        command: PendingCommand,
+        rows: u64,
+        error: Option<(String, String)>,
+    ) {
+        let Some(ts) = command.last_response_ts else {
+            return;
+        };
+        let latency = (ts - command.ts).num_microseconds().unwrap_or_default() as f64 / 1000.0;
+        let fingerprint = if command.query.is_empty() {
+            "<unknown>".to_owned()
+        } else {
+            sql::fingerprint(&command.query)
+        };
+        let client_host = client.rsplit_once(':').map_or(client, |(h, _)| h);
+        if let Some((code, message)) = &error {
+            self.formatter.event(&Event {
+                ts,
+                protocol: PROTOCOL,
+                kind: EventKind::ErrorResponse,
+                user: client.to_owned(),
+                method: sql::verb(&fingerprint),
+                addr: Some(server.to_owned()),
+                status: code.parse().ok(),
+                latency_ms: Some(latency),
+                detail: Some(format!(
+                    "{code} {message}: {}",
+                    sql::abbreviate(&fingerprint, 120)
+                )),
+                ..Default::default()
+            });
+        }
+        self.profile.record(
+            client_host,
+            fingerprint,
+            latency,
+            rows,
+            error
+                .as_ref()
+                .map(|(code, message)| (code.as_str(), message.as_str())),
+        );
+    }
+
+    /// Result sets without EOF markers only end when the client sends its next command, so
+    /// answered commands are completed at their last response instead of timing out
+    fn expire_pending(&mut self, opt_ts: Option<DateTime<Utc>>) {
+        let timeout = self.timeout;
+        let mut expired = Vec::new();
+        for stream in self.streams.values_mut() {
+            if stream.pending.as_ref().is_some_and(|c| {
+                opt_ts.is_none_or(|ts| {
+                    (ts - c.last_response_ts.unwrap_or(c.ts)).num_seconds() >= timeout
+                })
+            }) {
+                let command = stream.pending.take().unwrap();
+                expired.push((stream.client.clone(), stream.server.clone(), command));
+            }
+        }
+        for (client, server, command) in expired {
+            if command.last_response_ts.is_some() {
+                let rows = command.rows();
+                self.finish(&client, &server, command, rows, None);
+                continue;
+            }
+            self.timeouts += 1;
+            let fingerprint = sql::fingerprint(&command.query);
+            self.formatter.event(&Event {
+                ts: opt_ts.unwrap_or(command.ts),
+                protocol: PROTOCOL,
+                kind: EventKind::NoResponse,
+                user: client,
+                method: sql::verb(&fingerprint),
+                addr: Some(server),
+                elapsed_s: opt_ts.map(|ts| (ts - command.ts).num_seconds()),
+                detail: Some(sql::abbreviate(&fingerprint, 120)),
+                ..Default::default()
+            });
+        }
+    }
+
+    fn on_request(
+        &mut self,
+        ts: DateTime<Utc>,
+        stream_id: &str,
+        src: String,
+        dst: String,
+        cols: &[&str],
+    ) {
+        let stream = self.streams.entry(stream_id.to_owned()).or_default();
+        if stream.client.is_empty() {
+            stream.client = src;
+            stream.server = dst;
+        }
+        stream.authenticating = false;
+        let previous = stream.pending.take();
+        let command = match parse_number(first(cols[2])) {
+            Some(COM_QUERY) => Some((Command::Query, cols[3].to_owned())),
+            Some(COM_STMT_PREPARE) => Some((Command::Prepare, cols[3].to_owned())),
+            Some(COM_STMT_EXECUTE) => Some((
+                Command::Execute,
+                stream
+                    .statements
+                    .get(first(cols[4]))
+                    .cloned()
+                    .unwrap_or_default(),
+            )),
+            Some(COM_STMT_CLOSE) => {
+                stream.statements.remove(first(cols[4]));
+                None
+            }
+            _ => None,
+        };
+        stream.pending = command.map(|(command, query)| PendingCommand {
+            ts,
+            command,
+            query,
+            num_fields: None,
+            packets: 0,
+            eofs: 0,
+            last_response_ts: None,
+        });
+        let (client, server) = (stream.client.clone(), stream.server.clone());
+        if parse_number(first(cols[2])) == Some(COM_QUIT) {
+            self.streams.remove(stream_id);
+        }
+        // the client only sends the next command after the previous response ended
+        if let Some(previous) = previous.filter(|p| p.command != Command::Prepare) {
+            let rows = previous.rows();
+            self.finish(&client, &server, previous, rows, None);
+        }
+    }
+
+    fn on_response(&mut self, ts: DateTime<Utc>, stream_id: &str, cols: &[&str]) {
+        let Some(stream) = self.streams.get_mut(stream_id) else {
+            return;
+        };
+        let markers = if stream.deprecate_eof { 1 } else { 2 };
+        let Some(command) = stream.pending.as_mut() else {
+            if !stream.authenticating {
+                self.unmatched_responses += 1;
+            }
+            return;
+        };
+        command.last_response_ts = Some(ts);
+        command.packets += cols[11].split(',').filter(|p| !p.is_empty()).count() as u32;
+        if command.command != Command::Prepare && command.num_fields.is_none() {
+            command.num_fields = parse_number(first(cols[10]));
+        }
+        let mut error = None;
+        let mut done = false;
+        for code in cols[5].split(',').filter_map(parse_number) {
+            match code {
+                RESPONSE_ERR => {
+                    error = Some((
+                        first(cols[6]).to_owned(),
+                        format!("({}) {}", first(cols[7]), cols[8]),
+                    ));
+                    done = true;
+                }
+                RESPONSE_OK if command.num_fields.is_none() => done = true,
+                RESPONSE_OK | RESPONSE_EOF => {
+                    command.eofs += 1;
+                    // the marker after the column definitions and the one after the rows
+                    done = command.eofs == markers;
+                }
+                _ => {}
+            }
+            if done {
+                break;
+            }
+        }
+        if !done {
+            return;
+        }
+        let command = stream.pending.take().unwrap();
+        if command.command == Command::Prepare {
+            if error.is_none() {
+                stream
+                    .statements
+                    .insert(first(cols[4]).to_owned(), command.query);
+            }
+            return;
+        }
+        let rows = if command.num_fields.is_some() {
+            command.rows()
+        } else {
+            first(cols[9]).parse().unwrap_or_default()
+        };
+        let (client, server) = (stream.client.clone(), stream.server.clone());
+        self.finish(&client, &server, command, rows, error);
+    }
+
+    fn print_stats(&mut self, opt_ts: Option<DateTime<Utc>>) {
+        let mut output = String::with_capacity(200);
+        report::print_header(self.formatter.as_mut(), opt_ts);
+        self.profile.print(self.formatter.as_mut(), self.top);
+        write!(
+            output,
+            r#"
+ ------------ STATS ------------
+
+- queries: {}
+- rows: {}
+- errors: {}
+- fingerprints: {}
+- timeouts: {}
+- unmatched responses: {}
+- open streams: {}
+"#,
+            self.profile.queries(),
+            self.profile.rows(),
+            self.profile.errors(),
+            self.profile.fingerprints(),
+            self.timeouts,
+            self.unmatched_responses,
+            self.streams.len(),
+        )
+        .unwrap();
+        self.formatter.report(&output, 0);
+        report::print_footer(self.formatter.as_mut());
+    }
+}
+
+impl ProtocolAnalyzer for Analyzer {
+    fn new(cmd_args: &ArgsCommand, verbosity: u8) -> Self {
+        let (timeout, top) = match cmd_args {
+            ArgsCommand::Analyzer { timeout, top, .. } => (*timeout as i64, *top),
+            _ => (5, 10),
+        };
+        Self {
+            streams: HashMap::default(),
+            profile: QueryProfile::default(),
+            timeouts: 0,
+            unmatched_responses: 0,
+            timeout,
+            top,
+            last_timeout_check: None,
+            formatter: create_formatter(cmd_args, verbosity),
+            daily_report: DailyReport::default(),
+        }
+    }
+
+    fn analyze(&mut self, ts: DateTime<Utc>, cols: Vec<&str>) {
+        if self
+            .last_timeout_check
+            .is_none_or(|last| (ts - last).num_seconds() >= 1)
+        {
+            self.expire_pending(Some(ts));
+            self.last_timeout_check = Some(ts);
+        }
+        if self.daily_report.is_due(ts) {
+            self.print_stats(Some(ts));
+        }
+        let src = format!("{}:{}", cols[0], cols[13]);
+        let dst = format!("{}:{}", cols[1], cols[14]);
+        if let Some(extcaps) = parse_number(cols[15]) {
+            // login request, connections from before the capture keep the EOF markers
+            let stream = self.streams.entry(cols[12].to_owned()).or_default();
+            stream.client = src;
+            stream.server = dst;
+            stream.deprecate_eof = extcaps & EXTCAPS_DEPRECATE_EOF != 0;
+            stream.authenticating = true;
+        } else if !cols[2].is_empty() {
+            self.on_request(ts, cols[12], src, dst, &cols);
+        } else {
+            self.on_response(ts, cols[12], &cols);
+        }
+    }
+
+    fn add_protocol_fields(&self, tshark_args: &mut Vec<&str>) {
+        tshark_args.push("-e");
+        tshark_args.push("mysql.command");
+        tshark_args.push("-e");
+        tshark_args.push("mysql.query");
+        tshark_args.push("-e");
+        tshark_args.push("mysql.stmt_id");
+        tshark_args.push("-e");
+        tshark_args.push("mysql.response_code");
+        tshark_args.push("-e");
+        tshark_args.push("mysql.error_code");
+        tshark_args.push("-e");
+        tshark_args.push("mysql.sqlstate");
+        tshark_args.push("-e");
+        tshark_args.push("mysql.error.message");
+        tshark_args.push("-e");
+        tshark_args.push("mysql.affected_rows");
+        tshark_args.push("-e");
+        tshark_args.push("mysql.num_fields");
+        tshark_args.push("-e");
+        tshark_args.push("mysql.packet_number");
+        tshark_args.push("-e");
+        tshark_args.push("tcp.stream");
+        tshark_args.push("-e");
+        tshark_args.push("tcp.srcport");
+        tshark_args.push("-e");
+        tshark_args.push("tcp.dstport");
+        tshark_args.push("-e");
+        tshark_args.push("mysql.extcaps.client");
+        if !tshark_args.contains(&"-Y") {
+            tshark_args.push("-Y");
+            tshark_args.push("mysql");
+        }
+        if tshark_args.contains(&"-i") && !tshark_args.contains(&"-f") {
+            tshark_args.push("-f");
+            tshark_args.push("tcp port 3306");
+        }
+    }
+
+    fn end(&mut self) {
+        self.expire_pending(None);
+        self.print_stats(None);
+    }
+}
+
+// MARK: TESTS
+#[cfg(test)]
+mod test {
+    use chrono::{DateTime, TimeDelta};
+
+    use super::{Analyzer, ProtocolAnalyzer as _};
+    use crate::analyzers::test_args;
+
+    /// Columns of a client or server packet on a stream with the given fields set
+    fn packet<'a>(from_client: bool, stream: &'a str, fields: &[(usize, &'a str)]) -> Vec<&'a str> {
+        let mut cols = vec![""; 16];
+        let (client, server) = (["10.0.0.1", "10.0.0.2"], ["10.0.0.2", "10.0.0.1"]);
+        let port = if stream == "1" { "50001" } else { "50002" };
+        let ([src, dst], sport, dport) = if from_client {
+            (client, port, "3306")
+        } else {
+            (server, "3306", port)
+        };
+        cols[..2].copy_from_slice(&[src, dst]);
+        cols[12..15].copy_from_slice(&[stream, sport, dport]);
+        for &(index, value) in fields {
+            cols[index] = value;
+        }
+        cols
+    }
+
+    #[test]
+    fn result_sets_and_statements() {
+        let mut analyzer = Analyzer::new(&test_args(&[]), 0);
+        let start = DateTime::from_timestamp(1738062028, 0).unwrap();
+        let select = "SELECT id FROM t WHERE x = 1";
+        for (ms, cols) in [
+            // connection from before the capture, result sets end with EOF markers
+            (0, packet(true, "1", &[(2, "3"), (3, select)])),
+            (
+                5,
+                packet(
+                    false,
+                    "1",
+                    &[(5, "0xfe,0xfe"), (10, "1"), (11, "1,2,3,4,5,6")],
+                ),
+            ),
+            // MySQL 8 login with CLIENT_DEPRECATE_EOF, a single OK ends the rows
+            (10, packet(true, "2", &[(11, "1"), (15, "0x01ff")])),
+            (12, packet(false, "2", &[(5, "0x00"), (11, "2")])),
+            (20, packet(true, "2", &[(2, "3"), (3, select)])),
+            (
+                30,
+                packet(false, "2", &[(5, "0xfe"), (10, "1"), (11, "1,2,3,4,5")]),
+            ),
+            // prepared statement failing on execute
+            (
+                40,
+                packet(true, "1", &[(2, "22"), (3, "SELECT * FROM u WHERE id = ?")]),
+            ),
+            (42, packet(false, "1", &[(4, "7"), (5, "0x00"), (11, "1")])),
+            (50, packet(true, "1", &[(2, "23"), (4, "7")])),
+            (
+                53,
+                packet(
+                    false,
+                    "1",
+                    &[
+                        (5, "0xff"),
+                        (6, "1146"),
+                        (7, "42S02"),
+                        (8, "Table 'u' doesn't exist"),
+                    ],
+                ),
+            ),
+        ] {
+            analyzer.analyze(start + TimeDelta::milliseconds(ms), cols);
+        }
+        assert!(analyzer.streams.values().all(|s| s.pending.is_none()));
+        assert_eq!(analyzer.unmatched_responses, 0);
+        assert_eq!(analyzer.profile.queries(), 3);
+        assert_eq!(analyzer.profile.rows(), 4);
+        assert_eq!(analyzer.profile.errors(), 1);
+        assert_eq!(analyzer.profile.fingerprints(), 2);
+    }
+}
@@ -0,0 +1,424 @@
+use super::{
+    Event, EventKind, ProtocolAnalyzer,
+    report::{self, DailyReport, LatencyStats},
+};
+use crate::{
+    ArgsCommand,
+    formatters::{EventFormatter, create_formatter},
+};
+use ahash::HashMap;
+use chrono::{DateTime, NaiveDateTime, TimeZone as _, Utc};
+use std::fmt::Write as _;
+
+const MODE_CLIENT: &str = "3";
+const MODE_SERVER: &str = "4";
+/// Clients whose clock is further off than this are reported
+const MAX_OFFSET_MS: f64 = 100.0;
+
+struct PendingRequest {
+    ts: DateTime<Utc>,
+    transmit: DateTime<Utc>,
+}
+
+#[derive(Default)]
+struct ServerStats {
+    requests: u32,
+    responses: u32,
+    timeouts: u32,
+    stratum: String,
+    leap: String,
+    kiss_codes: HashMap<String, u32>,
+    delay: LatencyStats,
+}
+
+#[derive(Default)]
+struct ClientStats {
+    requests: u32,
+    responses: u32,
+    offsets: u32,
+    offset_sum_ms: f64,
+    max_offset_ms: f64,
+    last_offset_ms: f64,
+    out_of_sync: bool,
+}
+
+pub struct Analyzer {
+    /// (client, server, transmit timestamp as printed) echoed back as origin timestamp
+    pending: HashMap<(String, String, String), PendingRequest>,
+    servers: HashMap<String, ServerStats>,
+    clients: HashMap<String, ClientStats>,
+    unmatched_responses: u32,
+    timeout: i64,
+    top: usize,
+    last_timeout_check: Option<DateTime<Utc>>,
+    formatter: Box<dyn EventFormatter>,
+    daily_report: DailyReport,
+}
+
+const PROTOCOL: &str = "ntp";
+
+/// NTP timestamps as printed by tshark, "Jan 28, 2025 11:00:28.123456789 UTC" or ISO 8601
+fn parse_ntp_time(value: &str) -> Option<DateTime<Utc>> {
+    if value.is_empty() || value.starts_with("NULL") {
+        return None;
+    }
+    if let Ok(ts) = DateTime::parse_from_rfc3339(value) {
+        return Some(ts.to_utc());
+    }
+    let value = value.split_whitespace().collect::<Vec<_>>().join(" ");
+    NaiveDateTime::parse_from_str(&value, "%b %d, %Y %H:%M:%S%.f UTC")
+        .ok()
+        .map(|d| Utc.from_utc_datetime(&d))
+}
+
+fn leap_name(li: &str) -> &'static str {
+    match li {
+        "0" => "no warning",
+        "1" => "last minute 61 s",
+        "2" => "last minute 59 s",
+        "3" => "unsynchronized",
+        _ => "-",
+    }
+}
+
+/// Kiss code of stratum 0 answers, the reference id holds 4 ASCII letters
+fn kiss_code(refid: &str) -> String {
+    let hex = refid.replace(':', "");
+    hex::decode(&hex)
+        .ok()
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+        .map(|code| code.trim_end_matches('\0').to_owned())
+        .filter(|code| !code.is_empty() && code.chars().all(|c| c.is_ascii_alphanumeric()))
+        .unwrap_or(hex)
+}
+
+fn ms(delta: chrono::TimeDelta) -> f64 {
+    delta.num_microseconds().unwrap_or_default() as f64 / 1000.0
+}
+
+/// (offset, delay) in ms, the client receive time is derived from the capture clock
+fn offset_delay(
+    request: &PendingRequest,
+    ts: DateTime<Utc>,
+    receive: DateTime<Utc>,
+    transmit: DateTime<Utc>,
+) -> (f64, f64) {
+    let t1 = request.transmit;
+    let t4 = t1 + (ts - request.ts);
+    let offset = (ms(receive - t1) + ms(transmit - t4)) / 2.0;
+    let delay = ms(t4 - t1) - ms(transmit - receive);
+    (offset, delay)
+}
+
+impl Analyzer {
+    fn expire_pending(&mut self, opt_ts: Option<DateTime<Utc>>) {
+        let timeout = self.timeout;
+        let expired = self
+            .pending
+            .extract_if(|_, r| opt_ts.is_none_or(|ts| (ts - r.ts).num_seconds() >= timeout))
+            .collect::<Vec<_>>();
+        for ((client, server, _), request) in expired {
+            self.servers.entry(server.clone()).or_default().timeouts += 1;
+            self.formatter.event(&Event {
+                ts: opt_ts.unwrap_or(request.ts),
+                protocol: PROTOCOL,
+                kind: EventKind::NoResponse,
+                user: client,
+                method: "CLIENT".into(),
+                addr: Some(server),
+                elapsed_s: opt_ts.map(|ts| (ts - request.ts).num_seconds()),
+                ..Default::default()
+            });
+        }
+    }
+
+    fn print_stats(&mut self, opt_ts: Option<DateTime<Utc>>) {
+        let mut output = String::with_capacity(200);
+        report::print_header(self.formatter.as_mut(), opt_ts);
+        report::print_section(self.formatter.as_mut(), "Servers");
+        let mut servers = Vec::from_iter(self.servers.keys());
+        servers.sort();
+        for server in servers {
+            let stats = &self.servers[server];
+            write!(
+                output,
+                "{server:<26} {:6} requests {:6} responses {:4} timeouts  stratum {:>2}  leap {}",
+                stats.requests,
+                stats.responses,
+                stats.timeouts,
+                stats.stratum,
+                leap_name(&stats.leap)
+            )
+            .unwrap();
+            for (code, count) in report::top_n(&stats.kiss_codes, usize::MAX) {
+                write!(output, " KoD {code} {count}").unwrap();
+            }
+            write!(output, "\n{:<26} delay {}", "", stats.delay.summary()).unwrap();
+            self.formatter.report(&output, 1);
+            output.clear();
+        }
+
+        report::print_section(self.formatter.as_mut(), "Clients By Offset");
+        let mut clients = self
+            .clients
+            .iter()
+            .filter(|(_, c)| c.offsets > 0)
+            .collect::<Vec<_>>();
+        clients.sort_by(|a, b| {
+            b.1.max_offset_ms
+                .total_cmp(&a.1.max_offset_ms)
+                .then(a.0.cmp(b.0))
+        });
+        for (client, stats) in clients.into_iter().take(self.top) {
+            self.formatter.report(
+                &format!(
+                    "{client:<26} {:6} responses  offset avg {:10.1} ms  max {:10.1} ms  last {:10.1} ms",
+                    stats.responses,
+                    stats.offset_sum_ms / stats.offsets as f64,
+                    stats.max_offset_ms,
+                    stats.last_offset_ms
+                ),
+                1,
+            );
+        }
+
+        report::print_section(self.formatter.as_mut(), "Clients Without Answers");
+        let mut unanswered = self
+            .clients
+            .iter()
+            .filter(|(_, c)| c.responses == 0)
+            .collect::<Vec<_>>();
+        unanswered.sort_by(|a, b| b.1.requests.cmp(&a.1.requests).then(a.0.cmp(b.0)));
+        for (client, stats) in unanswered {
+            self.formatter
+                .report(&format!("{:8} {client}", stats.requests), 1);
+        }
+        write!(
+            output,
+            r#"
+ ------------ STATS ------------
+
+- total requests: {}
+- total responses: {}
+- pending requests: {}
+- unmatched responses: {}
+- clients out of sync: {}
+"#,
+            self.clients.values().map(|c| c.requests).sum::<u32>(),
+            self.clients.values().map(|c| c.responses).sum::<u32>(),
+            self.pending.len(),
+            self.unmatched_responses,
+            self.clients.values().filter(|c| c.out_of_sync).count(),
+        )
+        .unwrap();
+        self.formatter.report(&output, 0);
+        report::print_footer(self.formatter.as_mut());
+    }
+}
+
+impl ProtocolAnalyzer for Analyzer {
+    fn new(cmd_args: &ArgsCommand, verbosity: u8) -> Self {
+        let (timeout, top) = match cmd_args {
+            ArgsCommand::Analyzer { timeout, top, .. } => (*timeout as i64, *top),
+            _ => (5, 10),
+        };
+        Self {
+            pending: HashMap::default(),
+            servers: HashMap::default(),
+            clients: HashMap::default(),
+            unmatched_responses: 0,
+            timeout,
+            top,
+            last_timeout_check: None,
+            formatter: create_formatter(cmd_args, verbosity),
+            daily_report: DailyReport::default(),
+        }
+    }
+
+    fn analyze(&mut self, ts: DateTime<Utc>, cols: Vec<&str>) {
+        let mode = cols[2];
+        let leap = cols[3];
+        let stratum = cols[4];
+        let src = cols[0].to_owned();
+        let dst = cols[1].to_owned();
+        if self
+            .last_timeout_check
+            .is_none_or(|last| (ts - last).num_seconds() >= 1)
+        {
+            self.expire_pending(Some(ts));
+            self.last_timeout_check = Some(ts);
+        }
+        if self.daily_report.is_due(ts) {
+            self.print_stats(Some(ts));
+        }
+
+        if mode == MODE_CLIENT {
+            self.clients.entry(src.clone()).or_default().requests += 1;
+            self.servers.entry(dst.clone()).or_default().requests += 1;
+            // SNTP clients may send a zero transmit time, the capture time stands in
+            let transmit = parse_ntp_time(cols[8]).unwrap_or(ts);
+            self.pending.insert(
+                (src, dst, cols[8].to_owned()),
+                PendingRequest { ts, transmit },
+            );
+            return;
+        }
+        if mode != MODE_SERVER {
+            return;
+        }
+
+        let key = (dst, src, cols[6].to_owned());
+        let Some(request) = self.pending.remove(&key) else {
+            self.unmatched_responses += 1;
+            return;
+        };
+        let (client, server, _) = key;
+        let client_stats = self.clients.entry(client.clone()).or_default();
+        client_stats.responses += 1;
+        let stats = self.servers.entry(server.clone()).or_default();
+        stats.responses += 1;
+        // kiss-o'-death packets carry leap 3 and stratum 0 without describing the server clock
+        if stratum == "0" {
+            let code = kiss_code(cols[5]);
+            *stats.kiss_codes.entry(code.clone()).or_default() += 1;
+            self.formatter.event(&Event {
+                ts,
+                protocol: PROTOCOL,
+                kind: EventKind::ErrorResponse,
+                user: client,
+                method: "CLIENT".into(),
+                addr: Some(server),
+                detail: Some(format!("kiss-o'-death {code}")),
+                ..Default::default()
+            });
+            return;
+        }
+        if stratum != stats.stratum && !stats.stratum.is_empty() {
+            self.formatter.event(&Event {
+                ts,
+                protocol: PROTOCOL,
+                kind: EventKind::Warning,
+                user: client.clone(),
+                method: "SERVER".into(),
+                addr: Some(server.clone()),
+                detail: Some(format!(
+                    "stratum changed from {} to {stratum}",
+                    stats.stratum
+                )),
+                ..Default::default()
+            });
+        }
+        stats.stratum = stratum.to_owned();
+        stats.leap = leap.to_owned();
+        if leap == "3" {
+            self.formatter.event(&Event {
+                ts,
+                protocol: PROTOCOL,
+                kind: EventKind::Warning,
+                user: client.clone(),
+                method: "SERVER".into(),
+                addr: Some(server.clone()),
+                detail: Some("server unsynchronized (leap indicator 3)".into()),
+                ..Default::default()
+            });
+        }
+        let (Some(receive), Some(transmit)) = (parse_ntp_time(cols[7]), parse_ntp_time(cols[8]))
+        else {
+            return;
+        };
+        let (offset_ms, delay_ms) = offset_delay(&request, ts, receive, transmit);
+        stats.delay.add(delay_ms);
+        let client_stats = self.clients.entry(client.clone()).or_default();
+        client_stats.offsets += 1;
+        client_stats.offset_sum_ms += offset_ms;
+        client_stats.last_offset_ms = offset_ms;
+        if offset_ms.abs() > client_stats.max_offset_ms.abs() {
+            client_stats.max_offset_ms = offset_ms;
+        }
+        let out_of_sync = offset_ms.abs() > MAX_OFFSET_MS;
+        if out_of_sync && !client_stats.out_of_sync {
+            self.formatter.event(&Event {
+                ts,
+                protocol: PROTOCOL,
+                kind: EventKind::Warning,
+                user: client,
+                method: "CLIENT".into(),
+                addr: Some(server),
+                latency_ms: Some(delay_ms),
+                detail: Some(format!("clock offset {offset_ms:.1} ms")),
+                ..Default::default()
+            });
+        }
+        client_stats.out_of_sync = out_of_sync;
+    }
+
+    fn add_protocol_fields(&self, tshark_args: &mut Vec<&str>) {
+        tshark_args.push("-e");
+        tshark_args.push("ntp.flags.mode");
+        tshark_args.push("-e");
+        tshark_args.push("ntp.flags.li");
+        tshark_args.push("-e");
+        tshark_args.push("ntp.stratum");
+        tshark_args.push("-e");
+        tshark_args.push("ntp.refid");
+        tshark_args.push("-e");
+        tshark_args.push("ntp.org");
+        tshark_args.push("-e");
+        tshark_args.push("ntp.rec");
+        tshark_args.push("-e");
+        tshark_args.push("ntp.xmt");
+        if !tshark_args.contains(&"-Y") {
+            tshark_args.push("-Y");
+            tshark_args.push("ntp");
+        }
+        if tshark_args.contains(&"-i") && !tshark_args.contains(&"-f") {
+            tshark_args.push("-f");
+            tshark_args.push("udp port 123");
+        }
+    }
+
+    fn end(&mut self) {
+        self.expire_pending(None);
+        self.print_stats(None);
+    }
+}
+
+// MARK: TESTS
+#[cfg(test)]
+mod test {
+    use chrono::{DateTime, TimeDelta};
+
+    use super::{PendingRequest, kiss_code, offset_delay, parse_ntp_time};
+
+    #[test]
+    fn ntp_time_formats() {
+        let expected = DateTime::from_timestamp(1738062028, 123456789);
+        assert_eq!(
+            parse_ntp_time("Jan 28, 2025 11:00:28.123456789 UTC"),
+            expected
+        );
+        assert_eq!(parse_ntp_time("2025-01-28T11:00:28.123456789Z"), expected);
+        assert_eq!(
+            parse_ntp_time("Feb  3, 2025 00:00:00.000000000 UTC"),
+            DateTime::from_timestamp(1738540800, 0)
+        );
+        assert_eq!(parse_ntp_time("NULL"), None);
+        assert_eq!(kiss_code("52415445"), "RATE");
+    }
+
+    #[test]
+    fn offset_and_delay() {
+        // client 500 ms ahead, 10 ms each way, server holds the request 1 ms
+        let t1 = DateTime::from_timestamp(1738062028, 500_000_000).unwrap();
+        let request = PendingRequest {
+            ts: t1 - TimeDelta::milliseconds(500),
+            transmit: t1,
+        };
+        let receive = request.ts + TimeDelta::milliseconds(10);
+        let transmit = receive + TimeDelta::milliseconds(1);
+        let ts = transmit + TimeDelta::milliseconds(10);
+        let (offset, delay) = offset_delay(&request, ts, receive, transmit);
+        assert!((offset + 500.0).abs() < 0.001, "{offset}");
+        assert!((delay - 20.0).abs() < 0.001, "{delay}");
+    }
+}
@@ -0,0 +1,427 @@
+use super::{
+    Event, EventKind, ProtocolAnalyzer,
+    fields::{next, occurrences},
+    report::{self, DailyReport},
+    sql::{self, QueryProfile},
+};
+use crate::{
+    ArgsCommand,
+    formatters::{EventFormatter, create_formatter},
+};
+use ahash::HashMap;
+use chrono::{DateTime, Utc};
+use std::{collections::VecDeque, fmt::Write as _};
+
+/// Frontend message types, everything else comes from the server
+const FRONTEND: [&str; 11] = [
+    "Simple query",
+    "Parse",
+    "Bind",
+    "Execute",
+    "Describe",
+    "Close",
+    "Sync",
+    "Flush",
+    "Termination",
+    "Startup message",
+    "Password message",
+];
+
+struct PendingQuery {
+    ts: DateTime<Utc>,
+    query: String,
+    /// Simple queries complete at Ready for query and may run several statements
+    simple: bool,
+    rows: u64,
+    error: Option<(String, String)>,
+}
+
+#[derive(Default)]
+struct Stream {
+    client: String,
+    server: String,
+    /// Prepared statement name to query text, "" is the unnamed statement
+    statements: HashMap<String, String>,
+    /// Query of the statement in the last Bind
+    bound: String,
+    pending: VecDeque<PendingQuery>,
+    /// After an error the server skips messages until Sync
+    aborted: bool,
+}
+
+pub struct Analyzer {
+    streams: HashMap<String, Stream>,
+    profile: QueryProfile,
+    timeouts: u32,
+    unmatched_responses: u32,
+    timeout: i64,
+    top: usize,
+    last_timeout_check: Option<DateTime<Utc>>,
+    formatter: Box<dyn EventFormatter>,
+    daily_report: DailyReport,
+}
+
+const PROTOCOL: &str = "pgsql";
+
+/// Row count of a command tag such as "SELECT 5" or "INSERT 0 1"
+fn tag_rows(tag: &str) -> u64 {
+    tag.rsplit(' ')
+        .next()
+        .and_then(|n| n.parse().ok())
+        .unwrap_or_default()
+}
+
+impl Analyzer {
+    fn finish(&mut self, ts: DateTime<Utc>, client: &str, server: &str, query: PendingQuery) {
+        let latency = (ts - query.ts).num_microseconds().unwrap_or_default() as f64 / 1000.0;
+        let fingerprint = if query.query.is_empty() {
+            "<unknown>".to_owned()
+        } else {
+            sql::fingerprint(&query.query)
+        };
+        let client_host = client.rsplit_once(':').map_or(client, |(h, _)| h);
+        if let Some((code, message)) = &query.error {
+            self.formatter.event(&Event {
+                ts,
+                protocol: PROTOCOL,
+                kind: EventKind::ErrorResponse,
+                user: client.to_owned(),
+                method: sql::verb(&fingerprint),
+                addr: Some(server.to_owned()),
+                latency_ms: Some(latency),
+                detail: Some(format!(
+                    "{code} {message}: {}",
+                    sql::abbreviate(&fingerprint, 120)
+                )),
+                ..Default::default()
+            });
+        }
+        self.profile.record(
+            client_host,
+            fingerprint,
+            latency,
+            query.rows,
+            query
+                .error
+                .as_ref()
+                .map(|(code, message)| (code.as_str(), message.as_str())),
+        );
+    }
+
+    fn expire_pending(&mut self, opt_ts: Option<DateTime<Utc>>) {
+        let timeout = self.timeout;
+        let mut expired = Vec::new();
+        for stream in self.streams.values_mut() {
+            // queries complete in order, only the oldest ones can expire
+            while stream
+                .pending
+                .front()
+                .is_some_and(|q| opt_ts.is_none_or(|ts| (ts - q.ts).num_seconds() >= timeout))
+            {
+                let query = stream.pending.pop_front().unwrap();
+                expired.push((stream.client.clone(), stream.server.clone(), query));
+            }
+        }
+        for (client, server, query) in expired {
+            self.timeouts += 1;
+            let fingerprint = sql::fingerprint(&query.query);
+            self.formatter.event(&Event {
+                ts: opt_ts.unwrap_or(query.ts),
+                protocol: PROTOCOL,
+                kind: EventKind::NoResponse,
+                user: client,
+                method: sql::verb(&fingerprint),
+                addr: Some(server),
+                elapsed_s: opt_ts.map(|ts| (ts - query.ts).num_seconds()),
+                detail: Some(sql::abbreviate(&fingerprint, 120)),
+                ..Default::default()
+            });
+        }
+    }
+
+    fn print_stats(&mut self, opt_ts: Option<DateTime<Utc>>) {
+        let mut output = String::with_capacity(200);
+        report::print_header(self.formatter.as_mut(), opt_ts);
+        self.profile.print(self.formatter.as_mut(), self.top);
+        write!(
+            output,
+            r#"
+ ------------ STATS ------------
+
+- queries: {}
+- rows: {}
+- errors: {}
+- fingerprints: {}
+- timeouts: {}
+- unmatched responses: {}
+- open streams: {}
+"#,
+            self.profile.queries(),
+            self.profile.rows(),
+            self.profile.errors(),
+            self.profile.fingerprints(),
+            self.timeouts,
+            self.unmatched_responses,
+            self.streams.len(),
+        )
+        .unwrap();
+        self.formatter.report(&output, 0);
+        report::print_footer(self.formatter.as_mut());
+    }
+}
+
+impl ProtocolAnalyzer for Analyzer {
+    fn new(cmd_args: &ArgsCommand, verbosity: u8) -> Self {
+        let (timeout, top) = match cmd_args {
+            ArgsCommand::Analyzer { timeout, top, .. } => (*timeout as i64, *top),
+            _ => (5, 10),
+        };
+        Self {
+            streams: HashMap::default(),
+            profile: QueryProfile::default(),
+            timeouts: 0,
+            unmatched_responses: 0,
+            timeout,
+            top,
+            last_timeout_check: None,
+            formatter: create_formatter(cmd_args, verbosity),
+            daily_report: DailyReport::default(),
+        }
+    }
+
+    fn analyze(&mut self, ts: DateTime<Utc>, cols: Vec<&str>) {
+        if self
+            .last_timeout_check
+            .is_none_or(|last| (ts - last).num_seconds() >= 1)
+        {
+            self.expire_pending(Some(ts));
+            self.last_timeout_check = Some(ts);
+        }
+        if self.daily_report.is_due(ts) {
+            self.print_stats(Some(ts));
+        }
+        let types = cols[2].split(',').collect::<Vec<_>>();
+        let count = |names: &[&str]| types.iter().filter(|t| names.contains(t)).count();
+        let src = format!("{}:{}", cols[0], cols[9]);
+        let dst = format!("{}:{}", cols[1], cols[10]);
+        let frontend = types.iter().any(|t| FRONTEND.contains(t));
+        if !frontend && !self.streams.contains_key(cols[8]) {
+            // the connection started before the capture
+            return;
+        }
+        let stream = self.streams.entry(cols[8].to_owned()).or_default();
+        if frontend && stream.client.is_empty() {
+            stream.client = src.clone();
+            stream.server = dst.clone();
+        }
+
+        let mut queries = occurrences(cols[3], count(&["Simple query", "Parse"]));
+        // statement names have no commas but Describe and Close may carry them too
+        let names = cols[4].split(',').collect::<Vec<_>>();
+        let mut names = if names.len() == count(&["Parse", "Bind"]) {
+            names.into_iter()
+        } else {
+            Vec::new().into_iter()
+        };
+        let mut tags = occurrences(cols[5], count(&["Command completion"]));
+        let errors = count(&["Error", "Notice"]);
+        let mut codes = occurrences(cols[6], errors);
+        let mut messages = occurrences(cols[7], errors);
+        let mut completed = Vec::new();
+        let mut terminated = false;
+        for message_type in types {
+            match message_type {
+                "Simple query" => stream.pending.push_back(PendingQuery {
+                    ts,
+                    query: next(&mut queries).to_owned(),
+                    simple: true,
+                    rows: 0,
+                    error: None,
+                }),
+                "Parse" => {
+                    let query = next(&mut queries).to_owned();
+                    stream.statements.insert(next(&mut names).to_owned(), query);
+                }
+                "Bind" => {
+                    stream.bound = stream
+                        .statements
+                        .get(next(&mut names))
+                        .cloned()
+                        .unwrap_or_default();
+                }
+                "Execute" => stream.pending.push_back(PendingQuery {
+                    ts,
+                    query: stream.bound.clone(),
+                    simple: false,
+                    rows: 0,
+                    error: None,
+                }),
+                "Termination" => terminated = true,
+                "Command completion" | "Empty query" | "Portal suspended" => {
+                    let rows = if message_type == "Command completion" {
+                        tag_rows(next(&mut tags))
+                    } else {
+                        0
+                    };
+                    match stream.pending.front_mut() {
+                        Some(query) if query.simple => query.rows += rows,
+                        Some(_) => {
+                            let mut query = stream.pending.pop_front().unwrap();
+                            query.rows = rows;
+                            completed.push(query);
+                        }
+                        None => self.unmatched_responses += 1,
+                    }
+                }
+                "Error" | "Notice" => {
+                    let code = next(&mut codes).to_owned();
+                    let message = next(&mut messages).to_owned();
+                    if message_type == "Notice" {
+                        continue;
+                    }
+                    match stream.pending.front_mut() {
+                        Some(query) if query.simple => query.error = Some((code, message)),
+                        Some(_) => {
+                            let mut query = stream.pending.pop_front().unwrap();
+                            query.error = Some((code, message));
+                            completed.push(query);
+                            stream.aborted = true;
+                        }
+                        None => self.unmatched_responses += 1,
+                    }
+                }
+                "Ready for query" => {
+                    if stream.pending.front().is_some_and(|q| q.simple) {
+                        completed.push(stream.pending.pop_front().unwrap());
+                    }
+                    if stream.aborted {
+                        stream.pending.retain(|q| q.simple);
+                        stream.aborted = false;
+                    }
+                }
+                _ => {}
+            }
+        }
+        let (client, server) = (stream.client.clone(), stream.server.clone());
+        if terminated {
+            self.streams.remove(cols[8]);
+        }
+        for query in completed {
+            self.finish(ts, &client, &server, query);
+        }
+    }
+
+    fn add_protocol_fields(&self, tshark_args: &mut Vec<&str>) {
+        tshark_args.push("-e");
+        tshark_args.push("pgsql.type");
+        tshark_args.push("-e");
+        tshark_args.push("pgsql.query");
+        tshark_args.push("-e");
+        tshark_args.push("pgsql.statement");
+        tshark_args.push("-e");
+        tshark_args.push("pgsql.tag");
+        tshark_args.push("-e");
+        tshark_args.push("pgsql.code");
+        tshark_args.push("-e");
+        tshark_args.push("pgsql.message");
+        tshark_args.push("-e");
+        tshark_args.push("tcp.stream");
+        tshark_args.push("-e");
+        tshark_args.push("tcp.srcport");
+        tshark_args.push("-e");
+        tshark_args.push("tcp.dstport");
+        if !tshark_args.contains(&"-Y") {
+            tshark_args.push("-Y");
+            tshark_args.push("pgsql");
+        }
+        if tshark_args.contains(&"-i") && !tshark_args.contains(&"-f") {
+            tshark_args.push("-f");
+            tshark_args.push("tcp port 5432");
+        }
+    }
+
+    fn end(&mut self) {
+        self.expire_pending(None);
+        self.print_stats(None);
+    }
+}
+
+// MARK: TESTS
+#[cfg(test)]
+mod test {
+    use chrono::{DateTime, TimeDelta};
+
+    use super::{Analyzer, ProtocolAnalyzer as _};
+    use crate::analyzers::test_args;
+
+    #[test]
+    fn query_completion() {
+        let mut analyzer = Analyzer::new(&test_args(&[]), 0);
+        let start = DateTime::from_timestamp(1738062028, 0).unwrap();
+        let rows = "Row description,Data row,Command completion";
+        let simple_response = format!("{rows},{rows},Ready for query");
+        let extended_response = format!("Parse completion,Bind completion,{rows},Ready for query");
+        let select = "SELECT name FROM users WHERE id = $1";
+        let error = "relation \"users\" does not exist";
+        // types, query, statement, command tag, error code and message
+        for (ms, from_client, fields) in [
+            // two statements in one simple query complete together at Ready for query
+            (
+                0,
+                true,
+                ["Simple query", "SELECT 1; SELECT 2", "", "", "", ""],
+            ),
+            (
+                5,
+                false,
+                [
+                    simple_response.as_str(),
+                    "",
+                    "",
+                    "SELECT 1,SELECT 1",
+                    "",
+                    "",
+                ],
+            ),
+            (
+                10,
+                true,
+                ["Parse,Bind,Execute,Sync", select, "s1,s1", "", "", ""],
+            ),
+            (
+                14,
+                false,
+                [extended_response.as_str(), "", "", "SELECT 1", "", ""],
+            ),
+            // the server skips the second Execute after the error until Sync
+            (
+                20,
+                true,
+                ["Bind,Execute,Bind,Execute,Sync", "", "s1,s1", "", "", ""],
+            ),
+            (
+                23,
+                false,
+                ["Error,Ready for query", "", "", "", "42P01", error],
+            ),
+        ] {
+            let ([src, dst], [sport, dport]) = if from_client {
+                (["10.0.0.1", "10.0.0.2"], ["50000", "5432"])
+            } else {
+                (["10.0.0.2", "10.0.0.1"], ["5432", "50000"])
+            };
+            let [types, query, statement, tag, code, message] = fields;
+            let cols = vec![
+                src, dst, types, query, statement, tag, code, message, "1", sport, dport,
+            ];
+            analyzer.analyze(start + TimeDelta::milliseconds(ms), cols);
+        }
+        assert!(analyzer.streams["1"].pending.is_empty());
+        assert!(!analyzer.streams["1"].aborted);
+        assert_eq!(analyzer.unmatched_responses, 0);
+        assert_eq!(analyzer.timeouts, 0);
+        assert_eq!(analyzer.profile.queries(), 3);
+        assert_eq!(analyzer.profile.rows(), 3);
+        assert_eq!(analyzer.profile.errors(), 1);
+    }
+}
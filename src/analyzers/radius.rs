@@ -0,0 +1,581 @@
+use super::{
+    Event, EventKind, ProtocolAnalyzer,
+    report::{self, DailyReport, LatencyStats},
+};
+use crate::{
+    ArgsCommand,
+    formatters::{EventFormatter, create_formatter},
+};
+use ahash::HashMap;
+use chrono::{DateTime, Utc};
+use std::fmt::Write as _;
+
+const ACCESS_REQUEST: &str = "1";
+const ACCESS_ACCEPT: &str = "2";
+const ACCESS_REJECT: &str = "3";
+const ACCOUNTING_REQUEST: &str = "4";
+const ACCOUNTING_RESPONSE: &str = "5";
+const ACCESS_CHALLENGE: &str = "11";
+
+/// Accounting sessions without updates for this many interim intervals are dropped
+const SESSION_INTERIMS: i64 = 3;
+/// Interim interval assumed until a session sent two updates
+const DEFAULT_INTERIM_S: i64 = 3600;
+
+struct PendingRequest {
+    ts: DateTime<Utc>,
+    code: &'static str,
+    authenticator: String,
+    user: String,
+    nas: String,
+    retries: u32,
+}
+
+#[derive(Default)]
+struct AuthStats {
+    requests: u32,
+    accepts: u32,
+    rejects: u32,
+    challenges: u32,
+    timeouts: u32,
+    retransmissions: u32,
+}
+
+impl AuthStats {
+    fn summary(&self, key: &str) -> String {
+        let answered = self.accepts + self.rejects;
+        format!(
+            "{key:<30} {:6} requests {:6} accepts {:6} rejects {:5.1} %  {:4} challenges {:4} timeouts {:4} retransmissions",
+            self.requests,
+            self.accepts,
+            self.rejects,
+            self.rejects as f64 * 100.0 / answered.max(1) as f64,
+            self.challenges,
+            self.timeouts,
+            self.retransmissions
+        )
+    }
+}
+
+#[derive(Default)]
+struct ServerStats {
+    requests: u32,
+    timeouts: u32,
+    latency: LatencyStats,
+}
+
+#[derive(Default)]
+struct AccountingSession {
+    user: String,
+    nas: String,
+    start: Option<DateTime<Utc>>,
+    last_update: DateTime<Utc>,
+    /// Time between the last two updates
+    interim_s: Option<i64>,
+    duration_s: Option<i64>,
+    input_octets: u64,
+    output_octets: u64,
+    interims: u32,
+    stopped: bool,
+}
+
+impl AccountingSession {
+    fn duration_s(&self) -> i64 {
+        self.duration_s.unwrap_or_else(|| {
+            self.start
+                .map_or(0, |start| (self.last_update - start).num_seconds())
+        })
+    }
+
+    fn is_stale(&self, ts: DateTime<Utc>) -> bool {
+        let interim_s = self.interim_s.unwrap_or(DEFAULT_INTERIM_S).max(1);
+        (ts - self.last_update).num_seconds() > interim_s * SESSION_INTERIMS
+    }
+}
+
+pub struct Analyzer {
+    pending: HashMap<(String, String, String), PendingRequest>,
+    servers: HashMap<String, ServerStats>,
+    nas: HashMap<String, AuthStats>,
+    users: HashMap<String, AuthStats>,
+    sessions: HashMap<String, AccountingSession>,
+    codes: HashMap<&'static str, u32>,
+    unmatched_responses: u32,
+    lost_sessions: u32,
+    timeout: i64,
+    top: usize,
+    last_timeout_check: Option<DateTime<Utc>>,
+    formatter: Box<dyn EventFormatter>,
+    daily_report: DailyReport,
+}
+
+const PROTOCOL: &str = "radius";
+
+fn code_name(code: &str) -> &'static str {
+    match code {
+        ACCESS_REQUEST => "Access-Request",
+        ACCESS_ACCEPT => "Access-Accept",
+        ACCESS_REJECT => "Access-Reject",
+        ACCOUNTING_REQUEST => "Accounting-Request",
+        ACCOUNTING_RESPONSE => "Accounting-Response",
+        ACCESS_CHALLENGE => "Access-Challenge",
+        "40" => "Disconnect-Request",
+        "41" => "Disconnect-ACK",
+        "42" => "Disconnect-NAK",
+        "43" => "CoA-Request",
+        "44" => "CoA-ACK",
+        "45" => "CoA-NAK",
+        _ => "Other",
+    }
+}
+
+impl Analyzer {
+    fn on_accounting(&mut self, ts: DateTime<Utc>, user: &str, nas: &str, cols: &[&str]) {
+        let session_id = cols[9];
+        if session_id.is_empty() {
+            return;
+        }
+        let known = self.sessions.contains_key(session_id);
+        let session = self.sessions.entry(session_id.to_owned()).or_default();
+        session.user = user.to_owned();
+        session.nas = nas.to_owned();
+        if known && cols[8] == "3" {
+            session.interim_s = Some((ts - session.last_update).num_seconds());
+        }
+        session.last_update = ts;
+        // counters are cumulative over the session, keep the latest
+        if let Ok(octets) = cols[10].parse() {
+            session.input_octets = octets;
+        }
+        if let Ok(octets) = cols[11].parse() {
+            session.output_octets = octets;
+        }
+        if let Ok(seconds) = cols[12].parse() {
+            session.duration_s = Some(seconds);
+        }
+        match cols[8] {
+            "1" => session.start = Some(ts),
+            "2" => session.stopped = true,
+            "3" => session.interims += 1,
+            _ => (),
+        }
+    }
+
+    fn expire_pending(&mut self, opt_ts: Option<DateTime<Utc>>) {
+        let timeout = self.timeout;
+        let expired = self
+            .pending
+            .extract_if(|_, r| opt_ts.is_none_or(|ts| (ts - r.ts).num_seconds() >= timeout))
+            .collect::<Vec<_>>();
+        for (key, request) in expired {
+            self.on_timeout(opt_ts, key, request, "");
+        }
+
+        // sessions stay in the report until they missed several interim updates
+        let Some(ts) = opt_ts else {
+            return;
+        };
+        let stale = self
+            .sessions
+            .extract_if(|_, s| s.is_stale(ts))
+            .collect::<Vec<_>>();
+        for (id, session) in stale.into_iter().filter(|(_, s)| !s.stopped) {
+            self.lost_sessions += 1;
+            self.formatter.event(&Event {
+                ts,
+                protocol: PROTOCOL,
+                kind: EventKind::Warning,
+                user: session.user,
+                method: code_name(ACCOUNTING_REQUEST).to_owned(),
+                addr: Some(session.nas),
+                elapsed_s: Some((ts - session.last_update).num_seconds()),
+                detail: Some(format!(
+                    "accounting session {id} lost after {} interims without Stop",
+                    session.interims
+                )),
+                ..Default::default()
+            });
+        }
+    }
+
+    /// Request never answered, either expired or replaced by a new request on its id
+    fn on_timeout(
+        &mut self,
+        opt_ts: Option<DateTime<Utc>>,
+        (client, server, id): (String, String, String),
+        request: PendingRequest,
+        reason: &str,
+    ) {
+        self.servers.entry(server.clone()).or_default().timeouts += 1;
+        if request.code == ACCESS_REQUEST {
+            self.nas.entry(request.nas.clone()).or_default().timeouts += 1;
+            self.users.entry(request.user.clone()).or_default().timeouts += 1;
+        }
+        self.formatter.event(&Event {
+            ts: opt_ts.unwrap_or(request.ts),
+            protocol: PROTOCOL,
+            kind: EventKind::NoResponse,
+            user: request.user,
+            method: code_name(request.code).to_owned(),
+            addr: Some(server),
+            elapsed_s: opt_ts.map(|ts| (ts - request.ts).num_seconds()),
+            detail: Some(format!(
+                "id {id} from {client} after {} retransmissions{reason}",
+                request.retries
+            )),
+            ..Default::default()
+        });
+    }
+
+    fn print_stats(&mut self, opt_ts: Option<DateTime<Utc>>) {
+        let mut output = String::with_capacity(200);
+        report::print_header(self.formatter.as_mut(), opt_ts);
+        report::print_section(self.formatter.as_mut(), "Servers");
+        let mut servers = Vec::from_iter(self.servers.keys());
+        servers.sort();
+        for server in servers {
+            let stats = &self.servers[server];
+            write!(
+                output,
+                "{server:<22} {:6} requests {:4} timeouts\n{:<22} {}",
+                stats.requests,
+                stats.timeouts,
+                "",
+                stats.latency.summary()
+            )
+            .unwrap();
+            self.formatter.report(&output, 1);
+            output.clear();
+        }
+
+        report::print_section(self.formatter.as_mut(), "NAS");
+        let rejects = HashMap::from_iter(self.nas.iter().map(|(nas, s)| (nas, s.rejects)));
+        for (nas, _) in report::top_n(&rejects, usize::MAX) {
+            self.formatter.report(&self.nas[*nas].summary(nas), 1);
+        }
+        report::print_section(self.formatter.as_mut(), "Top Rejected Users");
+        let rejects = HashMap::from_iter(
+            self.users
+                .iter()
+                .filter(|(_, s)| s.rejects > 0)
+                .map(|(user, s)| (user, s.rejects)),
+        );
+        for (user, _) in report::top_n(&rejects, self.top) {
+            self.formatter.report(&self.users[*user].summary(user), 1);
+        }
+
+        report::print_section(self.formatter.as_mut(), "Top Accounting Sessions");
+        let bytes = HashMap::from_iter(
+            self.sessions
+                .iter()
+                .map(|(id, s)| (id, s.input_octets + s.output_octets)),
+        );
+        for (id, _) in report::top_n(&bytes, self.top) {
+            let session = &self.sessions[*id];
+            self.formatter.report(
+                &format!(
+                    "{id:<24} {:<24} {:<16} {:8} s {:12} in {:12} out {:4} interims  {}",
+                    session.user,
+                    session.nas,
+                    session.duration_s(),
+                    session.input_octets,
+                    session.output_octets,
+                    session.interims,
+                    if session.stopped { "STOPPED" } else { "ACTIVE" }
+                ),
+                1,
+            );
+        }
+        report::print_section(self.formatter.as_mut(), "Packet Codes");
+        for (code, count) in report::top_n(&self.codes, usize::MAX) {
+            self.formatter.report(&format!("{count:8} {code}"), 1);
+        }
+        write!(
+            output,
+            r#"
+ ------------ STATS ------------
+
+- total requests: {}
+- total timeouts: {}
+- pending requests: {}
+- unmatched responses: {}
+- retransmissions: {}
+- accounting sessions: {} active {} stopped
+- lost accounting sessions: {}
+"#,
+            self.servers.values().map(|s| s.requests).sum::<u32>(),
+            self.servers.values().map(|s| s.timeouts).sum::<u32>(),
+            self.pending.len(),
+            self.unmatched_responses,
+            self.nas.values().map(|s| s.retransmissions).sum::<u32>(),
+            self.sessions.values().filter(|s| !s.stopped).count(),
+            self.sessions.values().filter(|s| s.stopped).count(),
+            self.lost_sessions,
+        )
+        .unwrap();
+        self.formatter.report(&output, 0);
+        report::print_footer(self.formatter.as_mut());
+    }
+}
+
+impl ProtocolAnalyzer for Analyzer {
+    fn new(cmd_args: &ArgsCommand, verbosity: u8) -> Self {
+        let (timeout, top) = match cmd_args {
+            ArgsCommand::Analyzer { timeout, top, .. } => (*timeout as i64, *top),
+            _ => (5, 10),
+        };
+        Self {
+            pending: HashMap::default(),
+            servers: HashMap::default(),
+            nas: HashMap::default(),
+            users: HashMap::default(),
+            sessions: HashMap::default(),
+            codes: HashMap::default(),
+            unmatched_responses: 0,
+            lost_sessions: 0,
+            timeout,
+            top,
+            last_timeout_check: None,
+            formatter: create_formatter(cmd_args, verbosity),
+            daily_report: DailyReport::default(),
+        }
+    }
+
+    fn analyze(&mut self, ts: DateTime<Utc>, cols: Vec<&str>) {
+        let code = code_name(cols[2]);
+        let id = cols[3];
+        let authenticator = cols[4];
+        let user = cols[5];
+        // NAS-IP-Address, NAS-Identifier, then the packet source
+        let nas = [cols[6], cols[7], cols[0]]
+            .into_iter()
+            .find(|n| !n.is_empty())
+            .unwrap_or_default();
+        let src = format!("{}:{}", cols[0], cols[13]);
+        let dst = format!("{}:{}", cols[1], cols[14]);
+        if self
+            .last_timeout_check
+            .is_none_or(|last| (ts - last).num_seconds() >= 1)
+        {
+            self.expire_pending(Some(ts));
+            self.last_timeout_check = Some(ts);
+        }
+        if self.daily_report.is_due(ts) {
+            self.print_stats(Some(ts));
+        }
+        *self.codes.entry(code).or_default() += 1;
+
+        if matches!(cols[2], ACCESS_REQUEST | ACCOUNTING_REQUEST) {
+            let key = (src, dst, id.to_owned());
+            // same id and authenticator is the client retrying the same request
+            if let Some(request) = self.pending.get_mut(&key)
+                && request.authenticator == authenticator
+            {
+                request.retries += 1;
+                self.nas.entry(nas.to_owned()).or_default().retransmissions += 1;
+                if cols[2] == ACCESS_REQUEST {
+                    self.users
+                        .entry(user.to_owned())
+                        .or_default()
+                        .retransmissions += 1;
+                }
+                if request.retries == 1 {
+                    self.formatter.event(&Event {
+                        ts,
+                        protocol: PROTOCOL,
+                        kind: EventKind::Warning,
+                        user: user.to_owned(),
+                        method: code.to_owned(),
+                        addr: Some(key.1),
+                        elapsed_s: Some((ts - request.ts).num_seconds()),
+                        detail: Some(format!("retransmission of id {id} from {nas}")),
+                        ..Default::default()
+                    });
+                }
+                return;
+            }
+            // a new request reusing the id of an unanswered one, that one will never be matched
+            if let Some(displaced) = self.pending.remove(&key) {
+                self.on_timeout(Some(ts), key.clone(), displaced, ", id reused");
+            }
+            self.servers.entry(key.1.clone()).or_default().requests += 1;
+            if cols[2] == ACCESS_REQUEST {
+                self.nas.entry(nas.to_owned()).or_default().requests += 1;
+                self.users.entry(user.to_owned()).or_default().requests += 1;
+            } else {
+                self.on_accounting(ts, user, nas, &cols);
+            }
+            self.pending.insert(
+                key,
+                PendingRequest {
+                    ts,
+                    code: if cols[2] == ACCESS_REQUEST {
+                        ACCESS_REQUEST
+                    } else {
+                        ACCOUNTING_REQUEST
+                    },
+                    authenticator: authenticator.to_owned(),
+                    user: user.to_owned(),
+                    nas: nas.to_owned(),
+                    retries: 0,
+                },
+            );
+            return;
+        }
+
+        let key = (dst, src, id.to_owned());
+        let Some(request) = self.pending.remove(&key) else {
+            self.unmatched_responses += 1;
+            return;
+        };
+        let (_, server, _) = key;
+        let latency_ms = (ts - request.ts).num_microseconds().unwrap_or_default() as f64 / 1000.0;
+        self.servers
+            .entry(server.clone())
+            .or_default()
+            .latency
+            .add(latency_ms);
+        if request.code == ACCOUNTING_REQUEST {
+            return;
+        }
+        let nas_stats = self.nas.entry(request.nas.clone()).or_default();
+        let user_stats = self.users.entry(request.user.clone()).or_default();
+        match cols[2] {
+            ACCESS_ACCEPT => {
+                nas_stats.accepts += 1;
+                user_stats.accepts += 1;
+            }
+            ACCESS_CHALLENGE => {
+                nas_stats.challenges += 1;
+                user_stats.challenges += 1;
+            }
+            ACCESS_REJECT => {
+                nas_stats.rejects += 1;
+                user_stats.rejects += 1;
+                self.formatter.event(&Event {
+                    ts,
+                    protocol: PROTOCOL,
+                    kind: EventKind::ErrorResponse,
+                    user: request.user,
+                    method: code_name(request.code).to_owned(),
+                    addr: Some(server),
+                    latency_ms: Some(latency_ms),
+                    detail: Some(format!("{code} for NAS {}", request.nas)),
+                    ..Default::default()
+                });
+            }
+            _ => (),
+        }
+    }
+
+    fn add_protocol_fields(&self, tshark_args: &mut Vec<&str>) {
+        tshark_args.push("-e");
+        tshark_args.push("radius.code");
+        tshark_args.push("-e");
+        tshark_args.push("radius.id");
+        tshark_args.push("-e");
+        tshark_args.push("radius.authenticator");
+        tshark_args.push("-e");
+        tshark_args.push("radius.User_Name");
+        tshark_args.push("-e");
+        tshark_args.push("radius.NAS_IP_Address");
+        tshark_args.push("-e");
+        tshark_args.push("radius.NAS_Identifier");
+        tshark_args.push("-e");
+        tshark_args.push("radius.Acct_Status_Type");
+        tshark_args.push("-e");
+        tshark_args.push("radius.Acct_Session_Id");
+        tshark_args.push("-e");
+        tshark_args.push("radius.Acct_Input_Octets");
+        tshark_args.push("-e");
+        tshark_args.push("radius.Acct_Output_Octets");
+        tshark_args.push("-e");
+        tshark_args.push("radius.Acct_Session_Time");
+        tshark_args.push("-e");
+        tshark_args.push("udp.srcport");
+        tshark_args.push("-e");
+        tshark_args.push("udp.dstport");
+        if !tshark_args.contains(&"-Y") {
+            tshark_args.push("-Y");
+            tshark_args.push("radius");
+        }
+        if tshark_args.contains(&"-i") && !tshark_args.contains(&"-f") {
+            tshark_args.push("-f");
+            tshark_args.push("udp port 1812 or udp port 1813 or udp port 1645 or udp port 1646");
+        }
+    }
+
+    fn end(&mut self) {
+        self.expire_pending(None);
+        self.print_stats(None);
+    }
+}
+
+// MARK: TESTS
+#[cfg(test)]
+mod test {
+    use chrono::{DateTime, TimeDelta};
+
+    use super::{Analyzer, ProtocolAnalyzer as _};
+    use crate::analyzers::test_args;
+
+    #[test]
+    fn retransmission_and_reused_id() {
+        let mut analyzer = Analyzer::new(&test_args(&[]), 0);
+        let start = DateTime::from_timestamp(1738062028, 0).unwrap();
+        // retried once, then the NAS gives up and reuses the id for a new request
+        for (ms, code, auth) in [
+            (0, "1", "aa"),
+            (1000, "1", "aa"),
+            (2000, "1", "bb"),
+            (2020, "2", "cc"),
+        ] {
+            let (src, dst, sport, dport) = if code == "1" {
+                ("10.0.0.1", "10.0.0.2", "50000", "1812")
+            } else {
+                ("10.0.0.2", "10.0.0.1", "1812", "50000")
+            };
+            let cols = vec![
+                src, dst, code, "7", auth, "alice", "10.0.0.1", "", "", "", "", "", "", sport,
+                dport,
+            ];
+            analyzer.analyze(start + TimeDelta::milliseconds(ms), cols);
+        }
+        analyzer.end();
+        assert!(analyzer.pending.is_empty());
+        assert_eq!(analyzer.unmatched_responses, 0);
+        let server = &analyzer.servers["10.0.0.2:1812"];
+        assert_eq!((server.requests, server.timeouts), (2, 1));
+        assert_eq!(server.latency.max(), 20.0);
+        let nas = &analyzer.nas["10.0.0.1"];
+        assert_eq!(
+            (nas.requests, nas.retransmissions, nas.timeouts, nas.accepts),
+            (2, 1, 1, 1)
+        );
+    }
+
+    #[test]
+    fn accounting_sessions_age_out() {
+        let mut analyzer = Analyzer::new(&test_args(&[]), 0);
+        let start = DateTime::from_timestamp(1738062028, 0).unwrap();
+        // s1 sends interims every 5 min then goes silent, s2 stops
+        for (s, id, status, session) in [
+            (0, "1", "1", "s1"),
+            (0, "2", "1", "s2"),
+            (300, "3", "3", "s1"),
+            (600, "4", "3", "s1"),
+            (700, "5", "2", "s2"),
+            (1501, "6", "3", "s3"),
+        ] {
+            let cols = vec![
+                "10.0.0.1", "10.0.0.2", "4", id, "aa", "bob", "10.0.0.1", "", status, session,
+                "100", "200", "", "50000", "1813",
+            ];
+            analyzer.analyze(start + TimeDelta::seconds(s), cols);
+        }
+        assert_eq!(analyzer.lost_sessions, 1);
+        assert!(!analyzer.sessions.contains_key("s1"));
+        assert!(analyzer.sessions["s2"].stopped);
+        assert_eq!(analyzer.sessions.len(), 2);
+    }
+}
@@ -0,0 +1,637 @@
+use super::{
+    Event, EventKind, ProtocolAnalyzer,
+    report::{self, DailyReport, LatencyStats},
+};
+use crate::{
+    ArgsCommand,
+    formatters::{EventFormatter, create_formatter},
+};
+use ahash::HashMap;
+use chrono::{DateTime, Utc};
+use std::{collections::VecDeque, fmt::Write as _};
+
+const REDIS_PORT: &str = "6379";
+/// Redis' own slowlog default
+const SLOW_MS: f64 = 10.0;
+/// Multi-key commands touching more keys than this are flagged
+const LARGE_BATCH: usize = 100;
+/// Keys and prefixes beyond this are only counted, key spaces are usually unbounded
+const MAX_KEYS: usize = 10_000;
+
+/// Commands that stall every client of the server while they run
+const SERVER_BLOCKING: [&str; 5] = ["KEYS", "FLUSHALL", "FLUSHDB", "SAVE", "DEBUG"];
+/// Commands that legitimately wait for data, excluded from slow and timeout reporting
+const CLIENT_BLOCKING: [&str; 11] = [
+    "BLPOP",
+    "BRPOP",
+    "BRPOPLPUSH",
+    "BLMOVE",
+    "BLMPOP",
+    "BZPOPMIN",
+    "BZPOPMAX",
+    "BZMPOP",
+    "XREAD",
+    "XREADGROUP",
+    "WAIT",
+];
+/// Commands without key arguments
+const KEYLESS: [&str; 28] = [
+    "AUTH",
+    "PING",
+    "ECHO",
+    "SELECT",
+    "HELLO",
+    "QUIT",
+    "INFO",
+    "CLIENT",
+    "CONFIG",
+    "COMMAND",
+    "CLUSTER",
+    "MULTI",
+    "EXEC",
+    "DISCARD",
+    "UNWATCH",
+    "SCAN",
+    "KEYS",
+    "DBSIZE",
+    "FLUSHALL",
+    "FLUSHDB",
+    "TIME",
+    "SCRIPT",
+    "PUBLISH",
+    "SUBSCRIBE",
+    "PSUBSCRIBE",
+    "UNSUBSCRIBE",
+    "PUNSUBSCRIBE",
+    "MONITOR",
+];
+
+struct PendingCommand {
+    ts: DateTime<Utc>,
+    name: String,
+    /// First argument, for event details
+    arg: String,
+}
+
+#[derive(Default)]
+struct Stream {
+    client: String,
+    server: String,
+    pending: VecDeque<PendingCommand>,
+    /// Pub/sub and MONITOR connections only receive pushed messages
+    push_only: bool,
+}
+
+#[derive(Default)]
+struct CommandStats {
+    count: u32,
+    errors: u32,
+    latency: LatencyStats,
+}
+
+struct ErrorStats {
+    count: u32,
+    /// First error text seen for the prefix
+    message: String,
+}
+
+pub struct Analyzer {
+    streams: HashMap<String, Stream>,
+    commands: HashMap<String, CommandStats>,
+    errors: HashMap<String, ErrorStats>,
+    keys: HashMap<String, u32>,
+    prefixes: HashMap<String, u32>,
+    untracked_keys: u32,
+    /// (client, command, reason)
+    flagged: HashMap<(String, String, &'static str), u32>,
+    timeouts: u32,
+    unmatched_replies: u32,
+    /// Error replies in a pipelined batch whose command cannot be told
+    unplaced_errors: u32,
+    timeout: i64,
+    top: usize,
+    last_timeout_check: Option<DateTime<Utc>>,
+    formatter: Box<dyn EventFormatter>,
+    daily_report: DailyReport,
+}
+
+const PROTOCOL: &str = "redis";
+
+fn numbers(value: &str) -> impl Iterator<Item = i64> + '_ {
+    value.split(',').filter_map(|n| n.parse().ok())
+}
+
+/// Bulk string values rebuilt from their lengths since values may contain commas, None for null
+/// bulk strings. Values tshark had to escape fall back to the next separator.
+fn bulk_values<'a>(lengths: &str, values: &'a str) -> Vec<Option<&'a str>> {
+    let mut output = Vec::new();
+    let mut rest = values;
+    let mut consumed = false;
+    for len in numbers(lengths) {
+        if len < 0 {
+            output.push(None);
+            continue;
+        }
+        if consumed {
+            rest = rest.strip_prefix(',').unwrap_or(rest);
+        }
+        consumed = true;
+        let len = len as usize;
+        let end = if rest.is_char_boundary(len.min(rest.len()))
+            && (rest.len() == len || rest[len.min(rest.len())..].starts_with(','))
+        {
+            len.min(rest.len())
+        } else {
+            rest.find(',').unwrap_or(rest.len())
+        };
+        output.push(Some(&rest[..end]));
+        rest = &rest[end..];
+    }
+    output
+}
+
+/// Error replies start with an upper case code, commas inside the text are not separators
+fn error_replies(value: &str) -> Vec<String> {
+    let mut output: Vec<String> = Vec::new();
+    for piece in value.split(',').filter(|p| !p.is_empty()) {
+        let code = piece.split(' ').next().unwrap_or_default();
+        let is_start = !code.is_empty() && code.bytes().all(|b| b.is_ascii_uppercase());
+        match output.last_mut() {
+            Some(last) if !is_start => {
+                last.push(',');
+                last.push_str(piece);
+            }
+            _ => output.push(piece.to_owned()),
+        }
+    }
+    output
+}
+
+/// Key arguments of a command, `args` excludes the command name
+fn command_keys<'a>(name: &str, args: &[&'a str]) -> Vec<&'a str> {
+    match name {
+        _ if KEYLESS.contains(&name) => Vec::new(),
+        "DEL" | "UNLINK" | "EXISTS" | "MGET" | "TOUCH" | "WATCH" | "SINTER" | "SUNION"
+        | "SDIFF" | "PFCOUNT" => args.to_vec(),
+        "MSET" | "MSETNX" => args.iter().step_by(2).copied().collect(),
+        "BLPOP" | "BRPOP" | "BZPOPMIN" | "BZPOPMAX" => {
+            args[..args.len().saturating_sub(1)].to_vec()
+        }
+        "EVAL" | "EVALSHA" | "FCALL" => {
+            let count = args
+                .get(1)
+                .and_then(|n| n.parse::<usize>().ok())
+                .unwrap_or_default();
+            args.iter().skip(2).take(count).copied().collect()
+        }
+        _ => args.first().copied().into_iter().collect(),
+    }
+}
+
+/// Key with its last `:` separated component replaced, keys without separators have no prefix
+fn key_prefix(key: &str) -> Option<String> {
+    key.rsplit_once(':')
+        .map(|(prefix, _)| format!("{prefix}:*"))
+}
+
+impl Analyzer {
+    fn flag(
+        &mut self,
+        client: &str,
+        server: &str,
+        ts: DateTime<Utc>,
+        name: &str,
+        reason: &'static str,
+        detail: String,
+    ) {
+        let client_host = client.rsplit_once(':').map_or(client, |(h, _)| h);
+        let count = self
+            .flagged
+            .entry((client_host.to_owned(), name.to_owned(), reason))
+            .or_default();
+        *count += 1;
+        // reported once per client and command, the report has the totals
+        if *count == 1 {
+            self.formatter.event(&Event {
+                ts,
+                protocol: PROTOCOL,
+                kind: EventKind::Warning,
+                user: client.to_owned(),
+                method: name.to_owned(),
+                addr: Some(server.to_owned()),
+                detail: Some(detail),
+                ..Default::default()
+            });
+        }
+    }
+
+    fn on_command(&mut self, ts: DateTime<Utc>, stream_id: &str, args: &[&str]) {
+        let Some(first) = args.first() else {
+            return;
+        };
+        let name = first.to_uppercase();
+        let keys = command_keys(&name, &args[1..]);
+        for key in &keys {
+            if let Some(prefix) = key_prefix(key)
+                && (self.prefixes.len() < MAX_KEYS || self.prefixes.contains_key(&prefix))
+            {
+                *self.prefixes.entry(prefix).or_default() += 1;
+            }
+            if self.keys.len() < MAX_KEYS || self.keys.contains_key(*key) {
+                *self.keys.entry((*key).to_owned()).or_default() += 1;
+            } else {
+                self.untracked_keys += 1;
+            }
+        }
+        let stream = &self.streams[stream_id];
+        let (client, server) = (stream.client.clone(), stream.server.clone());
+        let arg = args.get(1).copied().unwrap_or_default().to_owned();
+        if SERVER_BLOCKING.contains(&name.as_str())
+            && !args[1..].iter().any(|a| a.eq_ignore_ascii_case("ASYNC"))
+        {
+            self.flag(
+                &client,
+                &server,
+                ts,
+                &name,
+                "blocks server",
+                format!("{name} {arg} blocks the server while it runs"),
+            );
+        }
+        if keys.len() > LARGE_BATCH {
+            self.flag(
+                &client,
+                &server,
+                ts,
+                &name,
+                "large batch",
+                format!("{name} with {} keys", keys.len()),
+            );
+        }
+        let stream = self.streams.get_mut(stream_id).unwrap();
+        stream.pending.push_back(PendingCommand { ts, name, arg });
+    }
+
+    /// Counts an error reply by its prefix, true for cluster redirects
+    fn count_error(&mut self, error: &str) -> bool {
+        let prefix = error.split(' ').next().unwrap_or_default().to_owned();
+        let redirect = prefix == "MOVED" || prefix == "ASK";
+        self.errors
+            .entry(prefix)
+            .or_insert_with(|| ErrorStats {
+                count: 0,
+                message: error.to_owned(),
+            })
+            .count += 1;
+        redirect
+    }
+
+    fn on_reply(
+        &mut self,
+        ts: DateTime<Utc>,
+        client: &str,
+        server: &str,
+        command: PendingCommand,
+        error: Option<String>,
+    ) {
+        let latency = (ts - command.ts).num_microseconds().unwrap_or_default() as f64 / 1000.0;
+        let stats = self.commands.entry(command.name.clone()).or_default();
+        stats.count += 1;
+        stats.latency.add(latency);
+        if let Some(error) = error {
+            stats.errors += 1;
+            // cluster redirects are routine
+            if !self.count_error(&error) {
+                self.formatter.event(&Event {
+                    ts,
+                    protocol: PROTOCOL,
+                    kind: EventKind::ErrorResponse,
+                    user: client.to_owned(),
+                    method: command.name,
+                    addr: Some(server.to_owned()),
+                    latency_ms: Some(latency),
+                    detail: Some(format!("{} {error}", command.arg)),
+                    ..Default::default()
+                });
+            }
+            return;
+        }
+        if latency > SLOW_MS && !CLIENT_BLOCKING.contains(&command.name.as_str()) {
+            self.formatter.event(&Event {
+                ts,
+                protocol: PROTOCOL,
+                kind: EventKind::Warning,
+                user: client.to_owned(),
+                method: command.name.clone(),
+                addr: Some(server.to_owned()),
+                latency_ms: Some(latency),
+                detail: Some(format!("slow {} {}", command.name, command.arg)),
+                ..Default::default()
+            });
+        }
+    }
+
+    fn expire_pending(&mut self, opt_ts: Option<DateTime<Utc>>) {
+        let timeout = self.timeout;
+        let mut expired = Vec::new();
+        for stream in self.streams.values_mut() {
+            // replies come in order, a blocked command holds up the whole connection
+            while stream.pending.front().is_some_and(|c| {
+                !CLIENT_BLOCKING.contains(&c.name.as_str())
+                    && opt_ts.is_none_or(|ts| (ts - c.ts).num_seconds() >= timeout)
+            }) {
+                let command = stream.pending.pop_front().unwrap();
+                expired.push((stream.client.clone(), stream.server.clone(), command));
+            }
+        }
+        for (client, server, command) in expired {
+            self.timeouts += 1;
+            self.formatter.event(&Event {
+                ts: opt_ts.unwrap_or(command.ts),
+                protocol: PROTOCOL,
+                kind: EventKind::NoResponse,
+                user: client,
+                method: command.name,
+                addr: Some(server),
+                elapsed_s: opt_ts.map(|ts| (ts - command.ts).num_seconds()),
+                detail: Some(command.arg),
+                ..Default::default()
+            });
+        }
+    }
+
+    fn print_stats(&mut self, opt_ts: Option<DateTime<Utc>>) {
+        let mut output = String::with_capacity(200);
+        report::print_header(self.formatter.as_mut(), opt_ts);
+        report::print_section(self.formatter.as_mut(), "Commands");
+        let mut commands = Vec::from_iter(&self.commands);
+        commands.sort_by(|a, b| b.1.count.cmp(&a.1.count).then(a.0.cmp(b.0)));
+        for (name, stats) in commands.into_iter().take(self.top) {
+            self.formatter.report(
+                &format!(
+                    "{name:<12} {:8} calls {:6} errors  {}",
+                    stats.count,
+                    stats.errors,
+                    stats.latency.summary()
+                ),
+                1,
+            );
+        }
+        report::print_section(self.formatter.as_mut(), "Error Replies");
+        let mut errors = Vec::from_iter(&self.errors);
+        errors.sort_by(|a, b| b.1.count.cmp(&a.1.count).then(a.0.cmp(b.0)));
+        for (_, stats) in errors.into_iter().take(self.top) {
+            self.formatter
+                .report(&format!("{:8} {}", stats.count, stats.message), 1);
+        }
+        report::print_section(self.formatter.as_mut(), "Hot Keys");
+        for (key, count) in report::top_n(&self.keys, self.top) {
+            self.formatter.report(&format!("{count:8} {key}"), 1);
+        }
+        report::print_section(self.formatter.as_mut(), "Hot Key Prefixes");
+        for (prefix, count) in report::top_n(&self.prefixes, self.top) {
+            self.formatter.report(&format!("{count:8} {prefix}"), 1);
+        }
+        report::print_section(self.formatter.as_mut(), "Flagged Commands");
+        for ((client, name, reason), count) in report::top_n(&self.flagged, self.top) {
+            self.formatter
+                .report(&format!("{count:8} {client:<16} {name:<10} {reason}"), 1);
+        }
+        write!(
+            output,
+            r#"
+ ------------ STATS ------------
+
+- commands: {}
+- pending commands: {}
+- timeouts: {}
+- unmatched replies: {}
+- unplaced error replies: {}
+- untracked keys: {}
+"#,
+            self.commands.values().map(|c| c.count).sum::<u32>(),
+            self.streams
+                .values()
+                .map(|s| s.pending.len())
+                .sum::<usize>(),
+            self.timeouts,
+            self.unmatched_replies,
+            self.unplaced_errors,
+            self.untracked_keys,
+        )
+        .unwrap();
+        self.formatter.report(&output, 0);
+        report::print_footer(self.formatter.as_mut());
+    }
+}
+
+impl ProtocolAnalyzer for Analyzer {
+    fn new(cmd_args: &ArgsCommand, verbosity: u8) -> Self {
+        let (timeout, top) = match cmd_args {
+            ArgsCommand::Analyzer { timeout, top, .. } => (*timeout as i64, *top),
+            _ => (5, 10),
+        };
+        Self {
+            streams: HashMap::default(),
+            commands: HashMap::default(),
+            errors: HashMap::default(),
+            keys: HashMap::default(),
+            prefixes: HashMap::default(),
+            untracked_keys: 0,
+            flagged: HashMap::default(),
+            timeouts: 0,
+            unmatched_replies: 0,
+            unplaced_errors: 0,
+            timeout,
+            top,
+            last_timeout_check: None,
+            formatter: create_formatter(cmd_args, verbosity),
+            daily_report: DailyReport::default(),
+        }
+    }
+
+    fn analyze(&mut self, ts: DateTime<Utc>, cols: Vec<&str>) {
+        if self
+            .last_timeout_check
+            .is_none_or(|last| (ts - last).num_seconds() >= 1)
+        {
+            self.expire_pending(Some(ts));
+            self.last_timeout_check = Some(ts);
+        }
+        if self.daily_report.is_due(ts) {
+            self.print_stats(Some(ts));
+        }
+        let src = format!("{}:{}", cols[0], cols[9]);
+        let stream_id = cols[8];
+        let stream = self.streams.entry(stream_id.to_owned()).or_default();
+        if stream.client.is_empty() {
+            // servers never speak first, so a stream opens with a command unless it started
+            // before the capture and its first packet is a reply
+            let is_reply = !cols[5].is_empty()
+                || !cols[6].is_empty()
+                || !cols[7].is_empty()
+                || cols[9] == REDIS_PORT;
+            let dst = format!("{}:{}", cols[1], cols[10]);
+            (stream.client, stream.server) = if is_reply {
+                (dst, src.clone())
+            } else {
+                (src.clone(), dst)
+            };
+        }
+        let from_server = src == stream.server;
+        let arrays = numbers(cols[2]).collect::<Vec<_>>();
+        let values = bulk_values(cols[3], cols[4]);
+
+        if !from_server {
+            // clients send each command as an array of bulk strings
+            let mut values = values.into_iter().map(Option::unwrap_or_default);
+            for len in arrays {
+                let args = values
+                    .by_ref()
+                    .take(len.max(0) as usize)
+                    .collect::<Vec<_>>();
+                self.on_command(ts, stream_id, &args);
+            }
+            return;
+        }
+
+        if stream.push_only {
+            return;
+        }
+        // top level replies are all values minus the elements nested in arrays
+        let strings = cols[5].split(',').filter(|s| !s.is_empty()).count();
+        let errors = error_replies(cols[6]);
+        let integers = numbers(cols[7]).count();
+        let nested = arrays.iter().filter(|n| **n > 0).sum::<i64>() as usize;
+        let replies = (strings + errors.len() + integers + values.len() + arrays.len())
+            .saturating_sub(nested);
+        // an error in a pipelined batch only belongs to a known command when the segment has a
+        // single reply or nothing but errors
+        let placed = replies == 1 || errors.len() == replies;
+        let mut errors = errors.into_iter();
+        if !placed {
+            for error in errors.by_ref() {
+                self.unplaced_errors += 1;
+                self.count_error(&error);
+            }
+        }
+        let stream = self.streams.get_mut(stream_id).unwrap();
+        let mut completed = Vec::new();
+        for _ in 0..replies {
+            let Some(command) = stream.pending.pop_front() else {
+                self.unmatched_replies += 1;
+                continue;
+            };
+            if matches!(
+                command.name.as_str(),
+                "SUBSCRIBE" | "PSUBSCRIBE" | "SSUBSCRIBE" | "MONITOR"
+            ) {
+                stream.push_only = true;
+            }
+            completed.push((command, errors.next()));
+            if stream.push_only {
+                break;
+            }
+        }
+        let (client, server) = (stream.client.clone(), stream.server.clone());
+        for (command, error) in completed {
+            self.on_reply(ts, &client, &server, command, error);
+        }
+    }
+
+    fn add_protocol_fields(&self, tshark_args: &mut Vec<&str>) {
+        tshark_args.push("-e");
+        tshark_args.push("resp.array.length");
+        tshark_args.push("-e");
+        tshark_args.push("resp.bulk_string.length");
+        tshark_args.push("-e");
+        tshark_args.push("resp.bulk_string.value");
+        tshark_args.push("-e");
+        tshark_args.push("resp.string");
+        tshark_args.push("-e");
+        tshark_args.push("resp.error");
+        tshark_args.push("-e");
+        tshark_args.push("resp.integer");
+        tshark_args.push("-e");
+        tshark_args.push("tcp.stream");
+        tshark_args.push("-e");
+        tshark_args.push("tcp.srcport");
+        tshark_args.push("-e");
+        tshark_args.push("tcp.dstport");
+        if !tshark_args.contains(&"-Y") {
+            tshark_args.push("-Y");
+            tshark_args.push("resp");
+        }
+        if tshark_args.contains(&"-i") && !tshark_args.contains(&"-f") {
+            tshark_args.push("-f");
+            tshark_args.push("tcp port 6379");
+        }
+    }
+
+    fn end(&mut self) {
+        self.expire_pending(None);
+        self.print_stats(None);
+    }
+}
+
+// MARK: TESTS
+#[cfg(test)]
+mod test {
+    use chrono::{DateTime, TimeDelta};
+
+    use super::{Analyzer, ProtocolAnalyzer as _, bulk_values, command_keys, error_replies};
+    use crate::analyzers::test_args;
+
+    #[test]
+    fn rebuilds_values() {
+        assert_eq!(
+            bulk_values("3,9,-1,5", "SET,reg:alice,a,b,c"),
+            vec![Some("SET"), Some("reg:alice"), None, Some("a,b,c")]
+        );
+        assert_eq!(
+            error_replies("ERR wrong number, of args,WRONGTYPE Operation"),
+            vec!["ERR wrong number, of args", "WRONGTYPE Operation"]
+        );
+        assert_eq!(command_keys("MSET", &["a", "1", "b", "2"]), vec!["a", "b"]);
+        assert_eq!(command_keys("BLPOP", &["q1", "q2", "0"]), vec!["q1", "q2"]);
+        assert_eq!(command_keys("PING", &[]), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn places_error_replies() {
+        let mut analyzer = Analyzer::new(&test_args(&[]), 0);
+        let start = DateTime::from_timestamp(1738062028, 0).unwrap();
+        let not_integer = "ERR value is not an integer or out of range";
+        // the client port is below the server port
+        let client = ["10.0.0.1", "10.0.0.2", "1000", "6379"];
+        let server = ["10.0.0.2", "10.0.0.1", "6379", "1000"];
+        for (ms, [src, dst, sport, dport], arrays, lengths, values, string, error) in [
+            // pipelined GET, SET and INCR, the error cannot be told apart
+            (
+                0,
+                client,
+                "2,3,2",
+                "3,1,3,1,1,4,1",
+                "GET,a,SET,b,1,INCR,c",
+                "",
+                "",
+            ),
+            (2, server, "", "1", "x", "OK", not_integer),
+            (10, client, "2", "4,1", "INCR,c", "", ""),
+            (11, server, "", "", "", "", not_integer),
+        ] {
+            let cols = vec![
+                src, dst, arrays, lengths, values, string, error, "", "1", sport, dport,
+            ];
+            analyzer.analyze(start + TimeDelta::milliseconds(ms), cols);
+        }
+        assert_eq!(analyzer.streams["1"].client, "10.0.0.1:1000");
+        assert!(analyzer.streams["1"].pending.is_empty());
+        assert_eq!(analyzer.unmatched_replies, 0);
+        assert_eq!(analyzer.unplaced_errors, 1);
+        assert_eq!(analyzer.errors["ERR"].count, 2);
+        assert_eq!(analyzer.commands["GET"].errors, 0);
+        let incr = &analyzer.commands["INCR"];
+        assert_eq!((incr.count, incr.errors), (2, 1));
+    }
+}
@@ -0,0 +1,128 @@
+use ahash::HashMap;
+use chrono::{DateTime, Datelike, Local, Utc};
+use itertools::Itertools;
+use std::hash::Hash;
+
+use super::TIME_FMT;
+use crate::formatters::EventFormatter;
+
+#[derive(Default)]
+pub struct DailyReport {
+    last_reported_ts: Option<DateTime<Utc>>,
+}
+
+impl DailyReport {
+    /// True on the first packet of a new day, the first packet only starts the clock
+    pub fn is_due(&mut self, ts: DateTime<Utc>) -> bool {
+        match self.last_reported_ts {
+            None => {
+                self.last_reported_ts = Some(ts);
+                false
+            }
+            Some(last) if ts.day() != last.day() => {
+                self.last_reported_ts = Some(ts);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+pub fn print_header(formatter: &mut dyn EventFormatter, opt_ts: Option<DateTime<Utc>>) {
+    if let Some(ts) = opt_ts {
+        formatter.report(
+            &format!(
+                "\n------------ Daily Report at {} ------------ \n",
+                ts.with_timezone(&Local).format(TIME_FMT)
+            ),
+            1,
+        );
+    } else {
+        formatter.report("\n------------ Final Report ------------ \n", 1);
+    }
+}
+
+pub fn print_section(formatter: &mut dyn EventFormatter, title: &str) {
+    formatter.report(&format!(" ------------ {title} ------------ \n"), 1);
+}
+
+pub fn print_footer(formatter: &mut dyn EventFormatter) {
+    formatter.report("----------------------------------\n", 1);
+}
+
+/// Response time samples in milliseconds
+#[derive(Default)]
+pub struct LatencyStats {
+    samples: Vec<f64>,
+}
+
+impl LatencyStats {
+    pub fn add(&mut self, ms: f64) {
+        self.samples.push(ms);
+    }
+
+    pub fn avg(&self) -> f64 {
+        if self.samples.is_empty() {
+            0.0
+        } else {
+            self.samples.iter().sum::<f64>() / self.samples.len() as f64
+        }
+    }
+
+    pub fn max(&self) -> f64 {
+        self.samples.iter().copied().fold(0.0, f64::max)
+    }
+
+    /// Nearest-rank percentile, `p` in 0..=100
+    pub fn percentile(&self, p: f64) -> f64 {
+        if self.samples.is_empty() {
+            return 0.0;
+        }
+        let sorted = self
+            .samples
+            .iter()
+            .copied()
+            .sorted_by(f64::total_cmp)
+            .collect_vec();
+        let rank = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+        sorted[rank.clamp(1, sorted.len()) - 1]
+    }
+
+    pub fn summary(&self) -> String {
+        format!(
+            "avg {:8.1} ms  p50 {:8.1} ms  p95 {:8.1} ms  max {:8.1} ms",
+            self.avg(),
+            self.percentile(50.0),
+            self.percentile(95.0),
+            self.max()
+        )
+    }
+}
+
+/// Highest counts first, ties by key
+pub fn top_n<K: Ord + Eq + Hash, V: Ord + Copy>(map: &HashMap<K, V>, n: usize) -> Vec<(&K, V)> {
+    map.iter()
+        .map(|(k, v)| (k, *v))
+        .sorted_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)))
+        .take(n)
+        .collect()
+}
+
+// MARK: TESTS
+#[cfg(test)]
+mod test {
+    use super::LatencyStats;
+
+    #[test]
+    fn latency_percentiles() {
+        let mut stats = LatencyStats::default();
+        for ms in [5.0, 1.0, 4.0, 2.0, 3.0, 10.0, 6.0, 9.0, 7.0, 8.0] {
+            stats.add(ms);
+        }
+        assert_eq!(stats.avg(), 5.5);
+        assert_eq!(stats.percentile(50.0), 5.0);
+        assert_eq!(stats.percentile(95.0), 10.0);
+        assert_eq!(stats.percentile(0.0), 1.0);
+        assert_eq!(stats.max(), 10.0);
+    }
+}
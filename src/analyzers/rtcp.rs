@@ -0,0 +1,417 @@
+use super::{
+    Event, EventKind, ProtocolAnalyzer,
+    report::{self, DailyReport, LatencyStats},
+};
+use crate::{
+    ArgsCommand,
+    formatters::{EventFormatter, create_formatter},
+};
+use ahash::HashMap;
+use chrono::{DateTime, Utc};
+use std::fmt::Write as _;
+
+/// Jitter is reported in RTP timestamp units, streams without SDP in the capture are assumed
+/// to use the 8 kHz clock of narrowband voice
+const DEFAULT_CLOCK_RATE_HZ: u32 = 8000;
+/// Seconds between 1900 (NTP era) and 1970
+const NTP_UNIX_OFFSET: i64 = 2_208_988_800;
+/// Reports above any of these limits mark the stream degraded
+const MAX_LOSS_PERCENT: f64 = 5.0;
+const MAX_JITTER_MS: f64 = 30.0;
+const MIN_MOS: f64 = 3.5;
+
+/// What one endpoint (reporter SSRC) says about the stream it receives
+#[derive(Default)]
+struct StreamStats {
+    addr: String,
+    source: String,
+    reports: u32,
+    loss_sum: f64,
+    max_loss: f64,
+    cumulative_lost: i64,
+    jitter_sum_ms: f64,
+    max_jitter_ms: f64,
+    rtt: LatencyStats,
+    mos_sum: f64,
+    mos_reports: u32,
+    min_mos: f64,
+    degraded: bool,
+}
+
+impl StreamStats {
+    fn avg_loss(&self) -> f64 {
+        self.loss_sum / self.reports.max(1) as f64
+    }
+
+    fn avg_jitter_ms(&self) -> f64 {
+        self.jitter_sum_ms / self.reports.max(1) as f64
+    }
+
+    fn avg_mos(&self) -> Option<f64> {
+        (self.mos_reports > 0).then(|| self.mos_sum / self.mos_reports as f64)
+    }
+}
+
+pub struct Analyzer {
+    streams: HashMap<String, StreamStats>,
+    /// RTP clock rate of the first payload type announced in SDP per media address
+    clock_rates: HashMap<String, u32>,
+    packet_types: HashMap<&'static str, u32>,
+    packets: u32,
+    top: usize,
+    formatter: Box<dyn EventFormatter>,
+    daily_report: DailyReport,
+}
+
+const PROTOCOL: &str = "rtcp";
+
+fn packet_type_name(pt: &str) -> &'static str {
+    match pt {
+        "200" => "SR",
+        "201" => "RR",
+        "202" => "SDES",
+        "203" => "BYE",
+        "204" => "APP",
+        "205" => "RTPFB",
+        "206" => "PSFB",
+        "207" => "XR",
+        _ => "OTHER",
+    }
+}
+
+fn first(value: &str) -> &str {
+    value.split(',').next().unwrap_or_default()
+}
+
+/// Decimal or 0x prefixed hex as printed by tshark
+fn parse_number(value: &str) -> Option<u32> {
+    match value.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+/// RFC 3551 clock rates of the static payload types
+fn static_clock_rate(payload_type: u32) -> Option<u32> {
+    match payload_type {
+        0 | 3 | 4 | 5 | 7 | 8 | 9 | 12 | 13 | 15 | 18 => Some(8000),
+        6 => Some(16000),
+        16 => Some(11025),
+        17 => Some(22050),
+        10 | 11 => Some(44100),
+        14 | 25 | 26 | 28 | 31 | 32 | 33 | 34 => Some(90000),
+        _ => None,
+    }
+}
+
+/// Media address and clock rate of every m-line, from its first payload type and the rtpmap
+/// attributes ("111 opus/48000/2")
+fn sdp_clock_rates(addr: &str, media_lines: &str, attr_values: &str) -> Vec<(String, u32)> {
+    let rtpmaps = attr_values
+        .split(',')
+        .filter_map(|value| {
+            let (payload_type, encoding) = value.trim().split_once(' ')?;
+            let rate = encoding.split('/').nth(1)?.parse::<u32>().ok()?;
+            Some((payload_type.parse::<u32>().ok()?, rate))
+        })
+        .collect::<HashMap<_, _>>();
+    media_lines
+        .split(',')
+        .filter_map(|line| {
+            // "audio 4000 RTP/AVP 111 0 101"
+            let mut parts = line.split_whitespace().skip(1);
+            let port = parts.next()?;
+            let payload_type = parts.nth(1)?.parse::<u32>().ok()?;
+            let rate = rtpmaps
+                .get(&payload_type)
+                .copied()
+                .or_else(|| static_clock_rate(payload_type))?;
+            Some((format!("{addr}:{port}"), rate))
+        })
+        .collect()
+}
+
+/// Middle 32 bits of the NTP timestamp, the unit of LSR and DLSR
+fn ntp_middle(ts: DateTime<Utc>) -> u32 {
+    let seconds = (ts.timestamp() + NTP_UNIX_OFFSET) as u32;
+    let fraction = (u64::from(ts.timestamp_subsec_nanos()) << 32) / 1_000_000_000;
+    (seconds << 16) | (fraction >> 16) as u32
+}
+
+/// RFC 3550 round trip A - LSR - DLSR, valid when captured next to the receiver of the report
+fn round_trip_ms(ts: DateTime<Utc>, lsr: u32, dlsr: u32) -> Option<f64> {
+    if lsr == 0 {
+        return None;
+    }
+    let rtt = ntp_middle(ts).wrapping_sub(lsr).wrapping_sub(dlsr);
+    // wrapped values come from clocks that are not NTP synchronized
+    (rtt < 10 << 16).then(|| rtt as f64 * 1000.0 / 65536.0)
+}
+
+impl Analyzer {
+    fn print_worst<F: Fn(&StreamStats) -> Option<f64>>(
+        &mut self,
+        title: &str,
+        value: F,
+        lowest: bool,
+    ) {
+        report::print_section(self.formatter.as_mut(), title);
+        let mut streams = self
+            .streams
+            .iter()
+            .filter_map(|(ssrc, s)| value(s).map(|v| (ssrc, s, v)))
+            .collect::<Vec<_>>();
+        streams.sort_by(|a, b| {
+            let order = if lowest {
+                a.2.total_cmp(&b.2)
+            } else {
+                b.2.total_cmp(&a.2)
+            };
+            order.then(a.0.cmp(b.0))
+        });
+        for (ssrc, stats, _) in streams.into_iter().take(self.top) {
+            let mut output = format!(
+                "{ssrc:<10} {:<22} from {:<10} {:5} reports  loss avg {:5.1} % max {:5.1} %  lost {:6}  jitter avg {:6.1} ms max {:6.1} ms",
+                stats.addr,
+                stats.source,
+                stats.reports,
+                stats.avg_loss(),
+                stats.max_loss,
+                stats.cumulative_lost,
+                stats.avg_jitter_ms(),
+                stats.max_jitter_ms,
+            );
+            if let Some(mos) = stats.avg_mos() {
+                write!(output, "  MOS avg {mos:.1} min {:.1}", stats.min_mos).unwrap();
+            }
+            write!(output, "\n{:<10} rtt {}", "", stats.rtt.summary()).unwrap();
+            self.formatter.report(&output, 1);
+        }
+    }
+
+    fn print_stats(&mut self, opt_ts: Option<DateTime<Utc>>) {
+        report::print_header(self.formatter.as_mut(), opt_ts);
+        self.print_worst(
+            "Worst Streams By Loss",
+            |s| Some(s.max_loss).filter(|l| *l > 0.0),
+            false,
+        );
+        self.print_worst("Worst Streams By Jitter", |s| Some(s.max_jitter_ms), false);
+        self.print_worst(
+            "Worst Streams By Round Trip",
+            |s| Some(s.rtt.max()).filter(|r| *r > 0.0),
+            false,
+        );
+        self.print_worst("Worst Streams By MOS", StreamStats::avg_mos, true);
+        report::print_section(self.formatter.as_mut(), "Packet Types");
+        for (pt, count) in report::top_n(&self.packet_types, usize::MAX) {
+            self.formatter.report(&format!("{count:8} {pt}"), 1);
+        }
+        let output = format!(
+            r#"
+ ------------ STATS ------------
+
+- total packets: {}
+- reporting streams: {}
+- degraded streams: {}
+- streams with XR MOS: {}
+"#,
+            self.packets,
+            self.streams.len(),
+            self.streams.values().filter(|s| s.degraded).count(),
+            self.streams.values().filter(|s| s.mos_reports > 0).count(),
+        );
+        self.formatter.report(&output, 0);
+        report::print_footer(self.formatter.as_mut());
+    }
+}
+
+impl ProtocolAnalyzer for Analyzer {
+    fn new(cmd_args: &ArgsCommand, verbosity: u8) -> Self {
+        let top = match cmd_args {
+            ArgsCommand::Analyzer { top, .. } => *top,
+            _ => 10,
+        };
+        Self {
+            streams: HashMap::default(),
+            clock_rates: HashMap::default(),
+            packet_types: HashMap::default(),
+            packets: 0,
+            top,
+            formatter: create_formatter(cmd_args, verbosity),
+            daily_report: DailyReport::default(),
+        }
+    }
+
+    fn analyze(&mut self, ts: DateTime<Utc>, cols: Vec<&str>) {
+        if self.daily_report.is_due(ts) {
+            self.print_stats(Some(ts));
+        }
+        if cols[2].is_empty() {
+            let addr = first(cols[13]);
+            for (media, rate) in sdp_clock_rates(addr, cols[14], cols[15]) {
+                self.clock_rates.insert(media, rate);
+            }
+            return;
+        }
+        self.packets += 1;
+        for pt in cols[2].split(',') {
+            *self.packet_types.entry(packet_type_name(pt)).or_default() += 1;
+        }
+        // every packet of a compound comes from the same sender
+        let Some(reporter) = cols[3].split(',').next().filter(|s| !s.is_empty()) else {
+            return;
+        };
+        let addr = format!("{}:{}", cols[0], cols[12]);
+        // RTP runs one port below RTCP unless both are multiplexed on the same port
+        let clock_rate = cols[12]
+            .parse::<u32>()
+            .ok()
+            .and_then(|port| {
+                self.clock_rates
+                    .get(&format!("{}:{}", cols[0], port.saturating_sub(1)))
+                    .or_else(|| self.clock_rates.get(&addr))
+            })
+            .copied()
+            .unwrap_or(DEFAULT_CLOCK_RATE_HZ);
+        let stats = self.streams.entry(reporter.to_owned()).or_default();
+        stats.addr = addr.clone();
+        let was_degraded = stats.degraded;
+
+        // report blocks come before BYE sources, only the first block per sender is kept
+        if let Some(fraction) = parse_number(first(cols[5])) {
+            let loss = fraction as f64 * 100.0 / 256.0;
+            let jitter_ms = parse_number(first(cols[7])).unwrap_or_default() as f64 * 1000.0
+                / clock_rate as f64;
+            stats.source = first(cols[4]).to_owned();
+            stats.reports += 1;
+            stats.loss_sum += loss;
+            stats.max_loss = stats.max_loss.max(loss);
+            stats.cumulative_lost = first(cols[6]).parse().unwrap_or(stats.cumulative_lost);
+            stats.jitter_sum_ms += jitter_ms;
+            stats.max_jitter_ms = stats.max_jitter_ms.max(jitter_ms);
+            if let Some(lsr) = parse_number(first(cols[8]))
+                && let Some(dlsr) = parse_number(first(cols[9]))
+                && let Some(rtt) = round_trip_ms(ts, lsr, dlsr)
+            {
+                stats.rtt.add(rtt);
+            }
+            stats.degraded = loss > MAX_LOSS_PERCENT || jitter_ms > MAX_JITTER_MS;
+        }
+        // 127 is the XR "unavailable" marker
+        if let Some(mos) = cols[10]
+            .split(',')
+            .chain(cols[11].split(','))
+            .filter_map(|m| m.parse::<f64>().ok())
+            .find(|m| *m > 0.0 && *m <= 5.0)
+        {
+            if stats.mos_reports == 0 || mos < stats.min_mos {
+                stats.min_mos = mos;
+            }
+            stats.mos_reports += 1;
+            stats.mos_sum += mos;
+            stats.degraded |= mos < MIN_MOS;
+        }
+
+        if stats.degraded && !was_degraded {
+            let mut detail = format!(
+                "degraded stream from {}: loss max {:.1} % jitter max {:.1} ms",
+                stats.source, stats.max_loss, stats.max_jitter_ms
+            );
+            if stats.mos_reports > 0 {
+                write!(detail, " MOS min {:.1}", stats.min_mos).unwrap();
+            }
+            self.formatter.event(&Event {
+                ts,
+                protocol: PROTOCOL,
+                kind: EventKind::Warning,
+                user: reporter.to_owned(),
+                method: "REPORT".into(),
+                addr: Some(addr),
+                detail: Some(detail),
+                ..Default::default()
+            });
+        }
+    }
+
+    fn add_protocol_fields(&self, tshark_args: &mut Vec<&str>) {
+        tshark_args.push("-e");
+        tshark_args.push("rtcp.pt");
+        tshark_args.push("-e");
+        tshark_args.push("rtcp.senderssrc");
+        tshark_args.push("-e");
+        tshark_args.push("rtcp.ssrc.identifier");
+        tshark_args.push("-e");
+        tshark_args.push("rtcp.ssrc.fraction");
+        tshark_args.push("-e");
+        tshark_args.push("rtcp.ssrc.cum_nr");
+        tshark_args.push("-e");
+        tshark_args.push("rtcp.ssrc.jitter");
+        tshark_args.push("-e");
+        tshark_args.push("rtcp.ssrc.lsr");
+        tshark_args.push("-e");
+        tshark_args.push("rtcp.ssrc.dlsr");
+        tshark_args.push("-e");
+        tshark_args.push("rtcp.xr.voipmetrics.moslq");
+        tshark_args.push("-e");
+        tshark_args.push("rtcp.xr.voipmetrics.moscq");
+        tshark_args.push("-e");
+        tshark_args.push("udp.srcport");
+        tshark_args.push("-e");
+        tshark_args.push("sdp.connection_info.address");
+        tshark_args.push("-e");
+        tshark_args.push("sdp.media");
+        tshark_args.push("-e");
+        tshark_args.push("sdp.media_attribute.value");
+        // SDP announces the RTP clock rates needed to convert jitter
+        if !tshark_args.contains(&"-Y") {
+            tshark_args.push("-Y");
+            tshark_args.push("rtcp || sdp");
+        }
+        if tshark_args.contains(&"-i") && !tshark_args.contains(&"-f") {
+            tshark_args.push("-f");
+            tshark_args.push("udp");
+        }
+    }
+
+    fn end(&mut self) {
+        self.print_stats(None);
+    }
+}
+
+// MARK: TESTS
+#[cfg(test)]
+mod test {
+    use chrono::{DateTime, TimeDelta};
+
+    use super::{ntp_middle, round_trip_ms, sdp_clock_rates};
+
+    #[test]
+    fn round_trip_from_lsr_dlsr() {
+        let sr_ts = DateTime::from_timestamp(1738062028, 250_000_000).unwrap();
+        let lsr = ntp_middle(sr_ts);
+        // receiver held the SR for 1 s, the report arrives 1.04 s after the SR was sent
+        let dlsr = 1 << 16;
+        let rr_ts = sr_ts + TimeDelta::milliseconds(1040);
+        let rtt = round_trip_ms(rr_ts, lsr, dlsr).unwrap();
+        assert!((rtt - 40.0).abs() < 0.1, "{rtt}");
+        assert_eq!(round_trip_ms(rr_ts, 0, dlsr), None);
+        assert_eq!(round_trip_ms(sr_ts, lsr, dlsr), None);
+    }
+
+    #[test]
+    fn clock_rates_from_sdp() {
+        let rates = sdp_clock_rates(
+            "10.0.0.1",
+            "audio 4000 RTP/AVP 111 0 101,video 4002 RTP/AVP 34",
+            "111 opus/48000/2,101 telephone-event/8000,sendrecv",
+        );
+        assert_eq!(
+            rates,
+            vec![
+                ("10.0.0.1:4000".to_owned(), 48000),
+                ("10.0.0.1:4002".to_owned(), 90000)
+            ]
+        );
+    }
+}
@@ -0,0 +1,356 @@
+use super::{Direction, Media, Negotiation};
+use ahash::HashMap;
+use chrono::{DateTime, Utc};
+use itertools::Itertools;
+use std::fmt::Write as _;
+
+pub fn parse_media(
+    addr: &str,
+    port: &str,
+    codecs: Vec<String>,
+    formats: String,
+    media_attrs: &str,
+    session_attrs: &str,
+) -> Media {
+    let mut ptime = None;
+    let mut direction = None;
+    // media level attributes take precedence over session level ones
+    for attr in media_attrs.split(',').chain(session_attrs.split(',')) {
+        let attr = attr.trim();
+        if let Some(value) = attr.strip_prefix("ptime:") {
+            ptime = ptime.or_else(|| value.trim().parse::<u16>().ok());
+        } else {
+            let dir = match attr {
+                "sendrecv" => Direction::SendRecv,
+                "sendonly" => Direction::SendOnly,
+                "recvonly" => Direction::RecvOnly,
+                "inactive" => Direction::Inactive,
+                _ => continue,
+            };
+            direction = direction.or(Some(dir));
+        }
+    }
+    Media {
+        addr: addr.into(),
+        port: port.into(),
+        codecs,
+        formats,
+        ptime,
+        direction: direction.unwrap_or_default(),
+    }
+}
+
+fn codec_list(media: &Media) -> Vec<String> {
+    if media.codecs.is_empty() {
+        media
+            .formats
+            .split(',')
+            .map(str::trim)
+            .filter(|f| !f.is_empty())
+            .map(str::to_owned)
+            .collect()
+    } else {
+        media.codecs.clone()
+    }
+}
+
+fn is_hold(media: &Media) -> bool {
+    media.direction != Direction::SendRecv || media.addr == "0.0.0.0"
+}
+
+fn count_offered(codecs: &mut HashMap<String, CodecCount>, offered: &[String]) {
+    for codec in offered {
+        codecs.entry(codec.clone()).or_default().offered += 1;
+    }
+}
+
+struct PendingOffer {
+    media: Media,
+    from_request: bool,
+}
+
+#[derive(Default)]
+struct CallNegotiation {
+    offer: Option<PendingOffer>,
+    answer: Option<Media>,
+    /// CSeq of the last completed offer/answer and whether its offer was in the request
+    answered: Option<(u16, bool)>,
+    held: bool,
+    last_seen: Option<DateTime<Utc>>,
+}
+
+#[derive(Default)]
+struct CodecCount {
+    offered: u32,
+    negotiated: u32,
+}
+
+#[derive(Default)]
+pub struct SdpTracker {
+    calls: HashMap<String, CallNegotiation>,
+    codecs: HashMap<String, CodecCount>,
+    ptimes: HashMap<u16, u32>,
+    negotiated: u32,
+    reinvites: u32,
+    holds: u32,
+    codec_changes: u32,
+    failures: u32,
+}
+
+impl SdpTracker {
+    /// Feeds the SDP body of a SIP message of the INVITE transaction `seq`, returns the
+    /// negotiation result once an answer is seen
+    pub fn on_sdp(
+        &mut self,
+        ts: DateTime<Utc>,
+        call_id: &str,
+        seq: u16,
+        from_request: bool,
+        media: Media,
+    ) -> Option<Negotiation> {
+        let call = self.calls.entry(call_id.to_owned()).or_default();
+        call.last_seen = Some(ts);
+        let offer = match call.offer.take() {
+            Some(offer) if offer.from_request != from_request => offer,
+            _ => {
+                match (from_request, call.answered) {
+                    // 183 and 200 repeat the answer, a forked 200 may replace the early one
+                    (false, Some((answered_seq, true))) if answered_seq == seq => {
+                        call.answer = Some(media);
+                        return None;
+                    }
+                    // retransmitted 200 carrying a late offer that was already answered
+                    (false, Some((answered_seq, false))) if answered_seq == seq => return None,
+                    // a response offer answers a request without SDP (late offer)
+                    (false, _) => {
+                        call.offer = Some(PendingOffer {
+                            media,
+                            from_request,
+                        });
+                        return None;
+                    }
+                    _ => {}
+                }
+                if call.answer.as_ref() != Some(&media) {
+                    call.offer = Some(PendingOffer {
+                        media,
+                        from_request,
+                    });
+                }
+                return None;
+            }
+        };
+        let offered = codec_list(&offer.media);
+        let answered = codec_list(&media);
+        let codec = answered
+            .iter()
+            .find(|a| offered.iter().any(|o| o.eq_ignore_ascii_case(a)))
+            .cloned();
+        let reinvite = call.answer.is_some();
+        if !reinvite {
+            count_offered(&mut self.codecs, &offered);
+        }
+        let held = is_hold(&offer.media) || is_hold(&media);
+        let ptime = media.ptime.or(offer.media.ptime);
+        let direction = media.direction;
+        let Some(codec) = codec else {
+            self.failures += 1;
+            call.answer = Some(media);
+            call.answered = Some((seq, offer.from_request));
+            return Some(Negotiation {
+                offered,
+                answered,
+                reinvite,
+                ..Default::default()
+            });
+        };
+        let mut changes = Vec::new();
+        if let Some(prev) = &call.answer {
+            self.reinvites += 1;
+            match (call.held, held) {
+                (false, true) => {
+                    self.holds += 1;
+                    changes.push("hold".to_owned());
+                }
+                (true, false) => changes.push("resume".to_owned()),
+                _ => (),
+            }
+            if let Some(prev_codec) = codec_list(prev).first()
+                && !prev_codec.eq_ignore_ascii_case(&codec)
+            {
+                self.codec_changes += 1;
+                changes.push(format!("codec {prev_codec} -> {codec}"));
+            }
+            if prev.addr != media.addr || prev.port != media.port {
+                changes.push(format!(
+                    "media {}:{} -> {}:{}",
+                    prev.addr, prev.port, media.addr, media.port
+                ));
+            }
+        } else {
+            self.negotiated += 1;
+            self.codecs.entry(codec.clone()).or_default().negotiated += 1;
+            if let Some(ptime) = ptime {
+                *self.ptimes.entry(ptime).or_default() += 1;
+            }
+        }
+        call.answer = Some(media);
+        call.answered = Some((seq, offer.from_request));
+        call.held = held;
+        Some(Negotiation {
+            offered,
+            answered,
+            codec: Some(codec),
+            ptime,
+            direction,
+            reinvite,
+            change: (!changes.is_empty()).then(|| changes.join(", ")),
+        })
+    }
+
+    /// Final INVITE error, only 488 Not Acceptable Here and 606 Not Acceptable reject the offer
+    pub fn on_error(&mut self, call_id: &str, status_code: u16) -> Option<Negotiation> {
+        let call = self.calls.get_mut(call_id)?;
+        let offer = call.offer.take()?;
+        let reinvite = call.answer.is_some();
+        if !reinvite {
+            self.calls.remove(call_id);
+        }
+        if !matches!(status_code, 488 | 606) {
+            return None;
+        }
+        let offered = codec_list(&offer.media);
+        if !reinvite {
+            count_offered(&mut self.codecs, &offered);
+        }
+        self.failures += 1;
+        Some(Negotiation {
+            offered,
+            reinvite,
+            ..Default::default()
+        })
+    }
+
+    pub fn end_call(&mut self, call_id: &str) {
+        self.calls.remove(call_id);
+    }
+
+    pub fn cleanup(&mut self, ts: DateTime<Utc>) {
+        self.calls.retain(|_, call| {
+            call.last_seen
+                .is_some_and(|last_seen| (ts - last_seen).num_hours() < 12)
+        });
+    }
+
+    pub fn write_stats(&self, output: &mut String) {
+        for (codec, count) in self
+            .codecs
+            .iter()
+            .sorted_by(|a, b| b.1.negotiated.cmp(&a.1.negotiated).then(a.0.cmp(b.0)))
+        {
+            writeln!(
+                output,
+                "{codec:24} negotiated {:6}  offered {:6}",
+                count.negotiated, count.offered
+            )
+            .unwrap();
+        }
+        write!(
+            output,
+            r#"
+- calls negotiated: {}
+- re-INVITEs: {} (holds: {}, codec changes: {})
+- negotiation failures: {}
+- ptime: {}"#,
+            self.negotiated,
+            self.reinvites,
+            self.holds,
+            self.codec_changes,
+            self.failures,
+            self.ptimes
+                .iter()
+                .sorted()
+                .map(|(ptime, count)| format!("{ptime} ms x {count}"))
+                .join(", ")
+        )
+        .unwrap();
+    }
+}
+
+// MARK: TESTS
+#[cfg(test)]
+mod test {
+    use super::{SdpTracker, parse_media};
+    use crate::analyzers::Direction;
+    use chrono::DateTime;
+
+    #[test]
+    fn offer_answer_hold() {
+        let mut tracker = SdpTracker::default();
+        let ts = DateTime::from_timestamp(1738062028, 0).unwrap();
+        let offer = parse_media(
+            "10.0.0.1",
+            "4000",
+            vec!["opus/48000".into(), "PCMU/8000".into()],
+            String::new(),
+            "rtpmap:111 opus/48000/2,ptime:20,sendrecv",
+            "",
+        );
+        assert_eq!(offer.ptime, Some(20));
+        assert!(tracker.on_sdp(ts, "c1", 1, true, offer.clone()).is_none());
+        let answer = parse_media(
+            "10.0.0.2",
+            "5000",
+            vec!["PCMU/8000".into()],
+            String::new(),
+            "",
+            "",
+        );
+        let n = tracker.on_sdp(ts, "c1", 1, false, answer.clone()).unwrap();
+        assert_eq!(n.codec.as_deref(), Some("PCMU/8000"));
+        assert!(!n.reinvite);
+        // repeated answer in 200 OK after 183
+        assert!(tracker.on_sdp(ts, "c1", 1, false, answer.clone()).is_none());
+        // a different 200 OK answer after forked early media is not an offer
+        let forked = parse_media(
+            "10.0.0.3",
+            "6000",
+            vec!["PCMU/8000".into()],
+            String::new(),
+            "",
+            "",
+        );
+        assert!(tracker.on_sdp(ts, "c1", 1, false, forked.clone()).is_none());
+
+        let hold = parse_media(
+            "10.0.0.1",
+            "4000",
+            vec!["PCMU/8000".into()],
+            String::new(),
+            "sendonly",
+            "",
+        );
+        assert_eq!(hold.direction, Direction::SendOnly);
+        assert!(tracker.on_sdp(ts, "c1", 2, true, hold).is_none());
+        let n = tracker.on_sdp(ts, "c1", 2, false, forked.clone()).unwrap();
+        assert!(n.reinvite);
+        assert_eq!(n.change.as_deref(), Some("hold"));
+
+        // late offer: re-INVITE without SDP, offer in the 200 OK, answer in the ACK
+        assert!(tracker.on_sdp(ts, "c1", 3, false, forked.clone()).is_none());
+        let n = tracker.on_sdp(ts, "c1", 3, true, answer.clone()).unwrap();
+        assert!(n.reinvite);
+        assert!(n.change.is_some_and(|c| c.starts_with("resume")));
+        // a retransmitted 200 OK is not a new offer
+        assert!(tracker.on_sdp(ts, "c1", 3, false, forked).is_none());
+        assert!(tracker.calls["c1"].offer.is_none());
+        assert_eq!(tracker.calls["c1"].answer.as_ref(), Some(&answer));
+
+        assert!(tracker.on_sdp(ts, "c2", 1, true, offer.clone()).is_none());
+        assert!(tracker.on_error("c2", 407).is_none());
+        assert!(tracker.on_sdp(ts, "c2", 1, true, offer).is_none());
+        let n = tracker.on_error("c2", 488).unwrap();
+        assert_eq!(n.offered, vec!["opus/48000", "PCMU/8000"]);
+        assert_eq!(tracker.failures, 1);
+    }
+}
@@ -1,8 +1,14 @@
-use super::ProtocolAnalyzer;
-use crate::ArgsCommand;
+use super::{
+    ErrorCategory, Event, EventKind, ProtocolAnalyzer, TIME_FMT,
+    report::{self, DailyReport},
+    sdp::{self, SdpTracker},
+};
+use crate::{
+    ArgsCommand,
+    formatters::{EventFormatter, create_formatter},
+};
 use ahash::HashMap;
-use chrono::{DateTime, Datelike, Local, Utc};
-use itertools::Itertools;
+use chrono::{DateTime, Local, Utc};
 use std::fmt::Write as _;
 
 struct RegRequest {
@@ -26,15 +32,15 @@ struct RegisterStatus {
     error_minutes: i64,
 }
 
-#[derive(Default)]
 pub struct Analyzer {
     register_req: HashMap<(String, u16), RegRequest>,
     register_status: HashMap<String, RegisterStatus>,
-    verbosity: u8,
-    last_reported_ts: Option<DateTime<Utc>>,
+    sdp: SdpTracker,
+    formatter: Box<dyn EventFormatter>,
+    daily_report: DailyReport,
 }
 
-const TIME_FMT: &str = "%Y-%m-%d %H:%M:%S%.3f";
+const PROTOCOL: &str = "sip";
 
 impl Analyzer {
     fn cleanup_old_register_req(&mut self, ts: DateTime<Utc>) {
@@ -51,12 +57,19 @@ impl Analyzer {
             .filter(|(_, s)| s.expires > 0)
         {
             if (ts - status.last_seen_ts).num_seconds() > status.expires.into() {
-                println!(
-                    "{} REGISTER {user:<10} EXPIRED!!!  {} seconds ({})",
-                    ts.with_timezone(&Local).format(TIME_FMT),
-                    status.expires,
-                    status.last_seen_ts.with_timezone(&Local).format(TIME_FMT)
-                );
+                self.formatter.event(&Event {
+                    ts,
+                    protocol: PROTOCOL,
+                    kind: EventKind::Expired,
+                    user: user.clone(),
+                    method: "REGISTER".into(),
+                    expires: Some(status.expires),
+                    stream: Some(status.last_stream),
+                    error: Some(ErrorCategory::Expired),
+                    addr: Some(status.from_addr.clone()),
+                    last_seen: Some(status.last_seen_ts),
+                    ..Default::default()
+                });
                 status.expires = 0;
                 status.repeat_count = 0;
                 status.last_error_code = 0;
@@ -73,29 +86,10 @@ impl Analyzer {
         }
     }
 
-    fn print_stats(&self, opt_ts: Option<DateTime<Utc>>) {
+    fn print_stats(&mut self, opt_ts: Option<DateTime<Utc>>) {
         let mut output = String::with_capacity(200);
-        if let Some(ts) = opt_ts {
-            println!(
-                "\n------------ Daily Report at {} ------------ \n",
-                ts.with_timezone(&Local).format(TIME_FMT)
-            );
-            if self.verbosity > 1 {
-                eprintln!(
-                    "\n------------ Daily Report at {} ------------ \n",
-                    ts.with_timezone(&Local).format(TIME_FMT)
-                );
-            }
-        } else {
-            println!("\n------------ Final Report ------------ \n");
-            if self.verbosity > 1 {
-                eprintln!("\n------------ Final Report ------------ \n");
-            }
-        }
-        println!(" ------------ Register Status ------------ \n");
-        if self.verbosity > 1 {
-            eprintln!(" ------------ Register Status ------------ \n");
-        }
+        report::print_header(self.formatter.as_mut(), opt_ts);
+        report::print_section(self.formatter.as_mut(), "Register Status");
         let mut keys = Vec::from_iter(self.register_status.keys());
         keys.sort();
         let mut registered = 0;
@@ -131,13 +125,10 @@ impl Analyzer {
                 )
                 .unwrap();
             }
-            println!("{output}");
-            if self.verbosity > 1 {
-                eprintln!("{output}");
-            }
+            self.formatter.report(&output, 1);
             output.clear();
         }
-        writeln!(
+        write!(
             output,
             r#"
  ------------ STATS ------------
@@ -150,22 +141,27 @@ impl Analyzer {
             self.register_status.len() - registered,
         )
         .unwrap();
-        print!("{output}");
-        if self.verbosity > 0 {
-            eprint!("{output}");
-        }
-        println!("----------------------------------\n");
-        if self.verbosity > 1 {
-            eprintln!("----------------------------------\n");
+        self.formatter.report(&output, 0);
+        output.clear();
+        report::print_section(self.formatter.as_mut(), "Codec Statistics");
+        self.sdp.write_stats(&mut output);
+        output.push('\n');
+        self.formatter.report(&output, 0);
+        report::print_footer(self.formatter.as_mut());
+        if let Some(ts) = opt_ts {
+            self.sdp.cleanup(ts);
         }
     }
 }
 
 impl ProtocolAnalyzer for Analyzer {
-    fn new(_cmd_args: &ArgsCommand, verbosity: u8) -> Self {
+    fn new(cmd_args: &ArgsCommand, verbosity: u8) -> Self {
         Self {
-            verbosity,
-            ..Default::default()
+            register_req: HashMap::default(),
+            register_status: HashMap::default(),
+            sdp: SdpTracker::default(),
+            formatter: create_formatter(cmd_args, verbosity),
+            daily_report: DailyReport::default(),
         }
     }
 
@@ -188,36 +184,39 @@ impl ProtocolAnalyzer for Analyzer {
         let media_codecs = cols[16]
             .split(',')
             .zip(cols[17].split(','))
-            .filter(|(codec, _)| codec != &"telephone-event")
+            .filter(|(codec, _)| !codec.is_empty() && codec != &"telephone-event")
+            .map(|(codec, rate)| format!("{codec}/{rate}"))
             .collect::<Vec<_>>();
         let media_formats = cols[18].to_owned();
-        let mut output = String::with_capacity(200);
+        let event = |kind, status: Option<u16>| Event {
+            ts,
+            protocol: PROTOCOL,
+            kind,
+            user: from_user.to_owned(),
+            method: method.to_owned(),
+            status,
+            stream: Some(udp_stream),
+            error: status.and_then(ErrorCategory::from_status),
+            ..Default::default()
+        };
         self.verified_expired_sessions(ts);
-        if self.last_reported_ts.is_none() {
-            self.last_reported_ts = Some(ts);
-        } else if ts.day() != self.last_reported_ts.unwrap().day() {
+        if self.daily_report.is_due(ts) {
             self.print_stats(Some(ts));
-            self.last_reported_ts = Some(ts);
         }
-        write!(
-            output,
-            "{} {method:<8} {from_user:<10} ",
-            ts.with_timezone(&Local).format(TIME_FMT)
-        )
-        .unwrap();
         match method {
             "REGISTER" => {
                 let key = (from_user.to_string(), seq);
                 let mut expires = cols[9].parse::<u16>().unwrap_or_default();
                 match status_code {
                     200..300 => {
-                        if expires == 0 {
-                            if let Some(req) = self.register_req.get(&key) {
-                                expires = req.expires;
-                            }
+                        if expires == 0
+                            && let Some(req) = self.register_req.get(&key)
+                        {
+                            expires = req.expires;
                         }
                         if expires == 0 {
-                            println!("{output}{status_code:03}/OK      UNREGISTERED");
+                            self.formatter
+                                .event(&event(EventKind::Unregistered, Some(status_code)));
                         }
                         if let Some(status) = self.register_status.get_mut(&key.0) {
                             if status.last_error_code == 401 {
@@ -246,28 +245,29 @@ impl ProtocolAnalyzer for Analyzer {
                                 status.last_reported_ts = ts;
                                 status.repeat_count = 0;
                                 if expires != 0 {
-                                    if let Some(err_ts) = status.last_error_ts.take() {
-                                        let err_time = (ts - err_ts).num_minutes();
-                                        println!(
-                                            "{output}{status_code:03}/OK      Expires:{expires:4} ({:2},{udp_stream:4}) {:<15} Last Error: {} minutes.",
-                                            status.repeat_count, status.from_addr, err_time
-                                        );
-                                        status.error_minutes += err_time;
-                                    } else {
-                                        println!(
-                                            "{output}{status_code:03}/OK      Expires:{expires:4} ({:2},{udp_stream:4}) {:<15}",
-                                            status.repeat_count, status.from_addr
-                                        );
-                                    }
+                                    let err_time = status
+                                        .last_error_ts
+                                        .take()
+                                        .map(|err_ts| (ts - err_ts).num_minutes());
+                                    self.formatter.event(&Event {
+                                        expires: Some(expires),
+                                        addr: Some(status.from_addr.clone()),
+                                        repeat: Some(status.repeat_count),
+                                        error_minutes: err_time,
+                                        ..event(EventKind::RegistrationOk, Some(status_code))
+                                    });
+                                    status.error_minutes += err_time.unwrap_or_default();
                                 }
                             } else {
                                 status.repeat_count += 1;
                             }
                         } else {
                             if expires != 0 {
-                                println!(
-                                    "{output}{status_code:03}/OK      Expires:{expires:4} ( F,{udp_stream:4}) {to_addr:<15}"
-                                );
+                                self.formatter.event(&Event {
+                                    expires: Some(expires),
+                                    addr: Some(to_addr.into()),
+                                    ..event(EventKind::RegistrationOk, Some(status_code))
+                                });
                             }
                             self.register_status.insert(
                                 key.0.clone(),
@@ -297,15 +297,16 @@ impl ProtocolAnalyzer for Analyzer {
                                 status.last_error_code = status_code;
                                 status.last_reported_ts = ts;
                                 status.repeat_count = 0;
-                                println!(
-                                    "{output}{status_code:03}/Redirect ({})",
-                                    status.repeat_count
-                                );
+                                self.formatter.event(&Event {
+                                    repeat: Some(status.repeat_count),
+                                    ..event(EventKind::Redirect, Some(status_code))
+                                });
                             } else {
                                 status.repeat_count += 1;
                             }
                         } else {
-                            println!("{output}{status_code:03}/Redirect");
+                            self.formatter
+                                .event(&event(EventKind::Redirect, Some(status_code)));
                             self.register_status.insert(
                                 key.0.clone(),
                                 RegisterStatus {
@@ -338,15 +339,16 @@ impl ProtocolAnalyzer for Analyzer {
                                 if status.last_error_ts.is_none() {
                                     status.last_error_ts = Some(ts);
                                 }
-                                println!(
-                                    "{output}{status_code:03}/Error ({})",
-                                    status.repeat_count
-                                );
+                                self.formatter.event(&Event {
+                                    repeat: Some(status.repeat_count),
+                                    ..event(EventKind::RegistrationError, Some(status_code))
+                                });
                             } else {
                                 status.repeat_count += 1;
                             }
                         } else {
-                            println!("{output}{status_code:03}/Error");
+                            self.formatter
+                                .event(&event(EventKind::RegistrationError, Some(status_code)));
                             self.register_status.insert(
                                 key.0.clone(),
                                 RegisterStatus {
@@ -384,20 +386,20 @@ impl ProtocolAnalyzer for Analyzer {
                                         }
                                         status.udp_streams.remove(&udp_stream);
                                         status.repeat_count = 0;
-                                        println!(
-                                            "{output}408/Timeout {} s ({})",
-                                            diff.num_seconds(),
-                                            status.repeat_count
-                                        );
+                                        self.formatter.event(&Event {
+                                            elapsed_s: Some(diff.num_seconds()),
+                                            repeat: Some(status.repeat_count),
+                                            ..event(EventKind::Timeout, Some(408))
+                                        });
                                     } else {
                                         status.repeat_count += 1;
                                     }
                                 } else {
-                                    println!(
-                                        "{output}408/Timeout {} s {:<15}",
-                                        diff.num_seconds(),
-                                        from_addr
-                                    );
+                                    self.formatter.event(&Event {
+                                        elapsed_s: Some(diff.num_seconds()),
+                                        addr: Some(from_addr.into()),
+                                        ..event(EventKind::Timeout, Some(408))
+                                    });
                                     self.register_status.insert(
                                         key.0.clone(),
                                         RegisterStatus {
@@ -436,15 +438,17 @@ impl ProtocolAnalyzer for Analyzer {
                                 auth_user: Some(user_name),
                                 ..
                             }) = self.register_req.get(&key)
+                                && status.last_error_code == 401
+                                && status.last_stream == udp_stream
                             {
-                                if status.last_error_code == 401 && status.last_stream == udp_stream
-                                {
-                                    status.errors += 1;
-                                    if status.last_error_ts.is_none() {
-                                        status.last_error_ts = Some(ts);
-                                    }
-                                    println!("{output}401/Unauthorized {user_name}");
+                                status.errors += 1;
+                                if status.last_error_ts.is_none() {
+                                    status.last_error_ts = Some(ts);
                                 }
+                                self.formatter.event(&Event {
+                                    auth_user: Some(user_name.clone()),
+                                    ..event(EventKind::Unauthorized, Some(401))
+                                });
                             }
                             status.last_stream = udp_stream;
                             status.last_error_code = 401;
@@ -454,43 +458,73 @@ impl ProtocolAnalyzer for Analyzer {
                         if let Some(status) = self.register_status.get_mut(&key.0) {
                             status.last_seen_ts = ts;
                         }
-                        println!("{output}{status_code:03}/Unknown");
+                        self.formatter
+                            .event(&event(EventKind::Unknown, Some(status_code)));
                     }
                 }
                 self.register_req.remove(&key);
             }
             "INVITE" | "BYE" | "CANCEL" | "ACK" => {
-                if status_code > 0 {
-                    write!(output, "<<-{to_user:>8} {status_code:03} CID:{call_id}").unwrap();
+                let kind = if status_code > 0 {
+                    EventKind::CallResponse
                 } else {
-                    write!(output, "->>{to_user:>8} REQ CID:{call_id}").unwrap();
-                }
-                if !from_display.is_empty() {
-                    write!(output, " From: {from_display}").unwrap();
-                }
-                if !sdp_addr.is_empty() {
-                    write!(
-                        output,
-                        " MEDIA {sdp_addr}:{sdp_port}\t{}",
-                        if media_codecs.is_empty() {
-                            media_formats
-                        } else {
-                            media_codecs
-                                .iter()
-                                .map(|(codec, rate)| format!("{codec}/{rate}"))
-                                .join(", ")
-                        }
+                    EventKind::CallRequest
+                };
+                let media = (!sdp_addr.is_empty()).then(|| {
+                    sdp::parse_media(
+                        sdp_addr,
+                        sdp_port,
+                        media_codecs,
+                        media_formats,
+                        cols[19],
+                        cols[20],
                     )
-                    .unwrap();
+                });
+                self.formatter.event(&Event {
+                    peer: Some(to_user.into()),
+                    call_id: Some(call_id.into()),
+                    display: (!from_display.is_empty()).then(|| from_display.into()),
+                    media: media.clone(),
+                    ..event(kind, (status_code > 0).then_some(status_code))
+                });
+                let negotiation = match (method, status_code) {
+                    ("INVITE" | "ACK", 0..300) => media.and_then(|media| {
+                        self.sdp.on_sdp(ts, call_id, seq, status_code == 0, media)
+                    }),
+                    ("INVITE", 300..) => self.sdp.on_error(call_id, status_code),
+                    ("BYE" | "CANCEL", 200..300) => {
+                        self.sdp.end_call(call_id);
+                        None
+                    }
+                    _ => None,
+                };
+                if let Some(negotiation) = negotiation {
+                    let kind = if negotiation.codec.is_some() {
+                        EventKind::Negotiated
+                    } else {
+                        EventKind::NegotiationFailed
+                    };
+                    self.formatter.event(&Event {
+                        peer: Some(to_user.into()),
+                        call_id: Some(call_id.into()),
+                        error: (kind == EventKind::NegotiationFailed)
+                            .then_some(ErrorCategory::MediaMismatch),
+                        negotiation: Some(negotiation),
+                        ..event(kind, (status_code > 0).then_some(status_code))
+                    });
                 }
-                println!("{output}");
             }
             m if !m.is_empty() => {
-                if status_code > 0 {
-                    println!("{output}<<-{to_user:>8} {status_code:03} CID:{call_id}");
+                let kind = if status_code > 0 {
+                    EventKind::Response
                 } else {
-                    println!("{output}->>{to_user:>8} REQ CID:{call_id}");
-                }
+                    EventKind::Request
+                };
+                self.formatter.event(&Event {
+                    peer: Some(to_user.into()),
+                    call_id: Some(call_id.into()),
+                    ..event(kind, (status_code > 0).then_some(status_code))
+                });
             }
             _ => (),
         }
@@ -531,6 +565,10 @@ impl ProtocolAnalyzer for Analyzer {
         tshark_args.push("sdp.sample_rate");
         tshark_args.push("-e");
         tshark_args.push("sdp.media.format");
+        tshark_args.push("-e");
+        tshark_args.push("sdp.media_attr");
+        tshark_args.push("-e");
+        tshark_args.push("sdp.session_attr");
         if !tshark_args.contains(&"-Y") {
             tshark_args.push("-Y");
             tshark_args.push("sip");
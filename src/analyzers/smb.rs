@@ -0,0 +1,956 @@
+use super::{
+    Event, EventKind, ProtocolAnalyzer,
+    fields::{next, occurrences, parse_number},
+    report::{self, DailyReport, LatencyStats},
+};
+use crate::{
+    ArgsCommand,
+    formatters::{EventFormatter, create_formatter},
+};
+use ahash::HashMap;
+use chrono::{DateTime, Utc};
+use std::fmt::Write as _;
+
+const COMMANDS: [&str; 19] = [
+    "NEGOTIATE",
+    "SESSION_SETUP",
+    "LOGOFF",
+    "TREE_CONNECT",
+    "TREE_DISCONNECT",
+    "CREATE",
+    "CLOSE",
+    "FLUSH",
+    "READ",
+    "WRITE",
+    "LOCK",
+    "IOCTL",
+    "CANCEL",
+    "KEEPALIVE",
+    "FIND",
+    "NOTIFY",
+    "GETINFO",
+    "SETINFO",
+    "BREAK",
+];
+const SESSION_SETUP: u32 = 1;
+const TREE_CONNECT: u32 = 3;
+const CREATE: u32 = 5;
+const CLOSE: u32 = 6;
+const READ: u32 = 8;
+const WRITE: u32 = 9;
+const NOTIFY: u32 = 15;
+const GETINFO: u32 = 16;
+const SETINFO: u32 = 17;
+/// Requests carrying a file id
+const FID_COMMANDS: [u32; 10] = [CLOSE, 7, READ, WRITE, 10, 11, 14, NOTIFY, GETINFO, SETINFO];
+
+const STATUS_SUCCESS: u32 = 0;
+const STATUS_PENDING: u32 = 0x0000_0103;
+/// Requests acknowledged with STATUS_PENDING, such as blocking locks, may complete much later
+const INTERIM_TIMEOUT_S: i64 = 3600;
+
+/// FileRenameInformation and its extended variant
+const RENAME_LEVELS: [&str; 2] = ["10", "65"];
+/// FileDispositionInformation and its extended variant
+const DELETE_LEVELS: [&str; 2] = ["13", "64"];
+/// File id of related operations in a compound request, they act on the compound's create
+const RELATED_FID: &str = "ffffffff-ffff-ffff-ffff-ffffffffffff";
+
+fn status_name(status: u32) -> &'static str {
+    match status {
+        0x0000_0000 => "SUCCESS",
+        0x0000_0103 => "PENDING",
+        0x0000_010c => "NOTIFY_ENUM_DIR",
+        0x8000_0005 => "BUFFER_OVERFLOW",
+        0x8000_0006 => "NO_MORE_FILES",
+        0xc000_000d => "INVALID_PARAMETER",
+        0xc000_0010 => "INVALID_DEVICE_REQUEST",
+        0xc000_0011 => "END_OF_FILE",
+        0xc000_0016 => "MORE_PROCESSING_REQUIRED",
+        0xc000_0022 => "ACCESS_DENIED",
+        0xc000_0033 => "OBJECT_NAME_INVALID",
+        0xc000_0034 => "OBJECT_NAME_NOT_FOUND",
+        0xc000_0035 => "OBJECT_NAME_COLLISION",
+        0xc000_003a => "OBJECT_PATH_NOT_FOUND",
+        0xc000_0043 => "SHARING_VIOLATION",
+        0xc000_0054 => "FILE_LOCK_CONFLICT",
+        0xc000_0055 => "LOCK_NOT_GRANTED",
+        0xc000_0056 => "DELETE_PENDING",
+        0xc000_006d => "LOGON_FAILURE",
+        0xc000_006e => "ACCOUNT_RESTRICTION",
+        0xc000_0071 => "PASSWORD_EXPIRED",
+        0xc000_0072 => "ACCOUNT_DISABLED",
+        0xc000_007f => "DISK_FULL",
+        0xc000_009a => "INSUFFICIENT_RESOURCES",
+        0xc000_00ba => "FILE_IS_A_DIRECTORY",
+        0xc000_00bb => "NOT_SUPPORTED",
+        0xc000_00cc => "BAD_NETWORK_NAME",
+        0xc000_0101 => "DIRECTORY_NOT_EMPTY",
+        0xc000_0103 => "NOT_A_DIRECTORY",
+        0xc000_0120 => "CANCELLED",
+        0xc000_0128 => "FILE_CLOSED",
+        0xc000_0203 => "USER_SESSION_DELETED",
+        0xc000_0225 => "NOT_FOUND",
+        0xc000_0234 => "ACCOUNT_LOCKED_OUT",
+        _ => "OTHER",
+    }
+}
+
+/// Statuses that are part of normal operation rather than failures
+fn is_routine(status: u32) -> bool {
+    matches!(
+        status,
+        0x0000_0000 | 0x0000_010c | 0x8000_0005 | 0x8000_0006 | 0xc000_0011 | 0xc000_0016
+    )
+}
+
+fn is_not_found(status: &str) -> bool {
+    matches!(
+        status,
+        "OBJECT_NAME_NOT_FOUND" | "OBJECT_PATH_NOT_FOUND" | "BAD_NETWORK_NAME" | "NOT_FOUND"
+    )
+}
+
+fn is_denied(status: &str) -> bool {
+    matches!(status, "ACCESS_DENIED" | "SHARING_VIOLATION")
+}
+
+fn is_true(value: &str) -> bool {
+    value == "1" || value.eq_ignore_ascii_case("true")
+}
+
+#[derive(Default)]
+struct PendingRequest {
+    ts: DateTime<Utc>,
+    command: u32,
+    session: String,
+    tid: String,
+    /// Share for tree connects, file path relative to the share otherwise
+    path: String,
+    fid: String,
+    user: String,
+    length: u64,
+    info_level: String,
+    new_name: String,
+    delete_on_close: bool,
+    /// The server answered STATUS_PENDING and completes the request later
+    interim: bool,
+}
+
+struct OpenFile {
+    /// (client, server)
+    conn: (String, String),
+    /// Time of the create or of the last read or write
+    last_access: DateTime<Utc>,
+    user: String,
+    share: String,
+    path: String,
+    read: u64,
+    written: u64,
+    delete: bool,
+}
+
+#[derive(Default)]
+struct CommandStats {
+    count: u32,
+    errors: u32,
+    latency: LatencyStats,
+}
+
+#[derive(Default)]
+struct AccessStats {
+    creates: u32,
+    reads: u32,
+    writes: u32,
+    deletes: u32,
+    renames: u32,
+    errors: u32,
+    bytes_read: u64,
+    bytes_written: u64,
+}
+
+pub struct Analyzer {
+    /// (tcp stream, message id)
+    pending: HashMap<(String, String), PendingRequest>,
+    /// (tcp stream, session id) to DOMAIN\user
+    sessions: HashMap<(String, String), String>,
+    /// (tcp stream, session id, tree id) to share
+    trees: HashMap<(String, String, String), String>,
+    /// (tcp stream, file id)
+    open_files: HashMap<(String, String), OpenFile>,
+    commands: HashMap<&'static str, CommandStats>,
+    /// (user, share)
+    access: HashMap<(String, String), AccessStats>,
+    statuses: HashMap<&'static str, u32>,
+    /// (status, user, path) for denied and not found errors
+    hotspots: HashMap<(&'static str, String, String), u32>,
+    logon_failures: HashMap<String, u32>,
+    timeouts: u32,
+    unmatched_responses: u32,
+    timeout: i64,
+    top: usize,
+    last_timeout_check: Option<DateTime<Utc>>,
+    formatter: Box<dyn EventFormatter>,
+    daily_report: DailyReport,
+}
+
+const PROTOCOL: &str = "smb";
+
+fn join_path(share: &str, path: &str) -> String {
+    if path.is_empty() {
+        share.to_owned()
+    } else {
+        format!("{share}\\{}", path.trim_start_matches('\\'))
+    }
+}
+
+impl Analyzer {
+    fn user(&self, stream: &str, session: &str) -> String {
+        self.sessions
+            .get(&(stream.to_owned(), session.to_owned()))
+            .cloned()
+            .unwrap_or_else(|| format!("session {session}"))
+    }
+
+    fn share(&self, stream: &str, session: &str, tid: &str) -> String {
+        self.trees
+            .get(&(stream.to_owned(), session.to_owned(), tid.to_owned()))
+            .cloned()
+            .unwrap_or_else(|| format!("tree {tid}"))
+    }
+
+    /// File access record, `conn` is (client, server)
+    fn audit(
+        &mut self,
+        ts: DateTime<Utc>,
+        conn: &(String, String),
+        user: &str,
+        op: &str,
+        detail: String,
+        latency_ms: Option<f64>,
+    ) {
+        self.formatter.event(&Event {
+            ts,
+            protocol: PROTOCOL,
+            kind: EventKind::Audit,
+            user: conn.0.clone(),
+            method: op.to_owned(),
+            addr: Some(conn.1.clone()),
+            auth_user: Some(user.to_owned()),
+            latency_ms,
+            detail: Some(detail),
+            ..Default::default()
+        });
+    }
+
+    /// Audit records of a closed file, or of one still open when the capture ends
+    fn close_file(&mut self, ts: DateTime<Utc>, file: &OpenFile, open: bool) {
+        let path = join_path(&file.share, &file.path);
+        let access = self
+            .access
+            .entry((file.user.clone(), file.share.clone()))
+            .or_default();
+        if file.written > 0 {
+            access.writes += 1;
+        }
+        if file.read > 0 {
+            access.reads += 1;
+        }
+        if file.delete {
+            access.deletes += 1;
+        }
+        let state = if open { ", still open" } else { "" };
+        for (op, bytes) in [("write", file.written), ("read", file.read)] {
+            if bytes > 0 {
+                self.audit(
+                    ts,
+                    &file.conn,
+                    &file.user,
+                    op,
+                    format!("{path} {bytes} bytes{state}"),
+                    None,
+                );
+            }
+        }
+        if file.delete {
+            self.audit(
+                ts,
+                &file.conn,
+                &file.user,
+                "delete",
+                format!("{path}{state}"),
+                None,
+            );
+        }
+    }
+
+    fn on_request(&mut self, ts: DateTime<Utc>, cols: &[&str]) {
+        let stream = cols[18];
+        let commands = cols[2]
+            .split(',')
+            .filter_map(parse_number)
+            .collect::<Vec<_>>();
+        let mut msg_ids = cols[4].split(',');
+        let mut sessions = cols[5].split(',');
+        let mut tids = cols[6].split(',');
+        let mut trees = cols[8].split(',');
+        // file names may contain commas, they only pair up when creates and renames account for
+        // all of them
+        let creates = commands.iter().filter(|c| **c == CREATE).count();
+        let renames = commands
+            .iter()
+            .filter(|c| matches!(**c, GETINFO | SETINFO))
+            .zip(cols[15].split(','))
+            .filter(|(c, level)| **c == SETINFO && RENAME_LEVELS.contains(level))
+            .count();
+        let mut filenames = occurrences(cols[9], creates + renames);
+        let mut fids = cols[10].split(',');
+        let mut delete_flags = cols[12].split(',');
+        let mut write_lengths = cols[14].split(',');
+        let mut info_levels = cols[15].split(',');
+        let user = match (cols[16], cols[17]) {
+            ("", _) => String::new(),
+            (user, "") => user.to_owned(),
+            (user, domain) => format!("{domain}\\{user}"),
+        };
+        // path of the create that related operations in the same compound refer to
+        let mut compound_path = String::new();
+        for command in commands {
+            let mut request = PendingRequest {
+                ts,
+                command,
+                session: next(&mut sessions).to_owned(),
+                tid: next(&mut tids).to_owned(),
+                ..Default::default()
+            };
+            let msg_id = next(&mut msg_ids).to_owned();
+            if FID_COMMANDS.contains(&command) {
+                request.fid = next(&mut fids).to_owned();
+                request.path = match self
+                    .open_files
+                    .get(&(stream.to_owned(), request.fid.clone()))
+                {
+                    Some(file) => file.path.clone(),
+                    None if request.fid == RELATED_FID => compound_path.clone(),
+                    None => String::new(),
+                };
+            }
+            match command {
+                SESSION_SETUP => request.user = user.clone(),
+                TREE_CONNECT => request.path = next(&mut trees).to_owned(),
+                CREATE => {
+                    request.path = next(&mut filenames).to_owned();
+                    request.delete_on_close = is_true(next(&mut delete_flags));
+                    compound_path = request.path.clone();
+                }
+                WRITE => request.length = next(&mut write_lengths).parse().unwrap_or_default(),
+                GETINFO => {
+                    next(&mut info_levels);
+                }
+                SETINFO => {
+                    request.info_level = next(&mut info_levels).to_owned();
+                    if RENAME_LEVELS.contains(&request.info_level.as_str()) {
+                        request.new_name = next(&mut filenames).to_owned();
+                    }
+                }
+                _ => {}
+            }
+            self.pending.insert((stream.to_owned(), msg_id), request);
+        }
+    }
+
+    fn on_response(&mut self, ts: DateTime<Utc>, cols: &[&str]) {
+        let stream = cols[18];
+        let conn = (
+            format!("{}:{}", cols[1], cols[20]),
+            format!("{}:{}", cols[0], cols[19]),
+        );
+        let (client, server) = &conn;
+        let headers = cols[2].split(',').count();
+        let mut msg_ids = cols[4].split(',');
+        let mut statuses = cols[7].split(',');
+        // async responses carry no tree id
+        let tids = cols[6].split(',').collect::<Vec<_>>();
+        let mut fids = cols[10].split(',');
+        let mut actions = cols[11].split(',');
+        // data length of successful reads, a read at the end of file returns less than asked for
+        let mut read_lengths = cols[13].split(',');
+        // file id of the create that related operations in the same compound refer to
+        let mut compound_fid = String::new();
+        for index in 0..headers {
+            let msg_id = next(&mut msg_ids);
+            let status = parse_number(next(&mut statuses)).unwrap_or_default();
+            let key = (stream.to_owned(), msg_id.to_owned());
+            if status == STATUS_PENDING {
+                if let Some(request) = self.pending.get_mut(&key) {
+                    request.interim = true;
+                }
+                continue;
+            }
+            let Some(mut request) = self.pending.remove(&key) else {
+                self.unmatched_responses += 1;
+                continue;
+            };
+            if request.fid == RELATED_FID {
+                request.fid = compound_fid.clone();
+            }
+            let tid = if tids.len() == headers {
+                tids[index]
+            } else {
+                tids[0]
+            };
+            let latency = (ts - request.ts).num_microseconds().unwrap_or_default() as f64 / 1000.0;
+            let name = COMMANDS
+                .get(request.command as usize)
+                .copied()
+                .unwrap_or("UNKNOWN");
+            let stats = self.commands.entry(name).or_default();
+            stats.count += 1;
+            stats.latency.add(latency);
+            let status_label = status_name(status);
+            *self.statuses.entry(status_label).or_default() += 1;
+
+            if request.command == SESSION_SETUP {
+                if status == STATUS_SUCCESS && !request.user.is_empty() {
+                    self.sessions
+                        .insert((stream.to_owned(), request.session.clone()), request.user);
+                } else if !is_routine(status) {
+                    let user = if request.user.is_empty() {
+                        "<unknown>".to_owned()
+                    } else {
+                        request.user
+                    };
+                    *self.logon_failures.entry(user.clone()).or_default() += 1;
+                    self.commands.entry(name).or_default().errors += 1;
+                    self.formatter.event(&Event {
+                        ts,
+                        protocol: PROTOCOL,
+                        kind: EventKind::ErrorResponse,
+                        user: client.clone(),
+                        method: name.to_owned(),
+                        addr: Some(server.clone()),
+                        auth_user: Some(user.clone()),
+                        latency_ms: Some(latency),
+                        detail: Some(format!("{status_label} {user}")),
+                        ..Default::default()
+                    });
+                }
+                continue;
+            }
+
+            let user = self.user(stream, &request.session);
+            let share = if request.command == TREE_CONNECT {
+                request.path.clone()
+            } else {
+                self.share(stream, &request.session, &request.tid)
+            };
+            let path = if request.command == TREE_CONNECT {
+                share.clone()
+            } else {
+                join_path(&share, &request.path)
+            };
+            if !is_routine(status) {
+                self.commands.entry(name).or_default().errors += 1;
+                self.access
+                    .entry((user.clone(), share.clone()))
+                    .or_default()
+                    .errors += 1;
+                let not_found = is_not_found(status_label);
+                if not_found || is_denied(status_label) {
+                    *self
+                        .hotspots
+                        .entry((status_label, user.clone(), path.clone()))
+                        .or_default() += 1;
+                }
+                // probing for missing files is routine for clients, the report has the totals
+                if !not_found {
+                    self.formatter.event(&Event {
+                        ts,
+                        protocol: PROTOCOL,
+                        kind: EventKind::ErrorResponse,
+                        user: client.clone(),
+                        method: name.to_owned(),
+                        addr: Some(server.clone()),
+                        auth_user: Some(user.clone()),
+                        latency_ms: Some(latency),
+                        detail: Some(format!("{status_label} {user} {path}")),
+                        ..Default::default()
+                    });
+                }
+                continue;
+            }
+
+            match request.command {
+                TREE_CONNECT => {
+                    self.trees.insert(
+                        (stream.to_owned(), request.session.clone(), tid.to_owned()),
+                        request.path,
+                    );
+                }
+                CREATE => {
+                    let fid = next(&mut fids);
+                    compound_fid = fid.to_owned();
+                    // 0 superseded, 1 opened, 2 created, 3 overwritten
+                    let action = next(&mut actions);
+                    if action != "1" {
+                        self.access
+                            .entry((user.clone(), share.clone()))
+                            .or_default()
+                            .creates += 1;
+                        self.audit(ts, &conn, &user, "create", path.clone(), Some(latency));
+                    }
+                    self.open_files.insert(
+                        (stream.to_owned(), fid.to_owned()),
+                        OpenFile {
+                            conn: conn.clone(),
+                            last_access: ts,
+                            user,
+                            share,
+                            path: request.path,
+                            read: 0,
+                            written: 0,
+                            delete: request.delete_on_close,
+                        },
+                    );
+                }
+                READ | WRITE => {
+                    let length = if request.command == READ && status == STATUS_SUCCESS {
+                        next(&mut read_lengths).parse().unwrap_or_default()
+                    } else if request.command == READ {
+                        0
+                    } else {
+                        request.length
+                    };
+                    let access = self.access.entry((user, share)).or_default();
+                    if request.command == READ {
+                        access.bytes_read += length;
+                    } else {
+                        access.bytes_written += length;
+                    }
+                    if let Some(file) = self
+                        .open_files
+                        .get_mut(&(stream.to_owned(), request.fid.clone()))
+                    {
+                        file.last_access = ts;
+                        if request.command == READ {
+                            file.read += length;
+                        } else {
+                            file.written += length;
+                        }
+                    }
+                }
+                SETINFO if DELETE_LEVELS.contains(&request.info_level.as_str()) => {
+                    if let Some(file) = self
+                        .open_files
+                        .get_mut(&(stream.to_owned(), request.fid.clone()))
+                    {
+                        file.delete = true;
+                    }
+                }
+                SETINFO if RENAME_LEVELS.contains(&request.info_level.as_str()) => {
+                    let new_name = request.new_name.trim_start_matches('\\').to_owned();
+                    self.access
+                        .entry((user.clone(), share.clone()))
+                        .or_default()
+                        .renames += 1;
+                    self.audit(
+                        ts,
+                        &conn,
+                        &user,
+                        "rename",
+                        format!("{path} -> {}", join_path(&share, &new_name)),
+                        Some(latency),
+                    );
+                    if let Some(file) = self
+                        .open_files
+                        .get_mut(&(stream.to_owned(), request.fid.clone()))
+                    {
+                        file.path = new_name;
+                    }
+                }
+                CLOSE => {
+                    if let Some(file) = self
+                        .open_files
+                        .remove(&(stream.to_owned(), request.fid.clone()))
+                    {
+                        self.close_file(ts, &file, false);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn expire_pending(&mut self, opt_ts: Option<DateTime<Utc>>) {
+        let timeout = self.timeout;
+        // change notifications may wait indefinitely for their response, so they stay pending
+        let expired = self
+            .pending
+            .extract_if(|_, r| {
+                let timeout = if r.interim {
+                    INTERIM_TIMEOUT_S
+                } else {
+                    timeout
+                };
+                r.command != NOTIFY && opt_ts.is_none_or(|ts| (ts - r.ts).num_seconds() >= timeout)
+            })
+            .collect::<Vec<_>>();
+        for ((stream, _), request) in expired {
+            self.timeouts += 1;
+            let user = self.user(&stream, &request.session);
+            let share = self.share(&stream, &request.session, &request.tid);
+            self.formatter.event(&Event {
+                ts: opt_ts.unwrap_or(request.ts),
+                protocol: PROTOCOL,
+                kind: EventKind::NoResponse,
+                user: format!("stream {stream}"),
+                method: COMMANDS
+                    .get(request.command as usize)
+                    .copied()
+                    .unwrap_or("UNKNOWN")
+                    .to_owned(),
+                addr: Some(share.clone()),
+                auth_user: Some(user),
+                elapsed_s: opt_ts.map(|ts| (ts - request.ts).num_seconds()),
+                detail: Some(join_path(&share, &request.path)),
+                ..Default::default()
+            });
+        }
+    }
+
+    fn print_stats(&mut self, opt_ts: Option<DateTime<Utc>>) {
+        let mut output = String::with_capacity(200);
+        report::print_header(self.formatter.as_mut(), opt_ts);
+        report::print_section(self.formatter.as_mut(), "Commands");
+        let mut commands = Vec::from_iter(&self.commands);
+        commands.sort_by(|a, b| b.1.count.cmp(&a.1.count).then(a.0.cmp(b.0)));
+        for (name, stats) in commands {
+            self.formatter.report(
+                &format!(
+                    "{name:<16} {:8} requests {:6} errors  {}",
+                    stats.count,
+                    stats.errors,
+                    stats.latency.summary()
+                ),
+                1,
+            );
+        }
+        report::print_section(self.formatter.as_mut(), "Access By User And Share");
+        let mut access = Vec::from_iter(&self.access);
+        access.sort_by(|a, b| {
+            (b.1.bytes_read + b.1.bytes_written)
+                .cmp(&(a.1.bytes_read + a.1.bytes_written))
+                .then(a.0.cmp(b.0))
+        });
+        for ((user, share), stats) in access.into_iter().take(self.top) {
+            self.formatter.report(
+                &format!(
+                    "{user:<24} {share:<32} created {:5} read {:5} ({:>10} B) written {:5} ({:>10} B) deleted {:5} renamed {:5} errors {:5}",
+                    stats.creates,
+                    stats.reads,
+                    stats.bytes_read,
+                    stats.writes,
+                    stats.bytes_written,
+                    stats.deletes,
+                    stats.renames,
+                    stats.errors
+                ),
+                1,
+            );
+        }
+        report::print_section(self.formatter.as_mut(), "Status Codes");
+        for (status, count) in report::top_n(&self.statuses, self.top) {
+            self.formatter.report(&format!("{count:8} {status}"), 1);
+        }
+        for (title, filter) in [
+            ("Access Denied Hotspots", is_denied as fn(&str) -> bool),
+            ("Not Found Hotspots", is_not_found),
+        ] {
+            report::print_section(self.formatter.as_mut(), title);
+            let hotspots = self
+                .hotspots
+                .iter()
+                .filter(|((status, _, _), _)| filter(status))
+                .map(|(k, v)| (k.clone(), *v))
+                .collect::<HashMap<_, _>>();
+            for ((status, user, path), count) in report::top_n(&hotspots, self.top) {
+                self.formatter
+                    .report(&format!("{count:8} {status:<20} {user:<24} {path}"), 1);
+            }
+        }
+        report::print_section(self.formatter.as_mut(), "Logon Failures");
+        for (user, count) in report::top_n(&self.logon_failures, self.top) {
+            self.formatter.report(&format!("{count:8} {user}"), 1);
+        }
+        write!(
+            output,
+            r#"
+ ------------ STATS ------------
+
+- sessions: {}
+- tree connects: {}
+- open files: {}
+- pending requests: {}
+- timeouts: {}
+- unmatched responses: {}
+"#,
+            self.sessions.len(),
+            self.trees.len(),
+            self.open_files.len(),
+            self.pending.len(),
+            self.timeouts,
+            self.unmatched_responses,
+        )
+        .unwrap();
+        self.formatter.report(&output, 0);
+        report::print_footer(self.formatter.as_mut());
+    }
+}
+
+impl ProtocolAnalyzer for Analyzer {
+    fn new(cmd_args: &ArgsCommand, verbosity: u8) -> Self {
+        let (timeout, top) = match cmd_args {
+            ArgsCommand::Analyzer { timeout, top, .. } => (*timeout as i64, *top),
+            _ => (5, 10),
+        };
+        Self {
+            pending: HashMap::default(),
+            sessions: HashMap::default(),
+            trees: HashMap::default(),
+            open_files: HashMap::default(),
+            commands: HashMap::default(),
+            access: HashMap::default(),
+            statuses: HashMap::default(),
+            hotspots: HashMap::default(),
+            logon_failures: HashMap::default(),
+            timeouts: 0,
+            unmatched_responses: 0,
+            timeout,
+            top,
+            last_timeout_check: None,
+            formatter: create_formatter(cmd_args, verbosity),
+            daily_report: DailyReport::default(),
+        }
+    }
+
+    fn analyze(&mut self, ts: DateTime<Utc>, cols: Vec<&str>) {
+        if self
+            .last_timeout_check
+            .is_none_or(|last| (ts - last).num_seconds() >= 1)
+        {
+            self.expire_pending(Some(ts));
+            self.last_timeout_check = Some(ts);
+        }
+        if self.daily_report.is_due(ts) {
+            self.print_stats(Some(ts));
+        }
+        // compounded messages travel in one direction
+        if cols[3].split(',').next().is_some_and(is_true) {
+            self.on_response(ts, &cols);
+        } else {
+            self.on_request(ts, &cols);
+        }
+    }
+
+    fn add_protocol_fields(&self, tshark_args: &mut Vec<&str>) {
+        tshark_args.push("-e");
+        tshark_args.push("smb2.cmd");
+        tshark_args.push("-e");
+        tshark_args.push("smb2.flags.response");
+        tshark_args.push("-e");
+        tshark_args.push("smb2.msg_id");
+        tshark_args.push("-e");
+        tshark_args.push("smb2.sesid");
+        tshark_args.push("-e");
+        tshark_args.push("smb2.tid");
+        tshark_args.push("-e");
+        tshark_args.push("smb2.nt_status");
+        tshark_args.push("-e");
+        tshark_args.push("smb2.tree");
+        tshark_args.push("-e");
+        tshark_args.push("smb2.filename");
+        tshark_args.push("-e");
+        tshark_args.push("smb2.fid");
+        tshark_args.push("-e");
+        tshark_args.push("smb2.create.action");
+        tshark_args.push("-e");
+        tshark_args.push("smb2.create_options.delete_on_close");
+        tshark_args.push("-e");
+        tshark_args.push("smb2.read_length");
+        tshark_args.push("-e");
+        tshark_args.push("smb2.write_length");
+        tshark_args.push("-e");
+        tshark_args.push("smb2.file_info.infolevel");
+        tshark_args.push("-e");
+        tshark_args.push("ntlmssp.auth.username");
+        tshark_args.push("-e");
+        tshark_args.push("ntlmssp.auth.domain");
+        tshark_args.push("-e");
+        tshark_args.push("tcp.stream");
+        tshark_args.push("-e");
+        tshark_args.push("tcp.srcport");
+        tshark_args.push("-e");
+        tshark_args.push("tcp.dstport");
+        if !tshark_args.contains(&"-Y") {
+            tshark_args.push("-Y");
+            tshark_args.push("smb2");
+        }
+        if tshark_args.contains(&"-i") && !tshark_args.contains(&"-f") {
+            tshark_args.push("-f");
+            tshark_args.push("tcp port 445");
+        }
+    }
+
+    fn end(&mut self) {
+        self.expire_pending(None);
+        let open_files = std::mem::take(&mut self.open_files);
+        let mut files = open_files.values().collect::<Vec<_>>();
+        files.sort_by_key(|f| f.last_access);
+        for file in files {
+            self.close_file(file.last_access, file, true);
+        }
+        self.open_files = open_files;
+        self.print_stats(None);
+    }
+}
+
+// MARK: TESTS
+#[cfg(test)]
+mod test {
+    use chrono::{DateTime, TimeDelta};
+
+    use super::{
+        Analyzer, ProtocolAnalyzer as _, RELATED_FID, is_denied, is_not_found, is_routine,
+        join_path, status_name,
+    };
+    use crate::analyzers::test_args;
+
+    /// Columns of a request or response on session 0x10 and tree 1 with the given fields set
+    fn message<'a>(response: bool, fields: &[(usize, &'a str)]) -> Vec<&'a str> {
+        let mut cols = vec![""; 21];
+        let (src, dst, sport, dport) = if response {
+            ("10.0.0.2", "10.0.0.1", "445", "50000")
+        } else {
+            ("10.0.0.1", "10.0.0.2", "50000", "445")
+        };
+        cols[..2].copy_from_slice(&[src, dst]);
+        cols[3] = if response { "1" } else { "0" };
+        cols[5..7].copy_from_slice(&["0x10", "1"]);
+        cols[18..].copy_from_slice(&["1", sport, dport]);
+        for &(index, value) in fields {
+            cols[index] = value;
+        }
+        cols
+    }
+
+    #[test]
+    fn paths_and_statuses() {
+        assert_eq!(
+            join_path("\\\\nas\\recordings", "\\2025\\call1.wav"),
+            "\\\\nas\\recordings\\2025\\call1.wav"
+        );
+        assert_eq!(join_path("\\\\nas\\recordings", ""), "\\\\nas\\recordings");
+        assert!(is_denied(status_name(0xc000_0022)));
+        assert!(is_not_found(status_name(0xc000_003a)));
+        assert!(is_routine(0x8000_0006));
+        assert!(!is_routine(0xc000_0043));
+    }
+
+    #[test]
+    fn reads_and_open_files() {
+        let mut analyzer = Analyzer::new(&test_args(&[]), 0);
+        let start = DateTime::from_timestamp(1738062028, 0).unwrap();
+        for (s, response, cmd, msg_id, status, fid, read_length) in [
+            (0, false, "5", "1", "", "", ""),
+            (0, true, "5", "1", "0x00000000", "abc", ""),
+            (1, false, "8", "2", "", "abc", "65536"),
+            (1, true, "8", "2", "0x00000000", "", "1000"),
+            // at the end of file the server returns less than asked for
+            (2, false, "8", "3", "", "abc", "65536"),
+            (2, true, "8", "3", "0xc0000011", "", ""),
+            // acknowledged with STATUS_PENDING, completed long after the timeout
+            (3, false, "8", "4", "", "abc", "65536"),
+            (3, true, "8", "4", "0x00000103", "", ""),
+            (60, false, "8", "5", "", "abc", "65536"),
+            (60, true, "8", "5", "0x00000000", "", "0"),
+            (61, true, "8", "4", "0x00000000", "", "500"),
+        ] {
+            let mut cols = message(
+                response,
+                &[
+                    (2, cmd),
+                    (4, msg_id),
+                    (7, status),
+                    (10, fid),
+                    (13, read_length),
+                ],
+            );
+            if cmd == "5" {
+                cols[if response { 11 } else { 9 }] = if response { "1" } else { "rec\\call.wav" };
+            }
+            analyzer.analyze(start + TimeDelta::seconds(s), cols);
+        }
+        assert!(analyzer.pending.is_empty());
+        assert_eq!((analyzer.timeouts, analyzer.unmatched_responses), (0, 0));
+        analyzer.end();
+        let access = &analyzer.access[&("session 0x10".to_owned(), "tree 1".to_owned())];
+        assert_eq!((access.reads, access.bytes_read), (1, 1500));
+    }
+
+    #[test]
+    fn names_with_commas_and_interim_cap() {
+        let mut analyzer = Analyzer::new(&test_args(&[]), 0);
+        let start = DateTime::from_timestamp(1738062028, 0).unwrap();
+        let name = "rec\\Smith, John.wav";
+        analyzer.analyze(start, message(false, &[(2, "5"), (4, "1"), (9, name)]));
+        // two creates and three names, none can be trusted
+        let names = "a.wav,rec\\Smith, John.wav";
+        analyzer.analyze(start, message(false, &[(2, "5,5"), (4, "2,3"), (9, names)]));
+        // create and rename in one compound
+        let fields = [
+            (2, "5,17"),
+            (4, "4,5"),
+            (9, "old.wav,new.wav"),
+            (10, RELATED_FID),
+            (15, "10"),
+        ];
+        analyzer.analyze(start, message(false, &fields));
+        let path = |msg_id: &str| {
+            analyzer.pending[&("1".to_owned(), msg_id.to_owned())]
+                .path
+                .clone()
+        };
+        assert_eq!(path("1"), name);
+        assert_eq!((path("2"), path("3")), (String::new(), String::new()));
+        assert_eq!(
+            (path("4"), path("5")),
+            ("old.wav".to_owned(), "old.wav".to_owned())
+        );
+        assert_eq!(
+            analyzer.pending[&("1".to_owned(), "5".to_owned())].new_name,
+            "new.wav"
+        );
+
+        // a lock acknowledged with STATUS_PENDING outlives the timeout but not the cap
+        analyzer.analyze(start, message(false, &[(2, "10"), (4, "6"), (10, "abc")]));
+        analyzer.analyze(
+            start,
+            message(true, &[(2, "10"), (4, "6"), (7, "0x00000103")]),
+        );
+        analyzer.analyze(
+            start + TimeDelta::seconds(60),
+            message(false, &[(2, "13"), (4, "7")]),
+        );
+        assert_eq!(analyzer.timeouts, 5);
+        analyzer.analyze(
+            start + TimeDelta::seconds(3600),
+            message(false, &[(2, "13"), (4, "8")]),
+        );
+        assert_eq!(analyzer.timeouts, 7);
+        assert!(
+            analyzer
+                .pending
+                .contains_key(&("1".to_owned(), "8".to_owned()))
+        );
+    }
+}
@@ -0,0 +1,789 @@
+use super::{
+    Event, EventKind, ProtocolAnalyzer,
+    report::{self, DailyReport, LatencyStats},
+};
+use crate::{
+    ArgsCommand,
+    formatters::{EventFormatter, create_formatter},
+};
+use ahash::{HashMap, HashSet};
+use chrono::{DateTime, Utc};
+use std::fmt::Write as _;
+
+const PDU_GET: &str = "0";
+const PDU_GETNEXT: &str = "1";
+const PDU_RESPONSE: &str = "2";
+const PDU_SET: &str = "3";
+const PDU_TRAP_V1: &str = "4";
+const PDU_GETBULK: &str = "5";
+const PDU_INFORM: &str = "6";
+const PDU_TRAP_V2: &str = "7";
+const PDU_REPORT: &str = "8";
+const SNMP_TRAP_OID: &str = "1.3.6.1.6.3.1.1.4.1.0";
+const AUTH_FAILURE_TRAP: &str = "1.3.6.1.6.3.1.1.5.5";
+/// usmStats counters carried by SNMPv3 reports
+const USM_STATS: &str = "1.3.6.1.6.3.15.1.1.";
+
+/// Well known OID prefixes, longest match wins
+const OID_NAMES: [(&str, &str); 18] = [
+    ("1.3.6.1.2.1.1.1", "sysDescr"),
+    ("1.3.6.1.2.1.1.3", "sysUpTime"),
+    ("1.3.6.1.2.1.1.5", "sysName"),
+    ("1.3.6.1.2.1.2.2.1.1", "ifIndex"),
+    ("1.3.6.1.2.1.2.2.1.2", "ifDescr"),
+    ("1.3.6.1.2.1.2.2.1.7", "ifAdminStatus"),
+    ("1.3.6.1.2.1.2.2.1.8", "ifOperStatus"),
+    ("1.3.6.1.2.1.31.1.1.1.1", "ifName"),
+    ("1.3.6.1.6.3.1.1.4.1", "snmpTrapOID"),
+    ("1.3.6.1.6.3.1.1.4.3", "snmpTrapEnterprise"),
+    ("1.3.6.1.6.3.1.1.5.1", "coldStart"),
+    ("1.3.6.1.6.3.1.1.5.2", "warmStart"),
+    ("1.3.6.1.6.3.1.1.5.3", "linkDown"),
+    ("1.3.6.1.6.3.1.1.5.4", "linkUp"),
+    ("1.3.6.1.6.3.1.1.5.5", "authenticationFailure"),
+    ("1.3.6.1.6.3.15.1.1.2", "usmStatsNotInTimeWindows"),
+    ("1.3.6.1.6.3.15.1.1.3", "usmStatsUnknownUserNames"),
+    ("1.3.6.1.6.3.15.1.1.5", "usmStatsWrongDigests"),
+];
+
+struct PendingRequest {
+    ts: DateTime<Utc>,
+    pdu: &'static str,
+    community: String,
+}
+
+#[derive(Default)]
+struct AgentStats {
+    requests: u32,
+    responses: u32,
+    timeouts: u32,
+    errors: u32,
+    traps: u32,
+    latency: LatencyStats,
+    /// Informs sent by the agent, acknowledged by the manager and kept out of the polling numbers
+    informs: u32,
+    inform_acks: u32,
+    inform_timeouts: u32,
+    inform_latency: LatencyStats,
+}
+
+#[derive(Default)]
+struct CommunityStats {
+    requests: u32,
+    responses: u32,
+}
+
+/// Varbinds of a v1/v2c message decoded from the UDP payload
+#[derive(Debug, PartialEq)]
+struct Pdu {
+    trap_oid: Option<String>,
+    varbinds: Vec<(String, String)>,
+}
+
+pub struct Analyzer {
+    /// (requester, responder, request id), informs are sent by the agent
+    pending: HashMap<(String, String, String), PendingRequest>,
+    agents: HashMap<String, AgentStats>,
+    /// (agent, community)
+    communities: HashMap<(String, String), CommunityStats>,
+    reported_communities: HashSet<(String, String)>,
+    error_status: HashMap<String, u32>,
+    traps: HashMap<String, u32>,
+    auth_failures: HashMap<String, u32>,
+    unmatched_responses: u32,
+    timeout: i64,
+    top: usize,
+    last_timeout_check: Option<DateTime<Utc>>,
+    formatter: Box<dyn EventFormatter>,
+    daily_report: DailyReport,
+}
+
+const PROTOCOL: &str = "snmp";
+
+fn pdu_name(pdu: &str) -> &'static str {
+    match pdu {
+        PDU_GET => "GET",
+        PDU_GETNEXT => "GETNEXT",
+        PDU_RESPONSE => "RESPONSE",
+        PDU_SET => "SET",
+        PDU_TRAP_V1 => "TRAP",
+        PDU_GETBULK => "GETBULK",
+        PDU_INFORM => "INFORM",
+        PDU_TRAP_V2 => "TRAPv2",
+        PDU_REPORT => "REPORT",
+        _ => "OTHER",
+    }
+}
+
+fn error_status_name(status: &str) -> &str {
+    match status {
+        "1" => "tooBig",
+        "2" => "noSuchName",
+        "3" => "badValue",
+        "4" => "readOnly",
+        "5" => "genErr",
+        "6" => "noAccess",
+        "7" => "wrongType",
+        "8" => "wrongLength",
+        "9" => "wrongEncoding",
+        "10" => "wrongValue",
+        "11" => "noCreation",
+        "12" => "inconsistentValue",
+        "13" => "resourceUnavailable",
+        "14" => "commitFailed",
+        "15" => "undoFailed",
+        "16" => "authorizationError",
+        "17" => "notWritable",
+        "18" => "inconsistentName",
+        _ => status,
+    }
+}
+
+fn oid_name(oid: &str) -> String {
+    OID_NAMES
+        .iter()
+        .filter(|(prefix, _)| {
+            oid.strip_prefix(prefix)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+        })
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map_or_else(
+            || oid.to_owned(),
+            |(prefix, name)| format!("{name}{}", &oid[prefix.len()..]),
+        )
+}
+
+/// One BER TLV: (tag, content, rest)
+fn tlv(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, data) = data.split_first()?;
+    let (&first, data) = data.split_first()?;
+    let (len, data) = if first & 0x80 == 0 {
+        (first as usize, data)
+    } else {
+        let n = (first & 0x7f) as usize;
+        if n == 0 || n > 4 || data.len() < n {
+            return None;
+        }
+        let len = data[..n]
+            .iter()
+            .fold(0usize, |acc, b| acc << 8 | *b as usize);
+        (len, &data[n..])
+    };
+    (data.len() >= len).then(|| (tag, &data[..len], &data[len..]))
+}
+
+fn ber_oid(content: &[u8]) -> String {
+    let mut output = String::new();
+    let mut value = 0u64;
+    for byte in content {
+        value = value << 7 | (byte & 0x7f) as u64;
+        if byte & 0x80 != 0 {
+            continue;
+        }
+        if output.is_empty() {
+            let first = (value / 40).min(2);
+            write!(output, "{first}.{}", value - first * 40).unwrap();
+        } else {
+            write!(output, ".{value}").unwrap();
+        }
+        value = 0;
+    }
+    output
+}
+
+fn ber_integer(content: &[u8]) -> i64 {
+    let sign = if content.first().is_some_and(|b| b & 0x80 != 0) {
+        -1
+    } else {
+        0
+    };
+    content.iter().fold(sign, |acc, b| acc << 8 | *b as i64)
+}
+
+fn ber_value(tag: u8, content: &[u8]) -> String {
+    match tag {
+        0x02 => ber_integer(content).to_string(),
+        0x04 => match std::str::from_utf8(content) {
+            Ok(text) if !text.chars().any(|c| c.is_control() && c != '\n') => {
+                format!("\"{}\"", text.trim_end_matches('\0'))
+            }
+            _ => content
+                .iter()
+                .map(|b| format!("{b:02x}"))
+                .collect::<Vec<_>>()
+                .join(":"),
+        },
+        0x05 => "null".into(),
+        0x06 => oid_name(&ber_oid(content)),
+        0x40 => content
+            .iter()
+            .map(u8::to_string)
+            .collect::<Vec<_>>()
+            .join("."),
+        0x41 | 0x42 | 0x43 | 0x46 => content
+            .iter()
+            .fold(0u64, |acc, b| acc << 8 | *b as u64)
+            .to_string(),
+        0x80 => "noSuchObject".into(),
+        0x81 => "noSuchInstance".into(),
+        0x82 => "endOfMibView".into(),
+        _ => format!("tag 0x{tag:02x}"),
+    }
+}
+
+/// Decodes v1/v2c messages, v3 scoped PDUs are left to the tshark fields
+fn parse_pdu(payload: &[u8]) -> Option<Pdu> {
+    let (0x30, message, _) = tlv(payload)? else {
+        return None;
+    };
+    let (0x02, version, rest) = tlv(message)? else {
+        return None;
+    };
+    if ber_integer(version) > 1 {
+        return None;
+    }
+    let (0x04, _, rest) = tlv(rest)? else {
+        return None;
+    };
+    let (pdu_tag, pdu, _) = tlv(rest)?;
+    let mut trap_oid = None;
+    let mut rest = pdu;
+    if pdu_tag == 0xa4 {
+        let (_, enterprise, after) = tlv(rest)?;
+        let (_, _agent_addr, after) = tlv(after)?;
+        let (_, generic, after) = tlv(after)?;
+        let (_, specific, after) = tlv(after)?;
+        let (_, _timestamp, after) = tlv(after)?;
+        // RFC 3584 mapping of v1 traps to v2 trap OIDs
+        trap_oid = Some(match ber_integer(generic) {
+            generic @ 0..=5 => format!("1.3.6.1.6.3.1.1.5.{}", generic + 1),
+            _ => format!("{}.0.{}", ber_oid(enterprise), ber_integer(specific)),
+        });
+        rest = after;
+    } else {
+        for _ in 0..3 {
+            rest = tlv(rest)?.2;
+        }
+    }
+    let (0x30, mut list, _) = tlv(rest)? else {
+        return None;
+    };
+    let mut varbinds = Vec::new();
+    while !list.is_empty() {
+        let (_, varbind, after) = tlv(list)?;
+        let (_, name, value) = tlv(varbind)?;
+        let (tag, value, _) = tlv(value)?;
+        let name = ber_oid(name);
+        if name == SNMP_TRAP_OID {
+            trap_oid = Some(ber_oid(value));
+        }
+        varbinds.push((name, ber_value(tag, value)));
+        list = after;
+    }
+    Some(Pdu { trap_oid, varbinds })
+}
+
+/// Trap name followed by its varbinds, without the sysUpTime and snmpTrapOID preamble
+fn trap_summary(pdu: &Pdu) -> String {
+    let mut output = pdu
+        .trap_oid
+        .as_deref()
+        .map_or_else(|| "-".to_owned(), oid_name);
+    for (name, value) in &pdu.varbinds {
+        if name == SNMP_TRAP_OID || name.starts_with("1.3.6.1.2.1.1.3") {
+            continue;
+        }
+        write!(output, " {}={value}", oid_name(name)).unwrap();
+    }
+    output
+}
+
+fn latency_ms(ts: DateTime<Utc>, request: &PendingRequest) -> f64 {
+    (ts - request.ts).num_microseconds().unwrap_or_default() as f64 / 1000.0
+}
+
+impl Analyzer {
+    fn expire_pending(&mut self, opt_ts: Option<DateTime<Utc>>) {
+        let timeout = self.timeout;
+        let expired = self
+            .pending
+            .extract_if(|_, r| opt_ts.is_none_or(|ts| (ts - r.ts).num_seconds() >= timeout))
+            .collect::<Vec<_>>();
+        for ((requester, responder, _), request) in expired {
+            let (manager, agent) = if request.pdu == PDU_INFORM {
+                (responder, requester)
+            } else {
+                (requester, responder)
+            };
+            let agent_host = agent.rsplit_once(':').map_or(agent.as_str(), |(h, _)| h);
+            let stats = self.agents.entry(agent_host.to_owned()).or_default();
+            if request.pdu == PDU_INFORM {
+                stats.inform_timeouts += 1;
+            } else {
+                stats.timeouts += 1;
+            }
+            let answers_others = stats.responses > 0;
+            let key = (agent_host.to_owned(), request.community.clone());
+            let community_ignored = request.pdu != PDU_INFORM
+                && !request.community.is_empty()
+                && answers_others
+                && self.communities.get(&key).is_some_and(|c| c.responses == 0);
+            self.formatter.event(&Event {
+                ts: opt_ts.unwrap_or(request.ts),
+                protocol: PROTOCOL,
+                kind: EventKind::NoResponse,
+                user: manager.clone(),
+                method: pdu_name(request.pdu).into(),
+                addr: Some(agent.clone()),
+                elapsed_s: opt_ts.map(|ts| (ts - request.ts).num_seconds()),
+                ..Default::default()
+            });
+            // agents drop requests with a wrong community without answering
+            if community_ignored && self.reported_communities.insert(key) {
+                *self
+                    .auth_failures
+                    .entry(format!("{agent_host} community {}", request.community))
+                    .or_default() += 1;
+                self.formatter.event(&Event {
+                    ts: opt_ts.unwrap_or(request.ts),
+                    protocol: PROTOCOL,
+                    kind: EventKind::Alert,
+                    user: manager,
+                    method: pdu_name(request.pdu).into(),
+                    addr: Some(agent),
+                    detail: Some(format!(
+                        "community \"{}\" never answered while the agent answers others",
+                        request.community
+                    )),
+                    ..Default::default()
+                });
+            }
+        }
+    }
+
+    fn on_notification(&mut self, ts: DateTime<Utc>, pdu: &'static str, cols: &[&str]) {
+        let src = format!("{}:{}", cols[0], cols[9]);
+        let dst = format!("{}:{}", cols[1], cols[10]);
+        let stats = self.agents.entry(cols[0].to_owned()).or_default();
+        stats.traps += 1;
+        if pdu == PDU_INFORM {
+            stats.informs += 1;
+        }
+        let decoded = hex::decode(cols[11].replace(':', ""))
+            .ok()
+            .and_then(|payload| parse_pdu(&payload));
+        let summary = decoded.as_ref().map_or_else(
+            // v3 notifications, only the varbind names are known
+            || {
+                cols[8]
+                    .split(',')
+                    .filter(|n| !n.is_empty())
+                    .map(oid_name)
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            },
+            trap_summary,
+        );
+        let trap_oid = decoded.as_ref().and_then(|p| p.trap_oid.clone());
+        *self
+            .traps
+            .entry(trap_oid.as_deref().map_or_else(|| "-".to_owned(), oid_name))
+            .or_default() += 1;
+        let auth_failure = trap_oid.as_deref() == Some(AUTH_FAILURE_TRAP);
+        if auth_failure {
+            *self
+                .auth_failures
+                .entry(format!("{} authenticationFailure trap", cols[0]))
+                .or_default() += 1;
+        }
+        self.formatter.event(&Event {
+            ts,
+            protocol: PROTOCOL,
+            kind: if auth_failure {
+                EventKind::Alert
+            } else {
+                EventKind::Warning
+            },
+            user: src.clone(),
+            method: pdu_name(pdu).into(),
+            addr: Some(dst.clone()),
+            detail: Some(summary),
+            ..Default::default()
+        });
+        if pdu == PDU_INFORM {
+            self.pending.insert(
+                (src, dst, cols[5].to_owned()),
+                PendingRequest {
+                    ts,
+                    pdu,
+                    community: String::new(),
+                },
+            );
+        }
+    }
+
+    fn on_response(&mut self, ts: DateTime<Utc>, pdu: &'static str, cols: &[&str]) {
+        let src = format!("{}:{}", cols[0], cols[9]);
+        let dst = format!("{}:{}", cols[1], cols[10]);
+        let Some(request) = self
+            .pending
+            .remove(&(dst.clone(), src.clone(), cols[5].to_owned()))
+        else {
+            self.unmatched_responses += 1;
+            return;
+        };
+        // informs are acknowledged by the manager, the agent is the destination
+        let agent = if request.pdu == PDU_INFORM {
+            cols[1]
+        } else {
+            cols[0]
+        };
+        let latency = latency_ms(ts, &request);
+        let stats = self.agents.entry(agent.to_owned()).or_default();
+        if request.pdu == PDU_INFORM {
+            stats.inform_acks += 1;
+            stats.inform_latency.add(latency);
+            return;
+        }
+        stats.responses += 1;
+        stats.latency.add(latency);
+        if let Some(community) = self
+            .communities
+            .get_mut(&(agent.to_owned(), request.community.clone()))
+        {
+            community.responses += 1;
+        }
+
+        if pdu == PDU_REPORT {
+            let counters = cols[8]
+                .split(',')
+                .filter(|n| n.starts_with(USM_STATS))
+                .collect::<Vec<_>>();
+            // unknownEngineIDs is the regular engine discovery step
+            if counters.is_empty()
+                || counters
+                    .iter()
+                    .all(|n| n.starts_with("1.3.6.1.6.3.15.1.1.4"))
+            {
+                return;
+            }
+            stats.errors += 1;
+            let detail = format!(
+                "{} user {}",
+                counters
+                    .iter()
+                    .map(|n| oid_name(n))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                first_or_dash(cols[7])
+            );
+            *self
+                .auth_failures
+                .entry(format!("{agent} {detail}"))
+                .or_default() += 1;
+            self.formatter.event(&Event {
+                ts,
+                protocol: PROTOCOL,
+                kind: EventKind::Alert,
+                user: dst,
+                method: pdu_name(request.pdu).into(),
+                addr: Some(src),
+                latency_ms: Some(latency),
+                detail: Some(detail),
+                ..Default::default()
+            });
+            return;
+        }
+        let status = cols[6];
+        if status.is_empty() || status == "0" {
+            return;
+        }
+        stats.errors += 1;
+        let name = error_status_name(status).to_owned();
+        *self.error_status.entry(name.clone()).or_default() += 1;
+        if status == "16" {
+            *self
+                .auth_failures
+                .entry(format!("{agent} authorizationError"))
+                .or_default() += 1;
+        }
+        self.formatter.event(&Event {
+            ts,
+            protocol: PROTOCOL,
+            kind: EventKind::ErrorResponse,
+            user: dst,
+            method: pdu_name(request.pdu).into(),
+            addr: Some(src),
+            status: status.parse().ok(),
+            latency_ms: Some(latency),
+            detail: Some(name),
+            ..Default::default()
+        });
+    }
+
+    fn print_stats(&mut self, opt_ts: Option<DateTime<Utc>>) {
+        let mut output = String::with_capacity(200);
+        report::print_header(self.formatter.as_mut(), opt_ts);
+        report::print_section(self.formatter.as_mut(), "Agents");
+        let mut agents = Vec::from_iter(&self.agents);
+        agents.sort_by(|a, b| b.1.timeouts.cmp(&a.1.timeouts).then(a.0.cmp(b.0)));
+        for (agent, stats) in agents.into_iter().take(self.top) {
+            write!(
+                output,
+                "{agent:<16} {:7} requests {:7} responses {:5} timeouts {:5} errors {:6} traps",
+                stats.requests, stats.responses, stats.timeouts, stats.errors, stats.traps
+            )
+            .unwrap();
+            if stats.responses > 0 {
+                write!(output, "\n{:<16} {}", "", stats.latency.summary()).unwrap();
+            }
+            if stats.informs > 0 {
+                write!(
+                    output,
+                    "\n{:<16} {:7} informs {:7} acked {:5} unacked  ack {}",
+                    "",
+                    stats.informs,
+                    stats.inform_acks,
+                    stats.inform_timeouts,
+                    stats.inform_latency.summary()
+                )
+                .unwrap();
+            }
+            self.formatter.report(&output, 1);
+            output.clear();
+        }
+
+        report::print_section(self.formatter.as_mut(), "Error Status");
+        for (status, count) in report::top_n(&self.error_status, self.top) {
+            self.formatter.report(&format!("{count:8} {status}"), 1);
+        }
+        report::print_section(self.formatter.as_mut(), "Traps");
+        for (trap, count) in report::top_n(&self.traps, self.top) {
+            self.formatter.report(&format!("{count:8} {trap}"), 1);
+        }
+        report::print_section(self.formatter.as_mut(), "Auth Failures");
+        for (failure, count) in report::top_n(&self.auth_failures, self.top) {
+            self.formatter.report(&format!("{count:8} {failure}"), 1);
+        }
+        report::print_section(self.formatter.as_mut(), "Unanswered Communities");
+        let mut unanswered = self
+            .communities
+            .iter()
+            .filter(|(_, c)| c.responses == 0)
+            .collect::<Vec<_>>();
+        unanswered.sort_by(|a, b| b.1.requests.cmp(&a.1.requests).then(a.0.cmp(b.0)));
+        for ((agent, community), stats) in unanswered.into_iter().take(self.top) {
+            self.formatter.report(
+                &format!("{:8} {agent:<16} \"{community}\"", stats.requests),
+                1,
+            );
+        }
+        write!(
+            output,
+            r#"
+ ------------ STATS ------------
+
+- total requests: {}
+- total responses: {}
+- total traps: {}
+- total informs: {} acked {}
+- pending requests: {}
+- unmatched responses: {}
+"#,
+            self.agents.values().map(|a| a.requests).sum::<u32>(),
+            self.agents.values().map(|a| a.responses).sum::<u32>(),
+            self.agents.values().map(|a| a.traps).sum::<u32>(),
+            self.agents.values().map(|a| a.informs).sum::<u32>(),
+            self.agents.values().map(|a| a.inform_acks).sum::<u32>(),
+            self.pending.len(),
+            self.unmatched_responses,
+        )
+        .unwrap();
+        self.formatter.report(&output, 0);
+        report::print_footer(self.formatter.as_mut());
+    }
+}
+
+fn first_or_dash(value: &str) -> &str {
+    Some(value.split(',').next().unwrap_or_default())
+        .filter(|v| !v.is_empty())
+        .unwrap_or("-")
+}
+
+impl ProtocolAnalyzer for Analyzer {
+    fn new(cmd_args: &ArgsCommand, verbosity: u8) -> Self {
+        let (timeout, top) = match cmd_args {
+            ArgsCommand::Analyzer { timeout, top, .. } => (*timeout as i64, *top),
+            _ => (5, 10),
+        };
+        Self {
+            pending: HashMap::default(),
+            agents: HashMap::default(),
+            communities: HashMap::default(),
+            reported_communities: HashSet::default(),
+            error_status: HashMap::default(),
+            traps: HashMap::default(),
+            auth_failures: HashMap::default(),
+            unmatched_responses: 0,
+            timeout,
+            top,
+            last_timeout_check: None,
+            formatter: create_formatter(cmd_args, verbosity),
+            daily_report: DailyReport::default(),
+        }
+    }
+
+    fn analyze(&mut self, ts: DateTime<Utc>, cols: Vec<&str>) {
+        if self
+            .last_timeout_check
+            .is_none_or(|last| (ts - last).num_seconds() >= 1)
+        {
+            self.expire_pending(Some(ts));
+            self.last_timeout_check = Some(ts);
+        }
+        if self.daily_report.is_due(ts) {
+            self.print_stats(Some(ts));
+        }
+        let pdu = match cols[4].split(',').next().unwrap_or_default() {
+            PDU_GET => PDU_GET,
+            PDU_GETNEXT => PDU_GETNEXT,
+            PDU_RESPONSE => PDU_RESPONSE,
+            PDU_SET => PDU_SET,
+            PDU_TRAP_V1 => PDU_TRAP_V1,
+            PDU_GETBULK => PDU_GETBULK,
+            PDU_INFORM => PDU_INFORM,
+            PDU_TRAP_V2 => PDU_TRAP_V2,
+            PDU_REPORT => PDU_REPORT,
+            _ => return,
+        };
+        match pdu {
+            PDU_TRAP_V1 | PDU_TRAP_V2 | PDU_INFORM => self.on_notification(ts, pdu, &cols),
+            PDU_RESPONSE | PDU_REPORT => self.on_response(ts, pdu, &cols),
+            _ => {
+                let manager = format!("{}:{}", cols[0], cols[9]);
+                let agent = format!("{}:{}", cols[1], cols[10]);
+                let community = cols[3].to_owned();
+                self.agents.entry(cols[1].to_owned()).or_default().requests += 1;
+                self.communities
+                    .entry((cols[1].to_owned(), community.clone()))
+                    .or_default()
+                    .requests += 1;
+                // retransmissions keep the first send time
+                self.pending
+                    .entry((manager, agent, cols[5].to_owned()))
+                    .or_insert(PendingRequest { ts, pdu, community });
+            }
+        }
+    }
+
+    fn add_protocol_fields(&self, tshark_args: &mut Vec<&str>) {
+        tshark_args.push("-e");
+        tshark_args.push("snmp.version");
+        tshark_args.push("-e");
+        tshark_args.push("snmp.community");
+        tshark_args.push("-e");
+        tshark_args.push("snmp.data");
+        tshark_args.push("-e");
+        tshark_args.push("snmp.request_id");
+        tshark_args.push("-e");
+        tshark_args.push("snmp.error_status");
+        tshark_args.push("-e");
+        tshark_args.push("snmp.msgUserName");
+        tshark_args.push("-e");
+        tshark_args.push("snmp.name");
+        tshark_args.push("-e");
+        tshark_args.push("udp.srcport");
+        tshark_args.push("-e");
+        tshark_args.push("udp.dstport");
+        tshark_args.push("-e");
+        tshark_args.push("udp.payload");
+        if !tshark_args.contains(&"-Y") {
+            tshark_args.push("-Y");
+            tshark_args.push("snmp");
+        }
+        if tshark_args.contains(&"-i") && !tshark_args.contains(&"-f") {
+            tshark_args.push("-f");
+            tshark_args.push("udp port 161 or udp port 162");
+        }
+    }
+
+    fn end(&mut self) {
+        self.expire_pending(None);
+        self.print_stats(None);
+    }
+}
+
+// MARK: TESTS
+#[cfg(test)]
+mod test {
+    use chrono::{DateTime, TimeDelta};
+
+    use super::{Analyzer, ProtocolAnalyzer as _, parse_pdu, trap_summary};
+    use crate::analyzers::test_args;
+
+    #[test]
+    fn v2c_link_down_trap() {
+        // public TRAPv2: sysUpTime.0, snmpTrapOID.0=linkDown, ifIndex.3=3, ifDescr.3="Gi0/3"
+        let mut list = Vec::new();
+        for (oid, value) in [
+            (vec![0x2b, 6, 1, 2, 1, 1, 3, 0], vec![0x43, 2, 0x30, 0x39]),
+            (
+                vec![0x2b, 6, 1, 6, 3, 1, 1, 4, 1, 0],
+                vec![0x06, 9, 0x2b, 6, 1, 6, 3, 1, 1, 5, 3],
+            ),
+            (vec![0x2b, 6, 1, 2, 1, 2, 2, 1, 1, 3], vec![0x02, 1, 3]),
+            (
+                vec![0x2b, 6, 1, 2, 1, 2, 2, 1, 2, 3],
+                vec![0x04, 5, b'G', b'i', b'0', b'/', b'3'],
+            ),
+        ] {
+            let mut varbind = vec![0x06, oid.len() as u8];
+            varbind.extend(oid);
+            varbind.extend(value);
+            list.extend([0x30, varbind.len() as u8]);
+            list.extend(varbind);
+        }
+        let mut pdu = vec![0x02, 1, 1, 0x02, 1, 0, 0x02, 1, 0, 0x30, list.len() as u8];
+        pdu.extend(list);
+        let mut message = vec![0x02, 1, 1, 0x04, 6];
+        message.extend(b"public");
+        message.extend([0xa7, pdu.len() as u8]);
+        message.extend(pdu);
+        let mut payload = vec![0x30, message.len() as u8];
+        payload.extend(message);
+
+        let pdu = parse_pdu(&payload).unwrap();
+        assert_eq!(pdu.trap_oid.as_deref(), Some("1.3.6.1.6.3.1.1.5.3"));
+        assert_eq!(
+            trap_summary(&pdu),
+            "linkDown ifIndex.3=3 ifDescr.3=\"Gi0/3\""
+        );
+    }
+
+    #[test]
+    fn requests_and_informs() {
+        let mut analyzer = Analyzer::new(&test_args(&[]), 0);
+        let start = DateTime::from_timestamp(1738062028, 0).unwrap();
+        let (nms, agent) = ("10.0.0.1", "10.0.0.2");
+        // GET answered by the agent, then an INFORM acknowledged by the manager
+        for (ms, src, dst, pdu, id, sport, dport) in [
+            (0, nms, agent, "0", "7", "40000", "161"),
+            (30, agent, nms, "2", "7", "161", "40000"),
+            (100, agent, nms, "6", "9", "50000", "162"),
+            (110, nms, agent, "2", "9", "162", "50000"),
+        ] {
+            let cols = vec![
+                src, dst, "1", "public", pdu, id, "0", "", "", sport, dport, "",
+            ];
+            analyzer.analyze(start + TimeDelta::milliseconds(ms), cols);
+        }
+        assert!(analyzer.pending.is_empty());
+        assert_eq!(analyzer.unmatched_responses, 0);
+        assert!(!analyzer.agents.contains_key(nms));
+        let stats = &analyzer.agents[agent];
+        assert_eq!((stats.requests, stats.responses, stats.traps), (1, 1, 1));
+        assert_eq!(stats.latency.max(), 30.0);
+        assert_eq!((stats.informs, stats.inform_acks), (1, 1));
+        assert_eq!(stats.inform_latency.max(), 10.0);
+    }
+}
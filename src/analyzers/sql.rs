@@ -0,0 +1,299 @@
+use ahash::{HashMap, HashSet};
+use std::fmt::Write as _;
+
+use super::report::{self, LatencyStats};
+use crate::formatters::EventFormatter;
+
+/// Fingerprints beyond this are counted under one bucket, unprepared ad-hoc SQL can be unbounded
+const MAX_FINGERPRINTS: usize = 10_000;
+const OTHER: &str = "<other>";
+
+/// Query text with literals, placeholders and comments replaced so executions of the same
+/// statement group together, value lists collapse to a single `?`
+pub fn fingerprint(query: &str) -> String {
+    let mut output = String::with_capacity(query.len());
+    let mut chars = query.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '-' if chars.peek() == Some(&'-') => {
+                chars.by_ref().find(|c| *c == '\n');
+                push_space(&mut output);
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut last = ' ';
+                for c in chars.by_ref() {
+                    if last == '*' && c == '/' {
+                        break;
+                    }
+                    last = c;
+                }
+                push_space(&mut output);
+            }
+            '\'' => {
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\\' => {
+                            chars.next();
+                        }
+                        '\'' if chars.peek() == Some(&'\'') => {
+                            chars.next();
+                        }
+                        '\'' => break,
+                        _ => {}
+                    }
+                }
+                output.push('?');
+            }
+            '"' | '`' => {
+                output.push(c);
+                for quoted in chars.by_ref() {
+                    output.push(quoted);
+                    if quoted == c {
+                        break;
+                    }
+                }
+            }
+            '$' if chars.peek().is_some_and(char::is_ascii_digit) => {
+                while chars.next_if(char::is_ascii_digit).is_some() {}
+                output.push('?');
+            }
+            '-' | '0'..='9' if starts_number(c, chars.peek(), &output) => {
+                while chars
+                    .next_if(|c| c.is_ascii_alphanumeric() || *c == '.')
+                    .is_some()
+                {}
+                output.push('?');
+            }
+            c if c.is_whitespace() => push_space(&mut output),
+            c => output.extend(c.to_lowercase()),
+        }
+    }
+    let mut output = output.trim().trim_end_matches(';').trim_end().to_owned();
+    loop {
+        let collapsed = output
+            .replace("?, ?", "?")
+            .replace("?,?", "?")
+            .replace("(?), (?)", "(?)")
+            .replace("(?),(?)", "(?)");
+        if collapsed == output {
+            return output;
+        }
+        output = collapsed;
+    }
+}
+
+/// Leading keyword of a fingerprint, used as the event method
+pub fn verb(fingerprint: &str) -> String {
+    fingerprint
+        .split(' ')
+        .next()
+        .filter(|word| !word.is_empty())
+        .unwrap_or("query")
+        .to_uppercase()
+}
+
+/// Fingerprint shortened for event details
+pub fn abbreviate(fingerprint: &str, max_chars: usize) -> String {
+    match fingerprint.char_indices().nth(max_chars) {
+        Some((end, _)) => format!("{}...", &fingerprint[..end]),
+        None => fingerprint.to_owned(),
+    }
+}
+
+/// Digits not part of an identifier, or a minus sign that cannot be an operator
+fn starts_number(c: char, next: Option<&char>, output: &str) -> bool {
+    if c == '-' {
+        next.is_some_and(char::is_ascii_digit)
+            && !output
+                .trim_end()
+                .chars()
+                .next_back()
+                .is_some_and(|p| p.is_alphanumeric() || matches!(p, '_' | ')' | '?' | '"' | '`'))
+    } else {
+        !output
+            .chars()
+            .next_back()
+            .is_some_and(|p| p.is_alphanumeric() || p == '_' || p == '$')
+    }
+}
+
+fn push_space(output: &mut String) {
+    if !output.is_empty() && !output.ends_with(' ') {
+        output.push(' ');
+    }
+}
+
+#[derive(Default)]
+struct QueryStats {
+    queries: u32,
+    rows: u64,
+    errors: HashMap<String, u32>,
+    latency: LatencyStats,
+}
+
+impl QueryStats {
+    fn record(&mut self, latency_ms: f64, rows: u64, error: Option<&str>) {
+        self.queries += 1;
+        self.rows += rows;
+        self.latency.add(latency_ms);
+        if let Some(code) = error {
+            *self.errors.entry(code.to_owned()).or_default() += 1;
+        }
+    }
+
+    fn total_ms(&self) -> f64 {
+        self.latency.avg() * self.queries as f64
+    }
+
+    fn summary(&self) -> String {
+        let mut output = format!(
+            "{:7} queries {:9} rows {:5} errors  {}",
+            self.queries,
+            self.rows,
+            self.errors.values().sum::<u32>(),
+            self.latency.summary()
+        );
+        for (code, count) in report::top_n(&self.errors, 3) {
+            write!(output, "  {code} {count}").unwrap();
+        }
+        output
+    }
+}
+
+struct ErrorStats {
+    count: u32,
+    /// First message seen for the code
+    message: String,
+}
+
+/// Completed queries aggregated per fingerprint and per client
+#[derive(Default)]
+pub struct QueryProfile {
+    fingerprints: HashMap<String, QueryStats>,
+    clients: HashMap<String, QueryStats>,
+    /// Clients issuing each fingerprint
+    fingerprint_clients: HashMap<String, HashSet<String>>,
+    errors: HashMap<String, ErrorStats>,
+}
+
+impl QueryProfile {
+    pub fn record(
+        &mut self,
+        client: &str,
+        fingerprint: String,
+        latency_ms: f64,
+        rows: u64,
+        error: Option<(&str, &str)>,
+    ) {
+        let fingerprint = if self.fingerprints.len() < MAX_FINGERPRINTS
+            || self.fingerprints.contains_key(&fingerprint)
+        {
+            fingerprint
+        } else {
+            OTHER.to_owned()
+        };
+        let code = error.map(|(code, _)| code);
+        self.clients
+            .entry(client.to_owned())
+            .or_default()
+            .record(latency_ms, rows, code);
+        self.fingerprint_clients
+            .entry(fingerprint.clone())
+            .or_default()
+            .insert(client.to_owned());
+        self.fingerprints
+            .entry(fingerprint)
+            .or_default()
+            .record(latency_ms, rows, code);
+        if let Some((code, message)) = error {
+            self.errors
+                .entry(code.to_owned())
+                .or_insert_with(|| ErrorStats {
+                    count: 0,
+                    message: message.to_owned(),
+                })
+                .count += 1;
+        }
+    }
+
+    pub fn queries(&self) -> u32 {
+        self.clients.values().map(|c| c.queries).sum()
+    }
+
+    pub fn rows(&self) -> u64 {
+        self.clients.values().map(|c| c.rows).sum()
+    }
+
+    pub fn errors(&self) -> u32 {
+        self.errors.values().map(|e| e.count).sum()
+    }
+
+    pub fn fingerprints(&self) -> usize {
+        self.fingerprints.len()
+    }
+
+    pub fn print(&self, formatter: &mut dyn EventFormatter, top: usize) {
+        report::print_section(formatter, "Fingerprints By Total Time");
+        let mut fingerprints = Vec::from_iter(&self.fingerprints);
+        fingerprints.sort_by(|a, b| b.1.total_ms().total_cmp(&a.1.total_ms()).then(a.0.cmp(b.0)));
+        for (fingerprint, stats) in fingerprints.iter().take(top) {
+            formatter.report(&self.fingerprint_line(fingerprint, stats), 1);
+        }
+        report::print_section(formatter, "Slowest Fingerprints");
+        fingerprints.sort_by(|a, b| {
+            b.1.latency
+                .percentile(95.0)
+                .total_cmp(&a.1.latency.percentile(95.0))
+                .then(a.0.cmp(b.0))
+        });
+        for (fingerprint, stats) in fingerprints.iter().take(top) {
+            formatter.report(&self.fingerprint_line(fingerprint, stats), 1);
+        }
+
+        report::print_section(formatter, "Clients");
+        let mut clients = Vec::from_iter(&self.clients);
+        clients.sort_by(|a, b| b.1.total_ms().total_cmp(&a.1.total_ms()).then(a.0.cmp(b.0)));
+        for (client, stats) in clients.into_iter().take(top) {
+            formatter.report(&format!("{client:<16} {}", stats.summary()), 1);
+        }
+
+        report::print_section(formatter, "Error Codes");
+        let mut errors = Vec::from_iter(&self.errors);
+        errors.sort_by(|a, b| b.1.count.cmp(&a.1.count).then(a.0.cmp(b.0)));
+        for (code, stats) in errors.into_iter().take(top) {
+            formatter.report(&format!("{:8} {code:<6} {}", stats.count, stats.message), 1);
+        }
+    }
+
+    fn fingerprint_line(&self, fingerprint: &str, stats: &QueryStats) -> String {
+        let clients = self
+            .fingerprint_clients
+            .get(fingerprint)
+            .map(HashSet::len)
+            .unwrap_or_default();
+        format!("{fingerprint}\n    {} {clients:4} clients", stats.summary())
+    }
+}
+
+// MARK: TESTS
+#[cfg(test)]
+mod test {
+    use super::fingerprint;
+
+    #[test]
+    fn normalizes_literals() {
+        assert_eq!(
+            fingerprint("SELECT * FROM cdr WHERE caller = 'O''Brien' AND id IN (1, 2, 3);"),
+            "select * from cdr where caller = ? and id in (?)"
+        );
+        assert_eq!(
+            fingerprint("insert into t2 (a, b)\n values ($1, $2), (-4.5, 0x1F) -- batch"),
+            "insert into t2 (a, b) values (?)"
+        );
+        assert_eq!(
+            fingerprint("/* app */ UPDATE \"Rates\" SET v=v+1 WHERE k = ?"),
+            "update \"Rates\" set v=v+? where k = ?"
+        );
+    }
+}
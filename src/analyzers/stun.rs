@@ -0,0 +1,772 @@
+use super::{
+    Event, EventKind, ProtocolAnalyzer,
+    report::{self, DailyReport, LatencyStats},
+};
+use crate::{
+    ArgsCommand,
+    formatters::{EventFormatter, create_formatter},
+};
+use ahash::HashMap;
+use chrono::{DateTime, Utc};
+use std::fmt::Write as _;
+
+const CLASS_REQUEST: u32 = 0;
+const CLASS_INDICATION: u32 = 1;
+const CLASS_SUCCESS: u32 = 2;
+const CLASS_ERROR: u32 = 3;
+const METHOD_BINDING: u32 = 0x001;
+const METHOD_ALLOCATE: u32 = 0x003;
+const METHOD_REFRESH: u32 = 0x004;
+const ATT_USERNAME: u32 = 0x0006;
+const ATT_XOR_RELAYED_ADDRESS: u32 = 0x0016;
+const ATT_XOR_MAPPED_ADDRESS: u32 = 0x0020;
+const ATT_USE_CANDIDATE: u32 = 0x0025;
+/// ICE sessions without a successful pair after this long are reported failed
+const ICE_FAILED_AFTER_S: i64 = 30;
+
+struct PendingRequest {
+    ts: DateTime<Utc>,
+    client: String,
+    server: String,
+    method: u32,
+    /// ICE check "remote:local" username
+    ice_session: Option<String>,
+    credentials: bool,
+    use_candidate: bool,
+}
+
+#[derive(Default)]
+struct MethodStats {
+    requests: u32,
+    successes: u32,
+    errors: u32,
+    timeouts: u32,
+    latency: LatencyStats,
+}
+
+struct Allocation {
+    relayed: String,
+    mapped: String,
+    lifetime: i64,
+    created: DateTime<Utc>,
+    expires: DateTime<Utc>,
+    refreshes: u32,
+}
+
+#[derive(Default)]
+struct PairStats {
+    checks: u32,
+    successes: u32,
+    failures: u32,
+    nominated: bool,
+}
+
+struct IceSession {
+    call_id: Option<String>,
+    /// (sender, receiver) of the checks
+    pairs: HashMap<(String, String), PairStats>,
+    first_ts: DateTime<Utc>,
+    last_ts: DateTime<Utc>,
+    failure_reported: bool,
+}
+
+impl IceSession {
+    fn succeeded(&self) -> bool {
+        self.pairs.values().any(|p| p.successes > 0)
+    }
+
+    fn summary(&self, key: &str) -> String {
+        let mut output = format!(
+            "{key:<30} {:<36} checks {:4} pairs {:3} succeeded {:3}",
+            self.call_id.as_deref().unwrap_or("-"),
+            self.pairs.values().map(|p| p.checks).sum::<u32>(),
+            self.pairs.len(),
+            self.pairs.values().filter(|p| p.successes > 0).count(),
+        );
+        let mut nominated = self
+            .pairs
+            .iter()
+            .filter(|(_, p)| p.nominated)
+            .map(|((src, dst), _)| format!("{src} <-> {dst}"))
+            .collect::<Vec<_>>();
+        nominated.sort();
+        if !nominated.is_empty() {
+            write!(output, " nominated {}", nominated.join(", ")).unwrap();
+        }
+        output
+    }
+}
+
+pub struct Analyzer {
+    pending: HashMap<String, PendingRequest>,
+    methods: HashMap<&'static str, MethodStats>,
+    error_codes: HashMap<String, u32>,
+    /// (client, server)
+    allocations: HashMap<(String, String), Allocation>,
+    /// client address as seen by the STUN server
+    mapped: HashMap<String, String>,
+    ice_sessions: HashMap<String, IceSession>,
+    /// SDP a=ice-ufrag to SIP Call-ID
+    ufrags: HashMap<String, String>,
+    retransmissions: u32,
+    indications: u32,
+    expired_allocations: u32,
+    timeout: i64,
+    top: usize,
+    last_timeout_check: Option<DateTime<Utc>>,
+    formatter: Box<dyn EventFormatter>,
+    daily_report: DailyReport,
+}
+
+const PROTOCOL: &str = "stun";
+
+fn method_name(method: u32) -> &'static str {
+    match method {
+        METHOD_BINDING => "Binding",
+        METHOD_ALLOCATE => "Allocate",
+        METHOD_REFRESH => "Refresh",
+        0x006 => "Send",
+        0x007 => "Data",
+        0x008 => "CreatePermission",
+        0x009 => "ChannelBind",
+        _ => "Other",
+    }
+}
+
+fn error_name(code: u16) -> &'static str {
+    match code {
+        300 => "Try Alternate",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        420 => "Unknown Attribute",
+        437 => "Allocation Mismatch",
+        438 => "Stale Nonce",
+        440 => "Address Family not Supported",
+        441 => "Wrong Credentials",
+        442 => "Unsupported Transport Protocol",
+        443 => "Peer Address Family Mismatch",
+        486 => "Allocation Quota Reached",
+        487 => "Role Conflict",
+        500 => "Server Error",
+        508 => "Insufficient Capacity",
+        _ => "Unknown",
+    }
+}
+
+fn first(value: &str) -> &str {
+    value.split(',').next().unwrap_or_default()
+}
+
+/// Decimal or 0x prefixed hex as printed by tshark
+fn parse_number(value: &str) -> Option<u32> {
+    match value.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+/// XOR address attributes by type, tshark lists their values in attribute order with one
+/// family for every address attribute, XOR or not
+fn xor_addresses(
+    types: &str,
+    families: &str,
+    ipv4s: &str,
+    ipv6s: &str,
+    ports: &str,
+) -> Vec<(u32, String)> {
+    let mut families = families.split(',');
+    let mut ipv4s = ipv4s.split(',');
+    let mut ipv6s = ipv6s.split(',');
+    let mut ports = ports.split(',');
+    let mut addresses = Vec::new();
+    for t in types.split(',').filter_map(parse_number) {
+        let xor = matches!(t, 0x0012 | ATT_XOR_RELAYED_ADDRESS | ATT_XOR_MAPPED_ADDRESS);
+        // MAPPED-ADDRESS, ALTERNATE-SERVER, RESPONSE-ORIGIN and OTHER-ADDRESS are not XORed
+        if !xor && !matches!(t, 0x0001 | 0x8023 | 0x802b | 0x802c) {
+            continue;
+        }
+        let family = families.next().and_then(parse_number);
+        if !xor {
+            continue;
+        }
+        let port = ports.next().unwrap_or_default();
+        match family {
+            Some(2) => {
+                addresses.push((t, format!("[{}]:{port}", ipv6s.next().unwrap_or_default())))
+            }
+            _ => addresses.push((t, format!("{}:{port}", ipv4s.next().unwrap_or_default()))),
+        }
+    }
+    addresses
+}
+
+/// ICE ufrags of an SDP from its full session and media attribute lines, Firefox only sets
+/// them at session level
+fn ice_ufrags<'a>(session_attrs: &'a str, media_attrs: &'a str) -> impl Iterator<Item = &'a str> {
+    session_attrs
+        .split(',')
+        .chain(media_attrs.split(','))
+        .filter_map(|attr| attr.strip_prefix("ice-ufrag:"))
+}
+
+/// Both directions of the connectivity checks of one call use "remote:local" ufrags
+fn ice_key(username: &str) -> String {
+    let mut ufrags = username.split(':').collect::<Vec<_>>();
+    ufrags.sort_unstable();
+    ufrags.join(":")
+}
+
+impl Analyzer {
+    fn expire_pending(&mut self, opt_ts: Option<DateTime<Utc>>) {
+        let timeout = self.timeout;
+        let expired = self
+            .pending
+            .extract_if(|_, r| opt_ts.is_none_or(|ts| (ts - r.ts).num_seconds() >= timeout))
+            .collect::<Vec<_>>();
+        for (_, request) in expired {
+            self.methods
+                .entry(method_name(request.method))
+                .or_default()
+                .timeouts += 1;
+            // unanswered ICE checks are how unusable candidate pairs show up
+            if let Some(key) = &request.ice_session {
+                if let Some(session) = self.ice_sessions.get_mut(key) {
+                    session
+                        .pairs
+                        .entry((request.client, request.server))
+                        .or_default()
+                        .failures += 1;
+                }
+                continue;
+            }
+            self.formatter.event(&Event {
+                ts: opt_ts.unwrap_or(request.ts),
+                protocol: PROTOCOL,
+                kind: EventKind::NoResponse,
+                user: request.client,
+                method: method_name(request.method).into(),
+                addr: Some(request.server),
+                elapsed_s: opt_ts.map(|ts| (ts - request.ts).num_seconds()),
+                ..Default::default()
+            });
+        }
+
+        let expired = self
+            .allocations
+            .extract_if(|_, a| opt_ts.is_some_and(|ts| ts > a.expires))
+            .collect::<Vec<_>>();
+        for ((client, server), allocation) in expired {
+            self.expired_allocations += 1;
+            self.formatter.event(&Event {
+                ts: allocation.expires,
+                protocol: PROTOCOL,
+                kind: EventKind::Warning,
+                user: client,
+                method: "Allocate".into(),
+                addr: Some(server),
+                detail: Some(format!(
+                    "relay {} expired without refresh after {} s",
+                    allocation.relayed,
+                    (allocation.expires - allocation.created).num_seconds()
+                )),
+                ..Default::default()
+            });
+        }
+
+        for (key, session) in &mut self.ice_sessions {
+            if session.failure_reported
+                || session.succeeded()
+                || opt_ts
+                    .is_some_and(|ts| (ts - session.first_ts).num_seconds() < ICE_FAILED_AFTER_S)
+            {
+                continue;
+            }
+            session.failure_reported = true;
+            let mut detail = format!(
+                "ICE failed, {} candidate pairs checked",
+                session.pairs.len()
+            );
+            if let Some(call_id) = &session.call_id {
+                write!(detail, " CID:{call_id}").unwrap();
+            }
+            self.formatter.event(&Event {
+                ts: session.last_ts,
+                protocol: PROTOCOL,
+                kind: EventKind::Alert,
+                user: key.clone(),
+                method: "Binding".into(),
+                addr: session.pairs.keys().next().map(|(_, dst)| dst.clone()),
+                call_id: session.call_id.clone(),
+                detail: Some(detail),
+                ..Default::default()
+            });
+        }
+    }
+
+    /// Call-ID of the SDP that announced either ufrag of the check username
+    fn call_id(&self, username: &str) -> Option<String> {
+        username
+            .split(':')
+            .find_map(|ufrag| self.ufrags.get(ufrag))
+            .cloned()
+    }
+
+    fn on_request(&mut self, ts: DateTime<Utc>, id: &str, request: PendingRequest) {
+        if self.pending.contains_key(id) {
+            self.retransmissions += 1;
+            return;
+        }
+        self.methods
+            .entry(method_name(request.method))
+            .or_default()
+            .requests += 1;
+        if let Some(key) = &request.ice_session {
+            let call_id = self.call_id(key);
+            let session = self
+                .ice_sessions
+                .entry(key.clone())
+                .or_insert_with(|| IceSession {
+                    call_id: None,
+                    pairs: HashMap::default(),
+                    first_ts: ts,
+                    last_ts: ts,
+                    failure_reported: false,
+                });
+            session.call_id = session.call_id.take().or(call_id);
+            session.last_ts = ts;
+            session
+                .pairs
+                .entry((request.client.clone(), request.server.clone()))
+                .or_default()
+                .checks += 1;
+        }
+        self.pending.insert(id.to_owned(), request);
+    }
+
+    fn on_success(&mut self, ts: DateTime<Utc>, request: PendingRequest, cols: &[&str]) {
+        let addresses = xor_addresses(cols[5], cols[18], cols[6], cols[17], cols[7]);
+        let address = |att| {
+            addresses
+                .iter()
+                .find(|(t, _)| *t == att)
+                .map(|(_, a)| a.clone())
+        };
+        let lifetime = first(cols[10]).parse::<i64>().ok();
+        let key = (request.client.clone(), request.server.clone());
+        match request.method {
+            METHOD_BINDING => {
+                if let Some(mapped) = address(ATT_XOR_MAPPED_ADDRESS) {
+                    self.mapped.insert(request.client.clone(), mapped);
+                }
+                let Some(session) = request
+                    .ice_session
+                    .as_ref()
+                    .and_then(|k| self.ice_sessions.get_mut(k))
+                else {
+                    return;
+                };
+                let first_success = !session.succeeded();
+                let pair = session.pairs.entry(key).or_default();
+                pair.successes += 1;
+                pair.nominated |= request.use_candidate;
+                if first_success {
+                    let mut detail = format!(
+                        "ICE pair {} <-> {} succeeded",
+                        request.client, request.server
+                    );
+                    if let Some(call_id) = &session.call_id {
+                        write!(detail, " CID:{call_id}").unwrap();
+                    }
+                    self.formatter.event(&Event {
+                        ts,
+                        protocol: PROTOCOL,
+                        kind: EventKind::Handshake,
+                        user: request.ice_session.unwrap_or_default(),
+                        method: "Binding".into(),
+                        addr: Some(request.server),
+                        call_id: session.call_id.clone(),
+                        latency_ms: Some(
+                            (ts - request.ts).num_microseconds().unwrap_or_default() as f64
+                                / 1000.0,
+                        ),
+                        detail: Some(detail),
+                        ..Default::default()
+                    });
+                }
+            }
+            METHOD_ALLOCATE => {
+                let relayed = address(ATT_XOR_RELAYED_ADDRESS).unwrap_or_default();
+                let mapped = address(ATT_XOR_MAPPED_ADDRESS).unwrap_or_default();
+                let lifetime = lifetime.unwrap_or(600);
+                self.formatter.event(&Event {
+                    ts,
+                    protocol: PROTOCOL,
+                    kind: EventKind::Handshake,
+                    user: request.client.clone(),
+                    method: "Allocate".into(),
+                    addr: Some(request.server.clone()),
+                    detail: Some(format!(
+                        "relay {relayed} mapped {mapped} lifetime {lifetime} s"
+                    )),
+                    ..Default::default()
+                });
+                self.mapped.insert(request.client, mapped.clone());
+                self.allocations.insert(
+                    key,
+                    Allocation {
+                        relayed,
+                        mapped,
+                        lifetime,
+                        created: ts,
+                        expires: ts + chrono::TimeDelta::seconds(lifetime),
+                        refreshes: 0,
+                    },
+                );
+            }
+            METHOD_REFRESH => match lifetime {
+                Some(0) => {
+                    self.allocations.remove(&key);
+                }
+                Some(lifetime) => {
+                    if let Some(allocation) = self.allocations.get_mut(&key) {
+                        allocation.refreshes += 1;
+                        allocation.lifetime = lifetime;
+                        allocation.expires = ts + chrono::TimeDelta::seconds(lifetime);
+                    }
+                }
+                None => {}
+            },
+            _ => {}
+        }
+    }
+
+    fn on_error(&mut self, ts: DateTime<Utc>, request: PendingRequest, code: u16) {
+        *self
+            .error_codes
+            .entry(format!("{code} {}", error_name(code)))
+            .or_default() += 1;
+        if let Some(session) = request
+            .ice_session
+            .as_ref()
+            .and_then(|k| self.ice_sessions.get_mut(k))
+        {
+            session
+                .pairs
+                .entry((request.client.clone(), request.server.clone()))
+                .or_default()
+                .failures += 1;
+        }
+        // the first 401 is the nonce challenge and 438 asks to retry with a fresh nonce
+        if (code == 401 && !request.credentials) || code == 438 {
+            return;
+        }
+        self.formatter.event(&Event {
+            ts,
+            protocol: PROTOCOL,
+            kind: EventKind::ErrorResponse,
+            user: request.client,
+            method: method_name(request.method).into(),
+            addr: Some(request.server),
+            status: Some(code),
+            latency_ms: Some(
+                (ts - request.ts).num_microseconds().unwrap_or_default() as f64 / 1000.0,
+            ),
+            detail: Some(format!("{code} {}", error_name(code))),
+            ..Default::default()
+        });
+    }
+
+    fn print_stats(&mut self, opt_ts: Option<DateTime<Utc>>) {
+        let mut output = String::with_capacity(200);
+        report::print_header(self.formatter.as_mut(), opt_ts);
+        report::print_section(self.formatter.as_mut(), "Methods");
+        let mut methods = Vec::from_iter(self.methods.keys().copied());
+        methods.sort();
+        for method in methods {
+            let stats = &self.methods[method];
+            self.formatter.report(
+                &format!(
+                    "{method:<16} {:7} requests {:7} success {:5} errors {:5} timeouts  {}",
+                    stats.requests,
+                    stats.successes,
+                    stats.errors,
+                    stats.timeouts,
+                    stats.latency.summary()
+                ),
+                1,
+            );
+        }
+
+        report::print_section(self.formatter.as_mut(), "Error Codes");
+        for (code, count) in report::top_n(&self.error_codes, self.top) {
+            self.formatter.report(&format!("{count:8} {code}"), 1);
+        }
+
+        report::print_section(self.formatter.as_mut(), "TURN Allocations");
+        let mut allocations = Vec::from_iter(&self.allocations);
+        allocations.sort_by_key(|(_, a)| a.created);
+        for ((client, server), allocation) in allocations.into_iter().take(self.top) {
+            self.formatter.report(
+                &format!(
+                    "{client:<22} -> {server:<22} relay {:<22} mapped {:<22} lifetime {:5} s refreshes {}",
+                    allocation.relayed, allocation.mapped, allocation.lifetime, allocation.refreshes
+                ),
+                1,
+            );
+        }
+
+        report::print_section(self.formatter.as_mut(), "Failed ICE Sessions");
+        let mut failed = self
+            .ice_sessions
+            .iter()
+            .filter(|(_, s)| !s.succeeded())
+            .collect::<Vec<_>>();
+        failed.sort_by_key(|(_, s)| s.first_ts);
+        for (key, session) in failed.into_iter().take(self.top) {
+            self.formatter.report(&session.summary(key), 1);
+        }
+        report::print_section(self.formatter.as_mut(), "Successful ICE Sessions");
+        let mut succeeded = self
+            .ice_sessions
+            .iter()
+            .filter(|(_, s)| s.succeeded())
+            .collect::<Vec<_>>();
+        succeeded.sort_by_key(|(_, s)| s.first_ts);
+        for (key, session) in succeeded.into_iter().rev().take(self.top) {
+            self.formatter.report(&session.summary(key), 1);
+        }
+        write!(
+            output,
+            r#"
+ ------------ STATS ------------
+
+- total requests: {}
+- pending requests: {}
+- retransmissions: {}
+- indications: {}
+- active allocations: {}
+- expired allocations: {}
+- mapped clients: {}
+- ICE sessions: {}
+- failed ICE sessions: {}
+"#,
+            self.methods.values().map(|m| m.requests).sum::<u32>(),
+            self.pending.len(),
+            self.retransmissions,
+            self.indications,
+            self.allocations.len(),
+            self.expired_allocations,
+            self.mapped.len(),
+            self.ice_sessions.len(),
+            self.ice_sessions
+                .values()
+                .filter(|s| !s.succeeded())
+                .count(),
+        )
+        .unwrap();
+        self.formatter.report(&output, 0);
+        report::print_footer(self.formatter.as_mut());
+    }
+}
+
+impl ProtocolAnalyzer for Analyzer {
+    fn new(cmd_args: &ArgsCommand, verbosity: u8) -> Self {
+        let (timeout, top) = match cmd_args {
+            ArgsCommand::Analyzer { timeout, top, .. } => (*timeout as i64, *top),
+            _ => (5, 10),
+        };
+        Self {
+            pending: HashMap::default(),
+            methods: HashMap::default(),
+            error_codes: HashMap::default(),
+            allocations: HashMap::default(),
+            mapped: HashMap::default(),
+            ice_sessions: HashMap::default(),
+            ufrags: HashMap::default(),
+            retransmissions: 0,
+            indications: 0,
+            expired_allocations: 0,
+            timeout,
+            top,
+            last_timeout_check: None,
+            formatter: create_formatter(cmd_args, verbosity),
+            daily_report: DailyReport::default(),
+        }
+    }
+
+    fn analyze(&mut self, ts: DateTime<Utc>, cols: Vec<&str>) {
+        if self
+            .last_timeout_check
+            .is_none_or(|last| (ts - last).num_seconds() >= 1)
+        {
+            self.expire_pending(Some(ts));
+            self.last_timeout_check = Some(ts);
+        }
+        if self.daily_report.is_due(ts) {
+            self.print_stats(Some(ts));
+        }
+
+        // SIP with SDP, remember the ICE credentials of the call
+        if !cols[14].is_empty() {
+            for ufrag in ice_ufrags(cols[15], cols[16]) {
+                self.ufrags.insert(ufrag.to_owned(), cols[14].to_owned());
+            }
+            return;
+        }
+        let (Some(class), Some(method)) =
+            (parse_number(first(cols[2])), parse_number(first(cols[3])))
+        else {
+            return;
+        };
+        let id = first(cols[4]);
+        let src = format!("{}:{}", cols[0], cols[12]);
+        let dst = format!("{}:{}", cols[1], cols[13]);
+        let types = cols[5]
+            .split(',')
+            .filter_map(parse_number)
+            .collect::<Vec<_>>();
+        match class {
+            CLASS_REQUEST => {
+                let username = first(cols[11]);
+                let ice_session =
+                    (method == METHOD_BINDING && username.contains(':')).then(|| ice_key(username));
+                self.on_request(
+                    ts,
+                    id,
+                    PendingRequest {
+                        ts,
+                        client: src,
+                        server: dst,
+                        method,
+                        ice_session,
+                        credentials: types.contains(&ATT_USERNAME),
+                        use_candidate: types.contains(&ATT_USE_CANDIDATE),
+                    },
+                );
+            }
+            CLASS_INDICATION => self.indications += 1,
+            CLASS_SUCCESS | CLASS_ERROR => {
+                let Some(request) = self.pending.remove(id) else {
+                    return;
+                };
+                let stats = self.methods.entry(method_name(request.method)).or_default();
+                stats
+                    .latency
+                    .add((ts - request.ts).num_microseconds().unwrap_or_default() as f64 / 1000.0);
+                if class == CLASS_SUCCESS {
+                    stats.successes += 1;
+                    self.on_success(ts, request, &cols);
+                } else {
+                    stats.errors += 1;
+                    let code = parse_number(first(cols[8])).unwrap_or_default() * 100
+                        + parse_number(first(cols[9])).unwrap_or_default();
+                    self.on_error(ts, request, code as u16);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn add_protocol_fields(&self, tshark_args: &mut Vec<&str>) {
+        tshark_args.push("-e");
+        tshark_args.push("stun.type.class");
+        tshark_args.push("-e");
+        tshark_args.push("stun.type.method");
+        tshark_args.push("-e");
+        tshark_args.push("stun.id");
+        tshark_args.push("-e");
+        tshark_args.push("stun.att.type");
+        tshark_args.push("-e");
+        tshark_args.push("stun.att.ipv4-xord");
+        tshark_args.push("-e");
+        tshark_args.push("stun.att.port-xord");
+        tshark_args.push("-e");
+        tshark_args.push("stun.att.error.class");
+        tshark_args.push("-e");
+        tshark_args.push("stun.att.error");
+        tshark_args.push("-e");
+        tshark_args.push("stun.att.lifetime");
+        tshark_args.push("-e");
+        tshark_args.push("stun.att.username");
+        tshark_args.push("-e");
+        tshark_args.push("udp.srcport");
+        tshark_args.push("-e");
+        tshark_args.push("udp.dstport");
+        tshark_args.push("-e");
+        tshark_args.push("sip.Call-ID");
+        tshark_args.push("-e");
+        tshark_args.push("sdp.session_attr");
+        tshark_args.push("-e");
+        tshark_args.push("sdp.media_attr");
+        tshark_args.push("-e");
+        tshark_args.push("stun.att.ipv6-xord");
+        tshark_args.push("-e");
+        tshark_args.push("stun.att.family");
+        if !tshark_args.contains(&"-Y") {
+            tshark_args.push("-Y");
+            tshark_args.push("stun || sdp");
+        }
+        if tshark_args.contains(&"-i") && !tshark_args.contains(&"-f") {
+            tshark_args.push("-f");
+            tshark_args.push("udp");
+        }
+    }
+
+    fn end(&mut self) {
+        self.expire_pending(None);
+        self.print_stats(None);
+    }
+}
+
+// MARK: TESTS
+#[cfg(test)]
+mod test {
+    use super::{ice_key, ice_ufrags, xor_addresses};
+
+    #[test]
+    fn xor_addresses_by_attribute() {
+        // Allocate success: XOR-RELAYED-ADDRESS, LIFETIME, XOR-MAPPED-ADDRESS, SOFTWARE
+        assert_eq!(
+            xor_addresses(
+                "0x0016,0x000d,0x0020,0x8022",
+                "0x01,0x01",
+                "198.51.100.7,203.0.113.9",
+                "",
+                "49152,50000"
+            ),
+            vec![
+                (0x0016, "198.51.100.7:49152".to_owned()),
+                (0x0020, "203.0.113.9:50000".to_owned())
+            ]
+        );
+        // IPv6 relay, IPv4 mapped address and a plain RESPONSE-ORIGIN in between
+        assert_eq!(
+            xor_addresses(
+                "0x0016,0x802b,0x0020",
+                "0x02,0x01,0x01",
+                "203.0.113.9",
+                "2001:db8::7",
+                "49152,50000"
+            ),
+            vec![
+                (0x0016, "[2001:db8::7]:49152".to_owned()),
+                (0x0020, "203.0.113.9:50000".to_owned())
+            ]
+        );
+        assert_eq!(ice_key("b7Xq:a1Yz"), ice_key("a1Yz:b7Xq"));
+    }
+
+    #[test]
+    fn ufrags_from_attribute_lines() {
+        // valueless attributes between the ICE ones, session level ufrag as sent by Firefox
+        let session = "ice-ufrag:a1Yz,ice-pwd:0123456789abcdef,ice-options:trickle";
+        let media =
+            "rtcp-mux,sendrecv,ice-ufrag:b7Xq,ice-pwd:fedcba9876543210,rtpmap:111 opus/48000/2";
+        assert_eq!(
+            ice_ufrags(session, media).collect::<Vec<_>>(),
+            vec!["a1Yz", "b7Xq"]
+        );
+        assert_eq!(ice_ufrags("", "rtcp-mux,sendrecv").count(), 0);
+    }
+}
@@ -0,0 +1,395 @@
+use super::{
+    Event, EventKind, LogRecord, ProtocolAnalyzer, TIME_FMT,
+    report::{self, DailyReport},
+};
+use crate::{
+    ArgsCommand,
+    formatters::{EventFormatter, OutputFormat, create_formatter},
+};
+use ahash::HashMap;
+use chrono::{DateTime, Local, Utc};
+use std::fmt::Write as _;
+
+/// Templates beyond this are only counted, random payloads would grow the table forever
+const MAX_TEMPLATES: usize = 10_000;
+
+const FACILITIES: [&str; 24] = [
+    "kern", "user", "mail", "daemon", "auth", "syslog", "lpr", "news", "uucp", "cron", "authpriv",
+    "ftp", "ntp", "audit", "alert", "clock", "local0", "local1", "local2", "local3", "local4",
+    "local5", "local6", "local7",
+];
+const SEVERITIES: [&str; 8] = [
+    "emerg", "alert", "crit", "err", "warning", "notice", "info", "debug",
+];
+
+#[derive(Debug, PartialEq)]
+struct Message<'a> {
+    facility: usize,
+    severity: usize,
+    timestamp: Option<&'a str>,
+    hostname: Option<&'a str>,
+    app: Option<&'a str>,
+    proc_id: Option<&'a str>,
+    msg_id: Option<&'a str>,
+    message: &'a str,
+}
+
+#[derive(Default)]
+struct SenderStats {
+    hostname: String,
+    messages: u32,
+    severities: [u32; 8],
+}
+
+struct TemplateStats {
+    count: u32,
+    /// Most severe level seen for the template
+    severity: usize,
+    first_ts: DateTime<Utc>,
+    last_ts: DateTime<Utc>,
+}
+
+pub struct Analyzer {
+    levels: HashMap<String, u32>,
+    senders: HashMap<String, SenderStats>,
+    /// (app, template)
+    templates: HashMap<(String, String), TemplateStats>,
+    untracked: u32,
+    invalid: u32,
+    /// Every message is emitted with --format json, only new templates otherwise
+    archive: bool,
+    top: usize,
+    formatter: Box<dyn EventFormatter>,
+    daily_report: DailyReport,
+}
+
+const PROTOCOL: &str = "syslog";
+
+fn nil(value: &str) -> Option<&str> {
+    Some(value).filter(|v| *v != "-" && !v.is_empty())
+}
+
+/// RFC 5424 structured data, returns what follows it
+fn skip_structured_data(value: &str) -> &str {
+    if !value.starts_with('[') {
+        return value.strip_prefix('-').unwrap_or(value).trim_start();
+    }
+    let mut quoted = false;
+    let mut escaped = false;
+    let mut chars = value.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => quoted = !quoted,
+            ']' if !quoted && chars.peek().is_none_or(|(_, next)| *next != '[') => {
+                return value[i + 1..].trim_start();
+            }
+            _ => {}
+        }
+    }
+    ""
+}
+
+/// RFC 3164 "Mmm dd hh:mm:ss" timestamp
+fn bsd_timestamp(value: &str) -> Option<&str> {
+    let timestamp = value.get(..15)?;
+    let bytes = timestamp.as_bytes();
+    (bytes[..3].iter().all(u8::is_ascii_alphabetic)
+        && bytes[3] == b' '
+        && bytes[9] == b':'
+        && bytes[12] == b':'
+        && value.as_bytes().get(15) == Some(&b' '))
+    .then_some(timestamp)
+}
+
+/// RFC 3164 "app[pid]: " tag, absent when the prefix contains spaces
+fn bsd_tag(value: &str) -> (Option<&str>, Option<&str>, &str) {
+    let Some((tag, message)) = value.split_once(": ").filter(|(t, _)| !t.contains(' ')) else {
+        return (None, None, value);
+    };
+    match tag.split_once('[') {
+        Some((app, pid)) => (nil(app), nil(pid.trim_end_matches(']')), message),
+        None => (nil(tag), None, message),
+    }
+}
+
+fn parse_message(raw: &str) -> Option<Message<'_>> {
+    let (pri, rest) = raw.strip_prefix('<')?.split_once('>')?;
+    let pri = pri.parse::<usize>().ok().filter(|p| *p < 192)?;
+    let mut message = Message {
+        facility: pri / 8,
+        severity: pri % 8,
+        timestamp: None,
+        hostname: None,
+        app: None,
+        proc_id: None,
+        msg_id: None,
+        message: "",
+    };
+    if let Some(rest) = rest.strip_prefix("1 ") {
+        let mut parts = rest.splitn(6, ' ');
+        message.timestamp = nil(parts.next().unwrap_or_default());
+        message.hostname = nil(parts.next().unwrap_or_default());
+        message.app = nil(parts.next().unwrap_or_default());
+        message.proc_id = nil(parts.next().unwrap_or_default());
+        message.msg_id = nil(parts.next().unwrap_or_default());
+        message.message = skip_structured_data(parts.next().unwrap_or_default());
+        message.message = message.message.trim_start_matches('\u{feff}');
+    } else if let Some(timestamp) = bsd_timestamp(rest) {
+        message.timestamp = Some(timestamp);
+        let (hostname, rest) = rest[16..].split_once(' ').unwrap_or((&rest[16..], ""));
+        message.hostname = nil(hostname);
+        (message.app, message.proc_id, message.message) = bsd_tag(rest);
+    } else {
+        (message.app, message.proc_id, message.message) = bsd_tag(rest);
+    }
+    message.message = message.message.trim_end_matches(['\r', '\n', '\0']);
+    Some(message)
+}
+
+/// Words carrying digits (counters, addresses, ids) are the variable part of a message
+fn template(message: &str) -> String {
+    message
+        .split_whitespace()
+        .map(|word| {
+            if word.bytes().any(|b| b.is_ascii_digit()) {
+                "<*>"
+            } else {
+                word
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl Analyzer {
+    fn print_stats(&mut self, opt_ts: Option<DateTime<Utc>>) {
+        report::print_header(self.formatter.as_mut(), opt_ts);
+        report::print_section(self.formatter.as_mut(), "Facility/Severity");
+        for (level, count) in report::top_n(&self.levels, usize::MAX) {
+            self.formatter.report(&format!("{count:8} {level}"), 1);
+        }
+
+        report::print_section(self.formatter.as_mut(), "Senders");
+        let mut senders = Vec::from_iter(&self.senders);
+        senders.sort_by(|a, b| b.1.messages.cmp(&a.1.messages).then(a.0.cmp(b.0)));
+        for (addr, stats) in senders.into_iter().take(self.top) {
+            self.formatter.report(
+                &format!(
+                    "{addr:<16} {:<24} {:8} messages  emerg-err {:6}  warning {:6}  notice-debug {:6}",
+                    stats.hostname,
+                    stats.messages,
+                    stats.severities[..4].iter().sum::<u32>(),
+                    stats.severities[4],
+                    stats.severities[5..].iter().sum::<u32>(),
+                ),
+                1,
+            );
+        }
+
+        report::print_section(self.formatter.as_mut(), "Top Templates");
+        let mut templates = Vec::from_iter(&self.templates);
+        templates.sort_by(|a, b| b.1.count.cmp(&a.1.count).then(a.0.cmp(b.0)));
+        for ((app, template), stats) in templates.into_iter().take(self.top) {
+            self.formatter.report(
+                &format!(
+                    "{:8} {:<7} {:<16} {template}\n{:<8} first {} last {}",
+                    stats.count,
+                    SEVERITIES[stats.severity],
+                    app,
+                    "",
+                    stats.first_ts.with_timezone(&Local).format(TIME_FMT),
+                    stats.last_ts.with_timezone(&Local).format(TIME_FMT),
+                ),
+                1,
+            );
+        }
+        let mut output = String::with_capacity(200);
+        write!(
+            output,
+            r#"
+ ------------ STATS ------------
+
+- total messages: {}
+- invalid messages: {}
+- senders: {}
+- templates: {}
+- messages without template slot: {}
+"#,
+            self.senders.values().map(|s| s.messages).sum::<u32>(),
+            self.invalid,
+            self.senders.len(),
+            self.templates.len(),
+            self.untracked,
+        )
+        .unwrap();
+        self.formatter.report(&output, 0);
+        report::print_footer(self.formatter.as_mut());
+    }
+}
+
+impl ProtocolAnalyzer for Analyzer {
+    fn new(cmd_args: &ArgsCommand, verbosity: u8) -> Self {
+        let (archive, top) = match cmd_args {
+            ArgsCommand::Analyzer { format, top, .. } => (*format == OutputFormat::Json, *top),
+            _ => (false, 10),
+        };
+        Self {
+            levels: HashMap::default(),
+            senders: HashMap::default(),
+            templates: HashMap::default(),
+            untracked: 0,
+            invalid: 0,
+            archive,
+            top,
+            formatter: create_formatter(cmd_args, verbosity),
+            daily_report: DailyReport::default(),
+        }
+    }
+
+    fn analyze(&mut self, ts: DateTime<Utc>, cols: Vec<&str>) {
+        if self.daily_report.is_due(ts) {
+            self.print_stats(Some(ts));
+        }
+        let Ok(payload) = hex::decode(cols[2].replace(':', "")) else {
+            self.invalid += 1;
+            return;
+        };
+        let raw = String::from_utf8_lossy(&payload);
+        let Some(message) = parse_message(&raw) else {
+            self.invalid += 1;
+            return;
+        };
+        let addr = cols[0];
+        let level = format!(
+            "{}.{}",
+            FACILITIES.get(message.facility).unwrap_or(&"-"),
+            SEVERITIES[message.severity]
+        );
+        *self.levels.entry(level.clone()).or_default() += 1;
+        let sender = self.senders.entry(addr.to_owned()).or_default();
+        sender.messages += 1;
+        sender.severities[message.severity] += 1;
+        if let Some(hostname) = message.hostname {
+            sender.hostname = hostname.to_owned();
+        }
+
+        let key = (
+            message.app.unwrap_or("-").to_owned(),
+            template(message.message),
+        );
+        let tracked = self.templates.len();
+        let new_template = match self.templates.get_mut(&key) {
+            Some(stats) => {
+                stats.count += 1;
+                stats.severity = stats.severity.min(message.severity);
+                stats.last_ts = ts;
+                false
+            }
+            None if tracked < MAX_TEMPLATES => {
+                self.templates.insert(
+                    key,
+                    TemplateStats {
+                        count: 1,
+                        severity: message.severity,
+                        first_ts: ts,
+                        last_ts: ts,
+                    },
+                );
+                true
+            }
+            None => {
+                self.untracked += 1;
+                false
+            }
+        };
+        if !new_template && !self.archive {
+            return;
+        }
+        self.formatter.event(&Event {
+            ts,
+            protocol: PROTOCOL,
+            kind: EventKind::Log,
+            user: message.hostname.unwrap_or(addr).to_owned(),
+            method: level,
+            addr: Some(addr.to_owned()),
+            log: Some(LogRecord {
+                facility: FACILITIES.get(message.facility).unwrap_or(&"-"),
+                severity: SEVERITIES[message.severity],
+                timestamp: message.timestamp.map(str::to_owned),
+                hostname: message.hostname.map(str::to_owned),
+                app: message.app.map(str::to_owned),
+                proc_id: message.proc_id.map(str::to_owned),
+                msg_id: message.msg_id.map(str::to_owned),
+                message: message.message.to_owned(),
+            }),
+            ..Default::default()
+        });
+    }
+
+    fn add_protocol_fields(&self, tshark_args: &mut Vec<&str>) {
+        tshark_args.push("-e");
+        tshark_args.push("udp.payload");
+        if !tshark_args.contains(&"-Y") {
+            tshark_args.push("-Y");
+            tshark_args.push("syslog");
+        }
+        if tshark_args.contains(&"-i") && !tshark_args.contains(&"-f") {
+            tshark_args.push("-f");
+            tshark_args.push("udp port 514");
+        }
+    }
+
+    fn end(&mut self) {
+        self.print_stats(None);
+    }
+}
+
+// MARK: TESTS
+#[cfg(test)]
+mod test {
+    use super::{Message, parse_message, template};
+
+    #[test]
+    fn rfc3164_and_rfc5424() {
+        assert_eq!(
+            parse_message("<34>Oct 11 22:14:15 mymachine su[230]: 'su root' failed for lonvick\n"),
+            Some(Message {
+                facility: 4,
+                severity: 2,
+                timestamp: Some("Oct 11 22:14:15"),
+                hostname: Some("mymachine"),
+                app: Some("su"),
+                proc_id: Some("230"),
+                msg_id: None,
+                message: "'su root' failed for lonvick",
+            })
+        );
+        assert_eq!(
+            parse_message(
+                r#"<165>1 2003-10-11T22:14:15.003Z host.example.com evntslog - ID47 [exampleSDID@32473 iut="3" eventID="1011"][x@1 a="]"] An application event"#
+            ),
+            Some(Message {
+                facility: 20,
+                severity: 5,
+                timestamp: Some("2003-10-11T22:14:15.003Z"),
+                hostname: Some("host.example.com"),
+                app: Some("evntslog"),
+                proc_id: None,
+                msg_id: Some("ID47"),
+                message: "An application event",
+            })
+        );
+        let message = parse_message("<13>link down on port 7").unwrap();
+        assert_eq!(
+            (message.hostname, message.message),
+            (None, "link down on port 7")
+        );
+        assert_eq!(parse_message("no priority"), None);
+        assert_eq!(
+            template("login failed for 10.0.0.1 port 5060 user admin"),
+            "login failed for <*> port <*> user admin"
+        );
+    }
+}
@@ -0,0 +1,470 @@
+use super::{
+    Event, EventKind, ProtocolAnalyzer,
+    report::{self, DailyReport, LatencyStats},
+};
+use crate::{
+    ArgsCommand,
+    formatters::{EventFormatter, create_formatter},
+};
+use ahash::HashMap;
+use chrono::{DateTime, Utc};
+use std::fmt::Write as _;
+
+const FIN: u16 = 0x01;
+const SYN: u16 = 0x02;
+const RST: u16 = 0x04;
+const ACK: u16 = 0x10;
+
+/// Connections without packets for this long are closed as idle
+const IDLE_SECONDS: i64 = 3600;
+/// Retransmission rate is only ranked for destinations with enough packets
+const MIN_RANKED_PACKETS: u64 = 20;
+/// Closed streams ignore late packets such as the last ACK of the FIN exchange for 2 MSL
+const LINGER_SECONDS: i64 = 240;
+
+#[derive(Default, PartialEq, Eq, Clone, Copy)]
+enum State {
+    #[default]
+    SynSent,
+    SynReceived,
+    Established,
+}
+
+#[derive(Default)]
+struct Connection {
+    client: String,
+    server: String,
+    start: DateTime<Utc>,
+    /// Last SYN sent, retransmitted SYNs restart the handshake
+    syn_ts: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+    state: State,
+    client_fin: bool,
+    server_fin: bool,
+    syn_retries: u32,
+    packets: u64,
+    bytes: u64,
+    retransmissions: u64,
+    dup_acks: u64,
+    zero_windows: u64,
+}
+
+#[derive(Default)]
+struct DestinationStats {
+    connections: u32,
+    failed: u32,
+    resets: u32,
+    packets: u64,
+    bytes: u64,
+    retransmissions: u64,
+    dup_acks: u64,
+    zero_windows: u64,
+    handshake_rtt: LatencyStats,
+    ack_rtt: LatencyStats,
+    lifetime_s: i64,
+}
+
+pub struct Analyzer {
+    connections: HashMap<u32, Connection>,
+    /// Streams closed by FIN or RST and when their last packet was seen
+    finished: HashMap<u32, DateTime<Utc>>,
+    destinations: HashMap<String, DestinationStats>,
+    failures: HashMap<(String, &'static str), u32>,
+    closed: u32,
+    failed: u32,
+    resets: u32,
+    timeout: i64,
+    top: usize,
+    last_timeout_check: Option<DateTime<Utc>>,
+    formatter: Box<dyn EventFormatter>,
+    daily_report: DailyReport,
+}
+
+const PROTOCOL: &str = "tcp";
+
+fn seconds_to_ms(value: &str) -> Option<f64> {
+    value.parse::<f64>().ok().map(|s| s * 1000.0)
+}
+
+impl Analyzer {
+    /// Connection attempt that never got established
+    fn on_failed(&mut self, ts: DateTime<Utc>, conn: Connection, reason: &'static str) {
+        self.failed += 1;
+        let dest = self.destinations.entry(conn.server.clone()).or_default();
+        dest.failed += 1;
+        dest.packets += conn.packets;
+        *self
+            .failures
+            .entry((conn.server.clone(), reason))
+            .or_default() += 1;
+        let kind = if reason == "no answer" {
+            EventKind::NoResponse
+        } else {
+            EventKind::ErrorResponse
+        };
+        self.formatter.event(&Event {
+            ts,
+            protocol: PROTOCOL,
+            kind,
+            user: conn.client,
+            method: "SYN".into(),
+            addr: Some(conn.server),
+            elapsed_s: (kind == EventKind::NoResponse).then(|| (ts - conn.start).num_seconds()),
+            detail: Some(format!("{reason} after {} SYN", conn.syn_retries + 1)),
+            ..Default::default()
+        });
+    }
+
+    fn on_closed(&mut self, ts: DateTime<Utc>, conn: Connection, reset_by: Option<&str>) {
+        self.closed += 1;
+        let dest = self.destinations.entry(conn.server.clone()).or_default();
+        dest.connections += 1;
+        dest.packets += conn.packets;
+        dest.bytes += conn.bytes;
+        dest.retransmissions += conn.retransmissions;
+        dest.dup_acks += conn.dup_acks;
+        dest.zero_windows += conn.zero_windows;
+        dest.lifetime_s += (conn.last_seen - conn.start).num_seconds();
+        if let Some(by) = reset_by {
+            self.resets += 1;
+            dest.resets += 1;
+            self.formatter.event(&Event {
+                ts,
+                protocol: PROTOCOL,
+                kind: EventKind::Alert,
+                user: conn.client,
+                method: "RST".into(),
+                addr: Some(conn.server),
+                elapsed_s: Some((ts - conn.start).num_seconds()),
+                detail: Some(format!(
+                    "RESET by {by} after {} s, {} packets {} retransmissions",
+                    (ts - conn.start).num_seconds(),
+                    conn.packets,
+                    conn.retransmissions
+                )),
+                ..Default::default()
+            });
+        }
+    }
+
+    fn expire_pending(&mut self, opt_ts: Option<DateTime<Utc>>) {
+        let timeout = self.timeout;
+        let expired = self
+            .connections
+            .extract_if(|_, c| {
+                opt_ts.is_none_or(|ts| {
+                    let idle = (ts - c.last_seen).num_seconds();
+                    (c.state != State::Established && idle >= timeout) || idle >= IDLE_SECONDS
+                })
+            })
+            .collect::<Vec<_>>();
+        if let Some(ts) = opt_ts {
+            self.finished
+                .retain(|_, last| (ts - *last).num_seconds() < LINGER_SECONDS);
+        }
+        for (_, conn) in expired {
+            let ts = opt_ts.unwrap_or(conn.last_seen);
+            match conn.state {
+                State::SynSent => self.on_failed(ts, conn, "no answer"),
+                State::SynReceived => self.on_failed(ts, conn, "handshake not completed"),
+                State::Established => self.on_closed(ts, conn, None),
+            }
+        }
+    }
+
+    fn print_stats(&mut self, opt_ts: Option<DateTime<Utc>>) {
+        let mut output = String::with_capacity(200);
+        report::print_header(self.formatter.as_mut(), opt_ts);
+        report::print_section(self.formatter.as_mut(), "Destinations");
+        let mut destinations = Vec::from_iter(self.destinations.keys());
+        destinations.sort();
+        for server in destinations {
+            let stats = &self.destinations[server];
+            write!(
+                output,
+                "{server:<22} {:6} connections {:4} failed {:4} resets {:8} packets {:10} bytes {:6} retrans {:6} dup-acks {:4} zero-win  avg lifetime {} s",
+                stats.connections,
+                stats.failed,
+                stats.resets,
+                stats.packets,
+                stats.bytes,
+                stats.retransmissions,
+                stats.dup_acks,
+                stats.zero_windows,
+                stats.lifetime_s / i64::from(stats.connections.max(1)),
+            )
+            .unwrap();
+            write!(
+                output,
+                "\n\thandshake rtt {}\n\tack rtt       {}",
+                stats.handshake_rtt.summary(),
+                stats.ack_rtt.summary()
+            )
+            .unwrap();
+            self.formatter.report(&output, 1);
+            output.clear();
+        }
+
+        report::print_section(self.formatter.as_mut(), "Failed Connection Attempts");
+        for ((server, reason), count) in report::top_n(&self.failures, self.top) {
+            self.formatter
+                .report(&format!("{count:8} {server:<22} {reason}"), 1);
+        }
+
+        report::print_section(self.formatter.as_mut(), "Top Retransmission Rates");
+        let mut offenders = self
+            .destinations
+            .iter()
+            .filter(|(_, s)| s.packets >= MIN_RANKED_PACKETS && s.retransmissions > 0)
+            .map(|(server, s)| (server, s.retransmissions as f64 * 100.0 / s.packets as f64))
+            .collect::<Vec<_>>();
+        offenders.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(b.0)));
+        for (server, rate) in offenders.into_iter().take(self.top) {
+            let stats = &self.destinations[server];
+            self.formatter.report(
+                &format!(
+                    "{rate:6.2} % {server:<22} {} of {} packets",
+                    stats.retransmissions, stats.packets
+                ),
+                1,
+            );
+        }
+        write!(
+            output,
+            r#"
+ ------------ STATS ------------
+
+- total connections closed: {}
+- failed connection attempts: {}
+- connections reset: {}
+- open connections: {}
+- total retransmissions: {}
+"#,
+            self.closed,
+            self.failed,
+            self.resets,
+            self.connections.len(),
+            self.destinations
+                .values()
+                .map(|s| s.retransmissions)
+                .sum::<u64>(),
+        )
+        .unwrap();
+        self.formatter.report(&output, 0);
+        report::print_footer(self.formatter.as_mut());
+    }
+}
+
+impl ProtocolAnalyzer for Analyzer {
+    fn new(cmd_args: &ArgsCommand, verbosity: u8) -> Self {
+        let (timeout, top) = match cmd_args {
+            ArgsCommand::Analyzer { timeout, top, .. } => (*timeout as i64, *top),
+            _ => (5, 10),
+        };
+        Self {
+            connections: HashMap::default(),
+            finished: HashMap::default(),
+            destinations: HashMap::default(),
+            failures: HashMap::default(),
+            closed: 0,
+            failed: 0,
+            resets: 0,
+            timeout,
+            top,
+            last_timeout_check: None,
+            formatter: create_formatter(cmd_args, verbosity),
+            daily_report: DailyReport::default(),
+        }
+    }
+
+    fn analyze(&mut self, ts: DateTime<Utc>, cols: Vec<&str>) {
+        let src = format!("{}:{}", cols[0], cols[3]);
+        let dst = format!("{}:{}", cols[1], cols[4]);
+        let stream = cols[2].parse::<u32>().unwrap_or_default();
+        let flags = u16::from_str_radix(cols[5].trim_start_matches("0x"), 16).unwrap_or_default();
+        let len = cols[6].parse::<u64>().unwrap_or_default();
+        let retransmission = !cols[7].is_empty();
+        let dup_ack = !cols[8].is_empty();
+        let zero_window = !cols[9].is_empty();
+        let ack_rtt = seconds_to_ms(cols[10]);
+        if self
+            .last_timeout_check
+            .is_none_or(|last| (ts - last).num_seconds() >= 1)
+        {
+            self.expire_pending(Some(ts));
+            self.last_timeout_check = Some(ts);
+        }
+        if self.daily_report.is_due(ts) {
+            self.print_stats(Some(ts));
+        }
+
+        if let Some(last) = self.finished.get_mut(&stream) {
+            // a new SYN reuses the stream, anything else belongs to the closed connection
+            if flags & (SYN | ACK) != SYN {
+                *last = ts;
+                return;
+            }
+            self.finished.remove(&stream);
+        }
+        let conn = self.connections.entry(stream).or_insert_with(|| {
+            // mid-stream captures start established, client is the side sending first
+            let state = if flags & (SYN | ACK) == SYN {
+                State::SynSent
+            } else {
+                State::Established
+            };
+            Connection {
+                client: src.clone(),
+                server: dst.clone(),
+                start: ts,
+                syn_ts: ts,
+                state,
+                ..Default::default()
+            }
+        });
+        let from_client = conn.client == src;
+        conn.last_seen = ts;
+        conn.packets += 1;
+        conn.bytes += len;
+        if retransmission {
+            conn.retransmissions += 1;
+        }
+        if dup_ack {
+            conn.dup_acks += 1;
+        }
+        if zero_window {
+            conn.zero_windows += 1;
+        }
+        if let Some(rtt) = ack_rtt {
+            self.destinations
+                .entry(conn.server.clone())
+                .or_default()
+                .ack_rtt
+                .add(rtt);
+        }
+
+        match conn.state {
+            State::SynSent if flags & (SYN | ACK) == SYN && from_client && conn.packets > 1 => {
+                conn.syn_retries += 1;
+                conn.syn_ts = ts;
+            }
+            State::SynSent if flags & (SYN | ACK) == SYN | ACK && !from_client => {
+                conn.state = State::SynReceived;
+            }
+            State::SynReceived if flags & (SYN | ACK) == ACK && from_client => {
+                conn.state = State::Established;
+                let rtt = (ts - conn.syn_ts).num_microseconds().unwrap_or_default() as f64 / 1000.0;
+                self.destinations
+                    .entry(conn.server.clone())
+                    .or_default()
+                    .handshake_rtt
+                    .add(rtt);
+            }
+            _ => (),
+        }
+
+        if flags & RST != 0 {
+            let conn = self.connections.remove(&stream).unwrap();
+            self.finished.insert(stream, ts);
+            let by = if from_client { "client" } else { "server" };
+            match conn.state {
+                State::Established => self.on_closed(ts, conn, Some(by)),
+                _ => self.on_failed(ts, conn, "refused"),
+            }
+            return;
+        }
+        if flags & FIN != 0 {
+            if from_client {
+                conn.client_fin = true;
+            } else {
+                conn.server_fin = true;
+            }
+        }
+        // last ACK of the FIN exchange is not needed for lifetime accounting
+        if conn.client_fin && conn.server_fin {
+            let conn = self.connections.remove(&stream).unwrap();
+            self.finished.insert(stream, ts);
+            self.on_closed(ts, conn, None);
+        }
+    }
+
+    fn add_protocol_fields(&self, tshark_args: &mut Vec<&str>) {
+        tshark_args.push("-e");
+        tshark_args.push("tcp.stream");
+        tshark_args.push("-e");
+        tshark_args.push("tcp.srcport");
+        tshark_args.push("-e");
+        tshark_args.push("tcp.dstport");
+        tshark_args.push("-e");
+        tshark_args.push("tcp.flags");
+        tshark_args.push("-e");
+        tshark_args.push("tcp.len");
+        tshark_args.push("-e");
+        tshark_args.push("tcp.analysis.retransmission");
+        tshark_args.push("-e");
+        tshark_args.push("tcp.analysis.duplicate_ack");
+        tshark_args.push("-e");
+        tshark_args.push("tcp.analysis.zero_window");
+        tshark_args.push("-e");
+        tshark_args.push("tcp.analysis.ack_rtt");
+        if !tshark_args.contains(&"-Y") {
+            tshark_args.push("-Y");
+            tshark_args.push("tcp");
+        }
+        if tshark_args.contains(&"-i") && !tshark_args.contains(&"-f") {
+            tshark_args.push("-f");
+            tshark_args.push("tcp");
+        }
+    }
+
+    fn end(&mut self) {
+        self.expire_pending(None);
+        self.print_stats(None);
+    }
+}
+
+// MARK: TESTS
+#[cfg(test)]
+mod test {
+    use chrono::{DateTime, TimeDelta};
+
+    use super::{Analyzer, ProtocolAnalyzer as _};
+    use crate::analyzers::test_args;
+
+    #[test]
+    fn graceful_close() {
+        let mut analyzer = Analyzer::new(&test_args(&[]), 0);
+        let start = DateTime::from_timestamp(1738062028, 0).unwrap();
+        let client = vec![
+            "10.0.0.1", "10.0.0.2", "7", "50000", "80", "", "0", "", "", "", "",
+        ];
+        let server = vec![
+            "10.0.0.2", "10.0.0.1", "7", "80", "50000", "", "0", "", "", "", "",
+        ];
+        // SYN retried once, SYN-ACK, ACK, then FIN from the server, FIN and the final ACK
+        for (ms, from_client, flags) in [
+            (0, true, "0x0002"),
+            (1000, true, "0x0002"),
+            (1020, false, "0x0012"),
+            (1040, true, "0x0010"),
+            (2000, false, "0x0011"),
+            (2020, true, "0x0011"),
+            (2040, false, "0x0010"),
+        ] {
+            let mut cols = if from_client {
+                client.clone()
+            } else {
+                server.clone()
+            };
+            cols[5] = flags;
+            analyzer.analyze(start + TimeDelta::milliseconds(ms), cols);
+        }
+        analyzer.end();
+        assert_eq!(analyzer.closed, 1);
+        assert!(analyzer.connections.is_empty());
+        assert_eq!(analyzer.destinations.len(), 1);
+        let dest = &analyzer.destinations["10.0.0.2:80"];
+        assert_eq!(dest.connections, 1);
+        assert_eq!(dest.handshake_rtt.max(), 40.0);
+    }
+}
@@ -0,0 +1,678 @@
+use super::{
+    Event, EventKind, ProtocolAnalyzer,
+    report::{self, DailyReport, LatencyStats},
+};
+use crate::{
+    ArgsCommand,
+    formatters::{EventFormatter, create_formatter},
+    utils::x509::{CertInfo, parse_certificate},
+};
+use ahash::HashMap;
+use chrono::{DateTime, Utc};
+use std::fmt::Write as _;
+
+#[derive(Default)]
+struct Connection {
+    start: DateTime<Utc>,
+    client: String,
+    server: String,
+    sni: String,
+    /// Versions from the supported_versions extension, else the legacy ClientHello version
+    offered_versions: Vec<u16>,
+    offered_ciphers: Vec<u16>,
+    alpn_offered: String,
+    version: Option<u16>,
+    cipher: Option<u16>,
+    alpn: String,
+    cert: Option<CertInfo>,
+}
+
+#[derive(Default)]
+struct EndpointStats {
+    connections: u32,
+    failures: u32,
+    versions: HashMap<u16, u32>,
+    ciphers: HashMap<String, u32>,
+    alpn: HashMap<String, u32>,
+    latency: LatencyStats,
+    cert: Option<CertInfo>,
+    cert_seen: DateTime<Utc>,
+    deprecated_warned: bool,
+    /// ClientHellos offering each deprecated version or weak cipher
+    offered_deprecated: HashMap<u16, u32>,
+    offered_weak: HashMap<String, u32>,
+}
+
+pub struct Analyzer {
+    connections: HashMap<u32, Connection>,
+    endpoints: HashMap<(String, String), EndpointStats>,
+    versions: HashMap<u16, u32>,
+    ciphers: HashMap<String, u32>,
+    client_failures: HashMap<(String, String), u32>,
+    handshakes: u32,
+    failures: u32,
+    timeout: i64,
+    top: usize,
+    cert_expiry_days: i64,
+    last_timeout_check: Option<DateTime<Utc>>,
+    formatter: Box<dyn EventFormatter>,
+    daily_report: DailyReport,
+}
+
+const PROTOCOL: &str = "tls";
+
+fn parse_hex(value: &str) -> Option<u16> {
+    let value = value.trim();
+    u32::from_str_radix(value.strip_prefix("0x").unwrap_or(value), 16)
+        .ok()
+        .and_then(|v| u16::try_from(v).ok())
+}
+
+/// GREASE values (RFC 8701) are random placeholders, never negotiated
+fn is_grease(value: u16) -> bool {
+    value & 0x0f0f == 0x0a0a
+}
+
+fn parse_hex_list(col: &str) -> Vec<u16> {
+    col.split(',')
+        .filter_map(parse_hex)
+        .filter(|v| !is_grease(*v))
+        .collect()
+}
+
+fn version_name(version: u16) -> &'static str {
+    match version {
+        0x0002 => "SSLv2",
+        0x0300 => "SSLv3",
+        0x0301 => "TLSv1.0",
+        0x0302 => "TLSv1.1",
+        0x0303 => "TLSv1.2",
+        0x0304 => "TLSv1.3",
+        _ => "unknown",
+    }
+}
+
+fn is_deprecated(version: u16) -> bool {
+    version < 0x0303
+}
+
+/// NULL, export, anonymous, RC4, DES and 3DES suites
+fn is_weak_cipher(cipher: u16) -> bool {
+    matches!(
+        cipher,
+        0x0000..=0x001b
+            | 0xc001..=0xc003
+            | 0xc006..=0xc008
+            | 0xc00b..=0xc00d
+            | 0xc010..=0xc012
+            | 0xc015..=0xc017
+    )
+}
+
+fn cipher_name(cipher: u16) -> String {
+    match cipher {
+        0x0004 => "TLS_RSA_WITH_RC4_128_MD5",
+        0x0005 => "TLS_RSA_WITH_RC4_128_SHA",
+        0x000a => "TLS_RSA_WITH_3DES_EDE_CBC_SHA",
+        0x002f => "TLS_RSA_WITH_AES_128_CBC_SHA",
+        0x0035 => "TLS_RSA_WITH_AES_256_CBC_SHA",
+        0x003c => "TLS_RSA_WITH_AES_128_CBC_SHA256",
+        0x009c => "TLS_RSA_WITH_AES_128_GCM_SHA256",
+        0x009d => "TLS_RSA_WITH_AES_256_GCM_SHA384",
+        0x1301 => "TLS_AES_128_GCM_SHA256",
+        0x1302 => "TLS_AES_256_GCM_SHA384",
+        0x1303 => "TLS_CHACHA20_POLY1305_SHA256",
+        0xc009 => "TLS_ECDHE_ECDSA_WITH_AES_128_CBC_SHA",
+        0xc00a => "TLS_ECDHE_ECDSA_WITH_AES_256_CBC_SHA",
+        0xc013 => "TLS_ECDHE_RSA_WITH_AES_128_CBC_SHA",
+        0xc014 => "TLS_ECDHE_RSA_WITH_AES_256_CBC_SHA",
+        0xc027 => "TLS_ECDHE_RSA_WITH_AES_128_CBC_SHA256",
+        0xc02b => "TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256",
+        0xc02c => "TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384",
+        0xc02f => "TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256",
+        0xc030 => "TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384",
+        0xcca8 => "TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256",
+        0xcca9 => "TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256",
+        other => return format!("0x{other:04x}"),
+    }
+    .to_owned()
+}
+
+fn alert_name(desc: &str) -> String {
+    match desc {
+        "0" => "close_notify",
+        "10" => "unexpected_message",
+        "20" => "bad_record_mac",
+        "40" => "handshake_failure",
+        "42" => "bad_certificate",
+        "43" => "unsupported_certificate",
+        "44" => "certificate_revoked",
+        "45" => "certificate_expired",
+        "46" => "certificate_unknown",
+        "47" => "illegal_parameter",
+        "48" => "unknown_ca",
+        "49" => "access_denied",
+        "50" => "decode_error",
+        "51" => "decrypt_error",
+        "70" => "protocol_version",
+        "71" => "insufficient_security",
+        "80" => "internal_error",
+        "86" => "inappropriate_fallback",
+        "90" => "user_canceled",
+        "109" => "missing_extension",
+        "112" => "unrecognized_name",
+        "116" => "certificate_required",
+        "120" => "no_application_protocol",
+        other => return format!("alert_{other}"),
+    }
+    .to_owned()
+}
+
+fn conn_event(ts: DateTime<Utc>, kind: EventKind, conn: &Connection, detail: String) -> Event {
+    Event {
+        ts,
+        protocol: PROTOCOL,
+        kind,
+        user: conn.client.clone(),
+        method: conn
+            .version
+            .map(version_name)
+            .unwrap_or_default()
+            .to_owned(),
+        addr: Some(conn.server.clone()),
+        detail: Some(detail),
+        ..Default::default()
+    }
+}
+
+impl Connection {
+    fn deprecated_offered(&self) -> impl Iterator<Item = u16> {
+        self.offered_versions
+            .iter()
+            .copied()
+            .filter(|version| is_deprecated(*version))
+    }
+
+    fn weak_offered(&self) -> impl Iterator<Item = u16> {
+        self.offered_ciphers
+            .iter()
+            .copied()
+            .filter(|cipher| is_weak_cipher(*cipher))
+    }
+
+    /// Offered versions, and the deprecated versions and weak ciphers among the offers
+    fn offer_detail(&self) -> String {
+        let versions = self
+            .offered_versions
+            .iter()
+            .map(|version| version_name(*version))
+            .collect::<Vec<_>>();
+        let mut detail = format!(
+            "offered={} ciphers={}",
+            versions.join(","),
+            self.offered_ciphers.len()
+        );
+        let deprecated = self
+            .deprecated_offered()
+            .map(version_name)
+            .collect::<Vec<_>>();
+        if !deprecated.is_empty() {
+            write!(detail, " deprecated_offered={}", deprecated.join(",")).unwrap();
+        }
+        let weak = self.weak_offered().map(cipher_name).collect::<Vec<_>>();
+        if !weak.is_empty() {
+            write!(detail, " weak_offered={}", weak.join(",")).unwrap();
+        }
+        detail
+    }
+}
+
+impl EndpointStats {
+    fn add_offers(&mut self, conn: &Connection) {
+        for version in conn.deprecated_offered() {
+            *self.offered_deprecated.entry(version).or_default() += 1;
+        }
+        for cipher in conn.weak_offered() {
+            *self.offered_weak.entry(cipher_name(cipher)).or_default() += 1;
+        }
+    }
+}
+
+impl Analyzer {
+    fn on_failure(&mut self, ts: DateTime<Utc>, conn: Connection, reason: String) {
+        self.failures += 1;
+        let endpoint = self
+            .endpoints
+            .entry((conn.server.clone(), conn.sni.clone()))
+            .or_default();
+        endpoint.failures += 1;
+        endpoint.add_offers(&conn);
+        *self
+            .client_failures
+            .entry((conn.client.clone(), reason.clone()))
+            .or_default() += 1;
+        let detail = format!("FAILED {reason} sni={} {}", conn.sni, conn.offer_detail());
+        self.formatter
+            .event(&conn_event(ts, EventKind::Alert, &conn, detail));
+    }
+
+    fn on_established(&mut self, ts: DateTime<Utc>, conn: Connection) {
+        let latency_ms = (ts - conn.start).num_microseconds().unwrap_or_default() as f64 / 1000.0;
+        let version = conn.version.unwrap_or_default();
+        let cipher = conn.cipher.map(cipher_name).unwrap_or_default();
+        self.handshakes += 1;
+        *self.versions.entry(version).or_default() += 1;
+        *self.ciphers.entry(cipher.clone()).or_default() += 1;
+        let endpoint = self
+            .endpoints
+            .entry((conn.server.clone(), conn.sni.clone()))
+            .or_default();
+        endpoint.connections += 1;
+        endpoint.add_offers(&conn);
+        endpoint.latency.add(latency_ms);
+        *endpoint.versions.entry(version).or_default() += 1;
+        *endpoint.ciphers.entry(cipher.clone()).or_default() += 1;
+        if !conn.alpn.is_empty() {
+            *endpoint.alpn.entry(conn.alpn.clone()).or_default() += 1;
+        }
+        let mut warnings = Vec::new();
+        if is_deprecated(version) && !endpoint.deprecated_warned {
+            endpoint.deprecated_warned = true;
+            warnings.push(format!(
+                "deprecated {} sni={}",
+                version_name(version),
+                conn.sni
+            ));
+        }
+        if let Some(cert) = &conn.cert {
+            if endpoint.cert.as_ref() != Some(cert)
+                && let Some(not_after) = cert.not_after
+                && (not_after - ts).num_days() < self.cert_expiry_days
+            {
+                warnings.push(format!(
+                    "certificate {} expires {} ({} days)",
+                    cert.subject,
+                    not_after.format("%Y-%m-%d"),
+                    (not_after - ts).num_days()
+                ));
+            }
+            endpoint.cert = Some(cert.clone());
+            endpoint.cert_seen = ts;
+        }
+
+        let mut detail = format!("sni={} cipher={cipher} {}", conn.sni, conn.offer_detail());
+        if !conn.alpn_offered.is_empty() {
+            write!(detail, " alpn={} ({})", conn.alpn, conn.alpn_offered).unwrap();
+        }
+        if let Some(cert) = &conn.cert {
+            write!(
+                detail,
+                " subject=\"{}\" issuer=\"{}\"",
+                cert.subject, cert.issuer
+            )
+            .unwrap();
+            if let Some(not_after) = cert.not_after {
+                write!(detail, " expires={}", not_after.format("%Y-%m-%d")).unwrap();
+            }
+        }
+        let event = Event {
+            latency_ms: Some(latency_ms),
+            ..conn_event(ts, EventKind::Handshake, &conn, detail)
+        };
+        self.formatter.event(&event);
+        for warning in warnings {
+            self.formatter
+                .event(&conn_event(ts, EventKind::Warning, &conn, warning));
+        }
+    }
+
+    fn expire_pending(&mut self, opt_ts: Option<DateTime<Utc>>) {
+        let timeout = self.timeout;
+        let expired = self
+            .connections
+            .extract_if(|_, c| opt_ts.is_none_or(|ts| (ts - c.start).num_seconds() >= timeout))
+            .collect::<Vec<_>>();
+        for (_, conn) in expired {
+            let reason = if conn.version.is_some() {
+                "incomplete handshake"
+            } else {
+                "no ServerHello"
+            };
+            self.on_failure(opt_ts.unwrap_or(conn.start), conn, reason.to_owned());
+        }
+    }
+
+    fn print_stats(&mut self, opt_ts: Option<DateTime<Utc>>) {
+        let mut output = String::with_capacity(200);
+        report::print_header(self.formatter.as_mut(), opt_ts);
+        report::print_section(self.formatter.as_mut(), "Endpoints");
+        let mut endpoints = Vec::from_iter(self.endpoints.keys());
+        endpoints.sort();
+        for key in endpoints {
+            let (server, sni) = key;
+            let stats = &self.endpoints[key];
+            write!(
+                output,
+                "{server:<22} {sni:<30} {:6} handshakes {:4} failures",
+                stats.connections, stats.failures
+            )
+            .unwrap();
+            for (version, count) in report::top_n(&stats.versions, usize::MAX) {
+                write!(output, " {} {count}", version_name(*version)).unwrap();
+            }
+            for (alpn, count) in report::top_n(&stats.alpn, usize::MAX) {
+                write!(output, " alpn {alpn} {count}").unwrap();
+            }
+            write!(output, "\n\t{}", stats.latency.summary()).unwrap();
+            for (cipher, count) in report::top_n(&stats.ciphers, usize::MAX) {
+                write!(output, "\n\t{count:6} {cipher}").unwrap();
+            }
+            if let Some(cert) = &stats.cert {
+                write!(
+                    output,
+                    "\n\tcertificate \"{}\" issued by \"{}\"",
+                    cert.subject, cert.issuer
+                )
+                .unwrap();
+                if let Some(not_after) = cert.not_after {
+                    write!(output, " expires {}", not_after.format("%Y-%m-%d")).unwrap();
+                }
+            }
+            self.formatter.report(&output, 1);
+            output.clear();
+        }
+
+        report::print_section(self.formatter.as_mut(), "Deprecated Versions");
+        let mut deprecated = 0;
+        let mut offering = 0;
+        let mut endpoints = Vec::from_iter(self.endpoints.iter().filter(|(_, s)| {
+            s.deprecated_warned || !s.offered_deprecated.is_empty() || !s.offered_weak.is_empty()
+        }));
+        endpoints.sort_by_key(|(key, _)| *key);
+        for ((server, sni), stats) in endpoints {
+            if stats.deprecated_warned {
+                deprecated += 1;
+            } else {
+                offering += 1;
+            }
+            let versions = stats
+                .versions
+                .iter()
+                .filter(|(version, _)| is_deprecated(**version))
+                .map(|(version, count)| format!("{} x {count}", version_name(*version)))
+                .collect::<Vec<_>>()
+                .join(", ");
+            write!(output, "{server:<22} {sni:<30} negotiated [{versions}]").unwrap();
+            let offered = report::top_n(&stats.offered_deprecated, usize::MAX)
+                .into_iter()
+                .map(|(version, count)| format!("{} x {count}", version_name(*version)))
+                .collect::<Vec<_>>()
+                .join(", ");
+            write!(output, " offered [{offered}]").unwrap();
+            for (cipher, count) in report::top_n(&stats.offered_weak, usize::MAX) {
+                write!(output, "\n\t{count:6} offered {cipher}").unwrap();
+            }
+            self.formatter.report(&output, 1);
+            output.clear();
+        }
+
+        report::print_section(
+            self.formatter.as_mut(),
+            &format!(
+                "Certificates Expiring Within {} Days",
+                self.cert_expiry_days
+            ),
+        );
+        let mut expiring = 0;
+        for ((server, sni), stats) in &self.endpoints {
+            if let Some(cert) = &stats.cert
+                && let Some(not_after) = cert.not_after
+                && (not_after - stats.cert_seen).num_days() < self.cert_expiry_days
+            {
+                expiring += 1;
+                let days = (not_after - stats.cert_seen).num_days();
+                let state = if days < 0 { "EXPIRED" } else { "expires" };
+                self.formatter.report(
+                    &format!(
+                        "{server:<22} {sni:<30} {state} {} ({days} days) \"{}\"",
+                        not_after.format("%Y-%m-%d"),
+                        cert.subject
+                    ),
+                    1,
+                );
+            }
+        }
+
+        report::print_section(self.formatter.as_mut(), "Handshake Failures");
+        for ((client, reason), count) in report::top_n(&self.client_failures, self.top) {
+            self.formatter
+                .report(&format!("{count:8} {client:<22} {reason}"), 1);
+        }
+        report::print_section(self.formatter.as_mut(), "Versions");
+        for (version, count) in report::top_n(&self.versions, usize::MAX) {
+            self.formatter
+                .report(&format!("{count:8} {}", version_name(*version)), 1);
+        }
+        report::print_section(self.formatter.as_mut(), "Cipher Suites");
+        for (cipher, count) in report::top_n(&self.ciphers, self.top) {
+            self.formatter.report(&format!("{count:8} {cipher}"), 1);
+        }
+        write!(
+            output,
+            r#"
+ ------------ STATS ------------
+
+- total handshakes: {}
+- handshake failures: {}
+- pending handshakes: {}
+- endpoints with deprecated versions: {deprecated}
+- endpoints only offered deprecated versions or weak ciphers: {offering}
+- expiring certificates: {expiring}
+"#,
+            self.handshakes,
+            self.failures,
+            self.connections.len(),
+        )
+        .unwrap();
+        self.formatter.report(&output, 0);
+        report::print_footer(self.formatter.as_mut());
+    }
+}
+
+impl ProtocolAnalyzer for Analyzer {
+    fn new(cmd_args: &ArgsCommand, verbosity: u8) -> Self {
+        let (timeout, top, cert_expiry_days) = match cmd_args {
+            ArgsCommand::Analyzer {
+                timeout,
+                top,
+                cert_expiry_days,
+                ..
+            } => (*timeout as i64, *top, *cert_expiry_days),
+            _ => (5, 10, 30),
+        };
+        Self {
+            connections: HashMap::default(),
+            endpoints: HashMap::default(),
+            versions: HashMap::default(),
+            ciphers: HashMap::default(),
+            client_failures: HashMap::default(),
+            handshakes: 0,
+            failures: 0,
+            timeout,
+            top,
+            cert_expiry_days,
+            last_timeout_check: None,
+            formatter: create_formatter(cmd_args, verbosity),
+            daily_report: DailyReport::default(),
+        }
+    }
+
+    fn analyze(&mut self, ts: DateTime<Utc>, cols: Vec<&str>) {
+        let src_addr = cols[0];
+        let dst_addr = cols[1];
+        let stream = cols[2].parse::<u32>().unwrap_or_default();
+        let handshake_types = cols[3].split(',').collect::<Vec<_>>();
+        let record_types = cols[12].split(',').collect::<Vec<_>>();
+        let (src_port, dst_port) = (cols[13], cols[14]);
+        if self
+            .last_timeout_check
+            .is_none_or(|last| (ts - last).num_seconds() >= 1)
+        {
+            self.expire_pending(Some(ts));
+            self.last_timeout_check = Some(ts);
+        }
+        if self.daily_report.is_due(ts) {
+            self.print_stats(Some(ts));
+        }
+
+        if handshake_types.contains(&"1") {
+            let mut offered_versions = parse_hex_list(cols[5]);
+            if offered_versions.is_empty() {
+                offered_versions.extend(parse_hex(cols[4]));
+            }
+            self.connections.insert(
+                stream,
+                Connection {
+                    start: ts,
+                    client: src_addr.to_owned(),
+                    server: format!("{dst_addr}:{dst_port}"),
+                    sni: cols[7].to_owned(),
+                    offered_versions,
+                    offered_ciphers: parse_hex_list(cols[6]),
+                    alpn_offered: cols[8].to_owned(),
+                    ..Default::default()
+                },
+            );
+        }
+        let Some(conn) = self.connections.get_mut(&stream) else {
+            return;
+        };
+        if handshake_types.contains(&"2") {
+            // TLS 1.3 ServerHello keeps 0x0303 as legacy version and negotiates by extension
+            conn.version = parse_hex_list(cols[5])
+                .first()
+                .copied()
+                .or_else(|| parse_hex(cols[4]));
+            conn.cipher = parse_hex_list(cols[6]).first().copied();
+            conn.alpn = cols[8].to_owned();
+        }
+        if handshake_types.contains(&"11")
+            && conn.cert.is_none()
+            && let Some(leaf) = cols[9].split(',').next()
+            && let Ok(der) = hex::decode(leaf)
+        {
+            conn.cert = parse_certificate(&der);
+        }
+        let from_server = src_port == conn.server.rsplit(':').next().unwrap_or_default();
+        if cols[10].split(',').any(|level| level == "2") {
+            let alert = alert_name(cols[11].split(',').next().unwrap_or_default());
+            let from = if from_server { "server" } else { "client" };
+            let conn = self.connections.remove(&stream).unwrap();
+            self.on_failure(ts, conn, format!("{alert} from {from}"));
+            return;
+        }
+        // TLS 1.3 encrypts the server flight after ServerHello as application data, the
+        // handshake is only done once the client sends its first encrypted record
+        let established = match conn.version {
+            Some(0x0304) => !from_server,
+            Some(_) => true,
+            None => false,
+        };
+        if established && record_types.contains(&"23") {
+            let conn = self.connections.remove(&stream).unwrap();
+            self.on_established(ts, conn);
+        }
+    }
+
+    fn add_protocol_fields(&self, tshark_args: &mut Vec<&str>) {
+        tshark_args.push("-e");
+        tshark_args.push("tcp.stream");
+        tshark_args.push("-e");
+        tshark_args.push("tls.handshake.type");
+        tshark_args.push("-e");
+        tshark_args.push("tls.handshake.version");
+        tshark_args.push("-e");
+        tshark_args.push("tls.handshake.extensions.supported_version");
+        tshark_args.push("-e");
+        tshark_args.push("tls.handshake.ciphersuite");
+        tshark_args.push("-e");
+        tshark_args.push("tls.handshake.extensions_server_name");
+        tshark_args.push("-e");
+        tshark_args.push("tls.handshake.extensions_alpn_str");
+        tshark_args.push("-e");
+        tshark_args.push("tls.handshake.certificate");
+        tshark_args.push("-e");
+        tshark_args.push("tls.alert_message.level");
+        tshark_args.push("-e");
+        tshark_args.push("tls.alert_message.desc");
+        tshark_args.push("-e");
+        tshark_args.push("tls.record.content_type");
+        tshark_args.push("-e");
+        tshark_args.push("tcp.srcport");
+        tshark_args.push("-e");
+        tshark_args.push("tcp.dstport");
+        if !tshark_args.contains(&"-Y") {
+            tshark_args.push("-Y");
+            tshark_args.push("tls");
+        }
+        if tshark_args.contains(&"-i") && !tshark_args.contains(&"-f") {
+            tshark_args.push("-f");
+            tshark_args.push("tcp port 443 or tcp port 5061");
+        }
+    }
+
+    fn end(&mut self) {
+        self.expire_pending(None);
+        self.print_stats(None);
+    }
+}
+
+// MARK: TESTS
+#[cfg(test)]
+mod test {
+    use chrono::{DateTime, TimeDelta};
+
+    use super::{Analyzer, ProtocolAnalyzer as _};
+    use crate::analyzers::test_args;
+
+    #[test]
+    fn offered_deprecated_versions_and_weak_ciphers() {
+        let mut analyzer = Analyzer::new(&test_args(&[]), 0);
+        let start = DateTime::from_timestamp(1738062028, 0).unwrap();
+        let hello = vec![
+            "10.0.0.1",
+            "10.0.0.2",
+            "5",
+            "1",
+            "0x0303",
+            "0x0a0a,0x0304,0x0303,0x0301",
+            "0x1301,0xc02f,0x000a,0x0005",
+            "api.local",
+            "",
+            "",
+            "",
+            "",
+            "22",
+            "50000",
+            "443",
+        ];
+        let server_hello = vec![
+            "10.0.0.2", "10.0.0.1", "5", "2", "0x0303", "0x0304", "0x1301", "", "", "", "", "",
+            "22,23", "443", "50000",
+        ];
+        let finished = vec![
+            "10.0.0.1", "10.0.0.2", "5", "", "", "", "", "", "", "", "", "", "23", "50000", "443",
+        ];
+        for (ms, cols) in [(0, hello), (20, server_hello), (40, finished)] {
+            analyzer.analyze(start + TimeDelta::milliseconds(ms), cols);
+        }
+        analyzer.end();
+        assert_eq!(analyzer.handshakes, 1);
+        let endpoint = &analyzer.endpoints[&("10.0.0.2:443".to_owned(), "api.local".to_owned())];
+        assert!(!endpoint.deprecated_warned);
+        assert_eq!(endpoint.offered_deprecated.len(), 1);
+        assert_eq!(endpoint.offered_deprecated[&0x0301], 1);
+        assert_eq!(endpoint.offered_weak.len(), 2);
+        assert_eq!(endpoint.offered_weak["TLS_RSA_WITH_RC4_128_SHA"], 1);
+    }
+}
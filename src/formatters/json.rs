@@ -0,0 +1,19 @@
+use crate::analyzers::Event;
+
+use super::EventFormatter;
+
+/// One JSON object per line on stdout, reports are kept on stderr
+pub struct JsonFormatter;
+
+impl EventFormatter for JsonFormatter {
+    fn event(&mut self, event: &Event) {
+        match serde_json::to_string(event) {
+            Ok(line) => println!("{line}"),
+            Err(e) => eprintln!("error serializing event: {e}"),
+        }
+    }
+
+    fn report(&mut self, text: &str, _min_verbosity: u8) {
+        eprintln!("{text}");
+    }
+}
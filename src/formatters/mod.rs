@@ -0,0 +1,29 @@
+use clap::ValueEnum;
+
+use crate::{ArgsCommand, analyzers::Event};
+
+pub mod json;
+pub mod text;
+
+#[derive(ValueEnum, Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+pub fn create_formatter(args: &ArgsCommand, verbosity: u8) -> Box<dyn EventFormatter> {
+    match args {
+        ArgsCommand::Analyzer {
+            format: OutputFormat::Json,
+            ..
+        } => Box::new(json::JsonFormatter),
+        _ => Box::new(text::TextFormatter::new(verbosity)),
+    }
+}
+
+pub trait EventFormatter {
+    fn event(&mut self, event: &Event);
+    /// Report text block, mirrored on stderr when `verbosity > min_verbosity`
+    fn report(&mut self, text: &str, min_verbosity: u8);
+}
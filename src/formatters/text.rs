@@ -0,0 +1,260 @@
+use chrono::Local;
+use std::fmt::Write as _;
+
+use crate::analyzers::{Direction, Event, EventKind, TIME_FMT};
+
+use super::EventFormatter;
+
+/// Column padded lines on stdout, reports mirrored on stderr by verbosity
+pub struct TextFormatter {
+    verbosity: u8,
+}
+
+impl TextFormatter {
+    pub fn new(verbosity: u8) -> Self {
+        Self { verbosity }
+    }
+}
+
+pub fn render(event: &Event) -> String {
+    let mut output = String::with_capacity(200);
+    write!(
+        output,
+        "{} {:<8} {:<10} ",
+        event.ts.with_timezone(&Local).format(TIME_FMT),
+        event.method,
+        event.user
+    )
+    .unwrap();
+    let status = event.status.unwrap_or_default();
+    let addr = event.addr.as_deref().unwrap_or_default();
+    match event.kind {
+        EventKind::Unregistered => write!(output, "{status:03}/OK      UNREGISTERED").unwrap(),
+        EventKind::RegistrationOk => {
+            let repeat = event
+                .repeat
+                .map_or_else(|| " F".to_owned(), |r| format!("{r:2}"));
+            write!(
+                output,
+                "{status:03}/OK      Expires:{:4} ({repeat},{:4}) {addr:<15}",
+                event.expires.unwrap_or_default(),
+                event.stream.unwrap_or_default(),
+            )
+            .unwrap();
+            if let Some(err_time) = event.error_minutes {
+                write!(output, " Last Error: {err_time} minutes.").unwrap();
+            }
+        }
+        EventKind::Expired => write!(
+            output,
+            "EXPIRED!!!  {} seconds ({})",
+            event.expires.unwrap_or_default(),
+            event
+                .last_seen
+                .unwrap_or_default()
+                .with_timezone(&Local)
+                .format(TIME_FMT)
+        )
+        .unwrap(),
+        EventKind::Redirect | EventKind::RegistrationError => {
+            let label = if event.kind == EventKind::Redirect {
+                "Redirect"
+            } else {
+                "Error"
+            };
+            write!(output, "{status:03}/{label}").unwrap();
+            if let Some(repeat) = event.repeat {
+                write!(output, " ({repeat})").unwrap();
+            }
+        }
+        EventKind::Timeout => {
+            write!(
+                output,
+                "{status:03}/Timeout {} s",
+                event.elapsed_s.unwrap_or_default()
+            )
+            .unwrap();
+            if let Some(repeat) = event.repeat {
+                write!(output, " ({repeat})").unwrap();
+            } else {
+                write!(output, " {addr:<15}").unwrap();
+            }
+        }
+        EventKind::Unauthorized => write!(
+            output,
+            "{status:03}/Unauthorized {}",
+            event.auth_user.as_deref().unwrap_or_default()
+        )
+        .unwrap(),
+        EventKind::CallRequest
+        | EventKind::CallResponse
+        | EventKind::Request
+        | EventKind::Response => {
+            let peer = event.peer.as_deref().unwrap_or_default();
+            let call_id = event.call_id.as_deref().unwrap_or_default();
+            if matches!(event.kind, EventKind::CallResponse | EventKind::Response) {
+                write!(output, "<<-{peer:>8} {status:03} CID:{call_id}").unwrap();
+            } else {
+                write!(output, "->>{peer:>8} REQ CID:{call_id}").unwrap();
+            }
+            if let Some(display) = &event.display {
+                write!(output, " From: {display}").unwrap();
+            }
+            if let Some(media) = &event.media {
+                write!(
+                    output,
+                    " MEDIA {}:{}\t{}",
+                    media.addr,
+                    media.port,
+                    if media.codecs.is_empty() {
+                        media.formats.clone()
+                    } else {
+                        media.codecs.join(", ")
+                    }
+                )
+                .unwrap();
+                if media.direction != Direction::SendRecv {
+                    write!(output, " {}", media.direction.as_str()).unwrap();
+                }
+            }
+        }
+        EventKind::Negotiated | EventKind::NegotiationFailed => {
+            let peer = event.peer.as_deref().unwrap_or_default();
+            let call_id = event.call_id.as_deref().unwrap_or_default();
+            let negotiation = event.negotiation.clone().unwrap_or_default();
+            if let Some(codec) = &negotiation.codec {
+                write!(output, "<->{peer:>8} SDP {codec}").unwrap();
+                if let Some(ptime) = negotiation.ptime {
+                    write!(output, " ptime:{ptime}").unwrap();
+                }
+                if negotiation.direction != Direction::SendRecv {
+                    write!(output, " {}", negotiation.direction.as_str()).unwrap();
+                }
+            } else if event.status.is_some() {
+                write!(output, "<->{peer:>8} SDP REJECTED {status:03}").unwrap();
+            } else {
+                write!(
+                    output,
+                    "<->{peer:>8} SDP MISMATCH answered: {}",
+                    negotiation.answered.join(", ")
+                )
+                .unwrap();
+            }
+            write!(
+                output,
+                " offered: {} CID:{call_id}",
+                negotiation.offered.join(", ")
+            )
+            .unwrap();
+            if negotiation.reinvite {
+                write!(output, " re-INVITE").unwrap();
+            }
+            if let Some(change) = &negotiation.change {
+                write!(output, " ({change})").unwrap();
+            }
+        }
+        EventKind::ErrorResponse => {
+            write!(
+                output,
+                "<<-{addr:<15} {}",
+                event.detail.as_deref().unwrap_or_default()
+            )
+            .unwrap();
+            if let Some(latency) = event.latency_ms {
+                write!(output, " ({latency:.1} ms)").unwrap();
+            }
+        }
+        EventKind::NoResponse => {
+            write!(output, "->>{addr:<15} NO RESPONSE").unwrap();
+            if let Some(elapsed) = event.elapsed_s {
+                write!(output, " {elapsed} s").unwrap();
+            }
+            write!(output, " {}", event.detail.as_deref().unwrap_or_default()).unwrap();
+        }
+        EventKind::Handshake | EventKind::Alert | EventKind::Warning => {
+            let label = match event.kind {
+                EventKind::Handshake => "HANDSHAKE",
+                EventKind::Alert => "ALERT",
+                _ => "WARNING",
+            };
+            write!(
+                output,
+                "<->{addr:<15} {label} {}",
+                event.detail.as_deref().unwrap_or_default()
+            )
+            .unwrap();
+            if let Some(latency) = event.latency_ms {
+                write!(output, " ({latency:.1} ms)").unwrap();
+            }
+        }
+        EventKind::Log => {
+            let log = event.log.clone().unwrap_or_default();
+            write!(output, "<<-{addr:<15} {}", log.message).unwrap();
+        }
+        EventKind::Audit => {
+            write!(
+                output,
+                "<->{addr:<15} {} {}",
+                event.auth_user.as_deref().unwrap_or_default(),
+                event.detail.as_deref().unwrap_or_default()
+            )
+            .unwrap();
+            if let Some(latency) = event.latency_ms {
+                write!(output, " ({latency:.1} ms)").unwrap();
+            }
+        }
+        EventKind::Unknown => write!(output, "{status:03}/Unknown").unwrap(),
+    }
+    output
+}
+
+impl EventFormatter for TextFormatter {
+    fn event(&mut self, event: &Event) {
+        println!("{}", render(event));
+    }
+
+    fn report(&mut self, text: &str, min_verbosity: u8) {
+        println!("{text}");
+        if self.verbosity > min_verbosity {
+            eprintln!("{text}");
+        }
+    }
+}
+
+// MARK: TESTS
+#[cfg(test)]
+mod test {
+    use chrono::{DateTime, Local};
+
+    use super::render;
+    use crate::analyzers::{Event, EventKind, TIME_FMT};
+
+    #[test]
+    fn render_register_ok() {
+        let ts = DateTime::from_timestamp(1738062028, 284088000).unwrap();
+        let event = Event {
+            ts,
+            protocol: "sip",
+            kind: EventKind::RegistrationOk,
+            user: "1001".into(),
+            method: "REGISTER".into(),
+            status: Some(200),
+            expires: Some(3600),
+            stream: Some(12),
+            addr: Some("10.0.0.5".into()),
+            ..Default::default()
+        };
+        assert_eq!(
+            render(&event),
+            format!(
+                "{} REGISTER 1001       200/OK      Expires:3600 ( F,  12) 10.0.0.5       ",
+                ts.with_timezone(&Local).format(TIME_FMT)
+            )
+        );
+        let json = serde_json::to_string(&event).unwrap();
+        assert_eq!(
+            json,
+            r#"{"ts":"2025-01-28T11:00:28.284088Z","protocol":"sip","kind":"registration_ok","user":"1001","method":"REGISTER","status":200,"expires":3600,"stream":12,"addr":"10.0.0.5"}"#
+        );
+    }
+}
@@ -1,5 +1,5 @@
 use chrono::{NaiveDateTime, TimeZone as _, Utc};
-use clap::{ArgAction, Parser, Subcommand, command};
+use clap::{ArgAction, Parser, Subcommand};
 use glob::glob;
 use regex::Regex;
 use replays::ReplaySender;
@@ -12,6 +12,7 @@ use tokio::{
     task,
 };
 use utils::str::MaybeReplaceVecExt as _;
+mod formatters;
 mod replays;
 mod utils;
 
@@ -66,7 +67,43 @@ enum ArgsCommand {
         )]
         replay_contraction: u64,
     },
-    Analyzer,
+    Analyzer {
+        #[clap(
+            long,
+            value_enum,
+            default_value_t,
+            help = "Output format for analyzer events"
+        )]
+        format: formatters::OutputFormat,
+        #[clap(
+            long,
+            help = "Seconds without response before a request is reported as unanswered",
+            default_value = "5"
+        )]
+        timeout: u64,
+        #[clap(long, help = "Number of entries in top lists", default_value = "10")]
+        top: usize,
+        #[clap(
+            long,
+            help = "Report TLS certificates expiring within this many days",
+            default_value = "30"
+        )]
+        cert_expiry_days: i64,
+        #[clap(
+            long,
+            help = "Gateway IP address watched for MAC changes by the arp analyzer, can be repeated"
+        )]
+        gateway: Vec<String>,
+        #[clap(long, help = "Write register values to a CSV file (modbus analyzer)")]
+        csv: Option<PathBuf>,
+        #[clap(long, help = "Frame definition JSON file (framed analyzer)")]
+        config: Option<PathBuf>,
+        #[clap(
+            long,
+            help = "Throughput interval in seconds, also prints a running top-N each interval (flows analyzer)"
+        )]
+        interval: Option<u64>,
+    },
 }
 
 const FIX_FIELDS: usize = 3;
@@ -132,7 +169,7 @@ async fn main() {
 
             data_field = add_dump_protocol_fields(&mut tshark_args, &args, *json);
         }
-        ArgsCommand::Analyzer => {
+        ArgsCommand::Analyzer { .. } => {
             if let Some(analyzer) = &analyzer {
                 tshark_args.push("-t");
                 tshark_args.push("e.6");
@@ -186,7 +223,7 @@ async fn main() {
 
                 }
                 _ = shutdown_rx1.recv() => {
-                    println!("Main Loop shutting down...");
+                    eprintln!("Main Loop shutting down...");
                     break;
                 }
             }
@@ -294,7 +331,7 @@ fn add_dump_protocol_fields(tshark_args: &mut Vec<&str>, args: &Args, json: bool
 async fn process_line(
     line: String,
     cmd_args: &ArgsCommand,
-    analyzer: &mut Option<impl ProtocolAnalyzer>,
+    analyzer: &mut Option<Box<dyn ProtocolAnalyzer>>,
     replayer: &mut Option<impl ReplaySender>,
     data_field: usize,
 ) {
@@ -351,11 +388,15 @@ async fn process_line(
                 println!("{line}");
             }
         }
-        ArgsCommand::Analyzer => analyze_line(&line, cmd_args, analyzer),
+        ArgsCommand::Analyzer { .. } => analyze_line(&line, cmd_args, analyzer),
     }
 }
 
-fn analyze_line(line: &str, _cmd_args: &ArgsCommand, analyzer: &mut Option<impl ProtocolAnalyzer>) {
+fn analyze_line(
+    line: &str,
+    _cmd_args: &ArgsCommand,
+    analyzer: &mut Option<Box<dyn ProtocolAnalyzer>>,
+) {
     let split_out = line.split('\t').collect::<Vec<&str>>();
     let dt = NaiveDateTime::parse_from_str(split_out[0], "%s.%6f")
         .map(|d| Utc.from_utc_datetime(&d))
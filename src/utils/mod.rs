@@ -3,6 +3,7 @@ use std::path::Path;
 
 use regex::Regex;
 pub(crate) mod str;
+pub(crate) mod x509;
 
 pub(crate) fn get_path_suffix(path: &Path, delimiter: char) -> Option<&str> {
     let file_name = path.file_name()?.to_str()?;
@@ -0,0 +1,120 @@
+use chrono::{DateTime, NaiveDateTime, TimeZone as _, Utc};
+
+/// Fields of interest from a DER encoded X.509 certificate
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CertInfo {
+    pub subject: String,
+    pub issuer: String,
+    pub not_after: Option<DateTime<Utc>>,
+}
+
+/// Splits one DER TLV, returns (tag, content, remaining bytes)
+fn read_tlv(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, data) = data.split_first()?;
+    let (&len, mut data) = data.split_first()?;
+    let len = if len & 0x80 == 0 {
+        len as usize
+    } else {
+        let n = (len & 0x7f) as usize;
+        if n == 0 || n > 4 || data.len() < n {
+            return None;
+        }
+        let len = data[..n]
+            .iter()
+            .fold(0usize, |acc, b| (acc << 8) | *b as usize);
+        data = &data[n..];
+        len
+    };
+    if data.len() < len {
+        return None;
+    }
+    Some((tag, &data[..len], &data[len..]))
+}
+
+fn attribute_name(oid: &[u8]) -> Option<&'static str> {
+    match oid {
+        [0x55, 0x04, 0x03] => Some("CN"),
+        [0x55, 0x04, 0x06] => Some("C"),
+        [0x55, 0x04, 0x07] => Some("L"),
+        [0x55, 0x04, 0x08] => Some("ST"),
+        [0x55, 0x04, 0x0a] => Some("O"),
+        [0x55, 0x04, 0x0b] => Some("OU"),
+        _ => None,
+    }
+}
+
+/// Name ::= SEQUENCE OF SET OF SEQUENCE { type OID, value ANY }
+fn parse_name(mut rdns: &[u8]) -> String {
+    let mut parts = Vec::new();
+    while let Some((_, set, rest)) = read_tlv(rdns) {
+        rdns = rest;
+        let Some((_, attr, _)) = read_tlv(set) else {
+            continue;
+        };
+        if let Some((0x06, oid, value)) = read_tlv(attr)
+            && let Some(name) = attribute_name(oid)
+            && let Some((_, value, _)) = read_tlv(value)
+        {
+            parts.push(format!("{name}={}", String::from_utf8_lossy(value)));
+        }
+    }
+    parts.join(", ")
+}
+
+fn parse_time(tag: u8, value: &[u8]) -> Option<DateTime<Utc>> {
+    let value = std::str::from_utf8(value).ok()?;
+    let value = match tag {
+        // UTCTime, YY >= 50 is 19YY (RFC 5280)
+        0x17 => {
+            let century = if value.get(..2)? >= "50" { "19" } else { "20" };
+            format!("{century}{value}")
+        }
+        0x18 => value.to_owned(),
+        _ => return None,
+    };
+    NaiveDateTime::parse_from_str(&value, "%Y%m%d%H%M%SZ")
+        .ok()
+        .map(|d| Utc.from_utc_datetime(&d))
+}
+
+pub fn parse_certificate(der: &[u8]) -> Option<CertInfo> {
+    let (_, cert, _) = read_tlv(der)?;
+    let (_, tbs, _) = read_tlv(cert)?;
+    let (tag, _, mut rest) = read_tlv(tbs)?;
+    // optional [0] version before the serial number
+    if tag == 0xa0 {
+        (_, _, rest) = read_tlv(rest)?;
+    }
+    let (_, _signature, rest) = read_tlv(rest)?;
+    let (_, issuer, rest) = read_tlv(rest)?;
+    let (_, validity, rest) = read_tlv(rest)?;
+    let (_, subject, _) = read_tlv(rest)?;
+    let (_, _not_before, validity) = read_tlv(validity)?;
+    let not_after = read_tlv(validity).and_then(|(tag, value, _)| parse_time(tag, value));
+    Some(CertInfo {
+        subject: parse_name(subject),
+        issuer: parse_name(issuer),
+        not_after,
+    })
+}
+
+// MARK: TESTS
+#[cfg(test)]
+mod test {
+    use chrono::DateTime;
+
+    use super::parse_certificate;
+
+    #[test]
+    fn parse_self_signed() {
+        let der = hex::decode(
+            "308201ac30820153a00302010202140b19dbdbdf9b6264509574c98266fec630c82dbc300a06082a8648ce3d040302302c3118301606035504030c0f7369702e6578616d706c652e636f6d3110300e060355040a0c074578616d706c65301e170d3236313031383139313231375a170d3236313032383139313231375a302c3118301606035504030c0f7369702e6578616d706c652e636f6d3110300e060355040a0c074578616d706c653059301306072a8648ce3d020106082a8648ce3d03010703420004e932c697446e3c79cb05363c358d11e78531e2e219e430e91da553fd380dbb14d51edf9b77bbbb4e6b305967606ecc054d9b191f0133e8be2db342a4936d4f89a3533051301d0603551d0e041604149bd93de052da5e080eab750b0ce733f12623a147301f0603551d230418301680149bd93de052da5e080eab750b0ce733f12623a147300f0603551d130101ff040530030101ff300a06082a8648ce3d04030203470030440220536ad21681c97b7482adc350115c7139ae6f454e6130d17cee6bbbde3c714673022020d11d83e221543085d21206f526246ac144dac028f8240d64e2194903537145",
+        )
+        .unwrap();
+        let cert = parse_certificate(&der).unwrap();
+        assert_eq!(cert.subject, "CN=sip.example.com, O=Example");
+        assert_eq!(cert.issuer, "CN=sip.example.com, O=Example");
+        assert_eq!(cert.not_after, DateTime::from_timestamp(1793214737, 0));
+        assert!(parse_certificate(&der[..40]).is_none());
+    }
+}